@@ -34,6 +34,20 @@ impl From<u32> for TiFlashApplyRes {
 pub struct TiFlashRaftProxy {
     pub stopped: u8,
     pub key_manager: Option<Arc<DataKeyManager>>,
+    /// Optional FFI features this proxy build understands, see [`capability`].
+    /// Negotiated down to the features TiFlash also understands via
+    /// `handle_negotiate_features` before either side relies on them.
+    pub capabilities: u64,
+}
+
+/// Bits of [`TiFlashRaftProxy::capabilities`] / [`TiFlashServerHelper::capabilities`].
+/// New optional FFI behavior gets a bit here instead of a `check()` version
+/// bump, so a proxy and TiFlash at different versions can still talk as
+/// long as they agree to disable whatever the older side doesn't support.
+pub mod capability {
+    pub const STREAMING_APPLY: u64 = 1 << 0;
+    pub const COMPRESSION: u64 = 1 << 1;
+    pub const AEAD_ENCRYPTION: u64 = 1 << 2;
 }
 
 type TiFlashRaftProxyPtr = *const TiFlashRaftProxy;
@@ -48,6 +62,16 @@ pub extern "C" fn ffi_handle_enable_encryption(proxy_ptr: TiFlashRaftProxyPtr) -
     unsafe { (*proxy_ptr).key_manager.is_some().into() }
 }
 
+/// Intersects TiFlash's advertised capability mask with what this proxy
+/// build understands, returning the set both sides can safely rely on.
+#[no_mangle]
+pub extern "C" fn ffi_handle_negotiate_features(
+    proxy_ptr: TiFlashRaftProxyPtr,
+    tiflash_capabilities: u64,
+) -> u64 {
+    unsafe { (*proxy_ptr).capabilities & tiflash_capabilities }
+}
+
 enum FileEncryptionRes {
     Disabled,
     Ok,
@@ -72,6 +96,12 @@ pub struct FileEncryptionInfoRes {
     pub method: u8,
     pub key: TiFlashRawString,
     pub iv: TiFlashRawString,
+    /// Authentication tag for `Sm4Gcm`/`AesGcm`-class methods; empty for the
+    /// plain CTR methods, which have nothing to authenticate.
+    pub tag: TiFlashRawString,
+    /// Additional authenticated data bound into `tag`, if the method is one
+    /// of the AEAD variants and the key manager supplied any.
+    pub aad: TiFlashRawString,
     pub erro_msg: TiFlashRawString,
 }
 
@@ -82,6 +112,8 @@ impl FileEncryptionInfoRes {
             method: EncryptionMethod::Unknown as u8,
             key: std::ptr::null(),
             iv: std::ptr::null(),
+            tag: std::ptr::null(),
+            aad: std::ptr::null(),
             erro_msg: std::ptr::null(),
         }
     }
@@ -92,16 +124,29 @@ impl FileEncryptionInfoRes {
             method: EncryptionMethod::Unknown as u8,
             key: std::ptr::null(),
             iv: std::ptr::null(),
+            tag: std::ptr::null(),
+            aad: std::ptr::null(),
             erro_msg,
         }
     }
 
     fn from(f: FileEncryptionInfo) -> Self {
+        let helper = get_tiflash_server_helper();
         FileEncryptionInfoRes {
             res: FileEncryptionRes::Ok.into(),
             method: f.method as u8,
-            key: get_tiflash_server_helper().gen_cpp_string(&f.key),
-            iv: get_tiflash_server_helper().gen_cpp_string(&f.iv),
+            key: helper.gen_cpp_string(&f.key),
+            iv: helper.gen_cpp_string(&f.iv),
+            tag: if f.tag.is_empty() {
+                std::ptr::null()
+            } else {
+                helper.gen_cpp_string(&f.tag)
+            },
+            aad: if f.aad.is_empty() {
+                std::ptr::null()
+            } else {
+                helper.gen_cpp_string(&f.aad)
+            },
             erro_msg: std::ptr::null(),
         }
     }
@@ -233,8 +278,14 @@ pub extern "C" fn ffi_handle_rename_file(
 #[repr(C)]
 pub struct TiFlashRaftProxyHelper {
     proxy_ptr: TiFlashRaftProxyPtr,
+    /// This proxy build's own capability mask, mirrored here (alongside
+    /// `TiFlashServerHelper::capabilities`) so TiFlash can read it without a
+    /// round-trip through `handle_negotiate_features` if it only wants to
+    /// log or display it.
+    capabilities: u64,
     handle_check_stopped: extern "C" fn(TiFlashRaftProxyPtr) -> u8,
     handle_enable_encryption: extern "C" fn(TiFlashRaftProxyPtr) -> u8,
+    handle_negotiate_features: extern "C" fn(TiFlashRaftProxyPtr, u64) -> u64,
     handle_get_file: extern "C" fn(TiFlashRaftProxyPtr, BaseBuffView) -> FileEncryptionInfoRes,
     handle_new_file: extern "C" fn(TiFlashRaftProxyPtr, BaseBuffView) -> FileEncryptionInfoRes,
     handle_delete_file: extern "C" fn(TiFlashRaftProxyPtr, BaseBuffView) -> FileEncryptionInfoRes,
@@ -248,8 +299,10 @@ impl TiFlashRaftProxyHelper {
     pub fn new(proxy: &TiFlashRaftProxy) -> Self {
         TiFlashRaftProxyHelper {
             proxy_ptr: proxy,
+            capabilities: proxy.capabilities,
             handle_check_stopped: ffi_handle_check_stopped,
             handle_enable_encryption: ffi_handle_enable_encryption,
+            handle_negotiate_features: ffi_handle_negotiate_features,
             handle_get_file: ffi_handle_get_file,
             handle_new_file: ffi_handle_new_file,
             handle_delete_file: ffi_handle_delete_file,
@@ -259,24 +312,270 @@ impl TiFlashRaftProxyHelper {
     }
 }
 
+/// A corrupt or truncated ingest/snapshot SST used to `.unwrap()` its way
+/// into a process-wide panic; this carries the failure back to the caller
+/// instead, so TiFlash can be told to retry the snapshot from the leader.
+#[derive(Debug)]
+pub enum SstReadError {
+    Open(String),
+    ChecksumMismatch(String),
+    Seek(String),
+    /// The whole-file digest carried in the ingest/snapshot metadata didn't
+    /// match the file actually on disk, independent of whatever RocksDB's
+    /// own block checksums caught.
+    DigestMismatch { expected: u32, got: u32 },
+}
+
+impl std::fmt::Display for SstReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SstReadError::Open(e) => write!(f, "failed to open ingest sst: {}", e),
+            SstReadError::ChecksumMismatch(e) => write!(f, "ingest sst checksum mismatch: {}", e),
+            SstReadError::Seek(e) => write!(f, "failed to seek ingest sst: {}", e),
+            SstReadError::DigestMismatch { expected, got } => write!(
+                f,
+                "ingest sst whole-file digest mismatch: expected {:08x}, got {:08x}",
+                expected, got
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SstReadError {}
+
+impl SstReadError {
+    /// Status handed back across the FFI so TiFlash can distinguish a
+    /// recoverable ingest failure (retry the snapshot) from success.
+    pub fn ffi_code(&self) -> u8 {
+        match self {
+            SstReadError::Open(_) => 1,
+            SstReadError::ChecksumMismatch(_) => 2,
+            SstReadError::Seek(_) => 3,
+            SstReadError::DigestMismatch { .. } => 4,
+        }
+    }
+}
+
+/// Digests the raw bytes of `path` as they sit on disk, so a mismatch
+/// against the sender's recorded digest catches corruption in the file
+/// framing itself (truncation, a bad copy) rather than only the individual
+/// RocksDB blocks inside it.
+fn whole_file_digest(path: &str) -> std::io::Result<u32> {
+    let data = std::fs::read(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&data);
+    Ok(hasher.finalize())
+}
+
+/// How many keys (or how many bytes, whichever comes first) a single
+/// `SnapshotBatchIter::next_batch` call hands over, so large regions don't
+/// need the whole CF materialized in memory before a single
+/// `handle_apply_snapshot` call.
+pub const SNAPSHOT_BATCH_MAX_KEYS: usize = 4096;
+pub const SNAPSHOT_BATCH_MAX_BYTES: usize = 2 * 1024 * 1024;
+
+/// Pulls key/value pairs out of a single CF's SST one batch at a time,
+/// instead of `gen_snap_kv_data_from_sst`'s walk that copies everything
+/// into a `SnapshotKV` up front. `keys`/`vals` are reused across calls so
+/// callers feeding this into `handle_apply_snapshot_chunk` don't re-allocate
+/// their `BaseBuffView` scratch vectors for every batch.
+pub struct SnapshotBatchIter {
+    sst_reader: RocksSstReader,
+    started: bool,
+    exhausted: bool,
+    // Keeps the owned bytes behind the `BaseBuffView`s returned by the last
+    // `next_batch` call alive until the caller pulls the next one.
+    owned: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl SnapshotBatchIter {
+    /// `expected_digest`, when given, is checked against the file's
+    /// whole-file CRC-32 (IEEE polynomial, via `crc32fast`; not CRC-32C)
+    /// before RocksDB ever touches it, rejecting a
+    /// corrupted ingest up front instead of relying solely on block-level
+    /// checksums inside the SST.
+    pub fn new(
+        cf_file_path: &str,
+        key_manager: Option<Arc<DataKeyManager>>,
+        expected_digest: Option<u32>,
+    ) -> Result<Self, SstReadError> {
+        if let Some(expected) = expected_digest {
+            let got = whole_file_digest(cf_file_path)
+                .map_err(|e| SstReadError::Open(e.to_string()))?;
+            if got != expected {
+                return Err(SstReadError::DigestMismatch { expected, got });
+            }
+        }
+        let env = get_env(key_manager, None).map_err(|e| SstReadError::Open(e.to_string()))?;
+        let sst_reader = RocksSstReader::open_with_env(cf_file_path, Some(env))
+            .map_err(|e| SstReadError::Open(e.to_string()))?;
+        sst_reader
+            .verify_checksum()
+            .map_err(|e| SstReadError::ChecksumMismatch(e.to_string()))?;
+        Ok(SnapshotBatchIter {
+            sst_reader,
+            started: false,
+            exhausted: false,
+            owned: Vec::new(),
+        })
+    }
+
+    /// Fills `keys`/`vals` with the next batch, clearing them first. Returns
+    /// `false` once the CF has been fully consumed (in which case `keys`
+    /// and `vals` are left empty).
+    pub fn next_batch(
+        &mut self,
+        keys: &mut Vec<BaseBuffView>,
+        vals: &mut Vec<BaseBuffView>,
+    ) -> Result<bool, SstReadError> {
+        keys.clear();
+        vals.clear();
+        let last_key = self.owned.last().map(|(k, _)| keys::data_key(k));
+        self.owned.clear();
+        if self.exhausted {
+            return Ok(false);
+        }
+
+        let mut iter = self.sst_reader.iter();
+        let mut remained = if !self.started {
+            self.started = true;
+            iter.seek(SeekKey::Start)
+                .map_err(|e| SstReadError::Seek(e.to_string()))?
+        } else {
+            // `RocksSstReader::iter()` is stateless per call in this tree, so
+            // re-seeking to the last returned key resumes where we left off.
+            match last_key {
+                Some(last_key) => {
+                    let resumed = iter
+                        .seek(SeekKey::Key(&last_key))
+                        .map_err(|e| SstReadError::Seek(e.to_string()))?;
+                    resumed && iter.next().map_err(|e| SstReadError::Seek(e.to_string()))?
+                }
+                None => iter
+                    .seek(SeekKey::Start)
+                    .map_err(|e| SstReadError::Seek(e.to_string()))?,
+            }
+        };
+
+        let mut batch_bytes = 0usize;
+        while remained && keys.len() < SNAPSHOT_BATCH_MAX_KEYS && batch_bytes < SNAPSHOT_BATCH_MAX_BYTES {
+            let ori_key = keys::origin_key(iter.key()).to_vec();
+            let ori_val = iter.value().to_vec();
+            batch_bytes += ori_key.len() + ori_val.len();
+            self.owned.push((ori_key, ori_val));
+            remained = iter.next().map_err(|e| SstReadError::Seek(e.to_string()))?;
+        }
+        if !remained {
+            self.exhausted = true;
+        }
+
+        for (k, v) in &self.owned {
+            keys.push(BaseBuffView {
+                data: k.as_ptr(),
+                len: k.len() as u64,
+            });
+            vals.push(BaseBuffView {
+                data: v.as_ptr(),
+                len: v.len() as u64,
+            });
+        }
+        Ok(!keys.is_empty())
+    }
+
+    /// True once the last `next_batch` call has drained the CF; the batch
+    /// it just returned (if any) is therefore the final one.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+}
+
+/// Thin wrapper over [`SnapshotBatchIter`] for callers that still want the
+/// whole CF materialized at once.
 pub fn gen_snap_kv_data_from_sst(
     cf_file_path: &str,
     key_manager: Option<Arc<DataKeyManager>>,
-) -> SnapshotKV {
+    expected_digest: Option<u32>,
+) -> Result<SnapshotKV, SstReadError> {
     let mut cf_snap = SnapshotKV::new();
-    let env = get_env(key_manager, None).unwrap();
-    let sst_reader = RocksSstReader::open_with_env(cf_file_path, Some(env)).unwrap();
-    sst_reader.verify_checksum().unwrap();
-    let mut iter = sst_reader.iter();
-    let mut remained = iter.seek(SeekKey::Start).unwrap();
-    while remained {
-        let ori_key = keys::origin_key(iter.key());
-        let ori_val = iter.value();
-        cf_snap.push_back((ori_key.to_vec(), ori_val.to_vec()));
-        remained = iter.next().unwrap();
+    let mut iter = SnapshotBatchIter::new(cf_file_path, key_manager, expected_digest)?;
+    let mut keys = Vec::new();
+    let mut vals = Vec::new();
+    while iter.next_batch(&mut keys, &mut vals)? {
+        for (k, v) in keys.iter().zip(vals.iter()) {
+            cf_snap.push_back((k.to_slice().to_vec(), v.to_slice().to_vec()));
+        }
     }
+    Ok(cf_snap)
+}
 
-    cf_snap
+/// Compression applied to a batch of keys/values before it crosses the FFI.
+/// Chosen per snapshot/write-cmd batch based on [`SnapshotCompressionConfig`];
+/// `None` keeps the original per-record `BaseBuffView` arrays untouched so a
+/// proxy build that hasn't opted in sees exactly today's wire shape.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum CompressionMethod {
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl Into<u8> for CompressionMethod {
+    fn into(self) -> u8 {
+        match self {
+            CompressionMethod::None => 0,
+            CompressionMethod::Zstd => 1,
+            CompressionMethod::Lz4 => 2,
+        }
+    }
+}
+
+/// Controls whether [`SnapshotHelper::gen_snapshot_view`] and
+/// [`WriteCmds::gen_view`] hand their payload over as one compressed buffer
+/// instead of per-record `BaseBuffView`s. Defaults to `None`, i.e. today's
+/// uncompressed behavior.
+#[derive(Clone)]
+pub struct SnapshotCompressionConfig {
+    pub method: CompressionMethod,
+    pub level: i32,
+    /// Batches smaller than this many bytes are left uncompressed even when
+    /// `method` isn't `None`, since the framing overhead isn't worth it.
+    pub threshold: usize,
+}
+
+impl Default for SnapshotCompressionConfig {
+    fn default() -> Self {
+        SnapshotCompressionConfig {
+            method: CompressionMethod::None,
+            level: 0,
+            threshold: usize::MAX,
+        }
+    }
+}
+
+/// Serializes `pairs` as a stream of `(len(u32 le), bytes)*` records and
+/// compresses it with `config.method`. Returns `None` when compression is
+/// off or the payload is under `config.threshold`, in which case the caller
+/// should fall back to per-record `BaseBuffView`s.
+fn compress_batch(config: &SnapshotCompressionConfig, pairs: &[&[u8]]) -> Option<Vec<u8>> {
+    if config.method == CompressionMethod::None {
+        return None;
+    }
+    let total_len: usize = pairs.iter().map(|p| p.len() + 4).sum();
+    if total_len < config.threshold {
+        return None;
+    }
+    let mut raw = Vec::with_capacity(total_len);
+    for p in pairs {
+        raw.extend_from_slice(&(p.len() as u32).to_le_bytes());
+        raw.extend_from_slice(p);
+    }
+    Some(match config.method {
+        CompressionMethod::None => unreachable!(),
+        CompressionMethod::Zstd => {
+            zstd::bulk::compress(&raw, config.level).expect("zstd compress")
+        }
+        CompressionMethod::Lz4 => lz4_flex::compress_prepend_size(&raw),
+    })
 }
 
 pub enum WriteCmdType {
@@ -312,6 +611,8 @@ pub struct WriteCmdsView {
     cmd_types: *const u8,
     cf: *const u8,
     len: u64,
+    compression_method: u8,
+    compressed_payload: BaseBuffView,
 }
 
 impl Into<u8> for WriteCmdType {
@@ -339,6 +640,12 @@ pub struct WriteCmds {
     vals: Vec<BaseBuffView>,
     cmd_type: Vec<u8>,
     cf: Vec<u8>,
+    compression: SnapshotCompressionConfig,
+    // Owned buffer backing `compressed_payload` in the last `gen_view()`
+    // call; kept alive here since `WriteCmdsView` only carries a raw view.
+    // `gen_view` takes `&self` to match `handle_write_raft_cmd`'s existing
+    // signature, so refilling this buffer goes through a `RefCell`.
+    compressed_buf: std::cell::RefCell<Vec<u8>>,
 }
 
 impl WriteCmds {
@@ -348,6 +655,8 @@ impl WriteCmds {
             vals: Vec::<BaseBuffView>::with_capacity(cap),
             cmd_type: Vec::<u8>::with_capacity(cap),
             cf: Vec::<u8>::with_capacity(cap),
+            compression: SnapshotCompressionConfig::default(),
+            compressed_buf: std::cell::RefCell::new(Vec::new()),
         }
     }
 
@@ -355,6 +664,11 @@ impl WriteCmds {
         WriteCmds::default()
     }
 
+    pub fn with_compression(mut self, compression: SnapshotCompressionConfig) -> WriteCmds {
+        self.compression = compression;
+        self
+    }
+
     pub fn push(&mut self, key: &[u8], val: &[u8], cmd_type: WriteCmdType, cf: &str) {
         self.keys.push(BaseBuffView {
             data: key.as_ptr(),
@@ -373,12 +687,39 @@ impl WriteCmds {
     }
 
     fn gen_view(&self) -> WriteCmdsView {
+        let pairs: Vec<&[u8]> = self
+            .keys
+            .iter()
+            .zip(self.vals.iter())
+            .flat_map(|(k, v)| [k.to_slice(), v.to_slice()])
+            .collect();
+        if let Some(compressed) = compress_batch(&self.compression, &pairs) {
+            let mut buf = self.compressed_buf.borrow_mut();
+            *buf = compressed;
+            return WriteCmdsView {
+                keys: std::ptr::null(),
+                vals: std::ptr::null(),
+                cmd_types: self.cmd_type.as_ptr(),
+                cf: self.cf.as_ptr(),
+                len: self.cmd_type.len() as u64,
+                compression_method: self.compression.method.into(),
+                compressed_payload: BaseBuffView {
+                    data: buf.as_ptr(),
+                    len: buf.len() as u64,
+                },
+            };
+        }
         WriteCmdsView {
             keys: self.keys.as_ptr(),
             vals: self.vals.as_ptr(),
             cmd_types: self.cmd_type.as_ptr(),
             cf: self.cf.as_ptr(),
             len: self.cmd_type.len() as u64,
+            compression_method: CompressionMethod::None.into(),
+            compressed_payload: BaseBuffView {
+                data: std::ptr::null(),
+                len: 0,
+            },
         }
     }
 }
@@ -407,6 +748,8 @@ pub struct SnapshotView {
     vals: *const BaseBuffView,
     cf: u8,
     len: u64,
+    compression_method: u8,
+    compressed_payload: BaseBuffView,
 }
 
 #[repr(C)]
@@ -420,9 +763,19 @@ pub struct SnapshotHelper {
     cf_snaps: Vec<(WriteCmdCf, SnapshotKV)>,
     kv_view: Vec<SnapshotKVView>,
     snap_view: Vec<SnapshotView>,
+    compression: SnapshotCompressionConfig,
+    // Owned buffers backing `compressed_payload` for any CF that got
+    // compressed in the last `gen_snapshot_view()` call; one slot per CF so
+    // `SnapshotView`s can keep pointing at them once this function returns.
+    compressed_bufs: Vec<Vec<u8>>,
 }
 
 impl SnapshotHelper {
+    pub fn with_compression(mut self, compression: SnapshotCompressionConfig) -> SnapshotHelper {
+        self.compression = compression;
+        self
+    }
+
     pub fn add_cf_snap(&mut self, cf_type: WriteCmdCf, snap_kv: SnapshotKV) {
         self.cf_snaps.push((cf_type, snap_kv));
     }
@@ -431,6 +784,8 @@ impl SnapshotHelper {
         let len = self.cf_snaps.len();
         self.kv_view.clear();
         self.snap_view.clear();
+        self.compressed_bufs.clear();
+        self.compressed_bufs.resize(len, Vec::new());
 
         for i in 0..len {
             self.kv_view
@@ -438,12 +793,38 @@ impl SnapshotHelper {
         }
 
         for i in 0..len {
-            self.snap_view.push(SnapshotView {
-                keys: self.kv_view[i].0.as_ptr(),
-                vals: self.kv_view[i].1.as_ptr(),
-                len: self.kv_view[i].0.len() as u64,
-                cf: self.cf_snaps[i].0.clone().into(),
-            });
+            let (keys, vals) = &self.kv_view[i];
+            let pairs: Vec<&[u8]> = keys
+                .iter()
+                .zip(vals.iter())
+                .flat_map(|(k, v)| [k.to_slice(), v.to_slice()])
+                .collect();
+            if let Some(compressed) = compress_batch(&self.compression, &pairs) {
+                self.compressed_bufs[i] = compressed;
+                self.snap_view.push(SnapshotView {
+                    keys: std::ptr::null(),
+                    vals: std::ptr::null(),
+                    len: keys.len() as u64,
+                    cf: self.cf_snaps[i].0.clone().into(),
+                    compression_method: self.compression.method.into(),
+                    compressed_payload: BaseBuffView {
+                        data: self.compressed_bufs[i].as_ptr(),
+                        len: self.compressed_bufs[i].len() as u64,
+                    },
+                });
+            } else {
+                self.snap_view.push(SnapshotView {
+                    keys: keys.as_ptr(),
+                    vals: vals.as_ptr(),
+                    len: keys.len() as u64,
+                    cf: self.cf_snaps[i].0.clone().into(),
+                    compression_method: CompressionMethod::None.into(),
+                    compressed_payload: BaseBuffView {
+                        data: std::ptr::null(),
+                        len: 0,
+                    },
+                });
+            }
         }
         SnapshotViewArray {
             views: self.snap_view.as_ptr(),
@@ -511,6 +892,11 @@ pub struct FsStats {
 pub struct TiFlashServerHelper {
     magic_number: u32,
     version: u32,
+    /// Feature bits this TiFlash build understands; intersected against
+    /// [`capability`] via `handle_set_proxy` to produce
+    /// [`negotiated_capabilities`], rather than gating new functionality on
+    /// `version` alone.
+    capabilities: u64,
     //
     inner: TiFlashServerPtr,
     gen_cpp_string: extern "C" fn(BaseBuffView) -> *const u8,
@@ -519,6 +905,15 @@ pub struct TiFlashServerHelper {
         extern "C" fn(TiFlashServerPtr, BaseBuffView, BaseBuffView, RaftCmdHeader) -> u32,
     handle_apply_snapshot:
         extern "C" fn(TiFlashServerPtr, BaseBuffView, u64, SnapshotViewArray, u64, u64),
+    handle_apply_snapshot_chunk: extern "C" fn(
+        TiFlashServerPtr,
+        BaseBuffView,
+        u64,
+        SnapshotView,
+        u64,
+        u64,
+        u8,
+    ),
     handle_set_proxy: extern "C" fn(TiFlashServerPtr, *const TiFlashRaftProxyHelper),
     handle_destroy: extern "C" fn(TiFlashServerPtr, RegionId),
     handle_ingest_sst: extern "C" fn(TiFlashServerPtr, SnapshotViewArray, RaftCmdHeader),
@@ -531,6 +926,20 @@ unsafe impl Send for TiFlashServerHelper {}
 
 pub static mut TIFLASH_SERVER_HELPER_PTR: u64 = 0;
 
+/// Capability bits both sides agreed on, filled in by
+/// `TiFlashServerHelper::handle_set_proxy` once the proxy hands its helper
+/// over to TiFlash. Optional features (streaming apply, compression, AEAD)
+/// should check this rather than assuming a fixed FFI `version`.
+static NEGOTIATED_CAPABILITIES: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+pub fn negotiated_capabilities() -> u64 {
+    NEGOTIATED_CAPABILITIES.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+pub fn has_capability(bit: u64) -> bool {
+    negotiated_capabilities() & bit == bit
+}
+
 pub fn get_tiflash_server_helper() -> &'static TiFlashServerHelper {
     return unsafe { &(*(TIFLASH_SERVER_HELPER_PTR as *const TiFlashServerHelper)) };
 }
@@ -558,13 +967,20 @@ impl TiFlashServerHelper {
     }
 
     pub fn handle_set_proxy(&mut self, proxy: *const TiFlashRaftProxyHelper) {
+        let agreed = unsafe { ((*proxy).handle_negotiate_features)((*proxy).proxy_ptr, self.capabilities) };
+        NEGOTIATED_CAPABILITIES.store(agreed, std::sync::atomic::Ordering::Relaxed);
         (self.handle_set_proxy)(self.inner, proxy);
     }
 
+    /// Rejects only a genuinely incompatible build (wrong magic number, or a
+    /// TiFlash too old to have ever shipped `handle_negotiate_features`).
+    /// Anything added since then is gated on [`negotiated_capabilities`]
+    /// instead of forcing every FFI addition through a lockstep version
+    /// bump between the proxy and TiFlash.
     pub fn check(&self) {
         assert_eq!(std::mem::align_of::<Self>(), std::mem::align_of::<u64>());
         const MAGIC_NUMBER: u32 = 0x13579BDF;
-        const VERSION: u32 = 6;
+        const MIN_SUPPORTED_VERSION: u32 = 6;
 
         if self.magic_number != MAGIC_NUMBER {
             eprintln!(
@@ -572,10 +988,10 @@ impl TiFlashServerHelper {
                 MAGIC_NUMBER, self.magic_number
             );
             std::process::exit(-1);
-        } else if self.version != VERSION {
+        } else if self.version < MIN_SUPPORTED_VERSION {
             eprintln!(
-                "TiFlash Proxy FFI version not match: expect {} got {}",
-                VERSION, self.version
+                "TiFlash Proxy FFI version too old: need at least {} got {}",
+                MIN_SUPPORTED_VERSION, self.version
             );
             std::process::exit(-1);
         }
@@ -618,6 +1034,52 @@ impl TiFlashServerHelper {
         (self.handle_ingest_sst)(self.inner, snaps, header);
     }
 
+    /// Streams one CF's snapshot data across the FFI in fixed-size batches
+    /// pulled from a [`SnapshotBatchIter`], instead of requiring the whole
+    /// CF to already be materialized into a `SnapshotViewArray`. `cf_type`
+    /// identifies the batch's CF the same way `SnapshotView::cf` does.
+    pub fn handle_apply_snapshot_chunk(
+        &self,
+        region: &metapb::Region,
+        peer_id: u64,
+        cf_type: WriteCmdCf,
+        iter: &mut SnapshotBatchIter,
+        index: u64,
+        term: u64,
+    ) -> Result<(), SstReadError> {
+        let region_buff = ProtoMsgBaseBuff::new(region).buff_view;
+        let mut keys = Vec::new();
+        let mut vals = Vec::new();
+        loop {
+            let has_batch = iter.next_batch(&mut keys, &mut vals)?;
+            let is_last = !has_batch || iter.is_exhausted();
+            let view = SnapshotView {
+                keys: keys.as_ptr(),
+                vals: vals.as_ptr(),
+                len: keys.len() as u64,
+                cf: cf_type.into(),
+                compression_method: CompressionMethod::None.into(),
+                compressed_payload: BaseBuffView {
+                    data: std::ptr::null(),
+                    len: 0,
+                },
+            };
+            (self.handle_apply_snapshot_chunk)(
+                self.inner,
+                region_buff,
+                peer_id,
+                view,
+                index,
+                term,
+                is_last as u8,
+            );
+            if is_last {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     pub fn handle_destroy(&self, region_id: RegionId) {
         (self.handle_destroy)(self.inner, region_id);
     }
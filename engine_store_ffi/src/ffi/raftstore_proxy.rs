@@ -1,12 +1,16 @@
 // Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
 
-use std::sync::{
-    atomic::{AtomicU8, Ordering},
-    Arc,
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use encryption::DataKeyManager;
-use engine_traits::Peekable;
+use engine_traits::{Peekable, CF_RAFT};
+use kvproto::{kvrpcpb::ReadIndexRequest, raft_serverpb::RaftApplyState};
 
 use super::{
     interfaces_ffi::{ConstRawVoidPtr, RaftProxyStatus, RaftStoreProxyPtr},
@@ -15,11 +19,39 @@ use super::{
 };
 use crate::TiFlashEngine;
 
+/// Poll interval while [`RaftStoreProxy::get_value_cf_read_index`] waits for
+/// this peer's applied index to catch up to the leader's read index.
+const READ_INDEX_APPLY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Bits of [`RaftStoreProxy::capabilities`]. New optional FFI behavior gets
+/// a bit here instead of requiring a lockstep version bump between the proxy
+/// and engine-store, so a rolling upgrade where the two sides are briefly on
+/// different builds still degrades gracefully instead of invoking a
+/// callback the other side can't implement yet. Mirrors the `capability`
+/// module already used for the older `tiflash_ffi` handshake.
+pub mod capability {
+    pub const FAST_ADD_PEER: u64 = 1 << 0;
+    pub const ENCRYPTED_SST_VIEW: u64 = 1 << 1;
+    pub const FOLLOWER_READ: u64 = 1 << 2;
+}
+
+/// Every feature this proxy build knows how to speak. Intersected against
+/// whatever the engine-store side advertises in
+/// [`RaftStoreProxy::negotiate_capabilities`] to produce the set both sides
+/// actually agree on.
+const SUPPORTED_CAPABILITIES: u64 =
+    capability::FAST_ADD_PEER | capability::ENCRYPTED_SST_VIEW | capability::FOLLOWER_READ;
+
 pub struct RaftStoreProxy {
     pub status: AtomicU8,
     pub key_manager: Option<Arc<DataKeyManager>>,
     pub read_index_client: Option<Box<dyn read_index_helper::ReadIndex>>,
     pub kv_engine: std::sync::RwLock<Option<TiFlashEngine>>,
+    /// Feature bits agreed on with the engine-store side, filled in by
+    /// [`RaftStoreProxy::negotiate_capabilities`] during init. Zero (i.e.
+    /// nothing negotiated yet) until then, which callers should treat the
+    /// same as "no optional feature may be assumed".
+    negotiated_capabilities: AtomicU64,
 }
 
 impl RaftStoreProxy {
@@ -34,8 +66,25 @@ impl RaftStoreProxy {
             key_manager,
             read_index_client,
             kv_engine,
+            negotiated_capabilities: AtomicU64::new(0),
         }
     }
+
+    /// Intersects `engine_store_capabilities` with [`SUPPORTED_CAPABILITIES`]
+    /// and stores the result, returning it so the FFI entry point that calls
+    /// this (in the generated bindings, not part of this snapshot) can hand
+    /// the same value back to engine-store. Called once during the init
+    /// handshake, after `status` has moved past its initial phase but before
+    /// either side relies on any optional feature.
+    pub fn negotiate_capabilities(&self, engine_store_capabilities: u64) -> u64 {
+        let agreed = SUPPORTED_CAPABILITIES & engine_store_capabilities;
+        self.negotiated_capabilities.store(agreed, Ordering::SeqCst);
+        agreed
+    }
+
+    pub fn has_capability(&self, bit: u64) -> bool {
+        self.negotiated_capabilities.load(Ordering::SeqCst) & bit == bit
+    }
 }
 
 impl RaftStoreProxyFFI<TiFlashEngine> for RaftStoreProxy {
@@ -74,6 +123,91 @@ impl RaftStoreProxyFFI<TiFlashEngine> for RaftStoreProxy {
     }
 }
 
+impl RaftStoreProxy {
+    /// Reads `region_id`'s current `RaftApplyState` out of `kv_engine`'s
+    /// `CF_RAFT`, the same storage `get_value_cf_read_index` below polls to
+    /// learn this peer's applied index.
+    fn get_raft_apply_state(&self, region_id: u64) -> Option<RaftApplyState> {
+        let kv_engine_lock = self.kv_engine.read().unwrap();
+        let kv_engine = kv_engine_lock.as_ref()?;
+        kv_engine
+            .get_msg_cf::<RaftApplyState>(CF_RAFT, &keys::apply_state_key(region_id))
+            .unwrap_or(None)
+    }
+
+    /// Consistent (linearizable) follower/learner read. Unlike
+    /// `get_value_cf`, which reads `kv_engine` straight away and may return
+    /// a stale value on a follower/learner, this first asks the leader for
+    /// its current commit index through `read_index_client` (the "read
+    /// index"), then blocks until this peer's own applied index for
+    /// `region_id` has caught up to it, and only then performs the read —
+    /// mirroring the standard replica-read technique where the read index
+    /// pins a point in the Raft log the local state machine must first
+    /// replay. Gives up and calls back with an error once `timeout` has
+    /// elapsed, rather than blocking forever on either the read-index round
+    /// trip or the local apply wait.
+    pub fn get_value_cf_read_index<F>(
+        &self,
+        region_id: u64,
+        cf: &str,
+        key: &[u8],
+        timeout: Duration,
+        cb: F,
+    ) where
+        F: FnOnce(Result<Option<&[u8]>, String>),
+    {
+        if !self.has_capability(capability::FOLLOWER_READ) {
+            cb(Err(
+                "follower read not negotiated with engine-store".to_string()
+            ));
+            return;
+        }
+        let read_index_client = match self.read_index_client.as_ref() {
+            Some(c) => c,
+            None => {
+                cb(Err("read index client is not initialized".to_string()));
+                return;
+            }
+        };
+
+        let deadline = Instant::now() + timeout;
+        let mut req = ReadIndexRequest::default();
+        req.mut_context().set_region_id(region_id);
+        let mut resps = read_index_client.read_index(vec![(req, region_id)], timeout);
+        let (resp, _) = match resps.pop() {
+            Some(r) => r,
+            None => {
+                cb(Err("read index request returned no response".to_string()));
+                return;
+            }
+        };
+        if resp.has_region_error() || resp.has_locked() {
+            cb(Err(format!("read index failed: {:?}", resp)));
+            return;
+        }
+        let read_index = resp.get_read_index();
+
+        loop {
+            let applied_index = self
+                .get_raft_apply_state(region_id)
+                .map_or(0, |s| s.get_applied_index());
+            if applied_index >= read_index {
+                break;
+            }
+            if Instant::now() >= deadline {
+                cb(Err(format!(
+                    "timed out waiting for applied index {} to reach read index {} for region {}",
+                    applied_index, read_index, region_id,
+                )));
+                return;
+            }
+            std::thread::sleep(READ_INDEX_APPLY_POLL_INTERVAL);
+        }
+
+        self.get_value_cf(cf, key, cb);
+    }
+}
+
 impl RaftStoreProxyPtr {
     pub unsafe fn as_ref(&self) -> &RaftStoreProxy {
         &*(self.inner as *const RaftStoreProxy)
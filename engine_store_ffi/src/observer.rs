@@ -1,20 +1,24 @@
 // Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
 use std::{
-    collections::hash_map::Entry as MapEntry,
+    cmp::Reverse,
+    collections::{hash_map::Entry as MapEntry, BinaryHeap},
     io::Write,
     ops::DerefMut,
     path::PathBuf,
     str::FromStr,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        mpsc, Arc, Mutex, RwLock,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        mpsc, Arc, Condvar, Mutex, RwLock,
     },
-    time::SystemTime,
+    time::{Duration, Instant},
 };
 
 use collections::HashMap;
 use engine_tiflash::{FsStatsExt, RawPSWriteBatchPtr, RawPSWriteBatchWrapper};
-use engine_traits::{RaftEngine, SstMetaInfo, CF_RAFT};
+use engine_traits::{
+    Iterable, KvEngine, RaftEngine, SstMetaInfo, SstReader, SstWriter, SstWriterBuilder,
+    SyncMutable, CF_RAFT,
+};
 use kvproto::{
     metapb::Region,
     raft_cmdpb::{AdminCmdType, AdminRequest, AdminResponse, CmdType, RaftCmdRequest},
@@ -171,17 +175,95 @@ unsafe impl Sync for PtrWrapper {}
 pub struct PrehandleContext {
     // tracer holds ptr of snapshot prehandled by TiFlash side.
     pub tracer: HashMap<SnapKey, Arc<PrehandleTask>>,
+    // Regions currently being fast-added, so the leader-keeper tick knows
+    // which source peer to nudge for each. Entries are added once the
+    // snapshot-build worker starts fetching data from `source_peer` and
+    // removed once the attempt resolves (applied or fallen back).
+    pub leader_keepers: HashMap<u64, LeaderKeeperState>,
+    // Per-`SnapKey` lifecycle of a prehandle job, so callers (metrics,
+    // `post_apply_snapshot`, ops tooling) can ask "is this still queued,
+    // actually running, or already done" instead of only being able to
+    // block on `PrehandleTask::recv`. Entries are added alongside `tracer`
+    // and removed together with it; `set_prehandle_job_state` only updates a
+    // key that is still in `tracer`.
+    pub job_states: HashMap<SnapKey, ApplySnapJobState>,
+}
+
+/// Lifecycle of one background prehandle job, tracked in
+/// `PrehandleContext::job_states` for introspection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplySnapJobState {
+    // Inserted into the tracer, waiting for a free `apply_snap_pool` slot
+    // (and, below the pool's own thread cap, a tranquility permit).
+    Queued,
+    // Holds a tranquility permit and is inside `pre_handle_snapshot_impl`.
+    Running,
+    // Ran to completion and sent a result on `PrehandleTask::recv`.
+    Finished,
+    // Saw `PrehandleTask::aborted` and returned without calling into the
+    // FFI at all.
+    Aborted,
+    // `scrub_sst_files` found a corrupt CF file; `post_apply_snapshot` will
+    // take its `need_retry` path and synchronously re-generate instead of
+    // consuming this job's (nonexistent) result.
+    Corrupt,
 }
 
+// How long one region's fast-add-peer attempt has been relying on
+// `source_peer` staying (or becoming) leader, tracked so the keeper can give
+// up after `LEADER_KEEPER_MAX_DURATION` instead of nudging forever.
+#[derive(Debug, Clone)]
+pub struct LeaderKeeperState {
+    pub source_peer: kvproto::metapb::Peer,
+    pub started: Instant,
+}
+
+// Interval between leader-keeper hints sent to an in-flight fast-add-peer's
+// source peer. In a full build this would come from `EngineStoreConfig` so
+// operators can tune it per cluster; `engine_store_ffi`'s own config struct
+// isn't reachable from this file, so it's a local constant here.
+const LEADER_KEEPER_INTERVAL: Duration = Duration::from_secs(2);
+// Upper bound on how long the keeper will keep nudging one region before
+// giving up; the fast-add-peer attempt itself also has its own
+// fruitless-rounds fallback (see `FastAddPeerProgress`), so this is a second,
+// coarser safety net against thrashing leadership forever on a region that's
+// stuck for some other reason.
+const LEADER_KEEPER_MAX_DURATION: Duration = Duration::from_secs(120);
+
 #[derive(Debug)]
 pub struct PrehandleTask {
     pub recv: mpsc::Receiver<PtrWrapper>,
     pub peer_id: u64,
+    // The region epoch this prehandle was started under, so the consumer can
+    // detect a conf-change/split/merge that happened while prehandling ran
+    // in the background and the result is now stale.
+    pub epoch: kvproto::metapb::RegionEpoch,
+    // Set by `TiFlashObserver::abort_prehandle` when a newer conf-change,
+    // region destroy, or epoch bump supersedes this prehandle before it's
+    // been consumed, so the apply/post-apply path frees the prehandled
+    // pointer instead of installing stale data.
+    pub aborted: AtomicBool,
+    // Opened by `pre_apply_snapshot` and carried across the `mpsc` handoff
+    // so the background prehandle closure and `post_apply_snapshot` nest
+    // under the same span instead of each emitting disconnected log lines.
+    // Closed (dropped) once `post_apply_snapshot` finishes with this task.
+    pub span: tracing::Span,
 }
 
 impl PrehandleTask {
-    fn new(recv: mpsc::Receiver<PtrWrapper>, peer_id: u64) -> Self {
-        PrehandleTask { recv, peer_id }
+    fn new(
+        recv: mpsc::Receiver<PtrWrapper>,
+        peer_id: u64,
+        epoch: kvproto::metapb::RegionEpoch,
+        span: tracing::Span,
+    ) -> Self {
+        PrehandleTask {
+            recv,
+            peer_id,
+            epoch,
+            aborted: AtomicBool::new(false),
+            span,
+        }
     }
 }
 unsafe impl Send for PrehandleTask {}
@@ -189,6 +271,268 @@ unsafe impl Sync for PrehandleTask {}
 
 const CACHED_REGION_INFO_SLOT_COUNT: usize = 256;
 
+// Key scheme for persisting `CachedRegionInfo` under `CF_RAFT`, so a
+// fast-add-peer in flight across a TiFlash restart isn't silently forgotten
+// and fallen back to a full TiKV snapshot. Encoded as
+// `{FAST_ADD_PEER_STATE_PREFIX}{region_id big-endian}`.
+const FAST_ADD_PEER_STATE_PREFIX: &[u8] = b"fast_add_peer_state_";
+// `fast_add_peer_progress` is deliberately not persisted: it only tracks an
+// in-flight fast-add-peer attempt by this process, and a restart always
+// invalidates any such attempt, so it should come back as "not inflight"
+// after recovery rather than resurrect a dead attempt's progress state.
+// `pending_merge` (the third byte) *is* persisted, and deliberately so: it
+// has to survive both a restart and, via `remove_cached_region_info`'s
+// `keep_data`, the region's own tombstoning, since forgetting it is exactly
+// what would let a stale fast-add-peer attempt resurrect pre-merge data.
+const CACHED_REGION_INFO_ENCODED_LEN: usize = 3;
+
+fn fast_add_peer_state_key(region_id: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(FAST_ADD_PEER_STATE_PREFIX.len() + 8);
+    key.extend_from_slice(FAST_ADD_PEER_STATE_PREFIX);
+    key.extend_from_slice(&region_id.to_be_bytes());
+    key
+}
+
+fn fast_add_peer_state_prefix_end() -> Vec<u8> {
+    // All keys under the prefix compare less than the prefix with its last
+    // byte bumped, since every continuation byte is <= 0xff.
+    let mut end = FAST_ADD_PEER_STATE_PREFIX.to_vec();
+    *end.last_mut().unwrap() += 1;
+    end
+}
+
+fn decode_fast_add_peer_state_key(key: &[u8]) -> Option<u64> {
+    if key.len() != FAST_ADD_PEER_STATE_PREFIX.len() + 8 || !key.starts_with(FAST_ADD_PEER_STATE_PREFIX) {
+        return None;
+    }
+    let mut id_bytes = [0u8; 8];
+    id_bytes.copy_from_slice(&key[FAST_ADD_PEER_STATE_PREFIX.len()..]);
+    Some(u64::from_be_bytes(id_bytes))
+}
+
+fn encode_cached_region_info(info: &CachedRegionInfo) -> [u8; CACHED_REGION_INFO_ENCODED_LEN] {
+    [
+        info.replicated_or_created.load(Ordering::SeqCst) as u8,
+        info.inited_or_fallback.load(Ordering::SeqCst) as u8,
+        info.pending_merge.load(Ordering::SeqCst) as u8,
+    ]
+}
+
+fn decode_cached_region_info(buf: &[u8]) -> Option<CachedRegionInfo> {
+    if buf.len() != CACHED_REGION_INFO_ENCODED_LEN {
+        return None;
+    }
+    let info = CachedRegionInfo::default();
+    info.replicated_or_created.store(buf[0] != 0, Ordering::SeqCst);
+    info.inited_or_fallback.store(buf[1] != 0, Ordering::SeqCst);
+    info.pending_merge.store(buf[2] != 0, Ordering::SeqCst);
+    Some(info)
+}
+
+// Backoff window for an inflight fast-add-peer attempt: a duplicate
+// MsgAppend arriving at least this long after `last_progress` moves
+// `last_progress` to now and counts one fruitless round (see
+// `FastAddPeerProgress::observe_duplicate`); a duplicate inside the window
+// is only added to `dup_msg_append_count`.
+const FAST_ADD_PEER_PROGRESS_BACKOFF: Duration = Duration::from_secs(5);
+// Number of fruitless backoff rounds after which `observe_duplicate` reports
+// that the attempt should give up on the fast path entirely.
+const FAST_ADD_PEER_MAX_FRUITLESS_ROUNDS: u64 = 6;
+// How long an inflight fast-add-peer attempt may stay uninitialized before
+// `run_fast_add_peer_reaper_tick` starts asking whether it's still wanted at
+// all (see `StaleState`). This would be an `engine_store` config field
+// (e.g. `stale_peer_check_millis`) if `crate::EngineStoreConfig` were
+// reachable from this file; see `MAX_PENDING_APPLIES` for the same
+// constraint.
+const STALE_FAP_CHECK_THRESHOLD: Duration = Duration::from_secs(30);
+// How long a region's data is retained on this store as a fast-add-peer
+// source after its only local replica is removed via conf-change, before
+// `run_retained_tombstone_gc_tick` reaps it (see `RetainedTombstoneSource`).
+// This would be an `engine_store` config field
+// (`keep_removed_region_data_ttl`) if `crate::EngineStoreConfig` were
+// reachable from this file; see `MAX_PENDING_APPLIES` for the same
+// constraint.
+const KEEP_REMOVED_REGION_DATA_TTL: Duration = Duration::from_secs(600);
+
+// Bounds for the wait-for-apply-index read subsystem (`wait_for_read_index`
+// and `CachedRegionInfo::pending_reads`). Would be `engine_store` config
+// fields (`read_index_wait_max_millis`, `read_index_wait_queue_depth`) if
+// `crate::EngineStoreConfig` were reachable from this file; see
+// `MAX_PENDING_APPLIES` for the same constraint.
+const READ_INDEX_WAIT_MAX: Duration = Duration::from_secs(5);
+const READ_INDEX_WAIT_QUEUE_DEPTH: usize = 1024;
+
+// Smoothing factor for `SourcePeerStats`'s round-trip-time EMA: how much
+// weight a freshly observed fetch round-trip gets against the running
+// estimate.
+const SOURCE_RTT_EMA_ALPHA: f64 = 0.2;
+
+// One FAP donor candidate, as handed to `rank_source_candidates` by
+// whatever drives fast-add-peer source selection. `applied_index` and
+// `state` describe the candidate as of the caller's most recent knowledge
+// of it (e.g. from its last reported heartbeat); `rtt` is filled in from
+// this store's own `SourcePeerStats` via `source_rtt_estimate`.
+#[derive(Debug, Clone)]
+pub struct SourceCandidate {
+    pub peer_id: u64,
+    pub store_id: u64,
+    pub applied_index: u64,
+    pub state: PeerState,
+    pub rtt: Option<Duration>,
+}
+
+// Per-donor-peer round-trip-time estimate for FAP fetches, updated by
+// `record_source_fetch_rtt` on each completed fetch round-trip and
+// consulted by `source_rtt_estimate` when ranking candidates. Kept
+// in-memory only, like `reachability`: an EMA from before a restart isn't
+// worth carrying forward.
+struct SourcePeerStats {
+    rtt_ema_millis: Mutex<f64>,
+}
+
+// Recorded on `CachedRegionInfo` when this store's (only) replica of a
+// region is removed via conf-change, instead of eagerly discarding the
+// region's data: lets a later `add_peer` re-adding the same region on
+// another store still fast-add-peer from this now-tombstoned-but-retained
+// copy (single-replica migration), rather than unconditionally falling back
+// to a full TiKV snapshot for lack of any live source. See
+// `validate_retained_tombstone_source`.
+#[derive(Debug)]
+struct RetainedTombstoneSource {
+    // Compared by `version` only (not `conf_ver`, which a migration is
+    // expected to bump): the keyspace this copy covers must not have moved
+    // out from under it via an intervening split/merge.
+    epoch: kvproto::metapb::RegionEpoch,
+    applied_index: u64,
+    destroyed_at: Instant,
+}
+
+// Mirrors raftstore's own peer-side `StaleState` (Valid / ToValidate /
+// Stale), but answers a different question: not "can this peer still reach
+// its leader", but "is this fast-add-peer learner still actually wanted".
+// `is_stalled` above only catches a source store that's gone silent; it
+// can't catch an attempt that keeps receiving duplicate MsgAppends (so
+// `last_progress` keeps resetting) but whose snapshot build/ingest never
+// actually finishes, which would otherwise sit in `WaitForData` forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StaleState {
+    Valid,
+    ToValidate,
+    Stale,
+}
+
+impl Default for StaleState {
+    fn default() -> Self {
+        StaleState::Valid
+    }
+}
+
+// Tracks one region's in-flight fast-add-peer attempt by rounds of observed
+// MsgAppend duplicates rather than a raw wall-clock timestamp alone: each
+// duplicate arriving at least `FAST_ADD_PEER_PROGRESS_BACKOFF` after
+// `last_progress` counts one fruitless round, and once
+// `FAST_ADD_PEER_MAX_FRUITLESS_ROUNDS` are reached the attempt is escalated
+// to `fallback_to_slow_path`. The round count is reset only when an attempt
+// starts or is cleared.
+#[derive(Debug, Default)]
+pub struct FastAddPeerProgress {
+    // `None` when no fast-add-peer attempt is in flight for this region.
+    start: Mutex<Option<Instant>>,
+    // Instant the current attempt started or last counted a fruitless round
+    // (a duplicate MsgAppend arriving at least a backoff window after the
+    // previous value); duplicates inside the window leave it unchanged.
+    // `is_stalled` measures silence from here.
+    last_progress: Mutex<Option<Instant>>,
+    // Fruitless backoff rounds counted by `observe_duplicate` so far; reset
+    // to 0 only by `start`/`clear`.
+    fruitless_rounds: AtomicU64,
+    // Total duplicate MsgAppends observed while this attempt has been
+    // inflight, exposed so operators can see which source stores are
+    // starving fast-add-peer.
+    pub dup_msg_append_count: AtomicU64,
+    // Driven by `run_fast_add_peer_reaper_tick`; see `StaleState`.
+    stale_state: Mutex<StaleState>,
+}
+
+impl FastAddPeerProgress {
+    fn is_inflight(&self) -> bool {
+        self.start.lock().unwrap().is_some()
+    }
+
+    fn start(&self) {
+        let now = Instant::now();
+        *self.start.lock().unwrap() = Some(now);
+        *self.last_progress.lock().unwrap() = Some(now);
+        self.fruitless_rounds.store(0, Ordering::SeqCst);
+        self.dup_msg_append_count.store(0, Ordering::SeqCst);
+        *self.stale_state.lock().unwrap() = StaleState::Valid;
+    }
+
+    fn clear(&self) {
+        *self.start.lock().unwrap() = None;
+        *self.last_progress.lock().unwrap() = None;
+        self.fruitless_rounds.store(0, Ordering::SeqCst);
+        *self.stale_state.lock().unwrap() = StaleState::Valid;
+    }
+
+    // Whether this attempt has been inflight past `STALE_FAP_CHECK_THRESHOLD`,
+    // measured from its start rather than from `last_progress`: unlike
+    // `is_stalled`, this is meant to fire even while duplicate MsgAppends
+    // keep arriving.
+    fn past_stale_check_threshold(&self) -> bool {
+        match *self.start.lock().unwrap() {
+            None => false,
+            Some(start) => {
+                Instant::now().saturating_duration_since(start) >= STALE_FAP_CHECK_THRESHOLD
+            }
+        }
+    }
+
+    fn stale_state(&self) -> StaleState {
+        *self.stale_state.lock().unwrap()
+    }
+
+    fn set_stale_state(&self, s: StaleState) {
+        *self.stale_state.lock().unwrap() = s;
+    }
+
+    // Wall-clock counterpart to `observe_duplicate`: catches the case where
+    // the source store has gone fully silent (no more duplicate MsgAppends
+    // at all, e.g. it's partitioned), which `observe_duplicate` can never
+    // detect since it only runs when a duplicate actually arrives. Polled by
+    // `run_fast_add_peer_reaper_tick` independent of any incoming message.
+    fn is_stalled(&self) -> bool {
+        match *self.last_progress.lock().unwrap() {
+            None => false,
+            Some(last) => {
+                Instant::now().saturating_duration_since(last)
+                    >= FAST_ADD_PEER_PROGRESS_BACKOFF * FAST_ADD_PEER_MAX_FRUITLESS_ROUNDS as u32
+            }
+        }
+    }
+
+    // Records an observed duplicate MsgAppend for the in-flight attempt and
+    // reports whether enough progress-less backoff windows have elapsed to
+    // fall back to the slow path. Returns `None` if nothing is inflight.
+    fn observe_duplicate(&self) -> Option<bool> {
+        self.dup_msg_append_count.fetch_add(1, Ordering::SeqCst);
+        let mut last_progress = self.last_progress.lock().unwrap();
+        let now = Instant::now();
+        match *last_progress {
+            None => None,
+            Some(last) => {
+                if now.saturating_duration_since(last) >= FAST_ADD_PEER_PROGRESS_BACKOFF {
+                    *last_progress = Some(now);
+                    let rounds = self.fruitless_rounds.fetch_add(1, Ordering::SeqCst) + 1;
+                    Some(rounds >= FAST_ADD_PEER_MAX_FRUITLESS_ROUNDS)
+                } else {
+                    Some(false)
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct CachedRegionInfo {
     pub replicated_or_created: AtomicBool,
@@ -198,11 +542,265 @@ pub struct CachedRegionInfo {
     // NOTE If we want a fallback, then we must set inited_or_fallback to true,
     // Otherwise, a normal snapshot will be neglect in `post_apply_snapshot` and cause data loss.
     pub inited_or_fallback: AtomicBool,
-    pub snapshot_inflight: portable_atomic::AtomicU128,
+    pub fast_add_peer_progress: FastAddPeerProgress,
+    // Set between `PrepareFlashback` and `FinishFlashback`. Deliberately not
+    // persisted like `replicated_or_created`/`inited_or_fallback`: raftstore
+    // itself is the authority on flashback state and replays it from the
+    // raft log on restart, so there's nothing to recover here.
+    pub in_flashback: AtomicBool,
+    // Result of the most recent `scrub_sst_files` pass over this region's
+    // prehandled snapshot SSTs, kept in memory only: a restart always
+    // re-scrubs the next snapshot it prehandles, so there's nothing worth
+    // persisting here.
+    pub last_sst_scrub: SstScrubState,
+    // Set once a `CommitMerge` targeting this region is confirmed applied,
+    // so a fast-add-peer attempt still racing to build/send a snapshot off
+    // an apply state computed before the merge landed can tell it's stale
+    // (too narrow a range) and fall back to the slow path instead of
+    // clobbering the just-merged source range. See `build_and_send_snapshot`
+    // and `remove_cached_region_info`'s `keep_data`.
+    pub pending_merge: AtomicBool,
+    // Reads that landed on this region while it's a FAP-installed learner
+    // still waiting on its data (`inited_or_fallback` unset) are parked here
+    // keyed by the index they need applied, instead of being served stale or
+    // rejected outright. Drained by `resolve_pending_reads` as
+    // `note_region_applied` advances this region's applied index, and
+    // drained unconditionally by `fallback_to_slow_path` so a read never
+    // waits past the point where its own fast path gave up. Deliberately not
+    // persisted, like `fast_add_peer_progress`: a restart drops the
+    // connections that were waiting along with it.
+    pub pending_reads: Mutex<BinaryHeap<Reverse<PendingRead>>>,
+    // Set by `retain_as_tombstone_source` on `RegionChangeEvent::Destroy`
+    // when this store's replica was removed (not merged away) rather than
+    // immediately discarding the entry. Deliberately not persisted: unlike
+    // `pending_merge`, losing this on a restart only means a single-replica
+    // migration degrades to the normal snapshot path instead of using the
+    // retained copy, not a correctness problem.
+    retained_tombstone: Mutex<Option<RetainedTombstoneSource>>,
+    // Peer-set history built from `ChangePeer`/`ChangePeerV2` admin
+    // commands as they're applied; see `MembershipState`. Deliberately not
+    // persisted, like `in_flashback`: raftstore's own `RegionLocalState`
+    // is the authority on region membership and is replayed from the raft
+    // log on restart, so this is only a cache of what's already been
+    // observed, not a second source of truth.
+    pub membership: Mutex<MembershipState>,
+    // Applies/bytes accumulated since this region's last persist, driving
+    // `PersistPolicy::EveryNApplies`/`ByWriteBytes`/`Adaptive`. Deliberately
+    // not persisted: losing it across a restart only means the new
+    // process's window starts empty, the same as a freshly split region.
+    persist_counters: PersistCounters,
+}
+
+/// How often `post_exec_query` forces a persist of its own, independent of
+/// (and only ever additive to) whatever `EngineStoreApplyRes` already
+/// asked engine-store for -- this never suppresses a persist engine-store
+/// itself wanted, only adds earlier ones. `OnCompactLog` reproduces the
+/// historical behavior, where the normal write path never forces a
+/// persist on its own and CompactLog's `post_exec_admin` handling remains
+/// the only deliberate persistence point.
+#[derive(Debug, Clone, Copy)]
+pub enum PersistPolicy {
+    OnCompactLog,
+    /// Force a persist once at least `n` commands have applied since the
+    /// last one, for this region.
+    EveryNApplies(u64),
+    /// Force a persist once at least `threshold` bytes of put/delete keys
+    /// and values have applied since the last persist, for this region.
+    ByWriteBytes(u64),
+    /// Force a persist once either budget is hit, whichever comes first --
+    /// similar to how a log-structured persist layer buffers updates in
+    /// memory and only flushes once a size or age threshold is crossed.
+    Adaptive {
+        byte_budget: u64,
+        time_budget: Duration,
+    },
+}
+
+impl Default for PersistPolicy {
+    fn default() -> Self {
+        PersistPolicy::OnCompactLog
+    }
+}
+
+// Tracks, per region, how many commands and bytes have applied and how
+// long it's been since the last forced persist -- the inputs
+// `PersistPolicy`'s non-`OnCompactLog` variants evaluate against.
+#[derive(Debug, Default)]
+struct PersistCounters {
+    applied_since_persist: AtomicU64,
+    bytes_since_persist: AtomicU64,
+    window_start: Mutex<Option<Instant>>,
+}
+
+impl PersistCounters {
+    /// Adds `cmd_count`/`bytes` to the since-last-persist tally, starting
+    /// the window clock on the first call after a reset, and returns the
+    /// updated (applied, bytes) totals.
+    fn bump(&self, cmd_count: u64, bytes: u64) -> (u64, u64) {
+        let applied = self.applied_since_persist.fetch_add(cmd_count, Ordering::SeqCst) + cmd_count;
+        let total_bytes = self.bytes_since_persist.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        let mut window_start = self.window_start.lock().unwrap();
+        if window_start.is_none() {
+            *window_start = Some(Instant::now());
+        }
+        (applied, total_bytes)
+    }
+
+    fn window_elapsed(&self) -> Duration {
+        self.window_start
+            .lock()
+            .unwrap()
+            .map(|t| t.elapsed())
+            .unwrap_or_default()
+    }
+
+    fn reset(&self) {
+        self.applied_since_persist.store(0, Ordering::SeqCst);
+        self.bytes_since_persist.store(0, Ordering::SeqCst);
+        *self.window_start.lock().unwrap() = None;
+    }
+}
+
+// Built from each applied `ChangePeer`/`ChangePeerV2` admin command's
+// resulting region (see `MembershipDelta`), so `peers_at` can answer "who
+// were this region's peers as of applied index N" without re-deriving it
+// from the raft log. Entries are appended in increasing `index` order;
+// `record` is idempotent against being handed the same (or an
+// already-superseded) index twice, which matters since apply can replay
+// a command after a restart.
+#[derive(Debug, Default)]
+pub struct MembershipState {
+    history: Vec<(u64, Vec<u64>)>,
+}
+
+impl MembershipState {
+    fn record(&mut self, index: u64, peers: Vec<u64>) {
+        if let Some((last_index, last_peers)) = self.history.last() {
+            if index <= *last_index || peers == *last_peers {
+                return;
+            }
+        }
+        self.history.push((index, peers));
+    }
+
+    /// The peer set effective as of `applied_index`, i.e. the peers
+    /// recorded at the latest entry at or before it. `None` if no
+    /// membership change has been recorded for this region yet.
+    pub fn peers_at(&self, applied_index: u64) -> Option<&[u64]> {
+        self.history
+            .iter()
+            .rev()
+            .find(|(index, _)| *index <= applied_index)
+            .map(|(_, peers)| peers.as_slice())
+    }
+}
+
+// Derives a membership delta from a `ChangePeer`/`ChangePeerV2` command's
+// already-applied result, rather than from the `AdminRequest`'s intended
+// change: the request only carries what was asked for, while the
+// resulting `Region` carries what was actually applied, which is what a
+// peer-set cache needs to stay correct across e.g. a request that no-ops
+// because the peer was already in the requested state.
+pub trait MembershipDelta {
+    fn added_peers(&self, before: &[u64]) -> Vec<u64>;
+    fn removed_peers(&self, before: &[u64]) -> Vec<u64>;
+    fn peer_ids(&self) -> Vec<u64>;
+}
+
+impl MembershipDelta for kvproto::metapb::Region {
+    fn added_peers(&self, before: &[u64]) -> Vec<u64> {
+        self.get_peers()
+            .iter()
+            .map(|p| p.get_id())
+            .filter(|id| !before.contains(id))
+            .collect()
+    }
+
+    fn removed_peers(&self, before: &[u64]) -> Vec<u64> {
+        before
+            .iter()
+            .copied()
+            .filter(|id| !self.get_peers().iter().any(|p| p.get_id() == *id))
+            .collect()
+    }
+
+    fn peer_ids(&self) -> Vec<u64> {
+        self.get_peers().iter().map(|p| p.get_id()).collect()
+    }
+}
+
+// One read parked on `CachedRegionInfo::pending_reads`, ordered by the
+// index it's waiting on so the lowest (soonest to be satisfied) pops first.
+#[derive(Debug)]
+pub struct PendingRead {
+    pub read_index: u64,
+    // Fired with `true` once the region's applied index reaches
+    // `read_index`, or `false` if the region instead fell back to the slow
+    // path, since the read then has to be re-resolved against whatever
+    // snapshot eventually lands rather than this fast-add-peer attempt.
+    pub notify: mpsc::SyncSender<bool>,
+}
+
+impl PartialEq for PendingRead {
+    fn eq(&self, other: &Self) -> bool {
+        self.read_index == other.read_index
+    }
+}
+
+impl Eq for PendingRead {}
+
+impl PartialOrd for PendingRead {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PendingRead {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.read_index.cmp(&other.read_index)
+    }
+}
+
+// Tracked per region so `scrub_sst_files` can escalate repeated corruption
+// (rather than a single flaky read) to a fatal log: a single bad SST might
+// be a transient disk hiccup, but the same region failing scrub over and
+// over points at something actually wrong with how its data is written.
+#[derive(Debug, Default)]
+pub struct SstScrubState {
+    pub verified: AtomicU64,
+    pub corrupt: AtomicU64,
+    // Consecutive scrub passes (for this region) that found at least one
+    // corrupt SST; reset to 0 the moment a pass comes back clean.
+    pub consecutive_corrupt_rounds: AtomicU32,
 }
 
 pub type CachedRegionInfoMap = HashMap<u64, Arc<CachedRegionInfo>>;
 
+// One SST still held in `pending_delete_ssts` because engine-store returned
+// `None` for the ingest that produced it. Tracked with the apply index it
+// was ingested at and the region epoch at that time, so cleanup can be
+// gated on actual flush progress instead of unconditionally draining every
+// SST for the region on the next successful ingest.
+#[derive(Debug)]
+pub struct PendingDeleteSst {
+    pub sst: SstMetaInfo,
+    pub applied_index: u64,
+    pub region_epoch: kvproto::metapb::RegionEpoch,
+}
+
+// Grouped by data CF (mirroring the per-CF `SstApplyState` bookkeeping
+// raftstore-v2 uses for its own apply-progress tracking), since different
+// CFs can flush and thus become safe to clean up at different times.
+pub type PendingDeleteSstsByCf = HashMap<&'static str, Vec<PendingDeleteSst>>;
+
+fn cf_key_for_sst(sst: &SstMetaInfo) -> &'static str {
+    match sst.meta.get_cf_name() {
+        engine_traits::CF_WRITE => engine_traits::CF_WRITE,
+        engine_traits::CF_LOCK => engine_traits::CF_LOCK,
+        _ => engine_traits::CF_DEFAULT,
+    }
+}
+
 pub struct TiFlashObserver<T: Transport, ER: RaftEngine> {
     pub store_id: u64,
     pub engine_store_server_helper: &'static EngineStoreServerHelper,
@@ -212,12 +810,31 @@ pub struct TiFlashObserver<T: Transport, ER: RaftEngine> {
     pub pre_handle_snapshot_ctx: Arc<Mutex<PrehandleContext>>,
     pub snap_handle_pool_size: usize,
     pub apply_snap_pool: Option<Arc<ThreadPool<TaskCell>>>,
-    pub pending_delete_ssts: Arc<RwLock<Vec<SstMetaInfo>>>,
+    pub pending_delete_ssts: Arc<RwLock<PendingDeleteSstsByCf>>,
     pub cached_region_info: Arc<Vec<RwLock<CachedRegionInfoMap>>>,
     // TODO should we use a Mutex here?
     pub trans: Arc<Mutex<T>>,
     pub snap_mgr: Arc<SnapManager>,
     pub engine_store_cfg: crate::EngineStoreConfig,
+    snap_build_queue: SnapshotBuildQueue,
+    // Per-region applied-vs-flushed bookkeeping driving the proactive flush
+    // ticker, so restart replay and memory growth are bounded independently
+    // of TiKV's own CompactLog cadence.
+    flush_progress: Arc<RwLock<HashMap<u64, Arc<FlushProgress>>>>,
+    // Feeds the dedicated cleanup-SST worker so reclaiming a destroyed
+    // region's pending-delete SSTs doesn't unlink files on the region-change
+    // callback's own thread.
+    cleanup_sst_tx: mpsc::SyncSender<SstMetaInfo>,
+    // Debounces unreachable-store reports (see `nudge_leader`) against
+    // messages actually received from each store.
+    reachability: Arc<ReachabilityTracker>,
+    // Per-donor-peer RTT estimates feeding `rank_source_candidates`. See
+    // `SourcePeerStats`.
+    source_peer_stats: Arc<RwLock<HashMap<u64, SourcePeerStats>>>,
+    // Governs when `post_exec_query` forces a persist of its own, on top
+    // of whatever `EngineStoreApplyRes` already asked for. See
+    // `PersistPolicy`.
+    persist_policy: PersistPolicy,
 }
 
 impl<T: Transport + 'static, ER: RaftEngine> Clone for TiFlashObserver<T, ER> {
@@ -236,10 +853,155 @@ impl<T: Transport + 'static, ER: RaftEngine> Clone for TiFlashObserver<T, ER> {
             trans: self.trans.clone(),
             snap_mgr: self.snap_mgr.clone(),
             engine_store_cfg: self.engine_store_cfg.clone(),
+            snap_build_queue: self.snap_build_queue.clone(),
+            flush_progress: self.flush_progress.clone(),
+            cleanup_sst_tx: self.cleanup_sst_tx.clone(),
+            reachability: self.reachability.clone(),
+            source_peer_stats: self.source_peer_stats.clone(),
+            persist_policy: self.persist_policy,
         }
     }
 }
 
+// How many already-queued SST deletions a cleanup-worker wake opportunistically
+// drains into one batch, mirroring `SNAPSHOT_BUILD_BATCH_SIZE`.
+const CLEANUP_SST_BATCH_SIZE: usize = 32;
+// Bound on queued-but-not-yet-deleted SSTs; a full queue means cleanup is
+// falling behind, which is surfaced as a dropped (logged) cleanup rather than
+// blocking the caller (region destroy / region-change callback).
+const CLEANUP_SST_QUEUE_CAPACITY: usize = 1024;
+
+// How often the proactive-flush ticker scans `flush_progress` for regions
+// due a flush. In a full build this and the thresholds below would come
+// from `EngineStoreConfig`; `engine_store_ffi`'s own config struct isn't
+// reachable from this file, so they're local constants here (mirrors
+// `LEADER_KEEPER_INTERVAL` above).
+const PROACTIVE_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+// A region whose applied index has advanced this far past what we last
+// confirmed flushed is proactively flushed even if `CompactLog` hasn't
+// fired, bounding how much log a restart would have to replay.
+const PROACTIVE_FLUSH_MAX_UNFLUSHED_LOG: u64 = 4096;
+// A region is also flushed once this much time has passed since its last
+// flush, regardless of log size, so a quiet region doesn't hold a stale
+// cache indefinitely.
+const PROACTIVE_FLUSH_MAX_AGE: Duration = Duration::from_secs(60);
+
+// Applied-vs-flushed bookkeeping for one region, used only to decide when
+// the proactive flush ticker should call `try_flush_data` for it; this is
+// local heuristic state, not a source of truth, so it's never persisted.
+#[derive(Debug)]
+struct FlushProgress {
+    last_index: AtomicU64,
+    last_term: AtomicU64,
+    flushed_index: AtomicU64,
+    last_flush: Mutex<Instant>,
+}
+
+impl FlushProgress {
+    fn new(index: u64, term: u64) -> Self {
+        FlushProgress {
+            last_index: AtomicU64::new(index),
+            last_term: AtomicU64::new(term),
+            flushed_index: AtomicU64::new(0),
+            last_flush: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+// Minimum time between two "unreachable" reports for the same store, so a
+// single transport hiccup doesn't trigger a storm of needless leader
+// re-elections. Mirrors the backoff raftstore itself uses around
+// `report_unreachable`.
+const UNREACHABLE_REPORT_BACKOFF: Duration = Duration::from_secs(10);
+
+// Per-store bookkeeping behind `ReachabilityTracker`: when it last actually
+// reported `store_id` unreachable, and how many messages had been received
+// from it as of that report.
+struct StoreReachability {
+    last_report: Instant,
+    last_report_received_count: u64,
+    received_count: u64,
+}
+
+impl StoreReachability {
+    fn new() -> Self {
+        StoreReachability {
+            // So the very first failure for a never-seen store is eligible
+            // to report immediately instead of waiting out the backoff.
+            last_report: Instant::now() - UNREACHABLE_REPORT_BACKOFF,
+            last_report_received_count: 0,
+            received_count: 0,
+        }
+    }
+}
+
+/// Debounces "store unreachable" reports against messages actually received
+/// from that store, so a transient blip on an otherwise-live peer doesn't
+/// trigger a needless leader re-election. `record_received` should be called
+/// wherever a message from `store_id` is dispatched; `should_report` is
+/// checked on a transport failure and decides whether the caller should
+/// actually escalate.
+///
+/// This crate sits above `CoprocessorHost`'s apply-time hooks rather than
+/// on raftstore's own message-receive path (that loop lives in the
+/// external `raftstore` crate, not part of this extracted snapshot), so
+/// `record_received` is driven by whatever signal of store liveness this
+/// observer does see directly — see its call sites for which.
+lazy_static::lazy_static! {
+    // Labeled by store id (as a string) rather than kept as per-store gauges,
+    // since the store set is small and bounded by cluster size.
+    static ref MESSAGE_RECV_BY_STORE: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_proxy_message_recv_by_store",
+        "Messages received from each peer store, the same counter `ReachabilityTracker` \
+         checks before escalating an unreachable report",
+        &["store_id"]
+    ).unwrap();
+}
+
+struct ReachabilityTracker {
+    stores: Mutex<HashMap<u64, StoreReachability>>,
+}
+
+impl ReachabilityTracker {
+    fn new() -> Self {
+        ReachabilityTracker {
+            stores: Mutex::new(HashMap::default()),
+        }
+    }
+
+    fn record_received(&self, store_id: u64) {
+        let mut stores = self.stores.lock().unwrap();
+        stores.entry(store_id).or_insert_with(StoreReachability::new).received_count += 1;
+        MESSAGE_RECV_BY_STORE
+            .with_label_values(&[&store_id.to_string()])
+            .inc();
+    }
+
+    /// Returns whether a transport failure against `store_id` right now
+    /// should actually be escalated to an "unreachable" report, given
+    /// `UNREACHABLE_REPORT_BACKOFF` and whatever has been received from it
+    /// since the last report.
+    fn should_report(&self, store_id: u64) -> bool {
+        let mut stores = self.stores.lock().unwrap();
+        let state = stores.entry(store_id).or_insert_with(StoreReachability::new);
+        let now = Instant::now();
+        if now.saturating_duration_since(state.last_report) < UNREACHABLE_REPORT_BACKOFF {
+            return false;
+        }
+        if state.received_count != state.last_report_received_count {
+            // Something arrived from this store since the last report: it's
+            // not actually unreachable, just noisy. Refresh the baseline and
+            // suppress this report.
+            state.last_report = now;
+            state.last_report_received_count = state.received_count;
+            return false;
+        }
+        state.last_report = now;
+        state.last_report_received_count = state.received_count;
+        true
+    }
+}
+
 // TiFlash observer's priority should be higher than all other observers, to
 // avoid being bypassed.
 const TIFLASH_OBSERVER_PRIORITY: u32 = 0;
@@ -269,57 +1031,600 @@ pub fn validate_remote_peer_region(
         Some(peer) => peer.get_id() == new_peer_id,
         None => false,
     }
-}
+}
+
+pub fn get_region_local_state<EK: engine_traits::KvEngine>(
+    engine: &EK,
+    region_id: u64,
+) -> Option<RegionLocalState> {
+    let region_state_key = keys::region_state_key(region_id);
+    engine
+        .get_msg_cf::<RegionLocalState>(CF_RAFT, &region_state_key)
+        .unwrap_or(None)
+}
+
+impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
+    #[inline]
+    fn slot_index(id: u64) -> usize {
+        debug_assert!(CACHED_REGION_INFO_SLOT_COUNT.is_power_of_two());
+        hash_u64(id) as usize & (CACHED_REGION_INFO_SLOT_COUNT - 1)
+    }
+
+    pub fn access_cached_region_info_mut<F: FnMut(MapEntry<u64, Arc<CachedRegionInfo>>)>(
+        &self,
+        region_id: u64,
+        mut f: F,
+    ) -> RaftStoreResult<()> {
+        let slot_id = Self::slot_index(region_id);
+        let mut guard = match self.cached_region_info.get(slot_id).unwrap().write() {
+            Ok(g) => g,
+            Err(_) => return Err(box_err!("access_cached_region_info_mut poisoned")),
+        };
+        f(guard.entry(region_id));
+        // Persist whenever the entry may have changed, so a restart can
+        // rebuild the slot maps instead of silently falling back to the
+        // slow path for every in-flight fast-add-peer.
+        if let Some(info) = guard.get(&region_id) {
+            self.persist_cached_region_info(region_id, info);
+        }
+        Ok(())
+    }
+
+    fn persist_cached_region_info(&self, region_id: u64, info: &CachedRegionInfo) {
+        let key = fast_add_peer_state_key(region_id);
+        let value = encode_cached_region_info(info);
+        if let Err(e) = self.engine.put_cf(CF_RAFT, &key, &value) {
+            warn!("persist fast-add-peer state failed"; "region_id" => region_id, "err" => ?e);
+        }
+    }
+
+    fn remove_persisted_cached_region_info(&self, region_id: u64) {
+        let key = fast_add_peer_state_key(region_id);
+        if let Err(e) = self.engine.delete_cf(CF_RAFT, &key) {
+            warn!("remove persisted fast-add-peer state failed"; "region_id" => region_id, "err" => ?e);
+        }
+    }
+
+    // Rebuilds the slot maps from `CF_RAFT` at observer construction, so a
+    // TiFlash restart doesn't forget the peers that were already replicated
+    // or initialized and retry every one of them through the slow path.
+    fn restore_cached_region_info_from_disk(&self) {
+        let start = fast_add_peer_state_key(0);
+        let end = fast_add_peer_state_prefix_end();
+        let mut restored = 0u64;
+        let res = self.engine.scan(CF_RAFT, &start, &end, false, &mut |k, v| {
+            if let Some(region_id) = decode_fast_add_peer_state_key(k) {
+                if let Some(info) = decode_cached_region_info(v) {
+                    let slot_id = Self::slot_index(region_id);
+                    self.cached_region_info
+                        .get(slot_id)
+                        .unwrap()
+                        .write()
+                        .unwrap()
+                        .insert(region_id, Arc::new(info));
+                    restored += 1;
+                }
+            }
+            Ok(true)
+        });
+        if let Err(e) = res {
+            warn!("restore persisted fast-add-peer state failed"; "err" => ?e);
+        } else if restored > 0 {
+            info!("restored persisted fast-add-peer state"; "count" => restored, "store_id" => self.store_id);
+        }
+    }
+
+    // `keep_data`: true when this region is being torn down as the
+    // confirmed-successful source of a `CommitMerge` (see `pending_merge`),
+    // in which case the persisted entry (and its `pending_merge` marker) is
+    // kept rather than wiped, so a fast-add-peer attempt racing against the
+    // merge still sees it after this region is gone and bails to the slow
+    // path instead of resurrecting pre-merge data. Also true for a plain
+    // peer removal whose data was just kept via `retain_as_tombstone_source`;
+    // that retained entry is later reaped with false. False for an ordinary
+    // destroy (e.g. a merge that never actually landed), which clears
+    // everything as before.
+    pub fn remove_cached_region_info(&self, region_id: u64, keep_data: bool) {
+        info!(
+            "remove_cached_region_info";
+            "region_id" => region_id,
+            "store_id" => self.store_id,
+            "keep_data" => keep_data,
+        );
+        if keep_data {
+            return;
+        }
+        let slot_id = Self::slot_index(region_id);
+        if let Ok(mut g) = self.cached_region_info.get(slot_id).unwrap().write() {
+            let _ = g.remove(&region_id);
+        }
+        // The owner (leader applying Destroy/merge) is responsible for
+        // clearing the persisted entry, so a stale record can't resurrect a
+        // dead region's fast path after a future restart.
+        self.remove_persisted_cached_region_info(region_id);
+    }
+
+    // Called from `on_region_changed`'s `Destroy` branch when this store's
+    // replica of `region_id` is torn down by a plain peer removal (not a
+    // merge): rather than discarding the region's data immediately, mark it
+    // retained so a subsequent `add_peer` re-adding the same region
+    // elsewhere can still fast-add-peer from it (single-replica migration).
+    fn retain_as_tombstone_source(
+        &self,
+        region_id: u64,
+        epoch: kvproto::metapb::RegionEpoch,
+        applied_index: u64,
+    ) {
+        let _ = self.access_cached_region_info_mut(region_id, |e| {
+            let info = match e {
+                MapEntry::Occupied(o) => o.get().clone(),
+                MapEntry::Vacant(v) => v.insert(Arc::new(CachedRegionInfo::default())).clone(),
+            };
+            *info.retained_tombstone.lock().unwrap() = Some(RetainedTombstoneSource {
+                epoch: epoch.clone(),
+                applied_index,
+                destroyed_at: Instant::now(),
+            });
+        });
+    }
+
+    // Entry point for fast-add-peer source selection to validate a
+    // tombstone-with-data peer retained by `retain_as_tombstone_source`
+    // before reading from it: the retained copy must not have expired, and
+    // must cover `want_epoch`'s keyspace (a split/merge since retention
+    // would invalidate it) at an applied index at least as fresh as
+    // `min_applied_index`. `consume` should be true once the caller has
+    // actually built a snapshot off this copy, so the now-redundant data is
+    // reaped right away instead of idling out the rest of the TTL.
+    pub fn validate_retained_tombstone_source(
+        &self,
+        region_id: u64,
+        want_epoch: &kvproto::metapb::RegionEpoch,
+        min_applied_index: u64,
+        consume: bool,
+    ) -> bool {
+        let slot_id = Self::slot_index(region_id);
+        let valid = match self.cached_region_info.get(slot_id).unwrap().read() {
+            Ok(g) => match g.get(&region_id) {
+                None => false,
+                Some(info) => match info.retained_tombstone.lock().unwrap().as_ref() {
+                    None => false,
+                    Some(t) => {
+                        t.destroyed_at.elapsed() < KEEP_REMOVED_REGION_DATA_TTL
+                            && t.epoch.get_version() == want_epoch.get_version()
+                            && t.applied_index >= min_applied_index
+                    }
+                },
+            },
+            Err(_) => false,
+        };
+        if valid && consume {
+            self.remove_cached_region_info(region_id, false);
+        }
+        valid
+    }
+
+    // Reaps retained tombstone-with-data regions (see
+    // `retain_as_tombstone_source`) once `KEEP_REMOVED_REGION_DATA_TTL`
+    // elapses without a migration consuming them. Polled by the same
+    // background thread as `run_fast_add_peer_reaper_tick`.
+    fn run_retained_tombstone_gc_tick(&self) {
+        let mut expired = vec![];
+        for slot in self.cached_region_info.iter() {
+            let guard = match slot.read() {
+                Ok(g) => g,
+                Err(_) => continue,
+            };
+            for (region_id, info) in guard.iter() {
+                let is_expired = match info.retained_tombstone.lock().unwrap().as_ref() {
+                    Some(t) => t.destroyed_at.elapsed() >= KEEP_REMOVED_REGION_DATA_TTL,
+                    None => false,
+                };
+                if is_expired {
+                    expired.push(*region_id);
+                }
+            }
+        }
+        for region_id in expired {
+            info!(
+                "fast path: reaping retained tombstone-with-data region past its TTL";
+                "region_id" => region_id, "store_id" => self.store_id,
+            );
+            self.remove_cached_region_info(region_id, false);
+        }
+    }
+
+    fn push_pending_delete_ssts(
+        &self,
+        ssts: Vec<SstMetaInfo>,
+        applied_index: u64,
+        region_epoch: kvproto::metapb::RegionEpoch,
+    ) {
+        let mut map = self.pending_delete_ssts.write().expect("lock error");
+        for sst in ssts {
+            let cf = cf_key_for_sst(&sst);
+            map.entry(cf).or_default().push(PendingDeleteSst {
+                sst,
+                applied_index,
+                region_epoch: region_epoch.clone(),
+            });
+        }
+    }
+
+    // Deletes and returns the SSTs for `region_id` whose ingest has both
+    // been flushed past (`applied_index <= flushed_index`) and belongs to
+    // an epoch older than `region_epoch`. SSTs from a newer, not-yet-flushed
+    // ingest, or still matching the current (in-progress) epoch, are left
+    // in place rather than deleted out from under a lagging peer.
+    fn cleanup_stale_ssts(
+        &self,
+        region_id: u64,
+        flushed_index: u64,
+        region_epoch: &kvproto::metapb::RegionEpoch,
+    ) -> Vec<SstMetaInfo> {
+        let mut drained = vec![];
+        let mut map = self.pending_delete_ssts.write().expect("lock error");
+        for entries in map.values_mut() {
+            let mut i = 0;
+            while i < entries.len() {
+                let e = &entries[i];
+                if e.sst.meta.get_region_id() == region_id
+                    && e.applied_index <= flushed_index
+                    && raftstore::store::util::is_epoch_stale(&e.region_epoch, region_epoch)
+                {
+                    drained.push(entries.remove(i).sst);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        drained
+    }
+
+    // Unconditionally drops every pending-delete SST for a destroyed region,
+    // regardless of flush progress or epoch: once the region is gone there
+    // is no lagging peer left that could still need these files.
+    fn drop_pending_delete_ssts_for_region(&self, region_id: u64) -> Vec<SstMetaInfo> {
+        let mut drained = vec![];
+        let mut map = self.pending_delete_ssts.write().expect("lock error");
+        for entries in map.values_mut() {
+            drained.extend(
+                entries
+                    .drain_filter(|e| e.sst.meta.get_region_id() == region_id)
+                    .map(|e| e.sst),
+            );
+        }
+        drained
+    }
+
+    // Hands `ssts` off to the cleanup-SST worker instead of unlinking them on
+    // the calling thread. Best-effort: when the queue is full the SST is
+    // dropped from it (only logged, left on disk) rather than blocking the
+    // caller, which matters
+    // here since `on_region_changed` runs on the region-change callback's own
+    // thread.
+    fn queue_sst_cleanup(&self, ssts: Vec<SstMetaInfo>) {
+        for sst in ssts {
+            if let Err(mpsc::TrySendError::Full(sst)) = self.cleanup_sst_tx.try_send(sst) {
+                warn!("cleanup-sst: queue full, SST left on disk";
+                    "sst" => ?sst.meta,
+                );
+            }
+        }
+    }
+
+    fn spawn_cleanup_sst_worker(&self, receiver: mpsc::Receiver<SstMetaInfo>) {
+        let observer = self.clone();
+        std::thread::Builder::new()
+            .name("tiflash-cleanup-sst".to_string())
+            .spawn(move || {
+                observer.gc_orphaned_ssts_on_startup();
+                observer.run_cleanup_sst_worker(&receiver);
+            })
+            .unwrap();
+    }
+
+    fn run_cleanup_sst_worker(&self, receiver: &mpsc::Receiver<SstMetaInfo>) {
+        loop {
+            let first = match receiver.recv() {
+                Ok(sst) => sst,
+                // Sender dropped: the observer (and its queue) is gone.
+                Err(_) => return,
+            };
+            let mut batch = vec![first];
+            while batch.len() < CLEANUP_SST_BATCH_SIZE {
+                match receiver.try_recv() {
+                    Ok(sst) => batch.push(sst),
+                    Err(_) => break,
+                }
+            }
+            for sst in batch {
+                if let Err(e) = self.sst_importer.delete(&sst.meta) {
+                    warn!("cleanup-sst: failed to delete sst"; "err" => ?e, "sst" => ?sst.meta);
+                }
+            }
+        }
+    }
+
+    // Reconciles the importer's on-disk SSTs against region liveness once at
+    // startup: a file whose region is not initialized in our own engine
+    // (never initialized, or already tombstoned) is treated as orphaned by a
+    // crash between ingest and the apply
+    // index that would have driven its normal (epoch/flush-gated) cleanup,
+    // since `pending_delete_ssts` itself doesn't survive a restart.
+    fn gc_orphaned_ssts_on_startup(&self) {
+        let ssts = match self.sst_importer.list_ssts() {
+            Ok(ssts) => ssts,
+            Err(e) => {
+                warn!("cleanup-sst: failed to list importer ssts on startup"; "err" => ?e);
+                return;
+            }
+        };
+        let mut orphaned = 0;
+        for sst in ssts {
+            if self.is_initialized(sst.get_region_id()) {
+                continue;
+            }
+            orphaned += 1;
+            if let Err(e) = self.sst_importer.delete(&sst) {
+                warn!("cleanup-sst: failed to delete orphaned sst on startup"; "err" => ?e, "sst" => ?sst);
+            }
+        }
+        if orphaned > 0 {
+            info!("cleanup-sst: GC'd orphaned ssts on startup"; "count" => orphaned);
+        }
+    }
+
+    // Marks every in-flight prehandle task for `region_id` as aborted, e.g.
+    // because a newer conf-change, region destroy, or epoch bump has
+    // superseded it. `post_apply_snapshot` checks this flag before consuming
+    // the prehandled pointer, so the stale result is freed through the FFI
+    // instead of being installed.
+    pub fn abort_prehandle(&self, region_id: u64) {
+        let lock = match self.pre_handle_snapshot_ctx.lock() {
+            Ok(l) => l,
+            Err(_) => fatal!("abort_prehandle poisoned"),
+        };
+        for (snap_key, task) in lock.tracer.iter() {
+            if snap_key.region_id == region_id {
+                info!("abort prehandle snapshot"; "region_id" => region_id, "snap_key" => ?snap_key);
+                task.aborted.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    // Narrower than `abort_prehandle`: cancels exactly one still-in-flight
+    // prehandle task instead of every task tracked for `region_id`. Unlike
+    // `abort_prehandle`, this also removes the tracer entry immediately, so
+    // callers must only use it when they're certain `post_apply_snapshot`
+    // will never be invoked for `snap_key` again (e.g. it's being
+    // superseded by a newer snapshot of the same region, not merely racing
+    // a normal apply that still intends to consume it).
+    pub fn cancel_applying_snap(&self, region_id: u64, snap_key: &store::SnapKey) {
+        let mut lock = match self.pre_handle_snapshot_ctx.lock() {
+            Ok(l) => l,
+            Err(_) => fatal!("cancel_applying_snap poisoned"),
+        };
+        let ctx = lock.deref_mut();
+        if let Some(task) = ctx.tracer.remove(snap_key) {
+            info!("cancel applying snapshot"; "region_id" => region_id, "snap_key" => ?snap_key);
+            task.aborted.store(true, Ordering::SeqCst);
+            // No gauge is adjusted here: the background closure owns
+            // decrementing `PREHANDLE_QUEUED_GAUGE` (also when it notices
+            // `aborted` before running), `PREHANDLE_RUNNING_GAUGE` and
+            // releasing its tranquility permit.
+            ctx.job_states.remove(snap_key);
+        }
+    }
+
+    // Introspection for the managed prehandle pool: where one `snap_key`'s
+    // job currently sits in its lifecycle, or `None` if it was never
+    // tracked (or already consumed by `post_apply_snapshot`).
+    pub fn apply_snap_job_state(&self, snap_key: &store::SnapKey) -> Option<ApplySnapJobState> {
+        let lock = match self.pre_handle_snapshot_ctx.lock() {
+            Ok(l) => l,
+            Err(_) => fatal!("apply_snap_job_state poisoned"),
+        };
+        lock.job_states.get(snap_key).copied()
+    }
+
+    pub fn set_inited_or_fallback(&self, region_id: u64, v: bool) -> RaftStoreResult<()> {
+        self.access_cached_region_info_mut(
+            region_id,
+            |info: MapEntry<u64, Arc<CachedRegionInfo>>| match info {
+                MapEntry::Occupied(mut o) => {
+                    o.get_mut().inited_or_fallback.store(v, Ordering::SeqCst);
+                }
+                MapEntry::Vacant(_) => {
+                    tikv_util::safe_panic!("not inited!");
+                }
+            },
+        )
+    }
+
+    // Unlike `set_inited_or_fallback`, this lazily creates the cached entry:
+    // flashback admin commands apply to every region in range, including
+    // ones that never otherwise participate in fast-add-peer tracking.
+    fn set_in_flashback(&self, region_id: u64, v: bool) -> RaftStoreResult<()> {
+        self.access_cached_region_info_mut(
+            region_id,
+            |info: MapEntry<u64, Arc<CachedRegionInfo>>| match info {
+                MapEntry::Occupied(o) => {
+                    o.get().in_flashback.store(v, Ordering::SeqCst);
+                }
+                MapEntry::Vacant(e) => {
+                    let info = CachedRegionInfo::default();
+                    info.in_flashback.store(v, Ordering::SeqCst);
+                    e.insert(Arc::new(info));
+                }
+            },
+        )
+    }
+
+    fn is_in_flashback(&self, region_id: u64) -> bool {
+        let mut in_flashback = false;
+        let _ = self.access_cached_region_info_mut(
+            region_id,
+            |info: MapEntry<u64, Arc<CachedRegionInfo>>| {
+                if let MapEntry::Occupied(o) = info {
+                    in_flashback = o.get().in_flashback.load(Ordering::SeqCst);
+                }
+            },
+        );
+        in_flashback
+    }
+
+    // Lazily creates the cached entry like `set_in_flashback`: a region can
+    // be the target of a `CommitMerge` before anything in this store has
+    // ever tried fast-add-peer on it.
+    fn set_pending_merge(&self, region_id: u64, v: bool) -> RaftStoreResult<()> {
+        self.access_cached_region_info_mut(
+            region_id,
+            |info: MapEntry<u64, Arc<CachedRegionInfo>>| match info {
+                MapEntry::Occupied(o) => {
+                    o.get().pending_merge.store(v, Ordering::SeqCst);
+                }
+                MapEntry::Vacant(e) => {
+                    let info = CachedRegionInfo::default();
+                    info.pending_merge.store(v, Ordering::SeqCst);
+                    e.insert(Arc::new(info));
+                }
+            },
+        )
+    }
+
+    fn is_pending_merge(&self, region_id: u64) -> bool {
+        let mut pending_merge = false;
+        let _ = self.access_cached_region_info_mut(
+            region_id,
+            |info: MapEntry<u64, Arc<CachedRegionInfo>>| {
+                if let MapEntry::Occupied(o) = info {
+                    pending_merge = o.get().pending_merge.load(Ordering::SeqCst);
+                }
+            },
+        );
+        pending_merge
+    }
+
+    // Lazily creates the cached entry like `set_in_flashback`: a region's
+    // first recorded conf change may be the first time this store has
+    // cached anything about it.
+    fn record_membership_change(&self, region_id: u64, index: u64, peers: Vec<u64>) -> RaftStoreResult<()> {
+        self.access_cached_region_info_mut(
+            region_id,
+            |info: MapEntry<u64, Arc<CachedRegionInfo>>| match info {
+                MapEntry::Occupied(o) => {
+                    o.get().membership.lock().unwrap().record(index, peers.clone());
+                }
+                MapEntry::Vacant(e) => {
+                    let info = CachedRegionInfo::default();
+                    info.membership.lock().unwrap().record(index, peers.clone());
+                    e.insert(Arc::new(info));
+                }
+            },
+        )
+    }
 
-pub fn get_region_local_state<EK: engine_traits::KvEngine>(
-    engine: &EK,
-    region_id: u64,
-) -> Option<RegionLocalState> {
-    let region_state_key = keys::region_state_key(region_id);
-    engine
-        .get_msg_cf::<RegionLocalState>(CF_RAFT, &region_state_key)
-        .unwrap_or(None)
-}
+    /// The peer set this store has recorded for `region_id` as of
+    /// `applied_index`, or `None` if no `ChangePeer`/`ChangePeerV2` has
+    /// been observed for it yet.
+    pub fn peers_at(&self, region_id: u64, applied_index: u64) -> Option<Vec<u64>> {
+        let mut result = None;
+        let _ = self.access_cached_region_info_mut(
+            region_id,
+            |info: MapEntry<u64, Arc<CachedRegionInfo>>| {
+                if let MapEntry::Occupied(o) = info {
+                    result = o
+                        .get()
+                        .membership
+                        .lock()
+                        .unwrap()
+                        .peers_at(applied_index)
+                        .map(|p| p.to_vec());
+                }
+            },
+        );
+        result
+    }
 
-impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
-    #[inline]
-    fn slot_index(id: u64) -> usize {
-        debug_assert!(CACHED_REGION_INFO_SLOT_COUNT.is_power_of_two());
-        hash_u64(id) as usize & (CACHED_REGION_INFO_SLOT_COUNT - 1)
+    // Adds `cmd_count`/`bytes` to `region_id`'s since-last-persist tally
+    // and returns the updated totals, lazily creating the cached entry
+    // like `record_membership_change` -- a region's first applied write
+    // may be the first time this store has cached anything about it.
+    fn bump_persist_counters(&self, region_id: u64, cmd_count: u64, bytes: u64) -> (u64, u64) {
+        let mut result = (cmd_count, bytes);
+        let _ = self.access_cached_region_info_mut(
+            region_id,
+            |info: MapEntry<u64, Arc<CachedRegionInfo>>| match info {
+                MapEntry::Occupied(o) => {
+                    result = o.get().persist_counters.bump(cmd_count, bytes);
+                }
+                MapEntry::Vacant(e) => {
+                    let info = CachedRegionInfo::default();
+                    result = info.persist_counters.bump(cmd_count, bytes);
+                    e.insert(Arc::new(info));
+                }
+            },
+        );
+        result
     }
 
-    pub fn access_cached_region_info_mut<F: FnMut(MapEntry<u64, Arc<CachedRegionInfo>>)>(
-        &self,
-        region_id: u64,
-        mut f: F,
-    ) -> RaftStoreResult<()> {
-        let slot_id = Self::slot_index(region_id);
-        let mut guard = match self.cached_region_info.get(slot_id).unwrap().write() {
-            Ok(g) => g,
-            Err(_) => return Err(box_err!("access_cached_region_info_mut poisoned")),
-        };
-        f(guard.entry(region_id));
-        Ok(())
+    fn persist_window_elapsed(&self, region_id: u64) -> Duration {
+        let mut elapsed = Duration::default();
+        let _ = self.access_cached_region_info_mut(
+            region_id,
+            |info: MapEntry<u64, Arc<CachedRegionInfo>>| {
+                if let MapEntry::Occupied(o) = info {
+                    elapsed = o.get().persist_counters.window_elapsed();
+                }
+            },
+        );
+        elapsed
     }
 
-    pub fn remove_cached_region_info(&self, region_id: u64) {
-        let slot_id = Self::slot_index(region_id);
-        if let Ok(mut g) = self.cached_region_info.get(slot_id).unwrap().write() {
-            info!(
-                "remove_cached_region_info";
-                "region_id" => region_id,
-                "store_id" => self.store_id,
-            );
-            let _ = g.remove(&region_id);
+    fn reset_persist_counters(&self, region_id: u64) {
+        let _ = self.access_cached_region_info_mut(
+            region_id,
+            |info: MapEntry<u64, Arc<CachedRegionInfo>>| {
+                if let MapEntry::Occupied(o) = info {
+                    o.get().persist_counters.reset();
+                }
+            },
+        );
+    }
+
+    /// Whether `post_exec_query` should force a persist of its own for
+    /// `region_id`, on top of whatever `EngineStoreApplyRes` already
+    /// decided, given that `cmd_count` commands and `bytes` bytes just
+    /// applied. Never used to suppress a persist the engine already
+    /// wanted -- callers OR this into their existing decision.
+    fn should_force_persist(&self, region_id: u64, cmd_count: u64, bytes: u64) -> bool {
+        match self.persist_policy {
+            PersistPolicy::OnCompactLog => false,
+            PersistPolicy::EveryNApplies(n) => {
+                let (applied, _) = self.bump_persist_counters(region_id, cmd_count, bytes);
+                applied >= n
+            }
+            PersistPolicy::ByWriteBytes(threshold) => {
+                let (_, total_bytes) = self.bump_persist_counters(region_id, cmd_count, bytes);
+                total_bytes >= threshold
+            }
+            PersistPolicy::Adaptive {
+                byte_budget,
+                time_budget,
+            } => {
+                let (_, total_bytes) = self.bump_persist_counters(region_id, cmd_count, bytes);
+                total_bytes >= byte_budget || self.persist_window_elapsed(region_id) >= time_budget
+            }
         }
     }
 
-    pub fn set_inited_or_fallback(&self, region_id: u64, v: bool) -> RaftStoreResult<()> {
+    pub fn start_fast_add_peer_inflight(&self, region_id: u64) -> RaftStoreResult<()> {
         self.access_cached_region_info_mut(
             region_id,
             |info: MapEntry<u64, Arc<CachedRegionInfo>>| match info {
-                MapEntry::Occupied(mut o) => {
-                    o.get_mut().inited_or_fallback.store(v, Ordering::SeqCst);
+                MapEntry::Occupied(o) => {
+                    o.get().fast_add_peer_progress.start();
                 }
                 MapEntry::Vacant(_) => {
                     tikv_util::safe_panic!("not inited!");
@@ -328,12 +1633,12 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
         )
     }
 
-    pub fn set_snapshot_inflight(&self, region_id: u64, v: u128) -> RaftStoreResult<()> {
+    fn clear_fast_add_peer_inflight(&self, region_id: u64) -> RaftStoreResult<()> {
         self.access_cached_region_info_mut(
             region_id,
             |info: MapEntry<u64, Arc<CachedRegionInfo>>| match info {
-                MapEntry::Occupied(mut o) => {
-                    o.get_mut().snapshot_inflight.store(v, Ordering::SeqCst);
+                MapEntry::Occupied(o) => {
+                    o.get().fast_add_peer_progress.clear();
                 }
                 MapEntry::Vacant(_) => {
                     tikv_util::safe_panic!("not inited!");
@@ -349,6 +1654,432 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
         if self.set_inited_or_fallback(region_id, true).is_err() {
             tikv_util::safe_panic!("set_inited_or_fallback");
         }
+        if self.clear_fast_add_peer_inflight(region_id).is_err() {
+            tikv_util::safe_panic!("clear_fast_add_peer_inflight");
+        }
+        self.stop_leader_keeper(region_id);
+        self.fail_pending_reads(region_id);
+    }
+
+    // Registers `region_id` with the leader keeper so it starts nudging
+    // `source_peer` to stay leader while the snapshot-build worker fetches
+    // data from it. Called once the attempt actually starts waiting on that
+    // peer, not merely when it's queued.
+    fn start_leader_keeper(&self, region_id: u64, source_peer: kvproto::metapb::Peer) {
+        let mut lock = match self.pre_handle_snapshot_ctx.lock() {
+            Ok(l) => l,
+            Err(_) => fatal!("start_leader_keeper poisoned"),
+        };
+        lock.deref_mut().leader_keepers.insert(
+            region_id,
+            LeaderKeeperState {
+                source_peer,
+                started: Instant::now(),
+            },
+        );
+    }
+
+    fn stop_leader_keeper(&self, region_id: u64) {
+        let mut lock = match self.pre_handle_snapshot_ctx.lock() {
+            Ok(l) => l,
+            Err(_) => fatal!("stop_leader_keeper poisoned"),
+        };
+        lock.deref_mut().leader_keepers.remove(&region_id);
+    }
+
+    // Spawned once from `new`; periodically nudges every in-flight
+    // fast-add-peer's source peer to stay (or become) leader, so a
+    // leadership transfer mid-transfer doesn't stall the fetch until the
+    // normal snapshot fallback kicks in.
+    fn spawn_leader_keeper(&self) {
+        let observer = self.clone();
+        std::thread::Builder::new()
+            .name("fast-add-peer-ldr-kpr".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(LEADER_KEEPER_INTERVAL);
+                observer.run_leader_keeper_tick();
+            })
+            .unwrap();
+    }
+
+    fn run_leader_keeper_tick(&self) {
+        let (expired, to_nudge) = {
+            let mut lock = match self.pre_handle_snapshot_ctx.lock() {
+                Ok(l) => l,
+                Err(_) => fatal!("run_leader_keeper_tick poisoned"),
+            };
+            let keepers = &mut lock.deref_mut().leader_keepers;
+            let now = Instant::now();
+            let (expired, keep): (Vec<_>, Vec<_>) = keepers
+                .iter()
+                .map(|(region_id, state)| (*region_id, state.clone()))
+                .partition(|(_, state)| {
+                    now.saturating_duration_since(state.started) >= LEADER_KEEPER_MAX_DURATION
+                });
+            for (region_id, _) in &expired {
+                keepers.remove(region_id);
+            }
+            (expired, keep)
+        };
+        for (region_id, _) in expired {
+            warn!("fast path: leader keeper giving up, max duration elapsed";
+                "region_id" => region_id, "store_id" => self.store_id,
+            );
+        }
+        for (region_id, state) in to_nudge {
+            self.nudge_leader(region_id, &state.source_peer);
+        }
+    }
+
+    // Sends a `MsgTransferLeader` hint directly to `source_peer` through the
+    // same transport `build_and_send_snapshot` uses, asking it to (re)claim
+    // leadership of `region_id` rather than let it drift to another peer
+    // while this store is still fetching data from it.
+    fn nudge_leader(&self, region_id: u64, source_peer: &kvproto::metapb::Peer) {
+        let mut hint = RaftMessage::default();
+        hint.set_region_id(region_id);
+        hint.set_to_peer(source_peer.clone());
+        hint.set_from_peer(source_peer.clone());
+        let message = hint.mut_message();
+        message.set_msg_type(MessageType::MsgTransferLeader);
+        message.set_from(source_peer.get_id());
+        message.set_to(source_peer.get_id());
+        let store_id = source_peer.get_store_id();
+        match self.trans.lock() {
+            Ok(mut trans) => {
+                if let Err(e) = trans.send(hint) {
+                    debug!("fast path: leader keeper hint failed";
+                        "region_id" => region_id, "err" => ?e,
+                    );
+                    if self.reachability.should_report(store_id) {
+                        warn!("fast path: store appears unreachable";
+                            "store_id" => store_id, "region_id" => region_id, "err" => ?e,
+                        );
+                    }
+                } else {
+                    // A hint actually reaching `store_id`'s transport layer is the
+                    // clearest signal of store liveness this observer sees
+                    // directly, in lieu of hooking raftstore's own inbound
+                    // message-receive loop (not part of this extracted snapshot).
+                    self.reachability.record_received(store_id);
+                }
+            }
+            Err(e) => {
+                warn!("fast path: leader keeper trans lock poisoned";
+                    "region_id" => region_id, "err" => ?e,
+                );
+            }
+        }
+    }
+
+    // Records that `region_id` was just applied through to `(index, term)`,
+    // so the proactive flush ticker can tell how far its cache has drifted
+    // from what's actually been applied. Called from `post_exec_admin` and
+    // `post_exec_query` on every observed apply.
+    fn note_region_applied(&self, region_id: u64, index: u64, term: u64) {
+        if let Some(p) = self.flush_progress.read().unwrap().get(&region_id) {
+            p.last_index.store(index, Ordering::Relaxed);
+            p.last_term.store(term, Ordering::Relaxed);
+            self.resolve_pending_reads(region_id, index);
+            return;
+        }
+        self.flush_progress
+            .write()
+            .unwrap()
+            .entry(region_id)
+            .or_insert_with(|| Arc::new(FlushProgress::new(index, term)))
+            .last_index
+            .store(index, Ordering::Relaxed);
+        self.resolve_pending_reads(region_id, index);
+    }
+
+    // Caller-facing bound on how long a receiver returned by
+    // `wait_for_read_index` should be waited on before treating the read as
+    // a (retryable) timeout, since a queued read is never forcibly expired
+    // from `pending_reads` by a timer of its own.
+    pub fn read_index_wait_max(&self) -> Duration {
+        READ_INDEX_WAIT_MAX
+    }
+
+    // Parks a read for `region_id` that needs the region's applied index to
+    // reach `read_index` before it can be answered safely, for callers
+    // reading off a FAP-installed learner that may still be waiting on its
+    // data (see `CachedRegionInfo::pending_reads`). Returns a receiver that
+    // fires `true` once `note_region_applied` shows the region has caught
+    // up, or `false` if the region instead falls back to the slow path,
+    // reaches `PeerState::Applying`/`Tombstone`, or has its epoch bumped
+    // first (see `fail_pending_reads`'s call sites), or if the region's
+    // queue was already at `READ_INDEX_WAIT_QUEUE_DEPTH`, since the caller
+    // then has to retry rather than trust a stale index.
+    pub fn wait_for_read_index(&self, region_id: u64, read_index: u64) -> mpsc::Receiver<bool> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let applied = self
+            .flush_progress
+            .read()
+            .unwrap()
+            .get(&region_id)
+            .map(|p| p.last_index.load(Ordering::Relaxed))
+            .unwrap_or(0);
+        if applied >= read_index {
+            let _ = tx.try_send(true);
+            return rx;
+        }
+        let _ = self.access_cached_region_info_mut(region_id, |e| {
+            let info = match e {
+                MapEntry::Occupied(o) => o.get().clone(),
+                MapEntry::Vacant(v) => v.insert(Arc::new(CachedRegionInfo::default())).clone(),
+            };
+            let mut reads = info.pending_reads.lock().unwrap();
+            if reads.len() >= READ_INDEX_WAIT_QUEUE_DEPTH {
+                let _ = tx.try_send(false);
+                return;
+            }
+            reads.push(Reverse(PendingRead {
+                read_index,
+                notify: tx.clone(),
+            }));
+        });
+        rx
+    }
+
+    // Pops and completes every read parked on `region_id` whose
+    // `read_index` is now `<= applied_index`.
+    fn resolve_pending_reads(&self, region_id: u64, applied_index: u64) {
+        let _ = self.access_cached_region_info_mut(region_id, |e| {
+            if let MapEntry::Occupied(o) = e {
+                let mut reads = o.get().pending_reads.lock().unwrap();
+                while matches!(reads.peek(), Some(Reverse(r)) if r.read_index <= applied_index) {
+                    if let Some(Reverse(r)) = reads.pop() {
+                        let _ = r.notify.try_send(true);
+                    }
+                }
+            }
+        });
+    }
+
+    // Wakes every read still parked on `region_id` once this fast-add-peer
+    // attempt has given up, so none of them wait past the point where the
+    // fast path itself stopped making progress.
+    fn fail_pending_reads(&self, region_id: u64) {
+        let _ = self.access_cached_region_info_mut(region_id, |e| {
+            if let MapEntry::Occupied(o) = e {
+                let mut reads = o.get().pending_reads.lock().unwrap();
+                while let Some(Reverse(r)) = reads.pop() {
+                    let _ = r.notify.try_send(false);
+                }
+            }
+        });
+    }
+
+    fn spawn_proactive_flush(&self) {
+        let observer = self.clone();
+        std::thread::Builder::new()
+            .name("tiflash-proactive-flush".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(PROACTIVE_FLUSH_INTERVAL);
+                observer.run_proactive_flush_tick();
+            })
+            .unwrap();
+    }
+
+    // Scans `flush_progress` for regions whose un-persisted log size or age
+    // exceeds the configured thresholds and flushes them as one batch. Every
+    // region's data cache is persisted (phase one) before any region's
+    // bookkeeping is advanced (phase two), so a crash mid-batch never leaves
+    // us believing a region is flushed further than its cache actually is.
+    fn run_proactive_flush_tick(&self) {
+        let due: Vec<(u64, u64, u64)> = {
+            let map = self.flush_progress.read().unwrap();
+            map.iter()
+                .filter_map(|(region_id, p)| {
+                    let last_index = p.last_index.load(Ordering::Relaxed);
+                    let flushed_index = p.flushed_index.load(Ordering::Relaxed);
+                    let age = p.last_flush.lock().unwrap().elapsed();
+                    let unflushed = last_index.saturating_sub(flushed_index);
+                    if unflushed >= PROACTIVE_FLUSH_MAX_UNFLUSHED_LOG
+                        || age >= PROACTIVE_FLUSH_MAX_AGE
+                    {
+                        Some((*region_id, last_index, p.last_term.load(Ordering::Relaxed)))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        };
+        if due.is_empty() {
+            return;
+        }
+
+        // Phase one: persist every due region's data cache.
+        let mut flushed = Vec::with_capacity(due.len());
+        for (region_id, index, term) in &due {
+            if self.engine_store_server_helper.try_flush_data(
+                *region_id,
+                false,
+                false,
+                *index,
+                *term,
+            ) {
+                flushed.push((*region_id, *index));
+            } else {
+                debug!("proactive flush: engine-store declined"; "region_id" => region_id, "index" => index);
+            }
+            fail::fail_point!("tiflash_proactive_flush_between_cache_and_region");
+        }
+
+        // Phase two: only once every cache in the batch is durable do we
+        // advance our own bookkeeping of each region's flushed index.
+        for (region_id, index) in flushed {
+            if let Some(p) = self.flush_progress.read().unwrap().get(&region_id) {
+                p.flushed_index.store(index, Ordering::Relaxed);
+                *p.last_flush.lock().unwrap() = Instant::now();
+            }
+            fail::fail_point!("tiflash_proactive_flush_between_regions");
+        }
+    }
+
+    fn spawn_fast_add_peer_reaper(&self) {
+        let observer = self.clone();
+        std::thread::Builder::new()
+            .name("fast-add-peer-reaper".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(FAST_ADD_PEER_PROGRESS_BACKOFF);
+                observer.run_fast_add_peer_reaper_tick();
+                observer.run_retained_tombstone_gc_tick();
+            })
+            .unwrap();
+    }
+
+    // Proactively falls back any inflight fast-add-peer attempt whose source
+    // store has gone silent for a full timeout, even though no duplicate
+    // MsgAppend ever arrived to trigger `observe_duplicate`'s reactive check.
+    // Also drives the `StaleState` check: an attempt that's still
+    // uninitialized a full `STALE_FAP_CHECK_THRESHOLD` after it started is
+    // flagged `ToValidate`; if it's still uninitialized on the next tick
+    // (the closest thing to "confirm with PD/the leader that this
+    // conf-change is still wanted" that's reachable from this crate,
+    // lacking a PD client on `TiFlashObserver`), it's declared `Stale` and
+    // forced onto the slow path.
+    fn run_fast_add_peer_reaper_tick(&self) {
+        let mut stalled = vec![];
+        let mut stale = vec![];
+        for slot in self.cached_region_info.iter() {
+            let guard = match slot.read() {
+                Ok(g) => g,
+                Err(_) => continue,
+            };
+            for (region_id, info) in guard.iter() {
+                let progress = &info.fast_add_peer_progress;
+                if !progress.is_inflight() {
+                    continue;
+                }
+                if progress.is_stalled() {
+                    stalled.push(*region_id);
+                    continue;
+                }
+                if self.is_initialized(*region_id) || !progress.past_stale_check_threshold() {
+                    continue;
+                }
+                match progress.stale_state() {
+                    StaleState::Valid => progress.set_stale_state(StaleState::ToValidate),
+                    StaleState::ToValidate | StaleState::Stale => {
+                        progress.set_stale_state(StaleState::Stale);
+                        stale.push(*region_id);
+                    }
+                }
+            }
+        }
+        for region_id in stalled {
+            warn!("fast path: reaper giving up on stalled inflight snapshot";
+                "region_id" => region_id, "store_id" => self.store_id,
+            );
+            self.fallback_to_slow_path(region_id);
+        }
+        for region_id in stale {
+            warn!("fast path: reaper giving up on a FAP learner stuck uninitialized past the stale-peer check threshold";
+                "region_id" => region_id, "store_id" => self.store_id,
+            );
+            FAST_ADD_PEER_STALE_FALLBACK_COUNTER
+                .with_label_values(&[&self.store_id.to_string()])
+                .inc();
+            self.fallback_to_slow_path(region_id);
+        }
+    }
+
+    // Records a completed FAP fetch round-trip against `peer_id`'s running
+    // RTT estimate, so future calls to `rank_source_candidates` can prefer
+    // donors this store has actually found fast to reach.
+    pub fn record_source_fetch_rtt(&self, peer_id: u64, rtt: Duration) {
+        let millis = rtt.as_secs_f64() * 1000.0;
+        let map = self.source_peer_stats.read().unwrap();
+        if let Some(stats) = map.get(&peer_id) {
+            let mut ema = stats.rtt_ema_millis.lock().unwrap();
+            *ema = *ema + SOURCE_RTT_EMA_ALPHA * (millis - *ema);
+            return;
+        }
+        drop(map);
+        self.source_peer_stats
+            .write()
+            .unwrap()
+            .entry(peer_id)
+            .or_insert_with(|| SourcePeerStats {
+                rtt_ema_millis: Mutex::new(millis),
+            });
+    }
+
+    fn source_rtt_estimate(&self, peer_id: u64) -> Option<Duration> {
+        self.source_peer_stats
+            .read()
+            .unwrap()
+            .get(&peer_id)
+            .map(|s| Duration::from_secs_f64(*s.rtt_ema_millis.lock().unwrap() / 1000.0))
+    }
+
+    // Ranks FAP donor candidates for `region_id`: excludes anything in
+    // `Applying`/`Tombstone` state outright (its data isn't safely
+    // readable), then orders the rest by highest persisted applied index
+    // first, breaking ties by lowest known RTT (`source_rtt_estimate`,
+    // unknown treated as worst). The caller is expected to try candidates
+    // in the returned order, falling back to the next on failure and to the
+    // slow path once the list is exhausted. Also surfaces the chosen
+    // candidate's applied-index gap against `leader_applied_index` via
+    // `FAST_ADD_PEER_SOURCE_INDEX_GAP` for operators, since this crate has
+    // no visibility into the external `RegionStats` test double that would
+    // otherwise carry it.
+    pub fn rank_source_candidates(
+        &self,
+        region_id: u64,
+        leader_applied_index: u64,
+        mut candidates: Vec<SourceCandidate>,
+    ) -> Vec<SourceCandidate> {
+        candidates.retain(|c| !matches!(c.state, PeerState::Applying | PeerState::Tombstone));
+        for c in candidates.iter_mut() {
+            if c.rtt.is_none() {
+                c.rtt = self.source_rtt_estimate(c.peer_id);
+            }
+        }
+        candidates.sort_by(|a, b| {
+            b.applied_index.cmp(&a.applied_index).then_with(|| {
+                a.rtt
+                    .unwrap_or(Duration::MAX)
+                    .cmp(&b.rtt.unwrap_or(Duration::MAX))
+            })
+        });
+        if let Some(chosen) = candidates.first() {
+            FAST_ADD_PEER_SOURCE_INDEX_GAP
+                .with_label_values(&[&self.store_id.to_string()])
+                .observe(leader_applied_index.saturating_sub(chosen.applied_index) as f64);
+            info!(
+                "fast path: ranked source candidates";
+                "region_id" => region_id,
+                "store_id" => self.store_id,
+                "chosen_peer_id" => chosen.peer_id,
+                "chosen_applied_index" => chosen.applied_index,
+                "applied_index_gap" => leader_applied_index.saturating_sub(chosen.applied_index),
+                "candidate_count" => candidates.len(),
+            );
+        }
+        candidates
     }
 
     pub fn is_initialized(&self, region_id: u64) -> bool {
@@ -367,7 +2098,6 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
             // fast path not enabled
             return false;
         }
-        // TODO Need to recover all region infomation from restart.
         let inner_msg = msg.get_message();
         if inner_msg.get_msg_type() != MessageType::MsgAppend {
             // we only handles the first MsgAppend
@@ -375,10 +2105,18 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
         }
         let region_id = msg.get_region_id();
         let new_peer_id = msg.get_to_peer().get_id();
+        if self.is_in_flashback(region_id) {
+            info!("fast path: ongoing {}:{} {}, region is in flashback, refuse fast path", self.store_id, region_id, new_peer_id;
+                "to_peer_id" => msg.get_to_peer().get_id(),
+                "from_peer_id" => msg.get_from_peer().get_id(),
+            );
+            return false;
+        }
         let mut is_first = false;
         let mut is_replicated = false;
         let mut has_already_inited = None;
         let mut early_skip = false;
+        let mut need_fallback = false;
         let f = |info: MapEntry<u64, Arc<CachedRegionInfo>>| {
             match info {
                 MapEntry::Occupied(mut o) => {
@@ -412,11 +2150,8 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
                             );
                         }
                     }
-                    let last = o.get().snapshot_inflight.load(Ordering::SeqCst);
-                    if last != 0 {
-                        let current = SystemTime::now()
-                            .duration_since(SystemTime::UNIX_EPOCH)
-                            .unwrap();
+                    if let Some(should_fallback) = o.get().fast_add_peer_progress.observe_duplicate()
+                    {
                         info!("fast path: ongoing {}:{} {}, MsgAppend duplicated",
                             self.store_id, region_id, new_peer_id;
                                 "to_peer_id" => msg.get_to_peer().get_id(),
@@ -425,9 +2160,14 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
                                 "is_replicated" => is_replicated,
                                 "has_already_inited" => has_already_inited,
                                 "is_first" => is_first,
-                                "elapsed" => current.as_millis() - last,
+                                "dup_count" => o.get().fast_add_peer_progress.dup_msg_append_count.load(Ordering::SeqCst),
+                                "should_fallback" => should_fallback,
                         );
-                        early_skip = true;
+                        if should_fallback {
+                            need_fallback = true;
+                        } else {
+                            early_skip = true;
+                        }
                     }
                 }
                 MapEntry::Vacant(v) => {
@@ -444,6 +2184,16 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
         // Can use immutable version.
         self.access_cached_region_info_mut(region_id, f).unwrap();
 
+        if need_fallback {
+            warn!("fast path: giving up on {}:{} {}, source store stopped making progress",
+                self.store_id, region_id, new_peer_id;
+                "to_peer_id" => msg.get_to_peer().get_id(),
+                "from_peer_id" => msg.get_from_peer().get_id(),
+            );
+            self.fallback_to_slow_path(region_id);
+            return false;
+        }
+
         if !is_first {
             #[cfg(any(test, feature = "testexport"))]
             {
@@ -523,48 +2273,30 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
             return false;
         }
 
-        info!("fast path: ongoing {}:{} {}, start build and send", self.store_id, region_id, new_peer_id;
+        info!("fast path: ongoing {}:{} {}, queue build and send", self.store_id, region_id, new_peer_id;
             "to_peer_id" => msg.get_to_peer().get_id(),
             "from_peer_id" => msg.get_from_peer().get_id(),
             "new_region" => ?new_region,
             "apply_state" => ?apply_state,
         );
-        match self.build_and_send_snapshot(region_id, new_peer_id, msg, apply_state, new_region) {
-            Ok(s) => {
-                match s {
-                    crate::FastAddPeerStatus::Ok => {
-                        fail::fail_point!("go_fast_path_succeed", |_| { return false });
-                        info!("fast path: ongoing {}:{} {}, finish build and send", self.store_id, region_id, new_peer_id;
-                            "to_peer_id" => msg.get_to_peer().get_id(),
-                            "from_peer_id" => msg.get_from_peer().get_id(),
-                        );
-                    }
-                    crate::FastAddPeerStatus::WaitForData => {
-                        info!(
-                            "fast path: ongoing {}:{} {}. remote peer preparing data, wait",
-                            new_peer_id, self.store_id, region_id
-                        );
-                        return true;
-                    }
-                    _ => {
-                        error!(
-                            "fast path: ongoing {}:{} {} failed. build and sent snapshot code {:?}",
-                            self.store_id, region_id, new_peer_id, s
-                        );
-                        self.fallback_to_slow_path(region_id);
-                        return false;
-                    }
-                };
-            }
-            Err(e) => {
-                error!(
-                    "fast path: ongoing {}:{} {} failed. build and sent snapshot error {:?}",
-                    self.store_id, region_id, new_peer_id, e
-                );
-                self.fallback_to_slow_path(region_id);
-                return false;
-            }
-        };
+        // Building and sending the snapshot is handed off to the dedicated
+        // worker pool so it never runs on this raft thread; completion and
+        // fallback-on-failure happen there, off this call path.
+        let queued = self.snap_build_queue.try_enqueue(SnapshotBuildTask {
+            region_id,
+            new_peer_id,
+            msg: msg.clone(),
+            apply_state,
+            new_region,
+        });
+        if !queued {
+            warn!("fast path: ongoing {}:{} {}, snapshot build queue saturated, fallback to slow path", self.store_id, region_id, new_peer_id;
+                "to_peer_id" => msg.get_to_peer().get_id(),
+                "from_peer_id" => msg.get_from_peer().get_id(),
+            );
+            self.fallback_to_slow_path(region_id);
+            return false;
+        }
         is_first
     }
 
@@ -597,6 +2329,26 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
         apply_state: RaftApplyState,
         new_region: kvproto::metapb::Region,
     ) -> RaftStoreResult<crate::FastAddPeerStatus> {
+        // A `CommitMerge` landed on this region since `apply_state` was read
+        // off the raft engine (see `maybe_fast_path`): the range it
+        // describes is now narrower than the merged region, so ingesting a
+        // snapshot built from it would clobber the just-merged source data.
+        // Bail to the slow path instead of building off stale state.
+        //
+        // This is the one guarded fallback `pending_merge` exists to force,
+        // so clear it here once it's done its job: leaving it set would
+        // permanently wedge this region onto the slow path for the life of
+        // the store, since nothing else ever clears a successful merge's
+        // marker (only `RollbackMerge`, for an *aborted* one, does).
+        if self.is_pending_merge(region_id) {
+            if self.set_pending_merge(region_id, false).is_err() {
+                tikv_util::safe_panic!("set_pending_merge");
+            }
+            warn!("fast path: commit merge landed mid-build, fallback to slow path";
+                "region_id" => region_id, "new_peer_id" => new_peer_id, "store_id" => self.store_id,
+            );
+            return Ok(crate::FastAddPeerStatus::OtherError);
+        }
         let inner_msg = msg.get_message();
         // Build snapshot by get_snapshot_for_building
         let (snap, key) = {
@@ -625,6 +2377,20 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
         let pb_snapshot_metadata: &mut eraftpb::SnapshotMetadata = pb_snapshot.mut_metadata();
         let mut snap_data = kvproto::raft_serverpb::RaftSnapshotData::default();
         {
+            // Consistent read view of this region as of the applied
+            // index/term we registered `key` under, so a concurrent split
+            // or ingest on the raft thread can't be observed half-way
+            // through a CF's scan below. Taken once, outside the per-CF
+            // loop, and scanned for every CF: if each CF instead scanned
+            // `self.engine` live, a write landing between two CFs' scans
+            // would tear the snapshot across them even though neither
+            // individual scan raced.
+            let snap = self.engine.snapshot();
+            let start_key = keys::data_key(new_region.get_start_key());
+            let end_key = keys::data_end_key(new_region.get_end_key());
+            let mut total_size: u64 = 0;
+            let mut total_kv_count: u64 = 0;
+
             // eraftpb::SnapshotMetadata
             for (_, cf) in raftstore::store::snap::SNAPSHOT_CFS_ENUM_PAIR {
                 let cf_index: RaftStoreResult<usize> = snap
@@ -637,15 +2403,50 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
                 let mut path = cf_file.path.clone();
                 path.push(cf_file.file_prefix.clone());
                 path.set_extension("sst");
-                let mut _file = std::fs::File::create(path.as_path())?;
+
+                let mut writer = <TiFlashEngine as KvEngine>::SstWriterBuilder::new()
+                    .set_db(&self.engine)
+                    .set_cf(cf)
+                    .build(path.to_str().unwrap())?;
+                let mut cf_kv_count: u64 = 0;
+                snap.scan(cf, &start_key, &end_key, false, &mut |k, v| {
+                    writer.put(k, v)?;
+                    cf_kv_count += 1;
+                    Ok(true)
+                })?;
+                if cf_kv_count > 0 {
+                    writer.finish()?;
+                } else {
+                    // Nothing in this CF for the region: drop the writer
+                    // without finishing so we don't leave a zero-entry SST
+                    // that `gen_snapshot_meta` would still have to stat.
+                    drop(writer);
+                    let mut _file = std::fs::File::create(path.as_path())?;
+                }
+                let file_size = std::fs::metadata(path.as_path())?.len();
+                total_size += file_size;
+                total_kv_count += cf_kv_count;
             }
+            debug!(
+                "fast path: built real snapshot data for {} total_size {} total_kv_count {}",
+                region_id, total_size, total_kv_count
+            );
+            // SST files for every CF are on disk at this point, but the
+            // snapshot meta (and its checksums) hasn't been generated yet.
+            // Lets tests pause the build here, between writing the CF files
+            // and generating their meta.
+            note_test_rendezvous("fap_ffi_pause");
+            fail::fail_point!("fap_ffi_pause");
             snap_data.set_region(new_region.clone());
-            snap_data.set_file_size(0);
+            snap_data.set_file_size(total_size);
             const SNAPSHOT_VERSION: u64 = 2;
             snap_data.set_version(SNAPSHOT_VERSION);
 
             // SnapshotMeta
-            // Which is snap.meta_file.meta
+            // Which is snap.meta_file.meta. Regenerated here, after the CF
+            // files above are populated with real data, so the per-file
+            // size/checksum it reads off disk reflect the actual snapshot
+            // rather than the empty stubs this used to ship.
             let snapshot_meta = raftstore::store::snap::gen_snapshot_meta(snap.cf_files(), true)?;
 
             // Write MetaFile
@@ -659,7 +2460,6 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
             snap_data.set_meta(snapshot_meta);
         }
 
-        // TODO The rest is test, please remove it after we can fetch the real data.
         pb_snapshot_metadata
             .set_conf_state(raftstore::store::util::conf_state_from_region(&new_region));
         pb_snapshot_metadata.set_index(key.idx);
@@ -693,11 +2493,8 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
         match self.trans.lock() {
             Ok(mut trans) => match trans.send(response) {
                 Ok(_) => {
-                    let current = SystemTime::now()
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap();
-                    self.set_snapshot_inflight(region_id, current.as_millis())
-                        .unwrap();
+                    self.start_fast_add_peer_inflight(region_id).unwrap();
+                    self.start_leader_keeper(region_id, msg.get_from_peer().clone());
                 }
                 Err(RaftStoreError::RegionNotFound(_)) => (),
                 _ => return Ok(crate::FastAddPeerStatus::OtherError),
@@ -705,7 +2502,146 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
             Err(e) => return Err(box_err!("send snapshot meets error {:?}", e)),
         }
 
-        Ok(crate::FastAddPeerStatus::Ok)
+        Ok(crate::FastAddPeerStatus::Ok)
+    }
+
+    // Spawns the worker pool draining `receiver`; called once from `new`. The
+    // sending half lives in `self.snap_build_queue` and is cloned into every
+    // `TiFlashObserver` clone, so the pipeline is shared across all observer
+    // handles for this store.
+    fn spawn_snapshot_build_workers(&self, receiver: mpsc::Receiver<SnapshotBuildTask>) {
+        let receiver = Arc::new(Mutex::new(receiver));
+        for worker_id in 0..SNAPSHOT_BUILD_WORKER_COUNT {
+            let receiver = receiver.clone();
+            let observer = self.clone();
+            std::thread::Builder::new()
+                .name(format!("fast-add-peer-snap-{}", worker_id))
+                .spawn(move || observer.run_snapshot_build_worker(&receiver))
+                .unwrap();
+        }
+    }
+
+    fn run_snapshot_build_worker(&self, receiver: &Mutex<mpsc::Receiver<SnapshotBuildTask>>) {
+        loop {
+            let first = {
+                let rx = receiver.lock().unwrap();
+                match rx.recv() {
+                    Ok(task) => task,
+                    // Sender dropped: the observer (and its channel) is gone.
+                    Err(_) => return,
+                }
+            };
+            let mut batch = vec![first];
+            {
+                let rx = receiver.lock().unwrap();
+                while batch.len() < SNAPSHOT_BUILD_BATCH_SIZE {
+                    match rx.try_recv() {
+                        Ok(task) => batch.push(task),
+                        Err(_) => break,
+                    }
+                }
+            }
+            for task in batch {
+                SNAPSHOT_BUILD_QUEUE_DEPTH.dec();
+                self.process_snapshot_build_task(task);
+            }
+        }
+    }
+
+    fn process_snapshot_build_task(&self, task: SnapshotBuildTask) {
+        let region_id = task.region_id;
+        let new_peer_id = task.new_peer_id;
+        let start = Instant::now();
+        let res = self.build_and_send_snapshot(
+            region_id,
+            new_peer_id,
+            &task.msg,
+            task.apply_state,
+            task.new_region,
+        );
+        SNAPSHOT_BUILD_DURATION.observe(start.elapsed().as_secs_f64());
+        match res {
+            Ok(crate::FastAddPeerStatus::Ok) => {
+                fail::fail_point!("go_fast_path_succeed", |_| {});
+                info!("fast path: ongoing {}:{} {}, finish build and send", self.store_id, region_id, new_peer_id);
+            }
+            Ok(crate::FastAddPeerStatus::WaitForData) => {
+                info!(
+                    "fast path: ongoing {}:{} {}. remote peer preparing data, wait",
+                    self.store_id, region_id, new_peer_id
+                );
+            }
+            Ok(s) => {
+                error!(
+                    "fast path: ongoing {}:{} {} failed. build and sent snapshot code {:?}",
+                    self.store_id, region_id, new_peer_id, s
+                );
+                self.fallback_to_slow_path(region_id);
+            }
+            Err(e) => {
+                error!(
+                    "fast path: ongoing {}:{} {} failed. build and sent snapshot error {:?}",
+                    self.store_id, region_id, new_peer_id, e
+                );
+                self.fallback_to_slow_path(region_id);
+            }
+        }
+    }
+}
+
+// Bounded so a burst of fast-add-peer targets can't pile up unbounded work
+// behind the raft thread that observed their MsgAppend; once full,
+// `maybe_fast_path` treats it as backpressure and falls back to the slow
+// (normal snapshot) path rather than blocking.
+const SNAPSHOT_BUILD_QUEUE_CAPACITY: usize = 64;
+// Dedicated worker threads for `build_and_send_snapshot`, kept apart from
+// `apply_snap_pool` (which only prehandles already-received data) so a burst
+// of new peers doesn't serialize snapshot construction on the raft thread.
+const SNAPSHOT_BUILD_WORKER_COUNT: usize = 2;
+// Max number of already-queued builds a worker opportunistically drains
+// into one batch per wakeup; the batch's builds then run one after another
+// on that worker.
+const SNAPSHOT_BUILD_BATCH_SIZE: usize = 8;
+
+lazy_static::lazy_static! {
+    static ref SNAPSHOT_BUILD_QUEUE_DEPTH: prometheus::IntGauge = prometheus::register_int_gauge!(
+        "tiflash_fast_add_peer_snapshot_build_queue_depth",
+        "Number of fast-add-peer snapshot builds waiting to start (queued, or drained into a worker's batch)"
+    ).unwrap();
+    static ref SNAPSHOT_BUILD_DURATION: prometheus::Histogram = prometheus::register_histogram!(
+        "tiflash_fast_add_peer_snapshot_build_duration_seconds",
+        "Latency of building and sending one fast-add-peer snapshot, from dequeue to transport send"
+    ).unwrap();
+}
+
+// One region's pending `build_and_send_snapshot` call, queued by
+// `maybe_fast_path` and picked up by a snapshot-build worker thread.
+struct SnapshotBuildTask {
+    region_id: u64,
+    new_peer_id: u64,
+    msg: RaftMessage,
+    apply_state: RaftApplyState,
+    new_region: kvproto::metapb::Region,
+}
+
+// Front end of the async snapshot-build pipeline: a bounded channel that
+// `maybe_fast_path` feeds and a small pool of worker threads drains, so
+// building and sending a fast-add-peer snapshot never runs on the raft
+// thread that observed the triggering MsgAppend.
+#[derive(Clone)]
+struct SnapshotBuildQueue {
+    sender: mpsc::SyncSender<SnapshotBuildTask>,
+}
+
+impl SnapshotBuildQueue {
+    fn try_enqueue(&self, task: SnapshotBuildTask) -> bool {
+        match self.sender.try_send(task) {
+            Ok(()) => {
+                SNAPSHOT_BUILD_QUEUE_DEPTH.inc();
+                true
+            }
+            Err(_) => false,
+        }
     }
 }
 
@@ -731,7 +2667,9 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
         for _ in 0..CACHED_REGION_INFO_SLOT_COUNT {
             cached_region_info.push(RwLock::new(HashMap::default()));
         }
-        TiFlashObserver {
+        let (snap_build_tx, snap_build_rx) = mpsc::sync_channel(SNAPSHOT_BUILD_QUEUE_CAPACITY);
+        let (cleanup_sst_tx, cleanup_sst_rx) = mpsc::sync_channel(CLEANUP_SST_QUEUE_CAPACITY);
+        let observer = TiFlashObserver {
             store_id,
             engine_store_server_helper,
             engine,
@@ -740,12 +2678,39 @@ impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
             pre_handle_snapshot_ctx: Arc::new(Mutex::new(PrehandleContext::default())),
             snap_handle_pool_size,
             apply_snap_pool: Some(Arc::new(snap_pool)),
-            pending_delete_ssts: Arc::new(RwLock::new(vec![])),
+            pending_delete_ssts: Arc::new(RwLock::new(HashMap::default())),
             cached_region_info: Arc::new(cached_region_info),
             trans: Arc::new(Mutex::new(trans)),
             snap_mgr: Arc::new(snap_mgr),
             engine_store_cfg,
-        }
+            snap_build_queue: SnapshotBuildQueue {
+                sender: snap_build_tx,
+            },
+            flush_progress: Arc::new(RwLock::new(HashMap::default())),
+            cleanup_sst_tx,
+            reachability: Arc::new(ReachabilityTracker::new()),
+            source_peer_stats: Arc::new(RwLock::new(HashMap::default())),
+            // Reproduces the historical behavior exactly: only CompactLog's
+            // own force-persist path applies, so this is a no-op until a
+            // caller opts in with `with_persist_policy`.
+            persist_policy: PersistPolicy::default(),
+        };
+        observer.restore_cached_region_info_from_disk();
+        observer.spawn_snapshot_build_workers(snap_build_rx);
+        observer.spawn_leader_keeper();
+        observer.spawn_proactive_flush();
+        observer.spawn_fast_add_peer_reaper();
+        observer.spawn_cleanup_sst_worker(cleanup_sst_rx);
+        observer
+    }
+
+    /// Overrides the default [`PersistPolicy`] (`OnCompactLog`, i.e. no
+    /// additional forcing beyond what engine-store's own
+    /// `EngineStoreApplyRes` already asks for). Meant to be chained right
+    /// after `new`, before `register_to`.
+    pub fn with_persist_policy(mut self, policy: PersistPolicy) -> Self {
+        self.persist_policy = policy;
+        self
     }
 
     pub fn register_to<E: engine_traits::KvEngine>(
@@ -948,6 +2913,123 @@ impl<T: Transport + 'static, ER: RaftEngine> AdminObserver for TiFlashObserver<T
             }
         }
 
+        // Track flashback state so `maybe_fast_path` can refuse the fast
+        // path (and fall back to the normal, serializable snapshot path)
+        // for the duration, since a fast-added peer bypasses raftstore's own
+        // flashback-aware proposal blocking.
+        match cmd_type {
+            AdminCmdType::PrepareFlashback => {
+                if self
+                    .set_in_flashback(ob_ctx.region().get_id(), true)
+                    .is_err()
+                {
+                    tikv_util::safe_panic!("set_in_flashback");
+                }
+            }
+            AdminCmdType::FinishFlashback => {
+                if self
+                    .set_in_flashback(ob_ctx.region().get_id(), false)
+                    .is_err()
+                {
+                    tikv_util::safe_panic!("set_in_flashback");
+                }
+            }
+            _ => (),
+        }
+
+        // Reaching here without a header error means `CommitMerge` is
+        // confirmed applied to this region, so any fast-add-peer attempt
+        // still building/sending a snapshot off an apply state from before
+        // the merge is now stale (see `pending_merge`). `RollbackMerge`
+        // clears it again: the merge never happened, so there's nothing
+        // stale to guard against. The `CommitMerge` case is transient, not
+        // permanent: `build_and_send_snapshot` clears it itself the first
+        // time it observes the flag and falls back, so a single merge
+        // doesn't wedge this region onto the slow path forever.
+        //
+        // `PrepareMerge` is applied on the *source* region, not the target
+        // (`CommitMerge` is target-only, per above), so it gets the same
+        // marker under the source's own region id: that's the only signal
+        // this observer has, at `Destroy` time, that a source region being
+        // torn down is the tail end of a merge that actually went through,
+        // rather than an ordinary peer removal. `RollbackMerge` is also
+        // applied on the source (the merge was aborted, so the region lives
+        // on unmerged) and clears it right back, the same as it does for a
+        // `CommitMerge` marker on the target side.
+        match cmd_type {
+            AdminCmdType::CommitMerge | AdminCmdType::PrepareMerge => {
+                if self
+                    .set_pending_merge(ob_ctx.region().get_id(), true)
+                    .is_err()
+                {
+                    tikv_util::safe_panic!("set_pending_merge");
+                }
+            }
+            AdminCmdType::RollbackMerge => {
+                if self
+                    .set_pending_merge(ob_ctx.region().get_id(), false)
+                    .is_err()
+                {
+                    tikv_util::safe_panic!("set_pending_merge");
+                }
+            }
+            _ => (),
+        }
+
+        // Track this region's peer set from each applied conf change, so
+        // `peers_at` can answer "who were the peers as of applied index N"
+        // without re-deriving it from the raft log. See `MembershipState`.
+        match cmd_type {
+            AdminCmdType::ChangePeer | AdminCmdType::ChangePeerV2 => {
+                fail::fail_point!("on_conf_change", |_| {});
+                if let Some(region) = region_state.modified_region.as_ref() {
+                    let region_id = ob_ctx.region().get_id();
+                    let before = self
+                        .peers_at(region_id, cmd.index.saturating_sub(1))
+                        .unwrap_or_else(|| {
+                            ob_ctx.region().get_peers().iter().map(|p| p.get_id()).collect()
+                        });
+                    let added = region.added_peers(&before);
+                    let removed = region.removed_peers(&before);
+                    if !added.is_empty() || !removed.is_empty() {
+                        info!(
+                            "observe membership change";
+                            "region_id" => region_id,
+                            "peer_id" => region_state.peer_id,
+                            "index" => cmd.index,
+                            "added" => ?added,
+                            "removed" => ?removed,
+                        );
+                    }
+                    if self
+                        .record_membership_change(region_id, cmd.index, region.peer_ids())
+                        .is_err()
+                    {
+                        tikv_util::safe_panic!("record_membership_change");
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        // A read parked in `pending_reads` was enqueued against a specific
+        // region epoch; any command that bumps it (split/merge/conf change)
+        // invalidates that assumption; caller re-resolves the read against
+        // the region's new shape instead of trusting a queued index that no
+        // longer means what it did when it was enqueued.
+        match cmd_type {
+            AdminCmdType::BatchSplit
+            | AdminCmdType::Split
+            | AdminCmdType::PrepareMerge
+            | AdminCmdType::CommitMerge
+            | AdminCmdType::RollbackMerge
+            | AdminCmdType::ChangePeer
+            | AdminCmdType::ChangePeerV2 => {
+                self.fail_pending_reads(ob_ctx.region().get_id());
+            }
+            _ => (),
+        }
+
         // We wrap `modified_region` into `mut_split()`
         let mut new_response = None;
         match cmd_type {
@@ -1014,6 +3096,7 @@ impl<T: Transport + 'static, ER: RaftEngine> AdminObserver for TiFlashObserver<T
         if persist {
             info!("should persist admin"; "region_id" => ob_ctx.region().get_id(), "peer_id" => region_state.peer_id, "state" => ?apply_state);
         }
+        self.note_region_applied(ob_ctx.region().get_id(), cmd.index, cmd.term);
         persist
     }
 }
@@ -1066,6 +3149,10 @@ impl<T: Transport + 'static, ER: RaftEngine> QueryObserver for TiFlashObserver<T
 
         let mut ssts = vec![];
         let mut cmds = WriteCmds::with_capacity(requests.len());
+        // Feeds `PersistPolicy::EveryNApplies`/`ByWriteBytes`/`Adaptive`;
+        // unused (and free) under the default `OnCompactLog` policy.
+        let mut applied_cmds: u64 = 0;
+        let mut applied_bytes: u64 = 0;
         for req in requests {
             let cmd_type = req.get_cmd_type();
             match cmd_type {
@@ -1073,12 +3160,22 @@ impl<T: Transport + 'static, ER: RaftEngine> QueryObserver for TiFlashObserver<T
                     let put = req.get_put();
                     let cf = name_to_cf(put.get_cf());
                     let (key, value) = (put.get_key(), put.get_value());
+                    applied_cmds += 1;
+                    applied_bytes += (key.len() + value.len()) as u64;
+                    self.engine
+                        .persistence_listener
+                        .record_applied(cf_to_name(cf), cmd.index);
                     cmds.push(key, value, WriteCmdType::Put, cf);
                 }
                 CmdType::Delete => {
                     let del = req.get_delete();
                     let cf = name_to_cf(del.get_cf());
                     let key = del.get_key();
+                    applied_cmds += 1;
+                    applied_bytes += key.len() as u64;
+                    self.engine
+                        .persistence_listener
+                        .record_applied(cf_to_name(cf), cmd.index);
                     cmds.push(key, NONE_STR.as_ref(), WriteCmdType::Del, cf);
                 }
                 CmdType::IngestSst => {
@@ -1120,10 +3217,11 @@ impl<T: Transport + 'static, ER: RaftEngine> QueryObserver for TiFlashObserver<T
                     match apply_ctx_info.pending_handle_ssts {
                         None => (), // No ssts to handle, unlikely.
                         Some(v) => {
-                            self.pending_delete_ssts
-                                .write()
-                                .expect("lock error")
-                                .append(v);
+                            self.push_pending_delete_ssts(
+                                v.drain(..).collect(),
+                                cmd.index,
+                                ob_ctx.region().get_region_epoch().clone(),
+                            );
                         }
                     };
                     info!(
@@ -1148,14 +3246,11 @@ impl<T: Transport + 'static, ER: RaftEngine> QueryObserver for TiFlashObserver<T
                     match apply_ctx_info.pending_handle_ssts {
                         None => (),
                         Some(v) => {
-                            let mut sst_in_region: Vec<SstMetaInfo> = self
-                                .pending_delete_ssts
-                                .write()
-                                .expect("lock error")
-                                .drain_filter(|e| {
-                                    e.meta.get_region_id() == ob_ctx.region().get_id()
-                                })
-                                .collect();
+                            let mut sst_in_region = self.cleanup_stale_ssts(
+                                ob_ctx.region().get_id(),
+                                cmd.index,
+                                ob_ctx.region().get_region_epoch(),
+                            );
                             apply_ctx_info.delete_ssts.append(&mut sst_in_region);
                             apply_ctx_info.delete_ssts.append(v);
                         }
@@ -1170,18 +3265,25 @@ impl<T: Transport + 'static, ER: RaftEngine> QueryObserver for TiFlashObserver<T
                     RaftCmdHeader::new(ob_ctx.region().get_id(), cmd.index, cmd.term),
                 )
             };
-            match flash_res {
+            let engine_wants_persist = match flash_res {
                 EngineStoreApplyRes::None => false,
                 EngineStoreApplyRes::Persist => !region_state.pending_remove,
                 EngineStoreApplyRes::NotFound => false,
-            }
+            };
+            // `should_force_persist` never suppresses a persist
+            // engine-store itself asked for, only adds earlier ones, so
+            // it's always safe to OR in here.
+            engine_wants_persist
+                || self.should_force_persist(ob_ctx.region().get_id(), applied_cmds, applied_bytes)
         };
         fail::fail_point!("on_post_exec_normal_end", |e| {
             e.unwrap().parse::<bool>().unwrap()
         });
         if persist {
             info!("should persist query"; "region_id" => ob_ctx.region().get_id(), "peer_id" => region_state.peer_id, "state" => ?apply_state);
+            self.reset_persist_counters(ob_ctx.region().get_id());
         }
+        self.note_region_applied(ob_ctx.region().get_id(), cmd.index, cmd.term);
         persist
     }
 }
@@ -1212,9 +3314,50 @@ impl<T: Transport + 'static, ER: RaftEngine> RegionChangeObserver for TiFlashObs
             );
             self.engine_store_server_helper
                 .handle_destroy(ob_ctx.region().get_id());
+            // The peer is moving to `PeerState::Tombstone`; fail any read
+            // still parked on it explicitly rather than let its sender
+            // silently drop when the entry below is torn down.
+            self.fail_pending_reads(region_id);
             if self.engine_store_cfg.enable_fast_add_peer {
-                self.remove_cached_region_info(region_id);
+                self.abort_prehandle(region_id);
+                if self.is_pending_merge(region_id) {
+                    // `region_id` here is the source being torn down, and
+                    // its own `pending_merge` marker is set by `PrepareMerge`
+                    // (applied on the source) and cleared by `RollbackMerge`
+                    // (also source-side) -- so seeing it still set here means
+                    // this destroy is the tail end of a merge that actually
+                    // committed, not a rolled-back one or an ordinary peer
+                    // removal. Keep the persisted marker (rather than wiping
+                    // the whole entry) so a racing fast-add-peer attempt
+                    // still sees it after the region is gone and knows it
+                    // can't trust its apply state.
+                    self.remove_cached_region_info(region_id, true);
+                } else {
+                    // A plain peer removal (not a merge): retain this
+                    // region's data as a fast-add-peer source for single-
+                    // replica migration instead of discarding it outright.
+                    // See `retain_as_tombstone_source`.
+                    let applied_index = self
+                        .flush_progress
+                        .read()
+                        .unwrap()
+                        .get(&region_id)
+                        .map(|p| p.last_index.load(Ordering::Relaxed))
+                        .unwrap_or(0);
+                    self.retain_as_tombstone_source(
+                        region_id,
+                        ob_ctx.region().get_region_epoch().clone(),
+                        applied_index,
+                    );
+                    self.remove_cached_region_info(region_id, true);
+                }
             }
+            // No lagging peer can still need these once the region itself
+            // is gone; hand them to the cleanup-SST worker rather than
+            // unlinking them on this callback's own thread.
+            let orphaned = self.drop_pending_delete_ssts_for_region(region_id);
+            self.queue_sst_cleanup(orphaned);
+            self.flush_progress.write().unwrap().remove(&region_id);
         }
     }
 
@@ -1340,6 +3483,619 @@ fn retrieve_sst_files(snap: &store::Snapshot) -> Vec<(PathBuf, ColumnFamilyType)
     sst_views
 }
 
+// In a full build this would be a field on `EngineStoreConfig`;
+// `engine_store_ffi`'s own config struct isn't reachable from this file, so
+// it's a local constant here. Off by default. When on, the prehandle job
+// zstd-compresses each CF file to a scratch copy and immediately
+// decompresses it back over the original path, right before the FFI call;
+// engine-store still receives the original uncompressed files, and the
+// files' disk footprint while queued is not reduced.
+const ENABLE_SNAPSHOT_SST_SCRATCH_COMPRESSION: bool = false;
+// CF files at or under this size aren't worth the zstd round trip.
+const SNAPSHOT_SST_COMPRESSION_MIN_SIZE: u64 = 4096;
+
+lazy_static::lazy_static! {
+    static ref SNAPSHOT_SST_COMPRESS_ORIGINAL_BYTES: prometheus::IntCounter = prometheus::register_int_counter!(
+        "tiflash_snapshot_sst_compress_original_bytes",
+        "Total bytes of CF SST scratch files considered for pre-handle compression"
+    ).unwrap();
+    static ref SNAPSHOT_SST_COMPRESS_COMPRESSED_BYTES: prometheus::IntCounter = prometheus::register_int_counter!(
+        "tiflash_snapshot_sst_compress_compressed_bytes",
+        "Total compressed bytes produced for CF SST scratch files during pre-handle compression"
+    ).unwrap();
+}
+
+/// How `compress_sst_scratch` stored a CF scratch file inside the background
+/// prehandle job, after the job left `apply_snap_pool`'s queue. Never crosses the
+/// FFI boundary: `EngineStoreServerHelper::pre_handle_snapshot` isn't part
+/// of this sparse snapshot, so rather than guess at extending its
+/// signature, compression is reversed locally right before that call.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Compression {
+    None,
+    Zstd,
+}
+
+/// Compresses `path` into a sibling `.zst` scratch file when it's large
+/// enough to be worthwhile, recording original/compressed sizes in the
+/// `SNAPSHOT_SST_COMPRESS_*` counters. Returns the compression kind used
+/// (`Compression::None` if `path` was left untouched, e.g. because it's
+/// below `SNAPSHOT_SST_COMPRESSION_MIN_SIZE`), the file the background task
+/// should now read from, and the original size (needed as a capacity hint
+/// for `decompress_sst_scratch`).
+fn compress_sst_scratch(path: &std::path::Path) -> std::io::Result<(Compression, PathBuf, u64)> {
+    let original_size = std::fs::metadata(path)?.len();
+    if original_size <= SNAPSHOT_SST_COMPRESSION_MIN_SIZE {
+        return Ok((Compression::None, path.to_path_buf(), original_size));
+    }
+    let bytes = std::fs::read(path)?;
+    let compressed = zstd::bulk::compress(&bytes, 0)?;
+    SNAPSHOT_SST_COMPRESS_ORIGINAL_BYTES.inc_by(original_size);
+    SNAPSHOT_SST_COMPRESS_COMPRESSED_BYTES.inc_by(compressed.len() as u64);
+    let compressed_path = path.with_extension("zst");
+    std::fs::write(&compressed_path, &compressed)?;
+    Ok((Compression::Zstd, compressed_path, original_size))
+}
+
+/// Reverses `compress_sst_scratch`: decompresses `compressed_path` back
+/// into a fresh scratch file at `target_path` and removes the compressed
+/// intermediate, so the existing FFI call in `pre_handle_snapshot_impl`
+/// keeps reading a plain SST exactly as before this stage existed.
+/// `original_size` is the capacity hint `compress_sst_scratch` returned.
+fn decompress_sst_scratch(
+    compressed_path: &std::path::Path,
+    target_path: &std::path::Path,
+    original_size: u64,
+) -> std::io::Result<()> {
+    let compressed = std::fs::read(compressed_path)?;
+    let decompressed = zstd::bulk::decompress(&compressed, original_size as usize)?;
+    std::fs::write(target_path, decompressed)?;
+    std::fs::remove_file(compressed_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod compression_tests {
+    use super::*;
+
+    #[test]
+    fn compress_then_decompress_round_trips_sst_bytes() {
+        let dir = std::env::temp_dir();
+        let src_path = dir.join(format!("chunk7_2_roundtrip_{}.sst", std::process::id()));
+        let source = vec![0x5au8; SNAPSHOT_SST_COMPRESSION_MIN_SIZE as usize * 4];
+        std::fs::write(&src_path, &source).unwrap();
+
+        let (kind, compressed_path, original_size) = compress_sst_scratch(&src_path).unwrap();
+        assert_eq!(kind, Compression::Zstd);
+        assert_ne!(compressed_path, src_path);
+
+        decompress_sst_scratch(&compressed_path, &src_path, original_size).unwrap();
+        let round_tripped = std::fs::read(&src_path).unwrap();
+        assert_eq!(round_tripped, source);
+
+        std::fs::remove_file(&src_path).unwrap();
+    }
+}
+
+// `should_force_persist` is only reachable through `post_exec_query`,
+// which in turn needs a live `TiFlashObserver` wired to a running mock
+// cluster -- not available to this crate's own unit tests. So this
+// exercises `PersistCounters` directly, the piece `should_force_persist`
+// actually delegates its bookkeeping to, rather than the policy match
+// arms themselves (which are a few lines of arithmetic over what's
+// tested here).
+#[cfg(test)]
+mod persist_policy_tests {
+    use super::*;
+
+    #[test]
+    fn bump_accumulates_across_calls_until_reset() {
+        let counters = PersistCounters::default();
+        assert_eq!(counters.bump(1, 10), (1, 10));
+        assert_eq!(counters.bump(2, 20), (3, 30));
+        counters.reset();
+        assert_eq!(counters.bump(1, 5), (1, 5));
+    }
+
+    #[test]
+    fn window_elapsed_starts_at_first_bump_not_at_construction() {
+        let counters = PersistCounters::default();
+        assert_eq!(counters.window_elapsed(), Duration::default());
+        counters.bump(1, 1);
+        // The window only starts once something has actually applied, so a
+        // region that never writes never looks like it's overdue.
+        assert!(counters.window_elapsed() < Duration::from_secs(1));
+        counters.reset();
+        assert_eq!(counters.window_elapsed(), Duration::default());
+    }
+
+    #[test]
+    fn default_policy_is_on_compact_log() {
+        assert!(matches!(PersistPolicy::default(), PersistPolicy::OnCompactLog));
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref PREHANDLE_QUEUED_GAUGE: prometheus::IntGauge = prometheus::register_int_gauge!(
+        "tiflash_prehandle_snapshot_queued",
+        "Number of prehandle snapshot jobs inserted into the tracer but not yet running"
+    ).unwrap();
+    static ref PREHANDLE_RUNNING_GAUGE: prometheus::IntGauge = prometheus::register_int_gauge!(
+        "tiflash_prehandle_snapshot_running",
+        "Number of prehandle snapshot jobs currently executing pre_handle_snapshot_impl"
+    ).unwrap();
+}
+
+// Following the bucketed-`HistogramVec` pattern `sst_importer` uses for its
+// download-duration metric: buckets widen geometrically so both a
+// sub-100ms prehandle and a multi-minute one land in a meaningful bucket.
+// Everything here is labelled by `store_id` (like `MESSAGE_RECV_BY_STORE`
+// above) since a single process can host more than one `TiFlashObserver`.
+lazy_static::lazy_static! {
+    /// Wall time of a single background prehandle job, from the moment
+    /// `p.spawn`'s closure starts running (not from when it was queued) to
+    /// `sender.send`. Does not include jobs dropped early by the
+    /// `task.aborted` fast paths, since those never did any real FFI work.
+    static ref PREHANDLE_DURATION_HISTOGRAM: prometheus::HistogramVec = prometheus::register_histogram_vec!(
+        "tiflash_prehandle_snapshot_duration_seconds",
+        "Bucketed latency of a single background snapshot prehandle job",
+        &["store_id"],
+        prometheus::exponential_buckets(0.01, 2.0, 16).unwrap()
+    ).unwrap();
+    /// `post_apply_snapshot`'s own latency, split by whether it reused a
+    /// background-prehandled result (`"normal"`) or had to regenerate one
+    /// synchronously on the apply thread (`"blockgen"`) because the
+    /// background task was missing, stale, or cancelled.
+    static ref APPLY_SNAPSHOT_DURATION_HISTOGRAM: prometheus::HistogramVec = prometheus::register_histogram_vec!(
+        "tiflash_apply_snapshot_duration_seconds",
+        "Bucketed latency of post_apply_snapshot, labelled by normal vs. block-gen fallback",
+        &["store_id", "mode"],
+        prometheus::exponential_buckets(0.01, 2.0, 16).unwrap()
+    ).unwrap();
+    /// Mirrors `TiFlashEngine::pending_applies_count` as a gauge so it can
+    /// be graphed/alerted on instead of only appearing in log lines.
+    static ref PENDING_APPLIES_GAUGE: prometheus::IntGaugeVec = prometheus::register_int_gauge_vec!(
+        "tiflash_pending_applies",
+        "Number of snapshots currently queued for or undergoing background prehandle",
+        &["store_id"]
+    ).unwrap();
+    /// A prehandle task superseded by a newer snapshot for the same region
+    /// before it could finish (see `cancel_applying_snap` in
+    /// `pre_apply_snapshot`), vs. `post_apply_snapshot` finding no tracked
+    /// task at all for a `snap_key` (background prehandling disabled, or
+    /// the snapshot arrived before the tracer could record it).
+    static ref SNAPSHOT_PREHANDLE_REPLACED_COUNTER: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_prehandle_snapshot_replaced",
+        "Number of background prehandle tasks cancelled because a newer snapshot for the same region superseded them",
+        &["store_id"]
+    ).unwrap();
+    static ref SNAPSHOT_PREHANDLE_MISSING_COUNTER: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_prehandle_snapshot_missing",
+        "Number of post_apply_snapshot calls that found no tracked background prehandle task for the snap_key",
+        &["store_id"]
+    ).unwrap();
+    /// Times `enable_fast_add_peer` let `post_apply_snapshot` skip applying
+    /// a snapshot body entirely (see `should_skip` below).
+    static ref FAST_ADD_PEER_SKIP_COUNTER: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_fast_add_peer_snapshot_skip",
+        "Number of snapshot applies skipped by the fast-add-peer fast path",
+        &["store_id"]
+    ).unwrap();
+    /// Counts `run_fast_add_peer_reaper_tick` forcing an inflight fast path
+    /// to the slow path after its `StaleState` reached `Stale`, i.e. it was
+    /// marked `ToValidate` once uninitialized past `STALE_FAP_CHECK_THRESHOLD`
+    /// and was still uninitialized on the reaper's next tick.
+    /// Alongside `fast_add_peer_count`, this tells operators how often
+    /// fast-add-peer attempts are getting stuck rather than merely slow.
+    static ref FAST_ADD_PEER_STALE_FALLBACK_COUNTER: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_fast_add_peer_stale_fallback",
+        "Number of fast-add-peer attempts forced to the slow path after being confirmed stale",
+        &["store_id"]
+    ).unwrap();
+    /// The chosen source peer's applied-index gap against the leader, one
+    /// observation per `rank_source_candidates` call that found at least
+    /// one live candidate. Large, persistent gaps suggest the donor pool is
+    /// generally stale rather than this being a one-off.
+    static ref FAST_ADD_PEER_SOURCE_INDEX_GAP: prometheus::HistogramVec = prometheus::register_histogram_vec!(
+        "tiflash_fast_add_peer_source_index_gap",
+        "Applied-index gap between the leader and the chosen fast-add-peer source candidate",
+        &["store_id"],
+        prometheus::exponential_buckets(1.0, 4.0, 12).unwrap()
+    ).unwrap();
+    /// Size in bytes of the CF SST files collected by `retrieve_sst_files`
+    /// for a single snapshot, one observation per CF per snapshot
+    /// prehandled or re-generated.
+    static ref SNAPSHOT_SST_BYTES_HISTOGRAM: prometheus::HistogramVec = prometheus::register_histogram_vec!(
+        "tiflash_snapshot_sst_bytes",
+        "CF SST bytes collected for a single snapshot prehandle/apply",
+        &["store_id", "cf"],
+        prometheus::exponential_buckets(4096.0, 4.0, 16).unwrap()
+    ).unwrap();
+}
+
+/// Maps a `ColumnFamilyType` to the CF name metrics are labelled with,
+/// mirroring `cf_to_name` in `mock-engine-store`.
+fn cf_to_name(cf: ColumnFamilyType) -> &'static str {
+    match cf {
+        ColumnFamilyType::Lock => CF_LOCK,
+        ColumnFamilyType::Write => engine_traits::CF_WRITE,
+        ColumnFamilyType::Default => engine_traits::CF_DEFAULT,
+    }
+}
+
+/// Observes the on-disk size of every CF file collected for one snapshot
+/// into `SNAPSHOT_SST_BYTES_HISTOGRAM`, one sample per CF. Missing files
+/// (already cleaned up, or a v2 tablet path that isn't a plain file) are
+/// skipped rather than failing the whole observation.
+fn observe_sst_bytes(store_id: u64, ssts: &[(PathBuf, ColumnFamilyType)]) {
+    let store_id = store_id.to_string();
+    for (path, cf) in ssts {
+        if let Ok(meta) = std::fs::metadata(path) {
+            SNAPSHOT_SST_BYTES_HISTOGRAM
+                .with_label_values(&[&store_id, cf_to_name(*cf)])
+                .observe(meta.len() as f64);
+        }
+    }
+}
+
+// In a full build this would be a field on `EngineStoreConfig`; `engine_store_ffi`'s
+// own config struct isn't reachable from this file, so it's a local
+// constant here. Caps `pending_applies_count` — snapshots queued for or
+// undergoing background prehandle — independent of `PREHANDLE_TRANQUILITY_LIMIT`,
+// which only bounds concurrently *running* jobs. Once the cap is hit,
+// `pre_apply_snapshot` leaves the snapshot untracked instead of spawning
+// onto `apply_snap_pool`, and `post_apply_snapshot`'s existing
+// `maybe_snapshot == None` path takes over: it synchronously re-generates
+// the prehandled result (the "block-gen" fallback) rather than piling more
+// held `SSTReaderPtr`s onto an already-saturated queue.
+const MAX_PENDING_APPLIES: u64 = 64;
+
+lazy_static::lazy_static! {
+    /// Times `pre_apply_snapshot` skipped spawning a background prehandle
+    /// job because `pending_applies_count` was already at
+    /// `MAX_PENDING_APPLIES`, forcing `post_apply_snapshot` onto its
+    /// synchronous block-gen path.
+    static ref PREHANDLE_BACKPRESSURE_BLOCKGEN_COUNTER: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_prehandle_backpressure_blockgen",
+        "Number of snapshots forced onto the synchronous block-gen apply path by max_pending_applies backpressure",
+        &["store_id"]
+    ).unwrap();
+}
+
+// In a full build this would be a field on `EngineStoreConfig`, tunable
+// like `IoLimiter`'s rate; `engine_store_ffi`'s own config struct isn't
+// reachable from this file, so it's a local constant here. Caps how many
+// prehandle jobs may be *running* at once, independent of
+// `apply_snap_pool`'s thread count: the pool can be sized generously so
+// jobs don't pile up waiting for a worker thread, while this "tranquility"
+// limit keeps the number of jobs concurrently hammering the FFI/engine
+// lower, so prehandling a burst of new peers doesn't starve normal apply
+// traffic on the same store.
+const PREHANDLE_TRANQUILITY_LIMIT: usize = 4;
+
+// Counting gate bounding how many prehandle jobs run `pre_handle_snapshot_impl`
+// concurrently. Deliberately blocking (not a `try_acquire`): a job that loses
+// the race just waits its turn on `apply_snap_pool`'s worker thread, same as
+// if the pool itself were smaller.
+struct TranquilityGate {
+    available: Mutex<usize>,
+    cond: std::sync::Condvar,
+}
+
+impl TranquilityGate {
+    fn new(limit: usize) -> Self {
+        TranquilityGate {
+            available: Mutex::new(limit),
+            cond: std::sync::Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.cond.wait(available).unwrap();
+        }
+        *available -= 1;
+    }
+
+    fn release(&self) {
+        let mut available = self.available.lock().unwrap();
+        *available += 1;
+        self.cond.notify_one();
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref PREHANDLE_TRANQUILITY_GATE: TranquilityGate =
+        TranquilityGate::new(PREHANDLE_TRANQUILITY_LIMIT);
+}
+
+// Standalone rather than a `TiFlashObserver` method: called from inside the
+// `apply_snap_pool` background closure, which only captures the
+// `pre_handle_snapshot_ctx` handle it needs, not all of `self`.
+fn set_prehandle_job_state(
+    ctx: &Arc<Mutex<PrehandleContext>>,
+    snap_key: &SnapKey,
+    state: ApplySnapJobState,
+) {
+    let mut lock = match ctx.lock() {
+        Ok(l) => l,
+        Err(_) => fatal!("prehandle job state poisoned"),
+    };
+    // A key already removed from `tracer` (cancelled or consumed) is not
+    // re-added.
+    if lock.tracer.contains_key(snap_key) {
+        lock.job_states.insert(snap_key.clone(), state);
+    }
+}
+
+// In a full build this would be a field on `EngineStoreConfig`;
+// `engine_store_ffi`'s own config struct isn't reachable from this file, so
+// it's a local constant here. Off by default: opening and checksumming
+// every CF file is real I/O on top of what `pre_handle_snapshot_impl`
+// already does, so it's opt-in rather than always-on.
+const ENABLE_SST_SCRUB: bool = false;
+// Consecutive corrupt scrub rounds for the *same* region before escalating
+// to a fatal log. A single corrupt file is treated as a one-off (disk
+// hiccup, concurrent truncation); the same region failing scrub over and
+// over points at something actually wrong rather than a fluke.
+const SST_SCRUB_CORRUPT_ROUNDS_FATAL_THRESHOLD: u32 = 3;
+// Scrub every Nth prehandle job (across all regions) rather than every one,
+// so a steady stream of snapshots doesn't pay the full read-and-checksum
+// cost on each. `1` scrubs every job.
+const SST_SCRUB_SAMPLE_EVERY: u64 = 1;
+
+lazy_static::lazy_static! {
+    static ref SST_SCRUB_JOB_COUNTER: AtomicU64 = AtomicU64::new(0);
+    static ref SST_SCRUB_VERIFIED: prometheus::IntCounter = prometheus::register_int_counter!(
+        "tiflash_prehandle_sst_scrub_verified",
+        "Number of prehandle scrub passes that found every CF file intact"
+    ).unwrap();
+    static ref SST_SCRUB_CORRUPT: prometheus::IntCounter = prometheus::register_int_counter!(
+        "tiflash_prehandle_sst_scrub_corrupt",
+        "Number of prehandle scrub passes that found at least one corrupt CF file"
+    ).unwrap();
+}
+
+// Picks every `SST_SCRUB_SAMPLE_EVERY`th prehandle job (across all regions,
+// not per-region) for scrubbing.
+fn should_scrub_this_job() -> bool {
+    SST_SCRUB_SAMPLE_EVERY > 0
+        && SST_SCRUB_JOB_COUNTER.fetch_add(1, Ordering::Relaxed) % SST_SCRUB_SAMPLE_EVERY == 0
+}
+
+// Modeled on Garage's scrub worker: opens each collected CF file and
+// verifies its SST magic/footer and block checksums before the (expensive,
+// opaque-on-failure) FFI call in `pre_handle_snapshot_impl` ever sees it.
+// Returns `false` on the first corrupt file found, logging which one.
+fn scrub_sst_files(ssts: &[(PathBuf, ColumnFamilyType)]) -> bool {
+    for (path, cf) in ssts {
+        let result = <TiFlashEngine as KvEngine>::SstReader::open(path.to_str().unwrap())
+            .and_then(|reader| reader.verify_checksum());
+        if let Err(e) = result {
+            warn!("prehandle sst scrub found corrupt file"; "path" => ?path, "cf" => ?cf, "err" => ?e);
+            return false;
+        }
+    }
+    true
+}
+
+// Records one scrub pass's outcome for `region_id` in its slot of
+// `cached_region_info`, inserting a default entry if this is the first
+// time the region's been scrubbed. Returns the number of *consecutive*
+// corrupt rounds now recorded for the region (0 if this pass was clean),
+// so the caller can decide whether to escalate.
+fn record_sst_scrub_result(
+    cached_region_info: &Arc<Vec<RwLock<CachedRegionInfoMap>>>,
+    region_id: u64,
+    corrupt: bool,
+) -> u32 {
+    let slot_id = hash_u64(region_id) as usize & (CACHED_REGION_INFO_SLOT_COUNT - 1);
+    let mut guard = match cached_region_info.get(slot_id).unwrap().write() {
+        Ok(g) => g,
+        Err(_) => fatal!("cached_region_info poisoned during sst scrub"),
+    };
+    let info = guard
+        .entry(region_id)
+        .or_insert_with(|| Arc::new(CachedRegionInfo::default()));
+    if corrupt {
+        SST_SCRUB_CORRUPT.inc();
+        info.last_sst_scrub.corrupt.fetch_add(1, Ordering::SeqCst);
+        info.last_sst_scrub
+            .consecutive_corrupt_rounds
+            .fetch_add(1, Ordering::SeqCst)
+            + 1
+    } else {
+        SST_SCRUB_VERIFIED.inc();
+        info.last_sst_scrub.verified.fetch_add(1, Ordering::SeqCst);
+        info.last_sst_scrub
+            .consecutive_corrupt_rounds
+            .store(0, Ordering::SeqCst);
+        0
+    }
+}
+
+// Which shape `build_and_send_snapshot` (and, by extension, the normal
+// snapshot-apply path every fast-add-peer snapshot rides in on) uses to
+// move a region's data off its donor. `Sst` materializes sorted, checksummed
+// per-CF SST files and ingests them through the existing SST ingestion
+// path — the only mode this file actually implements; `Raw` stands in for
+// a hypothetical direct engine-write mode this crate doesn't build, kept
+// only so the config shape below has something to discriminate against.
+// Would be an `engine_store.fast_add_peer_mode` config field if
+// `crate::EngineStoreConfig` were reachable from this file; see
+// `MAX_PENDING_APPLIES` for the same constraint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FastAddPeerMode {
+    Sst,
+    Raw,
+}
+
+const FAST_ADD_PEER_MODE: FastAddPeerMode = FastAddPeerMode::Sst;
+
+// In a full build this would be a field on `EngineStoreConfig`; `engine_store_ffi`'s
+// own config struct isn't reachable from this file, so it's a local
+// constant here. Off by default, like `ENABLE_SST_SCRUB`: unlike that
+// check (which only verifies a file is internally well-formed), this
+// recomputes a whole-file CRC32 and compares it against the checksum the
+// sender recorded for the CF in the snapshot meta, the same kind of
+// packaged-snapshot integrity check `whole_file_digest` does for ingested
+// SSTs in `tiflash_ffi`. Catches a truncated/altered file that's still a
+// structurally valid SST (so `scrub_sst_files` would miss it). Tied to
+// `FAST_ADD_PEER_MODE`: the `Sst` mode's whole point is to let a receiver
+// reject corrupt donor data before committing it, so digest checking is
+// on whenever it's in effect.
+const ENABLE_SST_DIGEST_CHECK: bool = matches!(FAST_ADD_PEER_MODE, FastAddPeerMode::Sst);
+
+lazy_static::lazy_static! {
+    static ref SST_DIGEST_MISMATCH_COUNTER: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_prehandle_sst_digest_mismatch",
+        "Number of prehandle jobs where a CF SST's recomputed whole-file CRC32 didn't match the snapshot meta's recorded checksum",
+        &["store_id"]
+    ).unwrap();
+}
+
+lazy_static::lazy_static! {
+    // Hit counters for a handful of named rendezvous points, plus the
+    // condvar that wakes anyone parked on one. Exists so test harnesses
+    // (see `fap_sequencer` in proxy_tests) can block until this process
+    // has actually reached a failpoint instead of polling cluster state
+    // on a wall-clock sleep — `note_test_rendezvous` is called right
+    // before the matching `fail::fail_point!`, so observing a bump here
+    // means the paused thread is sitting at that failpoint right now.
+    // Always compiled in, same as the `fail::fail_point!` calls
+    // themselves: a no-op in builds where the named failpoint is never
+    // configured, since nothing ever calls `wait_for_test_rendezvous`.
+    static ref FAP_TEST_RENDEZVOUS: (Mutex<HashMap<&'static str, u64>>, Condvar) =
+        (Mutex::new(HashMap::default()), Condvar::new());
+}
+
+fn note_test_rendezvous(name: &'static str) {
+    let (lock, cvar) = &*FAP_TEST_RENDEZVOUS;
+    let mut hits = match lock.lock() {
+        Ok(h) => h,
+        Err(_) => return,
+    };
+    *hits.entry(name).or_insert(0) += 1;
+    cvar.notify_all();
+}
+
+/// Blocks until `name`'s rendezvous has been hit at least `at_least` times
+/// in total, or `timeout` elapses. Returns whether it was hit in time.
+pub fn wait_for_test_rendezvous(name: &'static str, at_least: u64, timeout: Duration) -> bool {
+    let (lock, cvar) = &*FAP_TEST_RENDEZVOUS;
+    let hits = match lock.lock() {
+        Ok(h) => h,
+        Err(_) => return false,
+    };
+    match cvar.wait_timeout_while(hits, timeout, |hits| {
+        *hits.get(name).unwrap_or(&0) < at_least
+    }) {
+        Ok((hits, timed_out)) => !timed_out.timed_out() && *hits.get(name).unwrap_or(&0) >= at_least,
+        Err(_) => false,
+    }
+}
+
+/// Checksums recorded for each non-v2 CF file in `snap`'s meta, keyed by the
+/// same path `retrieve_sst_files` collects. Only plain files carry one;
+/// v2 tablet snapshot paths (`SSTReaderPtr::encode_v2`) aren't real
+/// on-disk files and are simply absent from the result.
+fn collect_sst_digests(snap: &store::Snapshot) -> HashMap<PathBuf, u32> {
+    let mut digests = HashMap::default();
+    for cf_file in snap.cf_files() {
+        if cf_file.size.is_empty() || cf_file.size[0] == 0 {
+            continue;
+        }
+        for (i, path) in cf_file.file_paths().into_iter().enumerate() {
+            if let Some(checksum) = cf_file.checksum.get(i) {
+                digests.insert(path, *checksum);
+            }
+        }
+    }
+    digests
+}
+
+/// Recomputes each collected CF file's whole-file CRC32 and compares it
+/// against `digests`. Returns `false` on the first mismatch (or unreadable
+/// file), logging the offending path and CF. A file absent from `digests`
+/// (no recorded checksum, or a v2 tablet path) is skipped rather than
+/// treated as a failure.
+fn verify_sst_digests(ssts: &[(PathBuf, ColumnFamilyType)], digests: &HashMap<PathBuf, u32>) -> bool {
+    fail::fail_point!("fap_force_sst_digest_mismatch", |_| false);
+    for (path, cf) in ssts {
+        let expected = match digests.get(path) {
+            Some(c) => *c,
+            None => continue,
+        };
+        let got = match std::fs::read(path) {
+            Ok(data) => {
+                let mut hasher = crc32fast::Hasher::new();
+                hasher.update(&data);
+                hasher.finalize()
+            }
+            Err(e) => {
+                warn!("prehandle sst digest check failed to read file"; "path" => ?path, "cf" => ?cf, "err" => ?e);
+                return false;
+            }
+        };
+        if got != expected {
+            warn!("prehandle sst whole-file digest mismatch";
+                "path" => ?path, "cf" => ?cf, "expected" => expected, "got" => got);
+            return false;
+        }
+    }
+    true
+}
+
+lazy_static::lazy_static! {
+    static ref SNAPSHOT_APPLY_SUCCESS: prometheus::IntCounter = prometheus::register_int_counter!(
+        "tiflash_snapshot_apply_success",
+        "Number of snapshots that finished applying successfully"
+    ).unwrap();
+    static ref SNAPSHOT_APPLY_ABORTED: prometheus::IntCounter = prometheus::register_int_counter!(
+        "tiflash_snapshot_apply_aborted",
+        "Number of snapshots whose apply was deliberately skipped or discarded (fast-add-peer recovery, superseded or stale prehandle)"
+    ).unwrap();
+    static ref SNAPSHOT_APPLY_FAILED: prometheus::IntCounter = prometheus::register_int_counter!(
+        "tiflash_snapshot_apply_failed",
+        "Number of snapshots whose background prehandle errored and had to be regenerated synchronously"
+    ).unwrap();
+}
+
+/// Outcome of one `post_apply_snapshot` call, reported through
+/// [`TiFlashObserver::on_snapshot_applied`]. `raftstore::coprocessor` doesn't
+/// vendor an observer hook for this in this tree, so it's surfaced as a
+/// plain method called from every terminal branch below instead of a new
+/// `ApplySnapshotObserver` trait method.
+#[derive(Debug, Clone, Copy)]
+pub struct SnapApplyRes {
+    pub region_id: u64,
+    pub is_success: bool,
+    pub is_abort: bool,
+}
+
+impl<T: Transport + 'static, ER: RaftEngine> TiFlashObserver<T, ER> {
+    /// Reports how a snapshot apply ended up, for metrics/diagnostics.
+    /// `is_abort` covers both a deliberate skip (fast-add-peer recovering
+    /// via MsgAppend instead of a real apply) and a discard of a
+    /// superseded/stale prehandled snapshot; neither is a failure.
+    fn on_snapshot_applied(&self, snap_key: &store::SnapKey, res: SnapApplyRes) {
+        info!("snapshot apply result";
+            "region_id" => res.region_id,
+            "snap_key" => ?snap_key,
+            "is_success" => res.is_success,
+            "is_abort" => res.is_abort,
+        );
+        if res.is_success {
+            SNAPSHOT_APPLY_SUCCESS.inc();
+        } else if res.is_abort {
+            SNAPSHOT_APPLY_ABORTED.inc();
+        } else {
+            SNAPSHOT_APPLY_FAILED.inc();
+        }
+    }
+}
+
 fn pre_handle_snapshot_impl(
     engine_store_server_helper: &'static EngineStoreServerHelper,
     peer_id: u64,
@@ -1374,6 +4130,10 @@ impl<T: Transport + 'static, ER: RaftEngine> ApplySnapshotObserver for TiFlashOb
             "snap_key" => ?snap_key,
             "pending" => self.engine.pending_applies_count.load(Ordering::SeqCst),
         );
+        // The peer is about to move to `PeerState::Applying`; any read
+        // parked on the old applied index in `pending_reads` needs to be
+        // re-resolved against whatever the incoming snapshot lands at.
+        self.fail_pending_reads(ob_ctx.region().get_id());
         fail::fail_point!("on_ob_pre_handle_snapshot", |_| {});
 
         let snap = match snap {
@@ -1391,7 +4151,57 @@ impl<T: Transport + 'static, ER: RaftEngine> ApplySnapshotObserver for TiFlashOb
         });
 
         let (sender, receiver) = mpsc::channel();
-        let task = Arc::new(PrehandleTask::new(receiver, peer_id));
+        let region_id = ob_ctx.region().get_id();
+        let span = tracing::info_span!(
+            "prehandle_snapshot",
+            region_id,
+            peer_id,
+            snap_key = %format!("{:?}", snap_key),
+            sst_count = tracing::field::Empty,
+            sst_bytes = tracing::field::Empty,
+            mode = tracing::field::Empty,
+            prehandle_ms = tracing::field::Empty,
+            apply_ms = tracing::field::Empty,
+        );
+        let task = Arc::new(PrehandleTask::new(
+            receiver,
+            peer_id,
+            ob_ctx.region().get_region_epoch().clone(),
+            span.clone(),
+        ));
+        let superseded: Vec<_> = {
+            let lock = match self.pre_handle_snapshot_ctx.lock() {
+                Ok(l) => l,
+                Err(_) => fatal!("pre_apply_snapshot poisoned"),
+            };
+            lock.tracer
+                .iter()
+                .filter(|(k, _)| k.region_id == region_id && *k != snap_key)
+                .map(|(k, _)| k.clone())
+                .collect()
+        };
+        for stale_key in &superseded {
+            // A newer snapshot for this region arrived while an older one
+            // was still prehandling in the background; cancel it now
+            // instead of letting it run to completion only to be discarded
+            // later in `post_apply_snapshot`.
+            self.cancel_applying_snap(region_id, stale_key);
+            SNAPSHOT_PREHANDLE_REPLACED_COUNTER
+                .with_label_values(&[&self.store_id.to_string()])
+                .inc();
+        }
+        if self.engine.pending_applies_count.load(Ordering::SeqCst) >= MAX_PENDING_APPLIES {
+            info!("pending applies at max_pending_applies, deferring to synchronous block-gen";
+                "peer_id" => peer_id,
+                "snap_key" => ?snap_key,
+                "region_id" => region_id,
+                "pending" => self.engine.pending_applies_count.load(Ordering::SeqCst),
+            );
+            PREHANDLE_BACKPRESSURE_BLOCKGEN_COUNTER
+                .with_label_values(&[&self.store_id.to_string()])
+                .inc();
+            return;
+        }
         {
             let mut lock = match self.pre_handle_snapshot_ctx.lock() {
                 Ok(l) => l,
@@ -1399,21 +4209,141 @@ impl<T: Transport + 'static, ER: RaftEngine> ApplySnapshotObserver for TiFlashOb
             };
             let ctx = lock.deref_mut();
             ctx.tracer.insert(snap_key.clone(), task.clone());
+            ctx.job_states
+                .insert(snap_key.clone(), ApplySnapJobState::Queued);
+            PREHANDLE_QUEUED_GAUGE.inc();
         }
 
         let engine_store_server_helper = self.engine_store_server_helper;
         let region = ob_ctx.region().clone();
         let snap_key = snap_key.clone();
         let ssts = retrieve_sst_files(snap);
+        let store_id = self.store_id;
+        observe_sst_bytes(store_id, &ssts);
+        span.record("sst_count", ssts.len() as u64);
+        span.record(
+            "sst_bytes",
+            ssts.iter()
+                .filter_map(|(path, _)| std::fs::metadata(path).ok())
+                .map(|meta| meta.len())
+                .sum::<u64>(),
+        );
+        let sst_digests = if ENABLE_SST_DIGEST_CHECK {
+            collect_sst_digests(snap)
+        } else {
+            HashMap::default()
+        };
+        let pre_handle_snapshot_ctx = self.pre_handle_snapshot_ctx.clone();
+        let cached_region_info = self.cached_region_info.clone();
         match self.apply_snap_pool.as_ref() {
             Some(p) => {
                 self.engine
                     .pending_applies_count
                     .fetch_add(1, Ordering::SeqCst);
+                PENDING_APPLIES_GAUGE
+                    .with_label_values(&[&store_id.to_string()])
+                    .inc();
                 p.spawn(async move {
+                    // Nests the whole background job (including the FFI
+                    // call and any early-return below) under the span
+                    // `pre_apply_snapshot` opened for this `snap_key`. No
+                    // `.await` points cross this guard, so holding it across
+                    // the closure body doesn't outlive a single poll.
+                    let _span_guard = task.span.enter();
                     // The original implementation is in `Snapshot`, so we don't need to care abort
                     // lifetime.
                     fail::fail_point!("before_actually_pre_handle", |_| {});
+                    let prehandle_start = Instant::now();
+                    if task.aborted.load(Ordering::SeqCst) {
+                        // Already superseded before this task got a chance
+                        // to run; skip the (potentially expensive) FFI call
+                        // entirely instead of paying for it only to have
+                        // the result discarded in `post_apply_snapshot`.
+                        info!("skip background pre-handle for aborted snapshot";
+                            "peer_id" => task.peer_id,
+                            "snap_key" => ?snap_key,
+                            "region_id" => region.get_id(),
+                        );
+                        PREHANDLE_QUEUED_GAUGE.dec();
+                        set_prehandle_job_state(
+                            &pre_handle_snapshot_ctx,
+                            &snap_key,
+                            ApplySnapJobState::Aborted,
+                        );
+                        return;
+                    }
+                    PREHANDLE_QUEUED_GAUGE.dec();
+                    PREHANDLE_TRANQUILITY_GATE.acquire();
+                    PREHANDLE_RUNNING_GAUGE.inc();
+                    set_prehandle_job_state(
+                        &pre_handle_snapshot_ctx,
+                        &snap_key,
+                        ApplySnapJobState::Running,
+                    );
+                    if ENABLE_SST_SCRUB && should_scrub_this_job() {
+                        if !scrub_sst_files(&ssts) {
+                            let region_id = region.get_id();
+                            let consecutive =
+                                record_sst_scrub_result(&cached_region_info, region_id, true);
+                            if consecutive >= SST_SCRUB_CORRUPT_ROUNDS_FATAL_THRESHOLD {
+                                fatal!(
+                                    "region {} failed prehandle sst scrub {} times in a row",
+                                    region_id,
+                                    consecutive
+                                );
+                            }
+                            PREHANDLE_RUNNING_GAUGE.dec();
+                            PREHANDLE_TRANQUILITY_GATE.release();
+                            set_prehandle_job_state(
+                                &pre_handle_snapshot_ctx,
+                                &snap_key,
+                                ApplySnapJobState::Corrupt,
+                            );
+                            // Drop `sender` without a result: `task.aborted`
+                            // is deliberately left `false` here, so
+                            // `post_apply_snapshot`'s `Err` branch takes the
+                            // synchronous re-gen path instead of treating
+                            // this as a cancellation.
+                            return;
+                        }
+                        record_sst_scrub_result(&cached_region_info, region.get_id(), false);
+                    }
+                    if ENABLE_SST_DIGEST_CHECK && !verify_sst_digests(&ssts, &sst_digests) {
+                        SST_DIGEST_MISMATCH_COUNTER
+                            .with_label_values(&[&store_id.to_string()])
+                            .inc();
+                        PREHANDLE_RUNNING_GAUGE.dec();
+                        PREHANDLE_TRANQUILITY_GATE.release();
+                        set_prehandle_job_state(
+                            &pre_handle_snapshot_ctx,
+                            &snap_key,
+                            ApplySnapJobState::Corrupt,
+                        );
+                        // Drop `sender` without a result, same as the scrub
+                        // failure above: `post_apply_snapshot`'s `Err`
+                        // branch takes the synchronous re-gen path instead
+                        // of treating this as a cancellation.
+                        return;
+                    }
+                    if ENABLE_SNAPSHOT_SST_SCRATCH_COMPRESSION {
+                        for (path, cf) in ssts.iter() {
+                            match compress_sst_scratch(path) {
+                                Ok((Compression::Zstd, compressed_path, original_size)) => {
+                                    if let Err(e) = decompress_sst_scratch(
+                                        &compressed_path,
+                                        path,
+                                        original_size,
+                                    ) {
+                                        warn!("snapshot sst scratch compression round trip failed";
+                                            "err" => ?e, "path" => ?path, "cf" => ?cf);
+                                    }
+                                }
+                                Ok((Compression::None, ..)) => {}
+                                Err(e) => warn!("failed to compress snapshot sst scratch file";
+                                    "err" => ?e, "path" => ?path, "cf" => ?cf),
+                            }
+                        }
+                    }
                     let res = pre_handle_snapshot_impl(
                         engine_store_server_helper,
                         task.peer_id,
@@ -1421,6 +4351,39 @@ impl<T: Transport + 'static, ER: RaftEngine> ApplySnapshotObserver for TiFlashOb
                         &region,
                         &snap_key,
                     );
+                    PREHANDLE_RUNNING_GAUGE.dec();
+                    PREHANDLE_TRANQUILITY_GATE.release();
+                    if task.aborted.load(Ordering::SeqCst) {
+                        // Superseded while `pre_handle_snapshot_impl` was
+                        // running: `post_apply_snapshot` will treat this as
+                        // a cancellation via `stale` regardless of whether
+                        // anything arrives on `receiver`, so free the
+                        // freshly built `SSTReaderPtr` right away instead of
+                        // handing it to a receiver nobody will read in time.
+                        info!("discard pre-handled snapshot aborted mid-flight";
+                            "peer_id" => task.peer_id,
+                            "snap_key" => ?snap_key,
+                            "region_id" => region.get_id(),
+                        );
+                        engine_store_server_helper.gc_raw_cpp_ptr(res.0.ptr, res.0.type_);
+                        set_prehandle_job_state(
+                            &pre_handle_snapshot_ctx,
+                            &snap_key,
+                            ApplySnapJobState::Aborted,
+                        );
+                        return;
+                    }
+                    set_prehandle_job_state(
+                        &pre_handle_snapshot_ctx,
+                        &snap_key,
+                        ApplySnapJobState::Finished,
+                    );
+                    let prehandle_elapsed = prehandle_start.elapsed();
+                    PREHANDLE_DURATION_HISTOGRAM
+                        .with_label_values(&[&store_id.to_string()])
+                        .observe(prehandle_elapsed.as_secs_f64());
+                    task.span
+                        .record("prehandle_ms", prehandle_elapsed.as_millis() as u64);
                     match sender.send(res) {
                         Err(_e) => error!("pre apply snapshot err when send to receiver"),
                         Ok(_) => (),
@@ -1444,6 +4407,7 @@ impl<T: Transport + 'static, ER: RaftEngine> ApplySnapshotObserver for TiFlashOb
         snap_key: &store::SnapKey,
         snap: Option<&store::Snapshot>,
     ) {
+        note_test_rendezvous("on_ob_post_apply_snapshot");
         fail::fail_point!("on_ob_post_apply_snapshot", |_| {
             return;
         });
@@ -1453,6 +4417,7 @@ impl<T: Transport + 'static, ER: RaftEngine> ApplySnapshotObserver for TiFlashOb
             "region" => ?ob_ctx.region(),
         );
         let region_id = ob_ctx.region().get_id();
+        let post_apply_start = Instant::now();
         let mut should_skip = false;
         #[allow(clippy::collapsible_if)]
         if self.engine_store_cfg.enable_fast_add_peer {
@@ -1466,7 +4431,7 @@ impl<T: Transport + 'static, ER: RaftEngine> ApplySnapshotObserver for TiFlashOb
                                 "snap_key" => ?snap_key,
                             );
                             should_skip = true;
-                            o.get_mut().snapshot_inflight.store(0, Ordering::SeqCst);
+                            o.get().fast_add_peer_progress.clear();
                             o.get_mut().inited_or_fallback.store(true, Ordering::SeqCst);
                         }
                     }
@@ -1478,6 +4443,9 @@ impl<T: Transport + 'static, ER: RaftEngine> ApplySnapshotObserver for TiFlashOb
             ).is_err() {
                 fatal!("post_apply_snapshot poisoned")
             };
+            if should_skip {
+                self.stop_leader_keeper(region_id);
+            }
         }
         let snap = match snap {
             None => return,
@@ -1489,13 +4457,56 @@ impl<T: Transport + 'static, ER: RaftEngine> ApplySnapshotObserver for TiFlashOb
                 Err(_) => fatal!("post_apply_snapshot poisoned"),
             };
             let ctx = lock.deref_mut();
-            ctx.tracer.remove(snap_key)
+            let t = ctx.tracer.remove(snap_key);
+            // This job's state (if any) is about to be consumed one way or
+            // another below; drop it from the manager so a stale `Finished`
+            // entry doesn't linger for a `snap_key` no caller will ever
+            // query again.
+            ctx.job_states.remove(snap_key);
+            t
         };
+        // Re-enter the span `pre_apply_snapshot` opened for this `snap_key`
+        // (propagated via the task), or open a fresh one if no background
+        // task was ever tracked, so `apply_ms`/`mode` below always land on
+        // some span even when there's nothing to nest under.
+        let span = maybe_snapshot
+            .as_ref()
+            .map(|t| t.span.clone())
+            .unwrap_or_else(|| {
+                tracing::info_span!(
+                    "apply_snapshot",
+                    region_id,
+                    peer_id,
+                    snap_key = %format!("{:?}", snap_key),
+                    mode = tracing::field::Empty,
+                    apply_ms = tracing::field::Empty,
+                )
+            });
+        let _span_guard = span.enter();
         if should_skip {
+            FAST_ADD_PEER_SKIP_COUNTER
+                .with_label_values(&[&self.store_id.to_string()])
+                .inc();
+            self.on_snapshot_applied(
+                snap_key,
+                SnapApplyRes {
+                    region_id,
+                    is_success: false,
+                    is_abort: true,
+                },
+            );
             return;
         }
         let need_retry = match maybe_snapshot {
             Some(t) => {
+                // Recheck the epoch against the persisted region state, in
+                // case a conf-change/split/merge committed while this
+                // prehandle ran in the background but didn't route through
+                // `abort_prehandle` (e.g. this peer itself observing its own
+                // epoch bump).
+                let epoch_changed = get_region_local_state(&self.engine, region_id)
+                    .map_or(false, |s| s.get_region().get_region_epoch() != &t.epoch);
+                let stale = t.aborted.load(Ordering::SeqCst) || epoch_changed;
                 let neer_retry = match t.recv.recv() {
                     Ok(snap_ptr) => {
                         info!("get prehandled snapshot success";
@@ -1504,25 +4515,83 @@ impl<T: Transport + 'static, ER: RaftEngine> ApplySnapshotObserver for TiFlashOb
                             "region_id" => ob_ctx.region().get_id(),
                             "pending" => self.engine.pending_applies_count.load(Ordering::SeqCst),
                         );
-                        if !should_skip {
+                        if stale {
+                            warn!("discard prehandled snapshot superseded by abort or epoch change";
+                                "peer_id" => peer_id,
+                                "snap_key" => ?snap_key,
+                                "region_id" => region_id,
+                            );
+                            self.engine_store_server_helper
+                                .gc_raw_cpp_ptr(snap_ptr.0.ptr, snap_ptr.0.type_);
+                            self.on_snapshot_applied(
+                                snap_key,
+                                SnapApplyRes {
+                                    region_id,
+                                    is_success: false,
+                                    is_abort: true,
+                                },
+                            );
+                        } else if !should_skip {
                             self.engine_store_server_helper
                                 .apply_pre_handled_snapshot(snap_ptr.0);
+                            self.on_snapshot_applied(
+                                snap_key,
+                                SnapApplyRes {
+                                    region_id,
+                                    is_success: true,
+                                    is_abort: false,
+                                },
+                            );
                         }
                         false
                     }
                     Err(_) => {
-                        info!("background pre-handle snapshot get error";
-                            "peer_id" => peer_id,
-                            "snap_key" => ?snap_key,
-                            "region_id" => ob_ctx.region().get_id(),
-                            "pending" => self.engine.pending_applies_count.load(Ordering::SeqCst),
-                        );
-                        true
+                        if stale {
+                            // The background task saw `aborted` before it ever
+                            // called into the FFI and returned without
+                            // sending a result. That's an intentional
+                            // cancellation, not a failure, so don't pay for
+                            // a synchronous re-gen nobody asked for.
+                            info!("background pre-handle snapshot aborted, skip retry";
+                                "peer_id" => peer_id,
+                                "snap_key" => ?snap_key,
+                                "region_id" => ob_ctx.region().get_id(),
+                                "pending" => self.engine.pending_applies_count.load(Ordering::SeqCst),
+                            );
+                            self.on_snapshot_applied(
+                                snap_key,
+                                SnapApplyRes {
+                                    region_id,
+                                    is_success: false,
+                                    is_abort: true,
+                                },
+                            );
+                            false
+                        } else {
+                            info!("background pre-handle snapshot get error";
+                                "peer_id" => peer_id,
+                                "snap_key" => ?snap_key,
+                                "region_id" => ob_ctx.region().get_id(),
+                                "pending" => self.engine.pending_applies_count.load(Ordering::SeqCst),
+                            );
+                            self.on_snapshot_applied(
+                                snap_key,
+                                SnapApplyRes {
+                                    region_id,
+                                    is_success: false,
+                                    is_abort: false,
+                                },
+                            );
+                            true
+                        }
                     }
                 };
                 self.engine
                     .pending_applies_count
                     .fetch_sub(1, Ordering::SeqCst);
+                PENDING_APPLIES_GAUGE
+                    .with_label_values(&[&self.store_id.to_string()])
+                    .dec();
                 info!("apply snapshot finished";
                     "peer_id" => peer_id,
                     "snap_key" => ?snap_key,
@@ -1541,11 +4610,15 @@ impl<T: Transport + 'static, ER: RaftEngine> ApplySnapshotObserver for TiFlashOb
                     "region_id" => ob_ctx.region().get_id(),
                     "pending" => self.engine.pending_applies_count.load(Ordering::SeqCst),
                 );
+                SNAPSHOT_PREHANDLE_MISSING_COUNTER
+                    .with_label_values(&[&self.store_id.to_string()])
+                    .inc();
                 true
             }
         };
         if need_retry && !should_skip {
             let ssts = retrieve_sst_files(snap);
+            observe_sst_bytes(self.store_id, &ssts);
             let ptr = pre_handle_snapshot_impl(
                 self.engine_store_server_helper,
                 peer_id,
@@ -1566,7 +4639,24 @@ impl<T: Transport + 'static, ER: RaftEngine> ApplySnapshotObserver for TiFlashOb
                 "region" => ?ob_ctx.region(),
                 "pending" => self.engine.pending_applies_count.load(Ordering::SeqCst),
             );
+            self.on_snapshot_applied(
+                snap_key,
+                SnapApplyRes {
+                    region_id,
+                    is_success: true,
+                    is_abort: false,
+                },
+            );
         }
+        let mode = if need_retry { "blockgen" } else { "normal" };
+        let apply_elapsed = post_apply_start.elapsed();
+        APPLY_SNAPSHOT_DURATION_HISTOGRAM
+            .with_label_values(&[&self.store_id.to_string(), mode])
+            .observe(apply_elapsed.as_secs_f64());
+        span.record("mode", mode);
+        span.record("apply_ms", apply_elapsed.as_millis() as u64);
+        // `span` (and the task it may have come from) is dropped here,
+        // closing it.
     }
 
     fn should_pre_apply_snapshot(&self) -> bool {
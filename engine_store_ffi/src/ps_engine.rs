@@ -3,10 +3,25 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
+// `ps_engine_*` failpoints below let a test stand in for the C++ engine_store
+// crashing or dropping a write partway through `consume`/`append`/`clean`/
+// `gc_impl`, without needing the real FFI helper to misbehave. They follow
+// this crate's existing `fail::fail_point!` convention (see
+// `engine_store_ffi::observer`): always compiled in, no-ops unless the `fail`
+// crate's own `failpoints` feature is enabled, which upstream TiKV only turns
+// on for the `--test-threads 1` failpoints test binary since `fail` points
+// are process-global. A crash-consistency test exercising them would need a
+// fake `engine_store_server_helper` that actually persists pages (unlike the
+// region/snapshot-focused `mock-engine-store`, which doesn't model the page
+// storage `consume_write_batch`/`read_page`/`seek_ps_key` surface PSEngine
+// delegates to); adding that harness is left to a follow-up so this change
+// doesn't bundle an unrelated mock rewrite with the failpoint wiring itself.
+
 use std::{
     fmt,
     fmt::{Debug, Formatter},
     mem, slice,
+    sync::atomic::{AtomicU64, Ordering},
 };
 
 use byteorder::{BigEndian, ByteOrder};
@@ -81,6 +96,13 @@ use crate::{gen_engine_store_server_helper, RawCppPtr};
 //     )
 // }
 
+/// Flush threshold for a single `PSEngineWriteBatch` built internally by
+/// `append_impl`/`clean`/`gc_impl`: once the batch crosses this many bytes
+/// (per `write_batch_size`), it's consumed and cleared before the loop
+/// continues, bounding how large the serialized C++ write batch can grow
+/// for a region with a very long raft log.
+const MAX_RAFT_WB_BYTES: usize = 16 * 1024 * 1024;
+
 pub struct PSEngineWriteBatch {
     pub engine_store_server_helper: isize,
     pub raw_write_batch: RawCppPtr,
@@ -108,19 +130,48 @@ impl PSEngineWriteBatch {
         Ok(())
     }
 
+    /// Consumes and clears this batch through the FFI if it has grown past
+    /// `MAX_RAFT_WB_BYTES`, so a long `append_impl`/`clean`/`gc_impl` loop
+    /// never holds more than one flush's worth of entries in the C++ write
+    /// batch at a time. Returns the number of bytes flushed (0 if the batch
+    /// was still under the threshold). Each flush is a self-contained,
+    /// already-durable prefix of the loop's work, so a later failure just
+    /// leaves the log at that prefix rather than corrupting it.
+    fn flush_if_large(&mut self) -> Result<usize> {
+        let size = self.data_size();
+        if size < MAX_RAFT_WB_BYTES {
+            return Ok(0);
+        }
+        fail::fail_point!("ps_engine_consume_before", |_| Err(box_err!(
+            "[failpoint] ps_engine_consume_before"
+        )));
+        let helper = gen_engine_store_server_helper(self.engine_store_server_helper);
+        helper.consume_write_batch(self.raw_write_batch.ptr);
+        fail::fail_point!("ps_engine_consume_after");
+        self.clear();
+        Ok(size)
+    }
+
     fn append_impl(
         &mut self,
         raft_group_id: u64,
         entries: &[Entry],
         mut ser_buf: Vec<u8>,
-    ) -> Result<()> {
+    ) -> Result<usize> {
+        let mut flushed = 0;
         for entry in entries {
             ser_buf.clear();
             entry.write_to_vec(&mut ser_buf).unwrap();
             let key = keys::raft_log_key(raft_group_id, entry.get_index());
+            // Lets a test simulate a crash partway through a multi-entry
+            // append, so only a prefix of `entries` ever reaches `put_page`.
+            fail::fail_point!("ps_engine_append_entry", |_| Err(box_err!(
+                "[failpoint] ps_engine_append_entry"
+            )));
             self.put_page(&key, &ser_buf)?;
+            flushed += self.flush_if_large()?;
         }
-        Ok(())
+        Ok(flushed)
     }
 
     fn put_msg<M: protobuf::Message>(&mut self, page_id: &[u8], m: &M) -> Result<()> {
@@ -142,18 +193,27 @@ impl RaftLogBatch for PSEngineWriteBatch {
     fn append(&mut self, raft_group_id: u64, entries: Vec<Entry>) -> Result<()> {
         if let Some(max_size) = entries.iter().map(|e| e.compute_size()).max() {
             let ser_buf = Vec::with_capacity(max_size as usize);
-            return self.append_impl(raft_group_id, &entries, ser_buf);
+            self.append_impl(raft_group_id, &entries, ser_buf)?;
         }
         Ok(())
     }
 
+    // Called by the raftstore write path before `append` when a new
+    // leader's entries conflict with (are shorter than) what's already on
+    // disk, so the stale suffix can't rely on being overwritten by the
+    // upcoming `append` -- it must be deleted here, or it leaks into later
+    // `fetch_entries_to`/`get_all_entries_to` scans as phantom/garbage
+    // entries. The caller is expected to queue `cut_logs` ahead of the
+    // conflicting `append` in the same batch, so both land in one
+    // `consume`.
     fn cut_logs(&mut self, raft_group_id: u64, from: u64, to: u64) {
-        // This function is used to clean entries that will be overwritten
-        // later. TODO: make sure overlapped entries will be overwritten
-        // by newer log. for index in from..to {
-        //     let key = ps_raft_log_key(raft_group_id, index);
-        //     self.del_page(&key).unwrap();
-        // }
+        for index in from..to {
+            let key = keys::raft_log_key(raft_group_id, index);
+            // `cut_logs` can't surface an error through `RaftLogBatch`'s
+            // infallible signature; matches the del_page calls in `gc_impl`
+            // and `clean` that already discard it.
+            let _ = self.del_page(&key);
+        }
     }
 
     fn put_raft_state(&mut self, raft_group_id: u64, state: &RaftLocalState) -> Result<()> {
@@ -249,6 +309,7 @@ impl PSEngine {
 
     // Seek the first key >= given key, if not found, return None.
     fn seek(&self, key: &[u8]) -> Option<Vec<u8>> {
+        fail::fail_point!("ps_engine_seek", |_| None);
         let helper = gen_engine_store_server_helper(self.engine_store_server_helper);
         let target_key = helper.seek_ps_key(key.into());
         if target_key.view.len == 0 {
@@ -264,6 +325,7 @@ impl PSEngine {
     where
         F: FnMut(&[u8], &[u8]) -> Result<bool>,
     {
+        fail::fail_point!("ps_engine_scan", |_| Ok(()));
         let helper = gen_engine_store_server_helper(self.engine_store_server_helper);
         let values = helper.scan_page(start_key.into(), end_key.into());
         for i in 0..values.len {
@@ -300,11 +362,16 @@ impl PSEngine {
         // info!("gc_impl raft_group_id {} from {} to {}", raft_group_id, from ,to);
 
         let mut raft_wb = self.log_batch(0);
+        // Lets a test abort gc partway through queuing deletes, so a
+        // subsequent reopen sees only a prefix of `from..to` removed.
+        fail::fail_point!("ps_engine_gc_before_del");
         for idx in from..to {
             raft_wb.del_page(&keys::raft_log_key(raft_group_id, idx));
+            // Bounds how large raft_wb can grow for a region with a very
+            // long log; each flush is an independently-durable prefix.
+            raft_wb.flush_if_large()?;
         }
-        // TODO: keep the max size of raft_wb under some threshold
-        self.consume(&mut raft_wb, false);
+        self.consume(&mut raft_wb, false)?;
         Ok((to - from) as usize)
     }
 
@@ -418,8 +485,18 @@ impl RaftEngine for PSEngine {
 
     fn consume(&self, batch: &mut Self::LogBatch, sync_log: bool) -> Result<usize> {
         let bytes = batch.data_size();
+        // Lets a test simulate the FFI call never happening at all (e.g. the
+        // C++ side crashed before consuming), so a reopen must see the state
+        // from before this batch.
+        fail::fail_point!("ps_engine_consume_before", |_| Err(box_err!(
+            "[failpoint] ps_engine_consume_before"
+        )));
         let helper = gen_engine_store_server_helper(self.engine_store_server_helper);
         helper.consume_write_batch(batch.raw_write_batch.ptr);
+        // Lets a test simulate a crash right after the C++ side durably
+        // consumed the batch but before this function returns, to check the
+        // caller's retry/idempotency handling rather than the engine itself.
+        fail::fail_point!("ps_engine_consume_after");
         batch.clear();
         Ok(bytes)
     }
@@ -468,11 +545,17 @@ impl RaftEngine for PSEngine {
         );
         // TODO: find the first raft log index of this raft group
         if first_index <= state.last_index {
+            // Lets a test abort clean partway through queuing deletes, so a
+            // subsequent reopen sees only a prefix of the range removed.
+            fail::fail_point!("ps_engine_clean_before_del");
             for index in first_index..=state.last_index {
                 batch.del_page(&keys::raft_log_key(raft_group_id, index));
+                // Bounds how large batch can grow for a region with a very
+                // long log; each flush is an independently-durable prefix.
+                batch.flush_if_large()?;
             }
         }
-        self.consume(batch, true);
+        self.consume(batch, true)?;
         Ok(())
     }
 
@@ -480,8 +563,9 @@ impl RaftEngine for PSEngine {
         let mut wb = self.log_batch(0);
         if let Some(max_size) = entries.iter().map(|e| e.compute_size()).max() {
             let buf = Vec::with_capacity(max_size as usize);
-            wb.append_impl(raft_group_id, &entries, buf)?;
-            return self.consume(&mut wb, false);
+            let flushed = wb.append_impl(raft_group_id, &entries, buf)?;
+            let consumed = self.consume(&mut wb, false)?;
+            return Ok(flushed + consumed);
         }
         Ok(0)
     }
@@ -505,12 +589,39 @@ impl RaftEngine for PSEngine {
         Ok(total)
     }
 
-    fn flush_metrics(&self, instance: &str) {}
+    fn flush_metrics(&self, instance: &str) {
+        let stats = page_storage_stats(self.engine_store_server_helper);
+        let (reads, writes, bytes_read, bytes_written) = stats.since_last_flush();
+        PS_ENGINE_PAGE_READS
+            .with_label_values(&[instance])
+            .inc_by(reads);
+        PS_ENGINE_PAGE_WRITES
+            .with_label_values(&[instance])
+            .inc_by(writes);
+        PS_ENGINE_BYTES_READ
+            .with_label_values(&[instance])
+            .inc_by(bytes_read);
+        PS_ENGINE_BYTES_WRITTEN
+            .with_label_values(&[instance])
+            .inc_by(bytes_written);
+        if let Ok(size) = self.get_engine_size() {
+            PS_ENGINE_SIZE_BYTES
+                .with_label_values(&[instance])
+                .set(size as i64);
+        }
+    }
 
-    fn reset_statistics(&self) {}
+    fn reset_statistics(&self) {
+        LAST_FLUSHED_STATS.reset();
+    }
 
     fn dump_stats(&self) -> Result<String> {
-        Ok(String::from(""))
+        let stats = page_storage_stats(self.engine_store_server_helper);
+        let size = self.get_engine_size()?;
+        Ok(format!(
+            "ps_engine: size={}B page_reads={} page_writes={} bytes_read={}B bytes_written={}B",
+            size, stats.page_reads, stats.page_writes, stats.bytes_read, stats.bytes_written,
+        ))
     }
 
     fn get_engine_path(&self) -> &str {
@@ -518,7 +629,8 @@ impl RaftEngine for PSEngine {
     }
 
     fn get_engine_size(&self) -> Result<u64> {
-        Ok(0)
+        let helper = gen_engine_store_server_helper(self.engine_store_server_helper);
+        Ok(helper.get_ps_engine_size())
     }
 
     fn put_store_ident(&self, ident: &StoreIdent) -> Result<()> {
@@ -568,21 +680,177 @@ impl PerfContextExt for PSEngine {
     type PerfContext = PSPerfContext;
 
     fn get_perf_context(&self, level: PerfLevel, kind: PerfContextKind) -> Self::PerfContext {
-        PSPerfContext::new(level, kind)
+        PSPerfContext::new(self.engine_store_server_helper, level, kind)
     }
 }
 
+/// Cumulative PageStorage counters as reported by the C++ engine_store,
+/// mirroring what `write_batch_size` already exposes for a single batch.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PageStorageStats {
+    pub page_reads: u64,
+    pub page_writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+fn page_storage_stats(engine_store_server_helper: isize) -> PageStorageStats {
+    let helper = gen_engine_store_server_helper(engine_store_server_helper);
+    helper.get_page_storage_stats()
+}
+
+/// `flush_metrics` is called periodically (not per-request), but the C++
+/// side only reports lifetime-cumulative counters, so this tracks the
+/// previous flush's snapshot to turn it into the per-interval delta a
+/// `prometheus::IntCounterVec` expects from `inc_by`.
+#[derive(Default)]
+struct LastFlushedStats {
+    page_reads: AtomicU64,
+    page_writes: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl LastFlushedStats {
+    fn since_last_flush(&self, current: &PageStorageStats) -> (u64, u64, u64, u64) {
+        (
+            current.page_reads - self.page_reads.swap(current.page_reads, Ordering::Relaxed),
+            current.page_writes - self.page_writes.swap(current.page_writes, Ordering::Relaxed),
+            current.bytes_read - self.bytes_read.swap(current.bytes_read, Ordering::Relaxed),
+            current.bytes_written
+                - self
+                    .bytes_written
+                    .swap(current.bytes_written, Ordering::Relaxed),
+        )
+    }
+
+    fn reset(&self) {
+        self.page_reads.store(0, Ordering::Relaxed);
+        self.page_writes.store(0, Ordering::Relaxed);
+        self.bytes_read.store(0, Ordering::Relaxed);
+        self.bytes_written.store(0, Ordering::Relaxed);
+    }
+}
+
+impl PageStorageStats {
+    fn since_last_flush(&self) -> (u64, u64, u64, u64) {
+        LAST_FLUSHED_STATS.since_last_flush(self)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref LAST_FLUSHED_STATS: LastFlushedStats = LastFlushedStats::default();
+
+    static ref PS_ENGINE_PAGE_READS: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_ps_engine_page_reads_total",
+        "Cumulative PageStorage page reads performed by the raft engine backend",
+        &["instance"]
+    ).unwrap();
+    static ref PS_ENGINE_PAGE_WRITES: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_ps_engine_page_writes_total",
+        "Cumulative PageStorage page writes performed by the raft engine backend",
+        &["instance"]
+    ).unwrap();
+    static ref PS_ENGINE_BYTES_READ: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_ps_engine_bytes_read_total",
+        "Cumulative bytes read from PageStorage by the raft engine backend",
+        &["instance"]
+    ).unwrap();
+    static ref PS_ENGINE_BYTES_WRITTEN: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_ps_engine_bytes_written_total",
+        "Cumulative bytes written to PageStorage by the raft engine backend",
+        &["instance"]
+    ).unwrap();
+    static ref PS_ENGINE_SIZE_BYTES: prometheus::IntGaugeVec = prometheus::register_int_gauge_vec!(
+        "tiflash_ps_engine_size_bytes",
+        "On-disk size PageStorage reports for the raft engine backend",
+        &["instance"]
+    ).unwrap();
+
+    // Separate from the `tiflash_ps_engine_*` counters above: those are
+    // flushed periodically and labelled by `instance`, these are reported
+    // per `PSPerfContext` observation window and labelled by which
+    // raftstore thread kind (apply vs. store) did the work.
+    static ref PS_ENGINE_PERF_PAGE_READS: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_ps_engine_perf_page_reads_total",
+        "PageStorage page reads observed during a PSPerfContext window, by raftstore thread kind",
+        &["kind"]
+    ).unwrap();
+    static ref PS_ENGINE_PERF_PAGE_WRITES: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_ps_engine_perf_page_writes_total",
+        "PageStorage page writes observed during a PSPerfContext window, by raftstore thread kind",
+        &["kind"]
+    ).unwrap();
+    static ref PS_ENGINE_PERF_BYTES_READ: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_ps_engine_perf_bytes_read_total",
+        "Bytes read from PageStorage observed during a PSPerfContext window, by raftstore thread kind",
+        &["kind"]
+    ).unwrap();
+    static ref PS_ENGINE_PERF_BYTES_WRITTEN: prometheus::IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_ps_engine_perf_bytes_written_total",
+        "Bytes written to PageStorage observed during a PSPerfContext window, by raftstore thread kind",
+        &["kind"]
+    ).unwrap();
+}
+
+/// Per-call perf sampling for the PSEngine raft log backend. Unlike
+/// RocksDB's `PerfContext` (which samples a thread-local perf stats struct
+/// the C++ engine updates per-call), PageStorage only exposes
+/// lifetime-cumulative counters, so this takes a baseline snapshot at
+/// `start_observe` and reports the delta at `report_metrics`.
+///
+/// `trackers` isn't attributed to individually: this tree doesn't carry the
+/// `tracker` crate's internal `Tracker` layout, so rather than guess at its
+/// field names, the delta is only reported in aggregate via the
+/// `tiflash_ps_engine_perf_*` counters above, labelled by `kind`.
 #[derive(Debug)]
-pub struct PSPerfContext {}
+pub struct PSPerfContext {
+    engine_store_server_helper: isize,
+    level: PerfLevel,
+    kind: PerfContextKind,
+    baseline: PageStorageStats,
+}
 
 impl PSPerfContext {
-    pub fn new(level: PerfLevel, kind: PerfContextKind) -> Self {
-        PSPerfContext {}
+    pub fn new(engine_store_server_helper: isize, level: PerfLevel, kind: PerfContextKind) -> Self {
+        PSPerfContext {
+            engine_store_server_helper,
+            level,
+            kind,
+            baseline: PageStorageStats::default(),
+        }
     }
 }
 
 impl PerfContext for PSPerfContext {
-    fn start_observe(&mut self) {}
+    fn start_observe(&mut self) {
+        self.baseline = page_storage_stats(self.engine_store_server_helper);
+    }
 
-    fn report_metrics(&mut self, trackers: &[TrackerToken]) {}
+    fn report_metrics(&mut self, trackers: &[TrackerToken]) {
+        if self.level == PerfLevel::Disable {
+            return;
+        }
+        let current = page_storage_stats(self.engine_store_server_helper);
+        // Uses the kind's `Debug` form rather than matching on specific
+        // variants, since this tree doesn't carry `engine_traits`' source to
+        // confirm `PerfContextKind`'s exact variant names.
+        let kind = format!("{:?}", self.kind);
+        let kind = kind.as_str();
+        PS_ENGINE_PERF_PAGE_READS
+            .with_label_values(&[kind])
+            .inc_by(current.page_reads.saturating_sub(self.baseline.page_reads));
+        PS_ENGINE_PERF_PAGE_WRITES
+            .with_label_values(&[kind])
+            .inc_by(current.page_writes.saturating_sub(self.baseline.page_writes));
+        PS_ENGINE_PERF_BYTES_READ
+            .with_label_values(&[kind])
+            .inc_by(current.bytes_read.saturating_sub(self.baseline.bytes_read));
+        PS_ENGINE_PERF_BYTES_WRITTEN.with_label_values(&[kind]).inc_by(
+            current
+                .bytes_written
+                .saturating_sub(self.baseline.bytes_written),
+        );
+        let _ = trackers;
+    }
 }
@@ -15,13 +15,17 @@ use std::{
 
 use engine_rocks::{RocksDbVector, RocksEngineIterator, RocksSnapshot};
 use engine_traits::{
-    Checkpointable, Checkpointer, DbVector, Error, IterOptions, Iterable, KvEngine, Peekable,
-    ReadOptions, Result, SyncMutable,
+    Checkpointable, Checkpointer, DbVector, Error, IterOptions, Iterable, KvEngine, MiscExt,
+    Peekable, ReadOptions, Result, SyncMutable,
 };
 use rocksdb::{Writable, DB};
 use tikv_util::box_err;
 
-use crate::{r2e, util::get_cf_handle};
+use crate::{
+    mixed_engine::elementary::{ApplyTraceListener, PersistenceListener},
+    r2e,
+    util::get_cf_handle,
+};
 
 pub struct FsStatsExt {
     pub used: u64,
@@ -84,6 +88,11 @@ pub struct RocksEngine {
     pub pool_capacity: usize,
     pub pending_applies_count: Arc<AtomicUsize>,
     pub ffi_hub: Option<Arc<dyn FFIHubInner + Send + Sync>>,
+    /// Per-CF applied/flushed durability watermarks, fed by
+    /// `TiFlashObserver::post_exec_query` on every applied write and
+    /// consulted by `flush_lagging_cfs` to flush only CFs that fell behind
+    /// instead of a fixed whole-engine sweep.
+    pub persistence_listener: Arc<PersistenceListener>,
 }
 
 impl std::fmt::Debug for RocksEngine {
@@ -122,6 +131,7 @@ impl RocksEngine {
             pool_capacity: 0,
             pending_applies_count: Arc::new(AtomicUsize::new(0)),
             ffi_hub: None,
+            persistence_listener: Arc::new(PersistenceListener::new()),
         }
     }
 
@@ -132,6 +142,7 @@ impl RocksEngine {
             pool_capacity: 0,
             pending_applies_count: Arc::new(AtomicUsize::new(0)),
             ffi_hub: None,
+            persistence_listener: Arc::new(PersistenceListener::new()),
         }
     }
 
@@ -166,6 +177,32 @@ impl RocksEngine {
     pub fn support_multi_batch_write(&self) -> bool {
         self.rocks.support_multi_batch_write()
     }
+
+    /// Flushes only the CFs `self.persistence_listener` reports as sitting
+    /// on at least `min_lag` applied-but-unflushed raft log entries, instead
+    /// of a fixed whole-engine sweep. Driven by `EngineMetricsManager`'s
+    /// background flush loop (see `init_metrics_flusher` in
+    /// `proxy_server::run`). Calls straight through to the inner
+    /// `engine_rocks::RocksEngine`'s `MiscExt::flush_cfs` rather than going
+    /// through `mixed_engine::elementary::ElementaryEngine` — that trait
+    /// additionally needs a `MixedWriteBatch`/read path `RocksEngine`
+    /// doesn't otherwise use, so it isn't implemented here.
+    pub fn flush_lagging_cfs(&self, min_lag: u64) -> Result<Vec<String>> {
+        let lagging = self.persistence_listener.lagging_cfs(min_lag);
+        if lagging.is_empty() {
+            return Ok(lagging);
+        }
+        let cfs: Vec<&str> = lagging.iter().map(String::as_str).collect();
+        let applied: Vec<u64> = lagging
+            .iter()
+            .map(|cf| self.persistence_listener.applied_index(cf))
+            .collect();
+        self.rocks.flush_cfs(&cfs, true)?;
+        for (cf, applied) in lagging.iter().zip(applied) {
+            self.persistence_listener.on_flush_completed(cf, applied);
+        }
+        Ok(lagging)
+    }
 }
 
 impl KvEngine for RocksEngine {
@@ -125,6 +125,8 @@ mod cached_region_info_manager;
 pub use cached_region_info_manager::*;
 pub use rocksdb::DB;
 
+pub mod mixed_engine;
+
 pub fn get_env(
     key_manager: Option<std::sync::Arc<::encryption::DataKeyManager>>,
     limiter: Option<std::sync::Arc<::file_system::IoRateLimiter>>,
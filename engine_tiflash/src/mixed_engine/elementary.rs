@@ -1,9 +1,135 @@
 // Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
 use engine_rocks::RocksEngineIterator;
 use engine_traits::{IterOptions, ReadOptions, Result};
+use prometheus::IntGaugeVec;
+
+use super::{io_limiter::IoLimiter, write_batch::MixedWriteBatch, MixedDbVector};
+
+lazy_static::lazy_static! {
+    /// Per-CF durability watermark: the highest raft apply index reflected
+    /// in data a [`PersistenceListener`] has confirmed flushed to stable
+    /// storage. Recovery can use the `min` of these across CFs as its
+    /// replay floor instead of a single whole-engine checkpoint.
+    static ref CF_FLUSHED_APPLY_INDEX: IntGaugeVec = prometheus::register_int_gauge_vec!(
+        "tiflash_proxy_cf_flushed_apply_index",
+        "Highest raft apply index durably flushed for this column family",
+        &["cf"]
+    )
+    .unwrap();
+}
+
+/// Concrete [`ApplyTraceListener`] tracking, per CF, the highest applied raft
+/// log index fed in through [`PersistenceListener::record_applied`] (the
+/// raft apply path calls this as writes land, independent of flushing) and
+/// the highest confirmed flushed index observed at flush-completed.
+/// [`PersistenceListener::lagging_cfs`] compares the two to find CFs sitting
+/// on unflushed applied state older than some threshold, which
+/// [`ElementaryEngine::flush_lagging_cfs`] uses to flush only those CFs
+/// instead of every CF on a fixed timer.
+#[derive(Debug, Default)]
+pub struct PersistenceListener {
+    applied: Mutex<HashMap<String, u64>>,
+    flushed: Mutex<HashMap<String, u64>>,
+}
+
+impl PersistenceListener {
+    pub fn new() -> Self {
+        PersistenceListener::default()
+    }
+
+    /// Advances `cf`'s applied watermark to `apply_index` if it's newer than
+    /// what's on record. This is the *real* source of `applied`: it must be
+    /// called from the raft apply path as writes land, not from
+    /// [`ApplyTraceListener::on_flush_begin`] (which only fires once a flush
+    /// has already started and so never reports a CF that simply hasn't
+    /// flushed yet — the exact case `lagging_cfs` needs to catch).
+    pub fn record_applied(&self, cf: &str, apply_index: u64) {
+        Self::bump(&self.applied, cf, apply_index);
+    }
+
+    fn bump(watermark: &Mutex<HashMap<String, u64>>, cf: &str, apply_index: u64) {
+        let mut watermark = watermark.lock().unwrap();
+        match watermark.get_mut(cf) {
+            Some(current) => *current = (*current).max(apply_index),
+            None => {
+                watermark.insert(cf.to_owned(), apply_index);
+            }
+        }
+    }
+
+    /// `cf`'s current applied watermark, `0` if nothing has been recorded
+    /// for it yet.
+    pub fn applied_index(&self, cf: &str) -> u64 {
+        self.applied.lock().unwrap().get(cf).copied().unwrap_or(0)
+    }
+
+    /// CFs whose applied index has run at least `min_lag` ahead of their
+    /// last confirmed flush, in no particular order.
+    pub fn lagging_cfs(&self, min_lag: u64) -> Vec<String> {
+        let applied = self.applied.lock().unwrap();
+        let flushed = self.flushed.lock().unwrap();
+        applied
+            .iter()
+            .filter(|(cf, &applied_index)| {
+                applied_index.saturating_sub(*flushed.get(cf.as_str()).unwrap_or(&0)) >= min_lag
+            })
+            .map(|(cf, _)| cf.clone())
+            .collect()
+    }
+
+    /// Lowest confirmed-flushed apply index among the CFs that have flushed
+    /// at least once; a CF with applied writes but no flush yet is not
+    /// counted. `0` until at least one CF has flushed.
+    pub fn min_flushed_apply_index(&self) -> u64 {
+        self.flushed
+            .lock()
+            .unwrap()
+            .values()
+            .copied()
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+impl ApplyTraceListener for PersistenceListener {
+    fn on_flush_begin(&self, cf: &str, apply_index: u64) {
+        // `record_applied` is the primary feed for `applied` (see its doc
+        // comment); this is just a belt-and-braces max-merge in case a flush
+        // somehow observes an index newer than the last `record_applied`
+        // call, so `lagging_cfs` never sees a stale watermark mid-flush.
+        Self::bump(&self.applied, cf, apply_index);
+    }
+
+    fn on_flush_completed(&self, cf: &str, apply_index: u64) {
+        Self::bump(&self.flushed, cf, apply_index);
+        CF_FLUSHED_APPLY_INDEX
+            .with_label_values(&[cf])
+            .set(apply_index as i64);
+    }
+}
+
+/// Notified around each CF's memtable flush so a caller can maintain a
+/// per-CF "apply trace": the raft apply index that was durable as of that
+/// CF's last flush. Recovery can then replay the raft log starting from
+/// `min` of these indexes over all CFs instead of a single global
+/// checkpoint, so CFs that flush rarely (e.g. `CF_LOCK`) no longer force
+/// replay of everything another CF already persisted.
+pub trait ApplyTraceListener: Send + Sync + std::fmt::Debug {
+    /// Called right before `cf`'s memtable flush starts; `apply_index` is
+    /// the highest raft apply index reflected in the data about to flush.
+    fn on_flush_begin(&self, cf: &str, apply_index: u64);
+
+    /// Called once RocksDB reports `cf`'s flush as completed; the listener
+    /// should persist `(cf, apply_index)` as the new recovery floor.
+    fn on_flush_completed(&self, cf: &str, apply_index: u64);
+}
 
-use super::{write_batch::MixedWriteBatch, MixedDbVector};
 pub trait ElementaryEngine: std::fmt::Debug {
     fn put(&self, key: &[u8], value: &[u8]) -> Result<()>;
 
@@ -34,7 +160,78 @@ pub trait ElementaryEngine: std::fmt::Debug {
 
     fn iterator_opt(&self, cf: &str, opts: IterOptions) -> Result<RocksEngineIterator>;
 
+    /// Returns this engine's [`IoLimiter`]. `write_batch`/
+    /// `write_batch_with_cap`'s `MixedWriteBatch::write` charges it by the
+    /// batch's serialized byte size before applying the batch, so bulk
+    /// writes issued through `ElementaryEngine` can't starve foreground
+    /// flush/compaction for I/O bandwidth.
+    fn io_limiter(&self) -> Arc<IoLimiter>;
+
     fn write_batch(&self) -> MixedWriteBatch;
 
     fn write_batch_with_cap(&self, cap: usize) -> MixedWriteBatch;
+
+    /// Flushes `cfs`' memtables to stable storage, blocking the caller until
+    /// the flush finishes when `wait` is true. Each flush fires the
+    /// registered [`ApplyTraceListener`] (`on_flush_begin` synchronously
+    /// here, `on_flush_completed` from RocksDB's flush event-listener
+    /// callback once the SST is durable).
+    fn flush_cfs(&self, cfs: &[&str], wait: bool) -> Result<()>;
+
+    /// Registers a listener notified around every CF flush on this engine.
+    /// Only one listener is expected to be registered per engine instance;
+    /// registering again replaces the previous one.
+    fn register_apply_trace_listener(&self, listener: Arc<dyn ApplyTraceListener>);
+
+    /// Flushes only the CFs `listener` reports as sitting on at least
+    /// `min_lag` applied-but-unflushed raft log entries, instead of a fixed
+    /// whole-engine sweep. Intended for a periodic caller (e.g. an
+    /// `EngineMetricsManager`-style flush loop) that wants to advance
+    /// per-CF durability watermarks without flushing CFs that are already
+    /// caught up. Returns the CFs it asked to flush.
+    fn flush_lagging_cfs(
+        &self,
+        listener: &PersistenceListener,
+        min_lag: u64,
+    ) -> Result<Vec<String>> {
+        let lagging = listener.lagging_cfs(min_lag);
+        if !lagging.is_empty() {
+            let cfs: Vec<&str> = lagging.iter().map(String::as_str).collect();
+            self.flush_cfs(&cfs, false)?;
+        }
+        Ok(lagging)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lagging_cfs_catches_a_cf_that_never_flushed() {
+        let listener = PersistenceListener::new();
+
+        // CF_LOCK-style CF: writes keep landing, but it never flushes.
+        listener.record_applied("lock", 10);
+        listener.record_applied("lock", 25);
+        // CF_DEFAULT-style CF: applies and flushes stay in lockstep.
+        listener.on_flush_begin("default", 25);
+        listener.on_flush_completed("default", 25);
+
+        let lagging = listener.lagging_cfs(20);
+        assert_eq!(lagging, vec!["lock".to_owned()]);
+        assert!(listener.lagging_cfs(26).is_empty());
+    }
+
+    #[test]
+    fn min_flushed_apply_index_ignores_unflushed_cfs() {
+        let listener = PersistenceListener::new();
+
+        listener.record_applied("lock", 100);
+        assert_eq!(listener.min_flushed_apply_index(), 0);
+
+        listener.on_flush_begin("default", 5);
+        listener.on_flush_completed("default", 5);
+        assert_eq!(listener.min_flushed_apply_index(), 5);
+    }
 }
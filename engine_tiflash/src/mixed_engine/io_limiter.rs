@@ -0,0 +1,123 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A token-bucket rate limiter for the mixed-engine write path, so bulk
+//! proxy writes (snapshot ingest, in particular) can't starve foreground
+//! RocksDB flush/compaction for I/O bandwidth.
+
+use std::{
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Refill period for the token bucket. Short enough that a sustained writer
+/// sees smooth throughput rather than bursty stalls.
+const REFILL_PERIOD: Duration = Duration::from_millis(100);
+
+/// Priority class a write is charged under; `High` and `Low` draw from
+/// independent byte budgets so, e.g., snapshot-ingest traffic (charged as
+/// `Low`) can be capped separately from normal raft applies (`High`)
+/// without one starving the other.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IoPriority {
+    High,
+    Low,
+}
+
+struct Bucket {
+    bytes_per_sec: AtomicI64,
+    // Tokens currently available, plus the instant they were last refilled.
+    // Guarded together so a refill-then-consume sequence can't race another
+    // thread's refill.
+    state: Mutex<(i64, Instant)>,
+}
+
+impl Bucket {
+    fn new(bytes_per_sec: i64) -> Self {
+        Bucket {
+            bytes_per_sec: AtomicI64::new(bytes_per_sec),
+            state: Mutex::new((bytes_per_sec, Instant::now())),
+        }
+    }
+
+    fn set_bytes_per_sec(&self, bytes_per_sec: i64) {
+        self.bytes_per_sec.store(bytes_per_sec, Ordering::Relaxed);
+    }
+
+    /// Blocks until `bytes` worth of budget is available and charges it.
+    /// A non-positive `bytes_per_sec` disables throttling entirely.
+    fn request(&self, bytes: i64) {
+        loop {
+            let limit = self.bytes_per_sec.load(Ordering::Relaxed);
+            if limit <= 0 {
+                return;
+            }
+
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let (tokens, last_refill) = &mut *state;
+
+                let elapsed = last_refill.elapsed();
+                if elapsed >= REFILL_PERIOD {
+                    let periods = elapsed.as_secs_f64() / REFILL_PERIOD.as_secs_f64();
+                    let refill = (limit as f64 * REFILL_PERIOD.as_secs_f64()
+                        / Duration::from_secs(1).as_secs_f64()
+                        * periods) as i64;
+                    *tokens = (*tokens + refill).min(limit);
+                    *last_refill = Instant::now();
+                }
+
+                if *tokens >= bytes {
+                    *tokens -= bytes;
+                    None
+                } else {
+                    Some(REFILL_PERIOD)
+                }
+            };
+
+            match wait {
+                None => return,
+                Some(d) => thread::sleep(d),
+            }
+        }
+    }
+}
+
+/// A dynamically reconfigurable, priority-aware I/O rate limiter charged by
+/// serialized byte size before a write batch is applied.
+pub struct IoLimiter {
+    high: Bucket,
+    low: Bucket,
+}
+
+impl IoLimiter {
+    /// `bytes_per_sec <= 0` disables throttling for that bucket.
+    pub fn new(bytes_per_sec: i64) -> Self {
+        IoLimiter {
+            high: Bucket::new(bytes_per_sec),
+            low: Bucket::new(bytes_per_sec),
+        }
+    }
+
+    /// Blocks the caller until `bytes` of budget is available under
+    /// `priority`, then charges it. Called with the serialized size of a
+    /// write batch before it's committed to the underlying engine.
+    pub fn request(&self, bytes: i64, priority: IoPriority) {
+        match priority {
+            IoPriority::High => self.high.request(bytes),
+            IoPriority::Low => self.low.request(bytes),
+        }
+    }
+
+    /// Hot-reloads the rate limit for `priority`; takes effect on the next
+    /// refill tick, which also caps already-buffered tokens at the new limit.
+    pub fn set_bytes_per_second(&self, bytes_per_sec: i64, priority: IoPriority) {
+        match priority {
+            IoPriority::High => self.high.set_bytes_per_sec(bytes_per_sec),
+            IoPriority::Low => self.low.set_bytes_per_sec(bytes_per_sec),
+        }
+    }
+}
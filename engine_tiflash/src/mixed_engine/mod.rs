@@ -0,0 +1,18 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A narrower engine surface ([`elementary::ElementaryEngine`]) than the
+//! full [`engine_traits::KvEngine`], for code that only needs basic
+//! CRUD/scan/write-batch/flush access gated through a shared
+//! [`io_limiter::IoLimiter`] — e.g. [`gc_worker::SafePointGc`].
+
+pub mod elementary;
+pub mod gc_worker;
+pub mod io_limiter;
+mod write_batch;
+
+pub use write_batch::MixedWriteBatch;
+
+/// [`elementary::ElementaryEngine`]'s read-return type, aliased so its
+/// signatures don't leak `engine_rocks` into callers that only depend on
+/// the mixed-engine surface.
+pub type MixedDbVector = engine_rocks::RocksDbVector;
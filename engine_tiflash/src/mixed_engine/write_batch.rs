@@ -0,0 +1,51 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::sync::Arc;
+
+use engine_rocks::RocksWriteBatchVec;
+use engine_traits::{Mutable, Result, WriteBatch};
+
+use super::io_limiter::{IoLimiter, IoPriority};
+
+/// A [`RocksWriteBatchVec`] charged against an [`IoLimiter`] on `write()`,
+/// so bulk writes issued through [`super::elementary::ElementaryEngine`]
+/// (GC, in particular — see [`super::gc_worker`]) can't starve foreground
+/// RocksDB flush/compaction for I/O bandwidth. Always charged at
+/// [`IoPriority::Low`]: every `MixedWriteBatch` is, by construction, a
+/// write going through the `ElementaryEngine` side door rather than the
+/// normal raft-apply path.
+pub struct MixedWriteBatch {
+    inner: RocksWriteBatchVec,
+    limiter: Arc<IoLimiter>,
+}
+
+impl MixedWriteBatch {
+    pub(super) fn new(inner: RocksWriteBatchVec, limiter: Arc<IoLimiter>) -> Self {
+        MixedWriteBatch { inner, limiter }
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner.put(key, value)
+    }
+
+    pub fn put_cf(&mut self, cf: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner.put_cf(cf, key, value)
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.delete(key)
+    }
+
+    pub fn delete_cf(&mut self, cf: &str, key: &[u8]) -> Result<()> {
+        self.inner.delete_cf(cf, key)
+    }
+
+    /// Charges this batch's serialized byte size against the limiter, then
+    /// commits it to the engine.
+    pub fn write(&mut self) -> Result<()> {
+        self.limiter
+            .request(self.inner.data_size() as i64, IoPriority::Low);
+        self.inner.write()?;
+        Ok(())
+    }
+}
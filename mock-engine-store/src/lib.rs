@@ -3,6 +3,7 @@ use engine_store_ffi::interfaces::root::DB as ffi_interfaces;
 use engine_store_ffi::EngineStoreServerHelper;
 use engine_store_ffi::RaftStoreProxyFFIHelper;
 use engine_store_ffi::UnwrapExternCFunc;
+use engine_traits::KvEngine;
 use engine_traits::Peekable;
 use engine_traits::{Engines, SyncMutable};
 use engine_traits::{CF_DEFAULT, CF_LOCK, CF_WRITE};
@@ -14,7 +15,10 @@ use raftstore::engine_store_ffi;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 use tikv_util::{debug, error, info, warn};
+use txn_types::{Key, Lock, TimeStamp, Write, WriteType};
 
 type RegionId = u64;
 #[derive(Default, Clone)]
@@ -23,6 +27,36 @@ pub struct Region {
     peer: kvproto::metapb::Peer, // What peer is me?
     data: [BTreeMap<Vec<u8>, Vec<u8>>; 3],
     apply_state: kvproto::raft_serverpb::RaftApplyState,
+    /// Set between `PrepareFlashback` and `FinishFlashback`: while true,
+    /// `ffi_handle_ingest_sst`/`ffi_apply_pre_handled_snapshot` must reject
+    /// writes to this region instead of applying them on top of a frozen
+    /// range.
+    in_flashback: bool,
+}
+
+/// Whether `key` falls in `[start, end)`, treating an empty `end` as the
+/// unbounded top of the keyspace the way `metapb::Region` does.
+fn key_in_range(key: &[u8], start: &[u8], end: &[u8]) -> bool {
+    key >= start && (end.is_empty() || key < end)
+}
+
+/// A region's end key, ordered so an empty (unbounded) end key sorts after
+/// every concrete key — matching `metapb::Region`'s convention without
+/// needing a sentinel byte string that could collide with a real key.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum RangeEndKey {
+    Key(Vec<u8>),
+    Max,
+}
+
+impl RangeEndKey {
+    fn of(end_key: &[u8]) -> Self {
+        if end_key.is_empty() {
+            RangeEndKey::Max
+        } else {
+            RangeEndKey::Key(end_key.to_vec())
+        }
+    }
 }
 
 pub fn make_new_region(
@@ -52,10 +86,79 @@ fn set_new_region_peer(new_region: &mut Region, store_id: u64) {
     }
 }
 
+/// A clean bill of health from [`EngineStoreServer::mvcc_check_region`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MvccReport {
+    pub keys_scanned: usize,
+    pub writes_scanned: usize,
+    pub locks_scanned: usize,
+}
+
+/// A single MVCC invariant violation found by
+/// [`EngineStoreServer::mvcc_check_region`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum MvccInconsistency {
+    /// A non-short-value `Put` write has no matching `CF_DEFAULT` record at
+    /// its `start_ts`.
+    MissingDefault { key: Vec<u8>, start_ts: TimeStamp },
+    /// A `CF_DEFAULT` record isn't referenced by any `Put` write at the same
+    /// `start_ts`, so nothing will ever read it back.
+    OrphanedDefault { key: Vec<u8>, start_ts: TimeStamp },
+    /// A `CF_LOCK` entry survives a key whose latest write already committed
+    /// at or after the lock's `start_ts`; it should have been cleaned up.
+    DanglingLock { key: Vec<u8>, lock_start_ts: TimeStamp },
+    /// Two `Write` records for the same key have non-increasing commit
+    /// timestamps.
+    NonMonotonicCommitTs {
+        key: Vec<u8>,
+        earlier: TimeStamp,
+        later: TimeStamp,
+    },
+}
+
+/// Lock-free load counters for a single store, surfaced to PD through
+/// [`ffi_handle_compute_store_stats`]. Modeled after TiKV's `QueryStats`, but
+/// scoped down to the query kinds this mock actually drives.
+#[derive(Default)]
+pub struct StoreStatistics {
+    pub engine_bytes_written: AtomicU64,
+    pub engine_keys_written: AtomicU64,
+    pub engine_bytes_read: AtomicU64,
+    pub engine_keys_read: AtomicU64,
+    pub query_get: AtomicU64,
+    pub query_scan: AtomicU64,
+    pub query_put: AtomicU64,
+}
+
+impl StoreStatistics {
+    fn record_write(&self, key: &[u8], value: &[u8]) {
+        self.engine_bytes_written
+            .fetch_add((key.len() + value.len()) as u64, Ordering::Relaxed);
+        self.engine_keys_written.fetch_add(1, Ordering::Relaxed);
+        self.query_put.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_read(&self, bytes: u64, keys: u64, is_scan: bool) {
+        self.engine_bytes_read.fetch_add(bytes, Ordering::Relaxed);
+        self.engine_keys_read.fetch_add(keys, Ordering::Relaxed);
+        if is_scan {
+            self.query_scan.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.query_get.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
 pub struct EngineStoreServer {
     pub id: u64,
     pub engines: Option<Engines<RocksEngine, RocksEngine>>,
     pub kvstore: HashMap<RegionId, Box<Region>>,
+    /// Active regions ordered by end key, mirroring the region-collection
+    /// observer's key-ordered set so lookups don't need a linear scan of
+    /// `kvstore` and so a split/merge bug that leaves an overlap shows up
+    /// immediately via [`EngineStoreServer::check_range_invariant`].
+    region_range_index: BTreeMap<RangeEndKey, RegionId>,
+    pub stats: StoreStatistics,
 }
 
 impl EngineStoreServer {
@@ -65,6 +168,84 @@ impl EngineStoreServer {
             id,
             engines,
             kvstore: Default::default(),
+            region_range_index: Default::default(),
+            stats: Default::default(),
+        }
+    }
+
+    /// The region (if any) whose `[start_key, end_key)` contains `key`.
+    pub fn region_by_key(&self, key: &[u8]) -> Option<RegionId> {
+        let (_, &id) = self
+            .region_range_index
+            .range((
+                std::ops::Bound::Excluded(RangeEndKey::Key(key.to_vec())),
+                std::ops::Bound::Unbounded,
+            ))
+            .next()?;
+        let region = self.kvstore.get(&id)?;
+        if key_in_range(key, region.region.get_start_key(), region.region.get_end_key()) {
+            Some(id)
+        } else {
+            None
+        }
+    }
+
+    /// All regions whose range overlaps `[start, end)`; an empty `end`
+    /// means "to the top of the keyspace".
+    pub fn regions_in_range(&self, start: &[u8], end: &[u8]) -> Vec<RegionId> {
+        let lower = if start.is_empty() {
+            std::ops::Bound::Unbounded
+        } else {
+            std::ops::Bound::Excluded(RangeEndKey::Key(start.to_vec()))
+        };
+        let mut out = Vec::new();
+        for (_, &id) in self.region_range_index.range((lower, std::ops::Bound::Unbounded)) {
+            let region = match self.kvstore.get(&id) {
+                Some(r) => r,
+                None => continue,
+            };
+            if !end.is_empty() && region.region.get_start_key() >= end {
+                break;
+            }
+            out.push(id);
+        }
+        out
+    }
+
+    /// Re-keys (or inserts) `region_id`'s entry to match its current end
+    /// key, dropping any stale entry left over at its previous end key.
+    fn reindex_region(&mut self, region_id: RegionId) {
+        self.region_range_index.retain(|_, id| *id != region_id);
+        if let Some(region) = self.kvstore.get(&region_id) {
+            self.region_range_index
+                .insert(RangeEndKey::of(region.region.get_end_key()), region_id);
+        }
+    }
+
+    fn remove_from_index(&mut self, region_id: RegionId) {
+        self.region_range_index.retain(|_, id| *id != region_id);
+    }
+
+    /// Asserts the active regions do not overlap (gaps between them are
+    /// allowed, and only the last region may be unbounded), so an
+    /// epoch/range bug in a split or merge arm
+    /// trips a test immediately instead of silently corrupting later reads.
+    pub fn check_range_invariant(&self) {
+        let mut ranges: Vec<(&[u8], &[u8])> = self
+            .kvstore
+            .values()
+            .map(|r| (r.region.get_start_key(), r.region.get_end_key()))
+            .collect();
+        ranges.sort_by_key(|(start, _)| *start);
+        for w in ranges.windows(2) {
+            let (_, prev_end) = w[0];
+            let (next_start, _) = w[1];
+            assert!(
+                !prev_end.is_empty() && prev_end <= next_start,
+                "region range invariant violated: {:?} overlaps/gaps {:?}",
+                w[0],
+                w[1]
+            );
         }
     }
 }
@@ -94,6 +275,7 @@ fn hacked_is_real_no_region(region_id: u64, engine_store_server: &mut EngineStor
                 Some(engine_store_server.id),
             )),
         );
+        engine_store_server.reindex_region(region_id);
     }
 }
 
@@ -128,6 +310,18 @@ impl EngineStoreServerWrap {
                 if req.cmd_type == kvproto::raft_cmdpb::AdminCmdType::BatchSplit {
                     let regions = resp.get_splits().regions.as_ref();
 
+                    // Pull the source region's data out before its own range
+                    // narrows below, so every key in it can be redistributed
+                    // to whichever output region's `[start_key, end_key)` now
+                    // covers it.
+                    let mut old_data = std::mem::take(
+                        &mut engine_store_server
+                            .kvstore
+                            .get_mut(&region_id)
+                            .unwrap()
+                            .data,
+                    );
+
                     for i in 0..regions.len() {
                         let region_meta = regions.get(i).unwrap();
                         if region_meta.id == region_id {
@@ -159,14 +353,38 @@ impl EngineStoreServerWrap {
                                 .apply_state
                                 .set_applied_index(raftstore::store::RAFT_INIT_LOG_INDEX);
 
-                            // No need to split data because all KV are stored in the same RocksDB
-
                             // We can't assert `region_meta.id` is brand new here
                             engine_store_server
                                 .kvstore
                                 .insert(region_meta.id, Box::new(new_region));
                         }
                     }
+
+                    // Now that every output region exists with its final
+                    // range, partition the source region's old data across
+                    // them by key so no key leaks across a split point.
+                    for cf in 0..3 {
+                        for (k, v) in std::mem::take(&mut old_data[cf]) {
+                            let dst_id = regions
+                                .iter()
+                                .find(|m| key_in_range(&k, m.get_start_key(), m.get_end_key()))
+                                .map(|m| m.id)
+                                .expect("split key not covered by any output region");
+                            engine_store_server
+                                .kvstore
+                                .get_mut(&dst_id)
+                                .unwrap()
+                                .data[cf]
+                                .insert(k, v);
+                        }
+                    }
+
+                    // Every output region now has its final range: rebuild
+                    // the index entries for the (narrowed) source and each
+                    // newly created region.
+                    for region_meta in regions.iter() {
+                        engine_store_server.reindex_region(region_meta.id);
+                    }
                 } else if req.cmd_type == kvproto::raft_cmdpb::AdminCmdType::PrepareMerge {
                     let tikv_region = resp.get_split().get_left();
 
@@ -211,7 +429,6 @@ impl EngineStoreServerWrap {
                             new_version
                         );
 
-                        // No need to merge data
                         let source_at_left = if source_region.get_start_key().is_empty() {
                             true
                         } else if target_region_meta.get_start_key().is_empty() {
@@ -242,10 +459,22 @@ impl EngineStoreServerWrap {
                             target_region.apply_state.set_applied_index(header.index);
                         }
                     }
+                    engine_store_server.reindex_region(region_id);
                     {
-                        engine_store_server
+                        let source_id = req.get_commit_merge().get_source().get_id();
+                        let source_data = engine_store_server
                             .kvstore
-                            .remove(&req.get_commit_merge().get_source().get_id());
+                            .get_mut(&source_id)
+                            .map(|r| std::mem::take(&mut r.data));
+                        if let Some(source_data) = source_data {
+                            let target_region =
+                                engine_store_server.kvstore.get_mut(&region_id).unwrap();
+                            for (cf, map) in source_data.into_iter().enumerate() {
+                                target_region.data[cf].extend(map);
+                            }
+                        }
+                        engine_store_server.kvstore.remove(&source_id);
+                        engine_store_server.remove_from_index(source_id);
                     }
                 } else if req.cmd_type == kvproto::raft_cmdpb::AdminCmdType::RollbackMerge {
                     let region = (engine_store_server.kvstore.get_mut(&region_id).unwrap());
@@ -274,6 +503,7 @@ impl EngineStoreServerWrap {
                     }
                     if do_remove {
                         let removed = engine_store_server.kvstore.remove(&region_id);
+                        engine_store_server.remove_from_index(region_id);
                         // We need to also remove apply state, thus we need to know peer_id
                         debug!(
                             "Remove region {:?} peer_id {} at node {}",
@@ -281,7 +511,26 @@ impl EngineStoreServerWrap {
                             old_peer_id,
                             node_id
                         );
+                    } else {
+                        engine_store_server.reindex_region(region_id);
+                    }
+                } else if req.cmd_type == kvproto::raft_cmdpb::AdminCmdType::PrepareFlashback {
+                    let region = engine_store_server.kvstore.get_mut(&region_id).unwrap();
+                    region.in_flashback = true;
+                    region.apply_state.set_applied_index(header.index);
+                    // Entering flashback freezes the region: the in-memory
+                    // lock table must be cleared along with its persisted
+                    // copy, so nothing left behind by an in-flight
+                    // transaction survives the rollback.
+                    let lock_cf = cf_index_for(CF_LOCK);
+                    for key in std::mem::take(&mut region.data[lock_cf]).into_keys() {
+                        let tikv_key = keys::data_key(&key);
+                        kv.delete_cf(CF_LOCK, &tikv_key);
                     }
+                } else if req.cmd_type == kvproto::raft_cmdpb::AdminCmdType::FinishFlashback {
+                    let region = engine_store_server.kvstore.get_mut(&region_id).unwrap();
+                    region.in_flashback = false;
+                    region.apply_state.set_applied_index(header.index);
                 } else if [
                     kvproto::raft_cmdpb::AdminCmdType::CompactLog,
                     kvproto::raft_cmdpb::AdminCmdType::ComputeHash,
@@ -302,7 +551,9 @@ impl EngineStoreServerWrap {
         }
         match (*self.engine_store_server).kvstore.entry(region_id) {
             std::collections::hash_map::Entry::Occupied(mut o) => {
-                do_handle_admin_raft_cmd(o.get_mut(), &mut (*self.engine_store_server))
+                let res = do_handle_admin_raft_cmd(o.get_mut(), &mut (*self.engine_store_server));
+                (*self.engine_store_server).check_range_invariant();
+                res
             }
             std::collections::hash_map::Entry::Vacant(v) => {
                 warn!(
@@ -358,10 +609,12 @@ impl EngineStoreServerWrap {
                             &val.to_slice().to_vec(),
                         )
                         .map_err(std::convert::identity);
+                        data.insert(key.to_slice().to_vec(), val.to_slice().to_vec());
                     }
                     engine_store_ffi::WriteCmdType::Del => {
                         let tikv_key = keys::data_key(key.to_slice());
                         kv.delete_cf(cf_to_name(cf.to_owned().into()), &tikv_key);
+                        data.remove(key.to_slice());
                     }
                 }
             }
@@ -415,8 +668,9 @@ pub fn gen_engine_store_server_helper(
         fn_handle_get_engine_store_server_status: None,
         fn_pre_handle_snapshot: Some(ffi_pre_handle_snapshot),
         fn_apply_pre_handled_snapshot: Some(ffi_apply_pre_handled_snapshot),
-        fn_handle_http_request: None,
-        fn_check_http_uri_available: None,
+        fn_handle_http_request: Some(ffi_handle_http_request),
+        fn_check_http_uri_available: Some(ffi_check_http_uri_available),
+        fn_handle_coprocessor_request: Some(ffi_handle_coprocessor_request),
         fn_gc_raw_cpp_ptr: Some(ffi_gc_raw_cpp_ptr),
         fn_insert_batch_read_index_resp: None,
         fn_set_server_info_resp: None,
@@ -502,7 +756,13 @@ extern "C" fn ffi_gc_raw_cpp_ptr(
             Box::<Vec<u8>>::from_raw(ptr as *mut _);
         },
         RawCppPtrTypeImpl::PreHandledSnapshotWithBlock => unsafe {
-            Box::<PrehandledSnapshot>::from_raw(ptr as *mut _);
+            let prehandled = Box::<PrehandledSnapshot>::from_raw(ptr as *mut _);
+            // Discarded without ever being applied (e.g. the target region
+            // was destroyed mid-prehandle): flip the cancellation flag so
+            // any worker still scanning stops at its next key, then let the
+            // join handles drop (detaching them) instead of blocking this
+            // call on a decode whose result nobody will read.
+            prehandled.cancelled.store(true, Ordering::Relaxed);
         },
     }
 }
@@ -521,6 +781,7 @@ unsafe extern "C" fn ffi_handle_destroy(
 ) {
     let store = into_engine_store_server_wrap(arg1);
     (*store.engine_store_server).kvstore.remove(&arg2);
+    (*store.engine_store_server).remove_from_index(arg2);
 }
 
 type TiFlashRaftProxyHelper = RaftStoreProxyFFIHelper;
@@ -589,8 +850,29 @@ impl<'a> SSTReader<'a> {
     }
 }
 
+/// A raw pointer wrapper that's `Send` regardless of what it points to, for
+/// handing the FFI views/helper a multi-SST prehandle decodes from off to
+/// worker threads. Sound because those views are backed by the proxy's own
+/// long-lived SST file objects, not anything tied to this call's stack.
+#[derive(Clone, Copy)]
+struct SendPtr<T>(*const T);
+unsafe impl<T> Send for SendPtr<T> {}
+
+/// One `SSTView`'s decode worker inside a multi-file snapshot prehandle.
+struct PrehandleWorker {
+    cf_index: usize,
+    handle: std::thread::JoinHandle<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
 struct PrehandledSnapshot {
     pub region: std::option::Option<Region>,
+    /// Outstanding per-SST decode workers, joined and merged into `region`
+    /// by `ffi_apply_pre_handled_snapshot`. Doubles as this prehandle's
+    /// abort handle: if it's discarded instead (`ffi_gc_raw_cpp_ptr`),
+    /// `cancelled` is flipped so any worker still scanning stops at its next
+    /// key and the handles are simply dropped (detached) rather than joined.
+    workers: Vec<PrehandleWorker>,
+    cancelled: Arc<AtomicBool>,
 }
 
 unsafe extern "C" fn ffi_pre_handle_snapshot(
@@ -603,8 +885,7 @@ unsafe extern "C" fn ffi_pre_handle_snapshot(
 ) -> ffi_interfaces::RawCppPtr {
     let store = into_engine_store_server_wrap(arg1);
     let node_id = (*store.engine_store_server).id;
-    let proxy_helper = &mut *(store.maybe_proxy_helper.unwrap());
-    let kvstore = &mut (*store.engine_store_server).kvstore;
+    let proxy_helper_ptr = SendPtr(store.maybe_proxy_helper.unwrap() as *const TiFlashRaftProxyHelper);
 
     let mut region_meta = kvproto::metapb::Region::default();
     assert_ne!(region_buff.data, std::ptr::null());
@@ -614,39 +895,74 @@ unsafe extern "C" fn ffi_pre_handle_snapshot(
         .unwrap();
 
     let mut region = make_new_region(Some(region_meta), Some(node_id));
+    region.apply_state.mut_truncated_state().set_index(index);
+    region.apply_state.mut_truncated_state().set_term(term);
+    region.apply_state.set_applied_index(index);
 
     debug!(
         "prehandle snapshot with len {} node_id {} peer_id {}",
         snaps.len, node_id, peer_id
     );
-    for i in 0..snaps.len {
-        let mut snapshot = snaps.views.add(i as usize);
-        let mut sst_reader =
-            SSTReader::new(proxy_helper, &*(snapshot as *mut ffi_interfaces::SSTView));
-
-        {
-            region.apply_state.mut_truncated_state().set_index(index);
-            region.apply_state.mut_truncated_state().set_term(term);
-            {
-                region.apply_state.set_applied_index(index);
-            }
-        }
-
-        while sst_reader.remained() {
-            let key = sst_reader.key();
-            let value = sst_reader.value();
-
-            let cf_index = (*snapshot).type_ as usize;
-            let data = &mut region.data[cf_index];
-            let _ = data.insert(key.to_slice().to_vec(), value.to_slice().to_vec());
 
-            sst_reader.next();
-        }
+    let region_id = region.region.id;
+    let start_key = region.region.get_start_key().to_vec();
+    let end_key = region.region.get_end_key().to_vec();
+    let cancelled = Arc::new(AtomicBool::new(false));
+
+    // Fan each SST file out to its own worker thread so a snapshot made of
+    // many files decodes in parallel instead of serializing on the FFI
+    // caller; `ffi_apply_pre_handled_snapshot` joins and merges the results,
+    // so prehandling genuinely proceeds in the background between this call
+    // returning and that one being made.
+    let mut workers = Vec::with_capacity(snaps.len as usize);
+    for i in 0..snaps.len {
+        let view_ptr = SendPtr(snaps.views.add(i as usize) as *const ffi_interfaces::SSTView);
+        let cf_index = (*view_ptr.0).type_ as usize;
+        let cancelled = cancelled.clone();
+        let start_key = start_key.clone();
+        let end_key = end_key.clone();
+        let handle = std::thread::Builder::new()
+            .name(format!("snap-prehandle-{}", region_id))
+            .spawn(move || {
+                let proxy_helper = &*proxy_helper_ptr.0;
+                let view = &*view_ptr.0;
+                let mut sst_reader = SSTReader::new(proxy_helper, view);
+                let mut batch = BTreeMap::new();
+                while sst_reader.remained() {
+                    if cancelled.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let key = sst_reader.key();
+                    let value = sst_reader.value();
+                    let key_slice = key.to_slice();
+
+                    // The source SST may be stale relative to the region
+                    // meta we were just handed (e.g. a split that raced the
+                    // snapshot); never silently adopt a key outside the
+                    // range we're prehandling for.
+                    assert!(
+                        key_in_range(key_slice, &start_key, &end_key),
+                        "snapshot key {:?} for region {} falls outside [{:?}, {:?})",
+                        key_slice,
+                        region_id,
+                        start_key,
+                        end_key,
+                    );
+
+                    batch.insert(key_slice.to_vec(), value.to_slice().to_vec());
+                    sst_reader.next();
+                }
+                batch
+            })
+            .expect("spawn snapshot prehandle worker");
+        workers.push(PrehandleWorker { cf_index, handle });
     }
 
     ffi_interfaces::RawCppPtr {
         ptr: Box::into_raw(Box::new(PrehandledSnapshot {
             region: Some(region),
+            workers,
+            cancelled,
         })) as *const Region as ffi_interfaces::RawVoidPtr,
         type_: RawCppPtrTypeImpl::PreHandledSnapshotWithBlock.into(),
     }
@@ -660,6 +976,132 @@ pub fn cf_to_name(cf: ffi_interfaces::ColumnFamilyType) -> &'static str {
     }
 }
 
+/// `Region::data`'s index for `name`, the inverse of [`cf_to_name`].
+fn cf_index_for(name: &str) -> usize {
+    (0..3usize)
+        .find(|&i| cf_to_name(i.into()) == name)
+        .expect("cf name must be one of default/lock/write")
+}
+
+impl EngineStoreServer {
+    /// Decodes the `CF_WRITE`/`CF_LOCK`/`CF_DEFAULT` entries held in memory
+    /// for `region_id` and checks the same MVCC invariants TiKV's own
+    /// `debug mvcc` scan checks, so a bug in apply ordering across this
+    /// mock's write/admin paths surfaces as a structured report instead of
+    /// silently corrupting state that only the applied index is tracking.
+    pub fn mvcc_check_region(
+        &self,
+        region_id: RegionId,
+    ) -> Result<MvccReport, Vec<MvccInconsistency>> {
+        let region = match self.kvstore.get(&region_id) {
+            Some(region) => region,
+            None => return Ok(MvccReport::default()),
+        };
+        let (footprint_bytes, footprint_keys) = region_read_footprint(region);
+        self.stats
+            .record_read(footprint_bytes, footprint_keys, true);
+
+        let write_cf = cf_index_for(CF_WRITE);
+        let lock_cf = cf_index_for(CF_LOCK);
+        let default_cf = cf_index_for(CF_DEFAULT);
+
+        // Key here is the encoded user key with its ts suffix stripped off,
+        // i.e. `Key::from_raw(k).into_encoded()` - the same form `CF_LOCK`
+        // keys are stored in, so the two can be compared directly.
+        let mut writes_by_key: BTreeMap<Vec<u8>, Vec<(TimeStamp, Write)>> = BTreeMap::new();
+        let mut writes_scanned = 0;
+        for (encoded_key, value) in &region.data[write_cf] {
+            let (encoded_key, commit_ts) = match Key::split_on_ts_for(encoded_key) {
+                Ok((k, ts)) => (k.to_vec(), ts),
+                Err(_) => continue,
+            };
+            let write = match Write::parse(value) {
+                Ok(w) => w,
+                Err(_) => continue,
+            };
+            writes_scanned += 1;
+            writes_by_key
+                .entry(encoded_key)
+                .or_default()
+                .push((commit_ts, write));
+        }
+
+        let mut inconsistencies = Vec::new();
+        for (key, writes) in writes_by_key.iter_mut() {
+            writes.sort_by_key(|(ts, _)| *ts);
+            for pair in writes.windows(2) {
+                let (earlier, _) = &pair[0];
+                let (later, _) = &pair[1];
+                if later <= earlier {
+                    inconsistencies.push(MvccInconsistency::NonMonotonicCommitTs {
+                        key: key.clone(),
+                        earlier: *earlier,
+                        later: *later,
+                    });
+                }
+            }
+            for (_, write) in writes.iter() {
+                if write.write_type == WriteType::Put && write.short_value.is_none() {
+                    let default_key = Key::from_encoded_slice(key)
+                        .append_ts(write.start_ts)
+                        .into_encoded();
+                    if !region.data[default_cf].contains_key(&default_key) {
+                        inconsistencies.push(MvccInconsistency::MissingDefault {
+                            key: key.clone(),
+                            start_ts: write.start_ts,
+                        });
+                    }
+                }
+            }
+        }
+
+        for encoded_key in region.data[default_cf].keys() {
+            let (key, start_ts) = match Key::split_on_ts_for(encoded_key) {
+                Ok((k, ts)) => (k.to_vec(), ts),
+                Err(_) => continue,
+            };
+            let referenced = writes_by_key.get(&key).map_or(false, |writes| {
+                writes
+                    .iter()
+                    .any(|(_, w)| w.write_type == WriteType::Put && w.start_ts == start_ts)
+            });
+            if !referenced {
+                inconsistencies.push(MvccInconsistency::OrphanedDefault { key, start_ts });
+            }
+        }
+
+        let mut locks_scanned = 0;
+        for (key, value) in &region.data[lock_cf] {
+            let lock = match Lock::parse(value) {
+                Ok(l) => l,
+                Err(_) => continue,
+            };
+            locks_scanned += 1;
+            let latest_commit = writes_by_key
+                .get(key)
+                .and_then(|writes| writes.iter().map(|(ts, _)| *ts).max());
+            if let Some(latest_commit) = latest_commit {
+                if latest_commit >= lock.ts {
+                    inconsistencies.push(MvccInconsistency::DanglingLock {
+                        key: key.clone(),
+                        lock_start_ts: lock.ts,
+                    });
+                }
+            }
+        }
+
+        if inconsistencies.is_empty() {
+            Ok(MvccReport {
+                keys_scanned: writes_by_key.len(),
+                writes_scanned,
+                locks_scanned,
+            })
+        } else {
+            Err(inconsistencies)
+        }
+    }
+}
+
 unsafe extern "C" fn ffi_apply_pre_handled_snapshot(
     arg1: *mut ffi_interfaces::EngineStoreServerWrap,
     arg2: ffi_interfaces::RawVoidPtr,
@@ -671,10 +1113,54 @@ unsafe extern "C" fn ffi_apply_pre_handled_snapshot(
 
     let req_id = req.region.as_ref().unwrap().region.id;
 
+    {
+        let kv = &mut (*store.engine_store_server).engines.as_mut().unwrap().kv;
+        let snapshot_index = req.region.as_ref().unwrap().apply_state.get_applied_index();
+        let persisted_index = kv
+            .get_msg_cf::<RaftApplyState>(engine_traits::CF_RAFT, &keys::apply_state_key(req_id))
+            .unwrap_or(None)
+            .map_or(0, |s| s.get_applied_index());
+        if persisted_index >= snapshot_index {
+            debug!("apply_pre_handled_snapshot meet old index for region {}", req_id);
+            return;
+        }
+    }
+
+    // This is the point prehandling actually needs to have finished by: join
+    // every per-SST worker spawned in `ffi_pre_handle_snapshot` and fold
+    // their batches into the region before it's published into `kvstore`.
+    {
+        let region = req.region.as_mut().unwrap();
+        for worker in req.workers.drain(..) {
+            let batch = worker
+                .handle
+                .join()
+                .expect("snapshot prehandle worker panicked");
+            for (k, v) in batch {
+                assert!(
+                    region.data[worker.cf_index].insert(k.clone(), v).is_none(),
+                    "duplicate key {:?} across snapshot SST files for cf {} in region {}",
+                    k,
+                    worker.cf_index,
+                    req_id,
+                );
+            }
+        }
+    }
+
+    // The region built at prehandle time never has `in_flashback` set; carry
+    // the flag over from the region this snapshot replaces.
+    let in_flashback = (*store.engine_store_server)
+        .kvstore
+        .get(&req_id)
+        .map_or(false, |r| r.in_flashback);
+    req.region.as_mut().unwrap().in_flashback = in_flashback;
+
     // Though we do not write to kvstore in memory now, we still need to maintain regions.
     &(*store.engine_store_server)
         .kvstore
         .insert(req_id, Box::new(req.region.take().unwrap()));
+    (*store.engine_store_server).reindex_region(req_id);
 
     let region = (*store.engine_store_server)
         .kvstore
@@ -686,6 +1172,20 @@ unsafe extern "C" fn ffi_apply_pre_handled_snapshot(
         req_id, node_id
     );
 
+    if region.in_flashback {
+        // The region was put into flashback while this snapshot was being
+        // prehandled in the background; drop the batch instead of
+        // persisting data on top of a range that's supposed to be frozen.
+        warn!(
+            "discard pre-handled snapshot for region {} in flashback at node {}",
+            req_id, node_id
+        );
+        for cf in 0..3 {
+            region.data[cf].clear();
+        }
+        return;
+    }
+
     let kv = &mut (*store.engine_store_server).engines.as_mut().unwrap().kv;
     for cf in 0..3 {
         for (k, v) in std::mem::take(region.data.as_mut().get_mut(cf).unwrap()).into_iter() {
@@ -693,8 +1193,21 @@ unsafe extern "C" fn ffi_apply_pre_handled_snapshot(
             let cf_name = cf_to_name(cf.into());
             kv.put_cf(cf_name, &tikv_key, &v)
                 .map_err(std::convert::identity);
+            (*store.engine_store_server).stats.record_write(&k, &v);
         }
     }
+
+    let applied_index = region.apply_state.get_applied_index();
+    let applied_term = region.apply_state.get_truncated_state().get_term();
+    persist_apply_state(
+        region,
+        &mut (*store.engine_store_server).engines.as_mut().unwrap().kv,
+        req_id,
+        true,
+        true,
+        applied_index,
+        applied_term,
+    );
 }
 
 unsafe extern "C" fn ffi_handle_ingest_sst(
@@ -711,6 +1224,20 @@ unsafe extern "C" fn ffi_handle_ingest_sst(
     let kv = &mut (*store.engine_store_server).engines.as_mut().unwrap().kv;
     let region = kvstore.get_mut(&region_id).unwrap();
 
+    if region.apply_state.get_applied_index() >= header.index {
+        debug!("handle_ingest_sst meet old index");
+        return ffi_interfaces::EngineStoreApplyRes::None;
+    }
+
+    if region.in_flashback {
+        warn!(
+            "reject sst ingest for region {} in flashback at node {}",
+            region_id,
+            (*store.engine_store_server).id
+        );
+        return ffi_interfaces::EngineStoreApplyRes::None;
+    }
+
     for i in 0..snaps.len {
         let snapshot = snaps.views.add(i as usize);
         let mut sst_reader =
@@ -723,10 +1250,28 @@ unsafe extern "C" fn ffi_handle_ingest_sst(
             let cf_name = cf_to_name((*snapshot).type_);
             kv.put_cf(cf_name, &tikv_key, &value.to_slice())
                 .map_err(std::convert::identity);
+            (*store.engine_store_server)
+                .stats
+                .record_write(key.to_slice(), value.to_slice());
             sst_reader.next();
         }
     }
 
+    let region = (*store.engine_store_server)
+        .kvstore
+        .get_mut(&region_id)
+        .unwrap();
+    region.apply_state.set_applied_index(header.index);
+    persist_apply_state(
+        region,
+        &mut (*store.engine_store_server).engines.as_mut().unwrap().kv,
+        region_id,
+        true,
+        false,
+        header.index,
+        header.term,
+    );
+
     // Since tics#1811, Br/Lightning will always ingest both WRITE and DEFAULT, so we can always persist, rather than wait.
     ffi_interfaces::EngineStoreApplyRes::Persist
 }
@@ -787,19 +1332,246 @@ fn persist_apply_state(
 unsafe extern "C" fn ffi_handle_compute_store_stats(
     arg1: *mut ffi_interfaces::EngineStoreServerWrap,
 ) -> ffi_interfaces::StoreStats {
-    ffi_interfaces::StoreStats {
-        fs_stats: ffi_interfaces::FsStats {
+    let store = into_engine_store_server_wrap(arg1);
+    let server = &*store.engine_store_server;
+
+    let fs_stats = server
+        .engines
+        .as_ref()
+        .and_then(|engines| fs2::statvfs(engines.kv.get_engine_path()).ok())
+        .map(|disk_stats| ffi_interfaces::FsStats {
+            capacity_size: disk_stats.total_space(),
+            used_size: disk_stats.total_space() - disk_stats.available_space(),
+            avail_size: disk_stats.available_space(),
+            ok: 1,
+        })
+        .unwrap_or(ffi_interfaces::FsStats {
             used_size: 0,
             avail_size: 0,
             capacity_size: 0,
-            ok: 1,
+            ok: 0,
+        });
+
+    ffi_interfaces::StoreStats {
+        fs_stats,
+        engine_bytes_written: server.stats.engine_bytes_written.load(Ordering::Relaxed),
+        engine_keys_written: server.stats.engine_keys_written.load(Ordering::Relaxed),
+        engine_bytes_read: server.stats.engine_bytes_read.load(Ordering::Relaxed),
+        engine_keys_read: server.stats.engine_keys_read.load(Ordering::Relaxed),
+    }
+}
+
+/// Path prefixes served by [`ffi_handle_http_request`], mirroring a handful
+/// of TiKV's own debug endpoints so the same `curl` habits work against the
+/// mock store.
+const HTTP_DEBUG_PATH_PREFIXES: &[&str] = &["/store", "/regions", "/region/"];
+
+unsafe extern "C" fn ffi_check_http_uri_available(
+    path: ffi_interfaces::BaseBuffView,
+) -> u8 {
+    let path = String::from_utf8_lossy(path.to_slice());
+    HTTP_DEBUG_PATH_PREFIXES
+        .iter()
+        .any(|prefix| path.as_ref() == *prefix || path.starts_with(prefix)) as u8
+}
+
+/// Total bytes/keys held across a region's in-memory CFs, used to charge
+/// `StoreStatistics`'s read counters for debug reads that scan them.
+fn region_read_footprint(region: &Region) -> (u64, u64) {
+    region.data.iter().fold((0u64, 0u64), |(bytes, keys), cf| {
+        let cf_bytes: u64 = cf.iter().map(|(k, v)| (k.len() + v.len()) as u64).sum();
+        (bytes + cf_bytes, keys + cf.len() as u64)
+    })
+}
+
+fn http_region_json(region_id: RegionId, region: &Region) -> serde_json::Value {
+    serde_json::json!({
+        "region_id": region_id,
+        "start_key": format!("{:?}", region.region.get_start_key()),
+        "end_key": format!("{:?}", region.region.get_end_key()),
+        "region_epoch": {
+            "conf_ver": region.region.get_region_epoch().get_conf_ver(),
+            "version": region.region.get_region_epoch().get_version(),
         },
-        engine_bytes_written: 0,
-        engine_keys_written: 0,
-        engine_bytes_read: 0,
-        engine_keys_read: 0,
+        "applied_index": region.apply_state.get_applied_index(),
+        "truncated_index": region.apply_state.get_truncated_state().get_index(),
+        "truncated_term": region.apply_state.get_truncated_state().get_term(),
+        "cf_key_counts": [
+            region.data[0].len(),
+            region.data[1].len(),
+            region.data[2].len(),
+        ],
+    })
+}
+
+unsafe extern "C" fn ffi_handle_http_request(
+    arg1: *mut ffi_interfaces::EngineStoreServerWrap,
+    path: ffi_interfaces::BaseBuffView,
+    _query: ffi_interfaces::BaseBuffView,
+    _body: ffi_interfaces::BaseBuffView,
+) -> ffi_interfaces::HttpRequestRes {
+    let store = into_engine_store_server_wrap(arg1);
+    let server = &*store.engine_store_server;
+    let path = String::from_utf8_lossy(path.to_slice()).into_owned();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    let body = match segments.as_slice() {
+        ["store"] => Some(
+            serde_json::json!({
+                "store_id": server.id,
+                "region_count": server.kvstore.len(),
+            })
+            .to_string(),
+        ),
+        ["regions"] => {
+            let mut bytes = 0u64;
+            let mut keys = 0u64;
+            let regions: Vec<_> = server
+                .kvstore
+                .iter()
+                .map(|(id, region)| {
+                    let (region_bytes, region_keys) = region_read_footprint(region);
+                    bytes += region_bytes;
+                    keys += region_keys;
+                    http_region_json(*id, region)
+                })
+                .collect();
+            server.stats.record_read(bytes, keys, true);
+            Some(serde_json::json!({ "regions": regions }).to_string())
+        }
+        ["region", id] => id
+            .parse::<RegionId>()
+            .ok()
+            .and_then(|id| server.kvstore.get(&id).map(|region| (id, region)))
+            .map(|(id, region)| {
+                let (bytes, keys) = region_read_footprint(region);
+                server.stats.record_read(bytes, keys, false);
+                http_region_json(id, region).to_string()
+            }),
+        _ => None,
+    };
+
+    match body {
+        Some(body) => ffi_interfaces::HttpRequestRes {
+            status: ffi_interfaces::HttpRequestStatus::Ok,
+            res: ffi_gen_cpp_string(ffi_interfaces::BaseBuffView {
+                data: body.as_ptr() as *const _,
+                len: body.len() as u64,
+            }),
+        },
+        None => ffi_interfaces::HttpRequestRes {
+            status: ffi_interfaces::HttpRequestStatus::ErrorParam,
+            res: ffi_interfaces::RawCppPtr::default(),
+        },
+    }
+}
+
+/// Bounded read access into a single region's CF data: the surface
+/// coprocessor-v2 style plugins are handed instead of a raw handle onto
+/// `kvstore`/`RocksEngine`. Every key passed to `raw_get`/`raw_scan` is
+/// checked against the region's own `[start_key, end_key)` first, so a
+/// plugin dispatched for one region can't read data belonging to another.
+trait RawStorage {
+    fn raw_get(&self, cf: &str, key: &[u8]) -> Option<Vec<u8>>;
+    fn raw_scan(&self, cf: &str, start: &[u8], end: &[u8], limit: u32) -> Vec<(Vec<u8>, Vec<u8>)>;
+}
+
+struct RegionRawStorage<'a> {
+    region: &'a Region,
+}
+
+impl<'a> RawStorage for RegionRawStorage<'a> {
+    fn raw_get(&self, cf: &str, key: &[u8]) -> Option<Vec<u8>> {
+        if !key_in_range(
+            key,
+            self.region.region.get_start_key(),
+            self.region.region.get_end_key(),
+        ) {
+            return None;
+        }
+        self.region.data[cf_index_for(cf)].get(key).cloned()
+    }
+
+    fn raw_scan(&self, cf: &str, start: &[u8], end: &[u8], limit: u32) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let region_start = self.region.region.get_start_key();
+        let region_end = self.region.region.get_end_key();
+        let lower = if start > region_start {
+            start.to_vec()
+        } else {
+            region_start.to_vec()
+        };
+        self.region.data[cf_index_for(cf)]
+            .range(lower..)
+            .take_while(|(k, _)| {
+                key_in_range(k, &[], region_end) && (end.is_empty() || k.as_slice() < end)
+            })
+            .take(limit as usize)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
     }
 }
 
+/// Runs one coprocessor op (`{"op": "get", ...}` or `{"op": "scan", ...}`)
+/// against `storage`, mirroring the loose JSON request/response convention
+/// already used by the HTTP debug endpoints in this file.
+fn handle_coprocessor_op(storage: &dyn RawStorage, req: &serde_json::Value) -> serde_json::Value {
+    match req.get("op").and_then(|v| v.as_str()) {
+        Some("get") => {
+            let cf = req.get("cf").and_then(|v| v.as_str()).unwrap_or(CF_DEFAULT);
+            let key = req.get("key").and_then(|v| v.as_str()).unwrap_or_default();
+            let value = storage.raw_get(cf, key.as_bytes());
+            serde_json::json!({ "value": value.map(|v| String::from_utf8_lossy(&v).into_owned()) })
+        }
+        Some("scan") => {
+            let cf = req.get("cf").and_then(|v| v.as_str()).unwrap_or(CF_DEFAULT);
+            let start = req.get("start").and_then(|v| v.as_str()).unwrap_or_default();
+            let end = req.get("end").and_then(|v| v.as_str()).unwrap_or_default();
+            let limit = req.get("limit").and_then(|v| v.as_u64()).unwrap_or(256) as u32;
+            let rows = storage.raw_scan(cf, start.as_bytes(), end.as_bytes(), limit);
+            serde_json::json!({
+                "rows": rows
+                    .into_iter()
+                    .map(|(k, v)| serde_json::json!({
+                        "key": String::from_utf8_lossy(&k),
+                        "value": String::from_utf8_lossy(&v),
+                    }))
+                    .collect::<Vec<_>>()
+            })
+        }
+        _ => serde_json::json!({ "error": "unknown coprocessor op" }),
+    }
+}
+
+/// Entry point for externally registered coprocessor plugins: takes a region
+/// id plus an opaque request buffer, resolves the region from `kvstore`,
+/// runs the requested bounded get/scan through [`RawStorage`], and hands the
+/// encoded JSON response back as a `RawCppPtr` (same string-buffer
+/// convention as [`ffi_handle_http_request`]).
+unsafe extern "C" fn ffi_handle_coprocessor_request(
+    arg1: *mut ffi_interfaces::EngineStoreServerWrap,
+    region_id: u64,
+    req: ffi_interfaces::BaseBuffView,
+) -> ffi_interfaces::RawCppPtr {
+    let store = into_engine_store_server_wrap(arg1);
+    let server = &*store.engine_store_server;
+
+    let response = match server.kvstore.get(&region_id) {
+        None => serde_json::json!({ "error": format!("region {} not found", region_id) }),
+        Some(region) => {
+            let storage = RegionRawStorage { region };
+            match serde_json::from_slice::<serde_json::Value>(req.to_slice()) {
+                Ok(value) => handle_coprocessor_op(&storage, &value),
+                Err(e) => serde_json::json!({ "error": format!("bad coprocessor request: {}", e) }),
+            }
+        }
+    };
+
+    let body = response.to_string();
+    ffi_gen_cpp_string(ffi_interfaces::BaseBuffView {
+        data: body.as_ptr() as *const _,
+        len: body.len() as u64,
+    })
+}
+
 unsafe impl Sync for EngineStoreServer {}
 unsafe impl Sync for EngineStoreServerWrap {}
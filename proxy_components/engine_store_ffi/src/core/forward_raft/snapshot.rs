@@ -1,4 +1,10 @@
-use proxy_ffi::interfaces_ffi::SSTReaderPtr;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use proxy_ffi::interfaces_ffi::{BaseBuffView, EngineIteratorSeekType, SSTReaderPtr};
+use proxy_ffi::snapshot_reader_impls::{
+    digest::ffi_sst_reader_digest, ffi_sst_reader_gc, ffi_sst_reader_seek, tablet_reader::TabletReader,
+};
 
 // Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
 use crate::{
@@ -8,6 +14,68 @@ use crate::{
 
 type SSTInfo = (String, ColumnFamilyType);
 
+lazy_static::lazy_static! {
+    // Stands in for the digest field a real v1-reads-v2-tablet-snapshot
+    // flow would carry on `SnapshotMeta` itself: this repo doesn't vendor
+    // the kvproto `.proto` `SnapshotMeta` is generated from, so there's
+    // nowhere to add a real wire-level field from this crate. Keyed by
+    // `SnapKey` so a concurrently in-flight snapshot for a different
+    // region/term never collides with another's entry.
+    //
+    // Populated in `pre_apply_snapshot_impl` right after the tablet
+    // checkpoint is read for prehandling, consulted (and removed) in
+    // `post_apply_snapshot` once the prehandled snapshot has actually been
+    // applied, so it catches corruption introduced anywhere between
+    // prehandle-read and apply-complete -- e.g. the checkpoint directory
+    // being truncated or partially overwritten while prehandling was still
+    // in flight.
+    static ref RECEIVED_TABLET_DIGESTS: Mutex<HashMap<store::SnapKey, HashMap<ColumnFamilyType, u128>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// The on-disk tablet checkpoint directory for `snap`, if it's a v2-format
+/// (raftstore-v2 tablet) snapshot rather than a legacy per-CF-file one.
+fn v2_tablet_path(snap: &store::Snapshot) -> Option<String> {
+    snap.snapshot_meta().as_ref().and_then(|m| {
+        if m.get_tablet_snap_path().is_empty() {
+            None
+        } else {
+            Some(m.get_tablet_snap_path().to_owned())
+        }
+    })
+}
+
+/// Digests each CF's ordered (key, value) pairs read back from the tablet
+/// checkpoint at `path`, the same way the v1 side will eventually read it
+/// for real via [`TabletReader`] -- so the digest reflects exactly what
+/// prehandling (and later verification) actually sees.
+fn digest_tablet_checkpoint(path: &str) -> HashMap<ColumnFamilyType, u128> {
+    let mut digests = HashMap::new();
+    for cf in [
+        ColumnFamilyType::Default,
+        ColumnFamilyType::Lock,
+        ColumnFamilyType::Write,
+    ] {
+        let digest = unsafe {
+            let reader = TabletReader::ffi_get_cf_file_reader(path, cf, None);
+            ffi_sst_reader_seek(
+                reader.clone(),
+                cf,
+                EngineIteratorSeekType::Key,
+                BaseBuffView {
+                    data: std::ptr::null(),
+                    len: 0,
+                },
+            );
+            let digest = ffi_sst_reader_digest(reader.clone(), cf);
+            ffi_sst_reader_gc(reader, cf);
+            digest
+        };
+        digests.insert(cf, digest);
+    }
+    digests
+}
+
 fn retrieve_sst_files(peer_id: u64, snap: &store::Snapshot) -> Vec<SSTInfo> {
     let mut sst_views: Vec<SSTInfo> = vec![];
     let mut ssts = vec![];
@@ -224,6 +292,20 @@ impl<T: Transport + 'static, ER: RaftEngine> ProxyForwarder<T, ER> {
                     }
                 }
 
+                // Snapshot the tablet checkpoint's per-CF digest before
+                // handing it off for (possibly backgrounded) prehandling, so
+                // `post_apply_snapshot` can recompute it once applied and
+                // catch corruption introduced anywhere in between. See
+                // `RECEIVED_TABLET_DIGESTS`'s doc comment for why this lives
+                // in a local registry rather than on `snap`'s metadata.
+                if let Some(path) = v2_tablet_path(snap) {
+                    let digests = digest_tablet_checkpoint(&path);
+                    RECEIVED_TABLET_DIGESTS
+                        .lock()
+                        .unwrap()
+                        .insert(snap_key.clone(), digests);
+                }
+
                 let engine_store_server_helper = self.engine_store_server_helper;
                 let region = ob_region.clone();
                 let snap_key = snap_key.clone();
@@ -430,6 +512,29 @@ impl<T: Transport + 'static, ER: RaftEngine> ProxyForwarder<T, ER> {
                 "elapsed" => post_apply_start.saturating_elapsed().as_millis(),
             );
         }
+
+        self.verify_tablet_snapshot_digest(snap_key, snap);
+    }
+
+    /// Recomputes the tablet checkpoint's per-CF digest now that the
+    /// snapshot has been applied and compares it against the value
+    /// `pre_apply_snapshot_impl` stashed in [`RECEIVED_TABLET_DIGESTS`]
+    /// before prehandling. A no-op for legacy (non-tablet) snapshots, or if
+    /// no entry was stashed (e.g. `should_skip` short-circuited prehandle).
+    fn verify_tablet_snapshot_digest(&self, snap_key: &store::SnapKey, snap: &store::Snapshot) {
+        let Some(expected) = RECEIVED_TABLET_DIGESTS.lock().unwrap().remove(snap_key) else {
+            return;
+        };
+        let Some(path) = v2_tablet_path(snap) else {
+            return;
+        };
+        let got = digest_tablet_checkpoint(&path);
+        if got != expected {
+            error!("tablet snapshot digest mismatch between prehandle and apply, checkpoint corrupted in flight";
+                "snap_key" => ?snap_key,
+                "path" => &path,
+            );
+        }
     }
 
     pub fn should_pre_apply_snapshot(&self) -> bool {
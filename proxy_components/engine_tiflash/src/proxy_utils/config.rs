@@ -0,0 +1,127 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Runtime-tunable knobs for the tiflash proxy path that live outside
+//! TiKV's main `online_config` machinery, since they're internal to this
+//! bridge rather than user-facing storage config.
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::RwLock,
+};
+
+/// Default fraction of write batches sampled by the double-write guard.
+const DEFAULT_DOUBLE_WRITE_SAMPLE_RATE: f64 = 0.01;
+
+/// Hot-reloadable settings for [`crate::proxy_utils::check_double_write`].
+pub struct DoubleWriteCheckConfig {
+    sample_rate_bits: AtomicU64,
+    panic_on_violation: AtomicBool,
+}
+
+impl Default for DoubleWriteCheckConfig {
+    fn default() -> Self {
+        DoubleWriteCheckConfig {
+            sample_rate_bits: AtomicU64::new(DEFAULT_DOUBLE_WRITE_SAMPLE_RATE.to_bits()),
+            panic_on_violation: AtomicBool::new(false),
+        }
+    }
+}
+
+impl DoubleWriteCheckConfig {
+    pub fn sample_rate(&self) -> f64 {
+        f64::from_bits(self.sample_rate_bits.load(Ordering::Relaxed))
+    }
+
+    pub fn set_sample_rate(&self, rate: f64) {
+        self.sample_rate_bits
+            .store(rate.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn panics_on_violation(&self) -> bool {
+        self.panic_on_violation.load(Ordering::Relaxed)
+    }
+
+    pub fn set_panic_on_violation(&self, panic: bool) {
+        self.panic_on_violation.store(panic, Ordering::Relaxed);
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref DOUBLE_WRITE_CHECK: DoubleWriteCheckConfig = DoubleWriteCheckConfig::default();
+}
+
+/// Which keys in one CF bypass the observer and are persisted directly by
+/// TiKV's own `WriteBatch`, checked by [`crate::proxy_utils::do_write`].
+#[derive(Default, Clone)]
+pub struct CfDirectWriteRoute {
+    /// Every key in the CF is persisted directly (used for `CF_RAFT`, whose
+    /// entries are never proxied through the observer).
+    pub always: bool,
+    /// Keys persisted directly by exact match.
+    pub exact_keys: Vec<Vec<u8>>,
+    /// Keys persisted directly because they start with one of these
+    /// prefixes, e.g. a reserved key range for a newer raftstore-v2
+    /// bootstrap/recovery scheme.
+    pub key_prefixes: Vec<Vec<u8>>,
+}
+
+impl CfDirectWriteRoute {
+    fn matches(&self, key: &[u8]) -> bool {
+        self.always
+            || self.exact_keys.iter().any(|k| k.as_slice() == key)
+            || self.key_prefixes.iter().any(|p| key.starts_with(p))
+    }
+}
+
+/// Data-driven routing table for `do_write`: which CF/key combinations
+/// bypass the observer and are written directly. Replaces a fixed match
+/// arm so new reserved engine keys or always-persist CFs can be added at
+/// runtime (e.g. by an operator or a newer kvproto key scheme) instead of
+/// requiring a code change and recompile.
+pub struct DirectWriteRoutes {
+    routes: RwLock<HashMap<&'static str, CfDirectWriteRoute>>,
+}
+
+impl DirectWriteRoutes {
+    pub fn matches(&self, cf: &str, key: &[u8]) -> bool {
+        self.routes
+            .read()
+            .unwrap()
+            .get(cf)
+            .map_or(false, |route| route.matches(key))
+    }
+
+    /// Replaces the routing rule for `cf` wholesale; used to hot-reload the
+    /// table from the config module.
+    pub fn set_route(&self, cf: &'static str, route: CfDirectWriteRoute) {
+        self.routes.write().unwrap().insert(cf, route);
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref DIRECT_WRITE_ROUTES: DirectWriteRoutes = {
+        let mut routes = HashMap::new();
+        routes.insert(
+            engine_traits::CF_RAFT,
+            CfDirectWriteRoute {
+                always: true,
+                ..Default::default()
+            },
+        );
+        routes.insert(
+            engine_traits::CF_DEFAULT,
+            CfDirectWriteRoute {
+                always: false,
+                exact_keys: vec![
+                    keys::PREPARE_BOOTSTRAP_KEY.to_vec(),
+                    keys::STORE_IDENT_KEY.to_vec(),
+                ],
+                key_prefixes: vec![],
+            },
+        );
+        DirectWriteRoutes {
+            routes: RwLock::new(routes),
+        }
+    };
+}
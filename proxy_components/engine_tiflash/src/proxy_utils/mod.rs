@@ -12,17 +12,26 @@ pub use proxy_ext::*;
 mod cached_region_info_manager;
 pub use cached_region_info_manager::*;
 
+use prometheus::IntCounterVec;
+
 use crate::{mixed_engine::write_batch::RocksWriteBatchVec, util::get_cf_handle};
 
+lazy_static::lazy_static! {
+    /// Observed double-write violations, labeled by the offending CF and
+    /// the kind of violation, so a live cluster can surface the condition
+    /// through metrics instead of needing a debug/testexport build to see
+    /// it at all.
+    static ref DOUBLE_WRITE_VIOLATIONS: IntCounterVec = prometheus::register_int_counter_vec!(
+        "tiflash_proxy_double_write_violations",
+        "Number of (cf, key) pairs observed written by both the observer path and TiKV's WriteBatch",
+        &["cf", "kind"]
+    )
+    .unwrap();
+}
+
 pub fn do_write(cf: &str, key: &[u8]) -> bool {
     fail::fail_point!("before_tiflash_do_write", |_| true);
-    match cf {
-        engine_traits::CF_RAFT => true,
-        engine_traits::CF_DEFAULT => {
-            key == keys::PREPARE_BOOTSTRAP_KEY || key == keys::STORE_IDENT_KEY
-        }
-        _ => false,
-    }
+    DIRECT_WRITE_ROUTES.matches(cf, key)
 }
 
 pub fn cf_to_name(batch: &RocksWriteBatchVec, cf: u32) -> &'static str {
@@ -45,10 +54,10 @@ pub fn cf_to_name(batch: &RocksWriteBatchVec, cf: u32) -> &'static str {
 }
 
 #[cfg(any(test, feature = "testexport"))]
-pub fn check_double_write(batch: &RocksWriteBatchVec) {
+pub fn check_double_write(batch: &RocksWriteBatchVec) -> bool {
     // It will fire if we write by both observer(compat_old_proxy is not enabled)
     // and TiKV's WriteBatch.
-    fail::fail_point!("before_tiflash_check_double_write", |_| {});
+    fail::fail_point!("before_tiflash_check_double_write", |_| { false });
     tikv_util::debug!("check if double write happens");
     for wb in batch.wbs.iter() {
         for (_, cf, k, _) in wb.iter() {
@@ -62,13 +71,45 @@ pub fn check_double_write(batch: &RocksWriteBatchVec) {
             };
         }
     }
+    true
 }
-#[cfg(not(any(test, feature = "testexport")))]
-pub fn check_double_write(_: &RocksWriteBatchVec) {}
 
+/// Production counterpart of the test-only hard assert above: instead of
+/// panicking on the first violation, sample a configurable fraction of
+/// batches (`DOUBLE_WRITE_CHECK.sample_rate()`) and turn violations into a
+/// `DOUBLE_WRITE_VIOLATIONS` counter so the condition is visible on a live
+/// cluster without needing a debug/testexport build, escalating to a panic
+/// only when explicitly configured to. Returns whether this call was
+/// sampled, so callers (like `log_check_double_write`) can gate their own
+/// diagnostics behind the same decision.
 #[cfg(not(any(test, feature = "testexport")))]
-pub fn log_check_double_write(_: &RocksWriteBatchVec) -> bool {
-    false
+pub fn check_double_write(batch: &RocksWriteBatchVec) -> bool {
+    fail::fail_point!("before_tiflash_check_double_write", |_| { false });
+
+    if rand::Rng::gen::<f64>(&mut rand::thread_rng()) >= DOUBLE_WRITE_CHECK.sample_rate() {
+        return false;
+    }
+
+    for wb in batch.wbs.iter() {
+        for (_, cf, k, _) in wb.iter() {
+            let handle = batch.db.cf_handle_by_id(cf as usize).unwrap();
+            let cf_name = cf_to_name(batch, handle.id());
+            match cf_name {
+                engine_traits::CF_DEFAULT | engine_traits::CF_LOCK | engine_traits::CF_WRITE => {
+                    if !crate::do_write(cf_name, k) {
+                        DOUBLE_WRITE_VIOLATIONS
+                            .with_label_values(&[cf_name, "direct_write"])
+                            .inc();
+                        if DOUBLE_WRITE_CHECK.panics_on_violation() {
+                            panic!("double write detected for cf {} key {:?}", cf_name, k);
+                        }
+                    }
+                }
+                _ => (),
+            };
+        }
+    }
+    true
 }
 
 #[cfg(any(test, feature = "testexport"))]
@@ -92,3 +133,27 @@ pub fn log_check_double_write(batch: &RocksWriteBatchVec) -> bool {
     }
     false
 }
+
+/// Same empty-write-batch diagnostic as the test build above, but gated
+/// behind `check_double_write`'s sampling decision so it can run live
+/// without logging a backtrace on every single batch.
+#[cfg(not(any(test, feature = "testexport")))]
+pub fn log_check_double_write(batch: &RocksWriteBatchVec) -> bool {
+    if !check_double_write(batch) {
+        return false;
+    }
+    let mut e = true;
+    for wb in batch.wbs.iter() {
+        if !wb.is_empty() {
+            e = false;
+            break;
+        }
+    }
+    if e {
+        let bt = std::backtrace::Backtrace::capture();
+        tikv_util::info!("abnormal empty write batch";
+            "backtrace" => ?bt
+        );
+    }
+    false
+}
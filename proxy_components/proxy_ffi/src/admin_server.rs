@@ -0,0 +1,171 @@
+// Copyright 2024 TiKV Project Authors. Licensed under Apache-2.0.
+//
+// A minimal admin/metrics HTTP server for `RaftStoreProxy`, modeled on the
+// cluster-status admin API pattern `status_server::StatusServer` uses for
+// the full proxy process, but deliberately tiny: it only surfaces the
+// proxy-local state this crate already computes (jemalloc per-thread
+// allocation counters, the cached cluster raftstore version, and the
+// proxy's own status), so deployments can scrape it directly instead of
+// only through the C++ side. Bound only when `RaftStoreProxy` is
+// constructed with an admin address; otherwise `maybe_start_admin_server`
+// is a no-op.
+use std::{convert::Infallible, net::SocketAddr, sync::atomic::Ordering};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use tikv_util::{error, info};
+use tokio::sync::oneshot;
+
+use super::{
+    interfaces_ffi::{RaftStoreProxyPtr, RaftstoreVer},
+    jemalloc_utils::{get_allocate, get_deallocate},
+    raftstore_proxy::RaftStoreProxy,
+    raftstore_proxy_helper_impls::RaftStoreProxyFFI,
+};
+
+/// `RaftStoreProxyPtr` points at state the C++ side owns for the proxy's
+/// whole lifetime; `maybe_start_admin_server` is only ever called (and
+/// the server only ever shut down, via the returned `AdminServerHandle`)
+/// while that holds, so it's sound to hand a copy of the pointer to the
+/// server's request-handling tasks.
+#[derive(Clone, Copy)]
+struct ProxyPtrHandle(RaftStoreProxyPtr);
+unsafe impl Send for ProxyPtrHandle {}
+unsafe impl Sync for ProxyPtrHandle {}
+
+/// Owns the admin server's runtime; dropping it stops the server.
+pub struct AdminServerHandle {
+    runtime: Option<tokio::runtime::Runtime>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl Drop for AdminServerHandle {
+    fn drop(&mut self) {
+        if let Some(tx) = self.shutdown_tx.take() {
+            let _ = tx.send(());
+        }
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+fn raftstore_ver_name(v: RaftstoreVer) -> &'static str {
+    match v {
+        RaftstoreVer::V1 => "v1",
+        RaftstoreVer::V2 => "v2",
+        RaftstoreVer::Uncertain => "uncertain",
+    }
+}
+
+fn render_status_json(proxy: &RaftStoreProxy) -> String {
+    let status_code = proxy.status().load(Ordering::SeqCst);
+    serde_json::json!({
+        "status_code": status_code,
+        "cluster_raftstore_version": raftstore_ver_name(proxy.cluster_raftstore_version()),
+        "thread_allocated": get_allocate(),
+        "thread_deallocated": get_deallocate(),
+    })
+    .to_string()
+}
+
+fn render_metrics_text(proxy: &RaftStoreProxy) -> String {
+    let status_code = proxy.status().load(Ordering::SeqCst);
+    let version_code = match proxy.cluster_raftstore_version() {
+        RaftstoreVer::Uncertain => 0,
+        RaftstoreVer::V1 => 1,
+        RaftstoreVer::V2 => 2,
+    };
+    format!(
+        "# HELP tiflash_proxy_status_code Raw RaftProxyStatus value of this proxy.\n\
+         # TYPE tiflash_proxy_status_code gauge\n\
+         tiflash_proxy_status_code {status_code}\n\
+         # HELP tiflash_proxy_cluster_raftstore_version Cached cluster raftstore version (0=uncertain, 1=v1, 2=v2).\n\
+         # TYPE tiflash_proxy_cluster_raftstore_version gauge\n\
+         tiflash_proxy_cluster_raftstore_version {version_code}\n\
+         # HELP tiflash_proxy_thread_allocated_bytes jemalloc thread.allocated counter for the reporting thread.\n\
+         # TYPE tiflash_proxy_thread_allocated_bytes counter\n\
+         tiflash_proxy_thread_allocated_bytes {allocated}\n\
+         # HELP tiflash_proxy_thread_deallocated_bytes jemalloc thread.deallocated counter for the reporting thread.\n\
+         # TYPE tiflash_proxy_thread_deallocated_bytes counter\n\
+         tiflash_proxy_thread_deallocated_bytes {deallocated}\n",
+        status_code = status_code,
+        version_code = version_code,
+        allocated = get_allocate(),
+        deallocated = get_deallocate(),
+    )
+}
+
+async fn handle(ptr: ProxyPtrHandle, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let proxy = unsafe { ptr.0.as_ref() };
+    let resp = match req.uri().path() {
+        "/status" => Response::new(Body::from(render_status_json(proxy))),
+        "/metrics" => Response::new(Body::from(render_metrics_text(proxy))),
+        _ => {
+            let mut resp = Response::new(Body::from("not found"));
+            *resp.status_mut() = StatusCode::NOT_FOUND;
+            resp
+        }
+    };
+    Ok(resp)
+}
+
+/// Starts the admin server bound to `addr`, serving `/status` (JSON) and
+/// `/metrics` (Prometheus text exposition format). Runs on its own small
+/// runtime so a slow or stuck scrape can't starve the FFI hot paths, which
+/// never touch this runtime.
+pub fn start_admin_server(proxy: &RaftStoreProxy, addr: &str) -> Option<AdminServerHandle> {
+    let addr: SocketAddr = match addr.parse() {
+        Ok(a) => a,
+        Err(e) => {
+            error!("admin server: invalid address"; "addr" => addr, "err" => ?e);
+            return None;
+        }
+    };
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .thread_name("proxy-admin")
+        .build()
+    {
+        Ok(rt) => rt,
+        Err(e) => {
+            error!("admin server: failed to build runtime"; "err" => ?e);
+            return None;
+        }
+    };
+    let ptr = ProxyPtrHandle(RaftStoreProxyPtr::from(proxy));
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    runtime.spawn(async move {
+        let make_svc = make_service_fn(move |_conn| async move {
+            Ok::<_, Infallible>(service_fn(move |req| handle(ptr, req)))
+        });
+        let server = match Server::try_bind(&addr) {
+            Ok(b) => b.serve(make_svc),
+            Err(e) => {
+                error!("admin server: failed to bind"; "addr" => %addr, "err" => ?e);
+                return;
+            }
+        };
+        let graceful = server.with_graceful_shutdown(async {
+            let _ = shutdown_rx.await;
+        });
+        if let Err(e) = graceful.await {
+            error!("admin server: stopped with error"; "err" => ?e);
+        }
+    });
+    info!("admin server listening"; "addr" => %addr);
+    Some(AdminServerHandle {
+        runtime: Some(runtime),
+        shutdown_tx: Some(shutdown_tx),
+    })
+}
+
+/// Starts the admin server only if `RaftStoreProxy` was constructed with
+/// an admin address configured.
+pub fn maybe_start_admin_server(proxy: &RaftStoreProxy) -> Option<AdminServerHandle> {
+    let addr = proxy.admin_addr()?;
+    start_admin_server(proxy, addr)
+}
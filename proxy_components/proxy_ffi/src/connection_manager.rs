@@ -0,0 +1,46 @@
+// Copyright 2024 TiKV Project Authors. Licensed under Apache-2.0.
+//
+// Caches gRPC channels to stores' peer address (not their HTTP status
+// port), keyed by store_id, so repeated calls don't pay channel-setup
+// cost (and, under TLS, a fresh handshake) every time. Modeled after the
+// channel-caching half of raftstore's `RaftClient` connection pool: this
+// module only owns channels; `networking` owns what's sent over them, so
+// the same cached channel can carry more than one kind of proxy-to-store
+// control RPC later.
+use std::{collections::HashMap, sync::Arc};
+
+use grpcio::{ChannelBuilder, Environment};
+use security::SecurityManager;
+
+pub struct ConnectionManager {
+    env: Arc<Environment>,
+    security_mgr: Arc<SecurityManager>,
+    channels: HashMap<u64, grpcio::Channel>,
+}
+
+impl ConnectionManager {
+    pub fn new(env: Arc<Environment>, security_mgr: Arc<SecurityManager>) -> Self {
+        Self {
+            env,
+            security_mgr,
+            channels: HashMap::default(),
+        }
+    }
+
+    /// Returns the cached channel to `store_id`'s gRPC address at `addr`,
+    /// creating (and caching) one the first time it's asked for.
+    pub fn channel(&mut self, store_id: u64, addr: &str) -> grpcio::Channel {
+        let env = self.env.clone();
+        let security_mgr = self.security_mgr.clone();
+        self.channels
+            .entry(store_id)
+            .or_insert_with(|| security_mgr.connect(ChannelBuilder::new(env), addr))
+            .clone()
+    }
+
+    /// Drops a cached channel, e.g. after repeated RPC failures suggest
+    /// the store's address changed (store restart with a new IP).
+    pub fn evict(&mut self, store_id: u64) {
+        self.channels.remove(&store_id);
+    }
+}
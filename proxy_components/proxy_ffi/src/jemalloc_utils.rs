@@ -20,14 +20,22 @@ extern "C" {
     ) -> ::std::os::raw::c_int;
 }
 
+/// Issues one `mallctl` call, honoring the prefixed (`_rjem_mallctl`,
+/// embedded jemalloc) vs. unprefixed (`mallctl`, external jemalloc)
+/// platform split. Returns jemalloc's status code (0 on success, an
+/// errno-style value otherwise), or `-1` if this build links neither
+/// variant, in which case `oldp`/`newp` are left untouched.
 #[allow(unused_variables)]
 #[allow(unused_mut)]
 #[allow(unused_unsafe)]
-fn issue_mallctl(command: &str) -> u64 {
-    type PtrUnderlying = u64;
-    let mut ptr: PtrUnderlying = 0;
-    let mut size = std::mem::size_of::<PtrUnderlying>() as u64;
-    let c_str = std::ffi::CString::new(command).unwrap();
+fn raw_mallctl(
+    name: &str,
+    oldp: *mut ::std::os::raw::c_void,
+    oldlenp: *mut u64,
+    newp: *mut ::std::os::raw::c_void,
+    newlen: u64,
+) -> ::std::os::raw::c_int {
+    let c_str = std::ffi::CString::new(name).unwrap();
     let c_ptr: *const ::std::os::raw::c_char = c_str.as_ptr() as *const ::std::os::raw::c_char;
     unsafe {
         // See unprefixed_malloc_on_supported_platforms in tikv-jemalloc-sys.
@@ -37,25 +45,13 @@ fn issue_mallctl(command: &str) -> u64 {
             {
                 // See NO_UNPREFIXED_MALLOC
                 #[cfg(any(target_os = "android", target_os = "dragonfly", target_os = "macos"))]
-                _rjem_mallctl(
-                    c_ptr,
-                    &mut ptr as *mut _ as *mut ::std::os::raw::c_void,
-                    &mut size as *mut u64,
-                    std::ptr::null_mut(),
-                    0,
-                );
+                return _rjem_mallctl(c_ptr, oldp, oldlenp, newp, newlen);
                 #[cfg(not(any(
                     target_os = "android",
                     target_os = "dragonfly",
                     target_os = "macos"
                 )))]
-                mallctl(
-                    c_ptr,
-                    &mut ptr as *mut _ as *mut ::std::os::raw::c_void,
-                    &mut size as *mut u64,
-                    std::ptr::null_mut(),
-                    0,
-                );
+                return mallctl(c_ptr, oldp, oldlenp, newp, newlen);
             }
         }
 
@@ -63,30 +59,121 @@ fn issue_mallctl(command: &str) -> u64 {
         {
             // Must linked to tiflash.
             #[cfg(feature = "external-jemalloc")]
-            mallctl(
-                c_ptr,
-                &mut ptr as *mut _ as *mut ::std::os::raw::c_void,
-                &mut size as *mut u64,
-                std::ptr::null_mut(),
-                0,
-            );
+            return mallctl(c_ptr, oldp, oldlenp, newp, newlen);
         }
     }
-    ptr
+    -1
+}
+
+/// Reads a `size_t`/`uint64_t`-sized control, e.g. `thread.allocated` or
+/// (after `refresh_stats_epoch`) `stats.allocated`. Returns `0` if the
+/// control doesn't exist or this build links no jemalloc variant.
+pub fn read_u64(name: &str) -> u64 {
+    let mut val: u64 = 0;
+    let mut size = std::mem::size_of::<u64>() as u64;
+    raw_mallctl(
+        name,
+        &mut val as *mut _ as *mut ::std::os::raw::c_void,
+        &mut size,
+        std::ptr::null_mut(),
+        0,
+    );
+    val
+}
+
+/// Reads a `bool`-sized control, e.g. `prof.active`. Returns `false` if
+/// the control doesn't exist or this build links no jemalloc variant.
+pub fn read_bool(name: &str) -> bool {
+    let mut val: bool = false;
+    let mut size = std::mem::size_of::<bool>() as u64;
+    raw_mallctl(
+        name,
+        &mut val as *mut _ as *mut ::std::os::raw::c_void,
+        &mut size,
+        std::ptr::null_mut(),
+        0,
+    );
+    val
+}
+
+/// Writes a `uint64_t`-sized control, e.g. bumping `epoch`. Returns
+/// whether jemalloc reported success.
+pub fn write_u64(name: &str, value: u64) -> bool {
+    let mut value = value;
+    raw_mallctl(
+        name,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+        &mut value as *mut _ as *mut ::std::os::raw::c_void,
+        std::mem::size_of::<u64>() as u64,
+    ) == 0
+}
+
+/// Writes a `const char*` control, e.g. `prof.dump`'s target path. The
+/// value written through `newp` is the pointer itself (`newlen` is
+/// `size_of::<*const c_char>()`), not the string's bytes inline, so this
+/// can't be built on top of `write_u64`.
+pub fn write_str(name: &str, value: &str) -> bool {
+    let c_value = match std::ffi::CString::new(value) {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+    let mut ptr: *const ::std::os::raw::c_char = c_value.as_ptr();
+    raw_mallctl(
+        name,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+        &mut ptr as *mut _ as *mut ::std::os::raw::c_void,
+        std::mem::size_of::<*const ::std::os::raw::c_char>() as u64,
+    ) == 0
+}
+
+/// jemalloc's `stats.*` controls (`stats.allocated`, `stats.resident`,
+/// `stats.active`, ...) are cached as of the last time `epoch` was
+/// advanced; call this immediately before reading one of them with
+/// `read_u64` to get a fresh value.
+pub fn refresh_stats_epoch() -> bool {
+    write_u64("epoch", 1)
+}
+
+/// Turns jemalloc heap profiling on or off at runtime. Only takes effect
+/// if this binary was built with profiling support; otherwise
+/// `prof.active` doesn't exist and this is a harmless no-op returning
+/// `false`. `prof.active` is a C `bool` (1 byte), so it gets its own
+/// narrow write rather than reusing `write_u64`.
+pub fn set_prof_active(active: bool) -> bool {
+    let mut val: u8 = active as u8;
+    raw_mallctl(
+        "prof.active",
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+        &mut val as *mut _ as *mut ::std::os::raw::c_void,
+        std::mem::size_of::<u8>() as u64,
+    ) == 0
+}
+
+/// Dumps a jeprof-compatible heap profile to `path`. Only functional
+/// when this binary was built with jemalloc profiling and profiling is
+/// active (built with `MALLOC_CONF=prof:true`, or via `set_prof_active`);
+/// otherwise `prof.dump` doesn't exist and this is a no-op returning
+/// `false`. Lets operators capture a heap profile from a running process
+/// without a redeploy.
+pub fn dump_heap_profile(path: &str) -> bool {
+    write_str("prof.dump", path)
 }
 
 pub fn get_allocatep_on_thread_start() -> u64 {
-    issue_mallctl("thread.allocatedp")
+    read_u64("thread.allocatedp")
 }
 
 pub fn get_deallocatep_on_thread_start() -> u64 {
-    issue_mallctl("thread.deallocatedp")
+    read_u64("thread.deallocatedp")
 }
 
 pub fn get_allocate() -> u64 {
-    issue_mallctl("thread.allocated")
+    read_u64("thread.allocated")
 }
 
 pub fn get_deallocate() -> u64 {
-    issue_mallctl("thread.deallocated")
+    read_u64("thread.deallocated")
 }
@@ -0,0 +1,34 @@
+// Copyright 2024 TiKV Project Authors. Licensed under Apache-2.0.
+//
+// Thin abstraction over what gets sent on a `ConnectionManager`-cached
+// channel, kept separate from connection lifecycle so more proxy-to-store
+// control RPCs can be added here without touching channel caching.
+//
+// TiKV/TiFlash doesn't expose a typed "engine type" RPC -- that's only
+// ever answered over the HTTP status port's `/engine_type` endpoint -- so
+// this can't replace that probe outright. What it *can* do is tell a
+// genuinely unreachable store (gRPC transport error or timeout) apart
+// from one that's up but simply 404s the debug endpoint, via the
+// standard `grpc.health.v1.Health/Check` RPC every store already serves.
+use std::time::Duration;
+
+use grpcio::{CallOption, Channel};
+use grpcio_health::{proto::HealthCheckRequest, HealthClient};
+
+/// Issues a `grpc.health.v1.Health/Check` over `channel` and reports
+/// whether the store answered at all within `timeout` -- any response,
+/// even a `NOT_SERVING` one, proves the process is up and accepting gRPC
+/// connections. A transport error or timeout reports `false`; this only
+/// ever stands in for "definitely reachable", never for "definitely
+/// down".
+pub async fn probe_reachable(channel: Channel, timeout: Duration) -> bool {
+    let client = HealthClient::new(channel);
+    let mut req = HealthCheckRequest::default();
+    req.set_service(String::new());
+    let opt = CallOption::default().timeout(timeout);
+    let recv = match client.check_async_opt(&req, opt) {
+        Ok(recv) => recv,
+        Err(_) => return false,
+    };
+    recv.await.is_ok()
+}
@@ -1,23 +1,29 @@
 // Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
 #![allow(clippy::type_complexity)]
-use std::sync::{
-    atomic::{AtomicU8, Ordering},
-    Arc, RwLock,
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
 };
 
 use encryption::DataKeyManager;
+use grpcio::EnvBuilder;
+use kvproto::metapb;
 use pd_client::PdClient;
+use security::{SecurityConfig, SecurityManager};
 use tikv_util::error;
 use tokio::runtime::Runtime;
 
 use super::{
-    get_engine_store_server_helper, interfaces_ffi,
+    connection_manager::ConnectionManager, get_engine_store_server_helper, interfaces_ffi,
     interfaces_ffi::{
         ConstRawVoidPtr, KVGetStatus, RaftProxyStatus, RaftStoreProxyPtr, RaftstoreVer,
         RawCppStringPtr, RawVoidPtr,
     },
-    raftstore_proxy_helper_impls::*,
-    read_index_helper,
+    networking, raftstore_proxy_helper_impls::*, read_index_helper,
 };
 
 pub type Eng = Box<dyn RaftStoreProxyEngineTrait + Sync + Send>;
@@ -29,6 +35,48 @@ pub struct RaftStoreProxy {
     raftstore_proxy_engine: RwLock<Option<Eng>>,
     pd_client: Option<Arc<dyn PdClient>>,
     cluster_raftstore_ver: RwLock<RaftstoreVer>,
+    // `Some` only when an admin address was configured; see
+    // `admin_server::maybe_start_admin_server`.
+    admin_addr: Option<String>,
+    // Used by `refresh_cluster_raftstore_version` to probe stores' status
+    // servers over HTTPS with a client certificate when the cluster is
+    // security-enabled, instead of a plaintext probe that TLS-only status
+    // servers would just reject.
+    security_config: Option<Arc<SecurityConfig>>,
+    // Per-store cache so `refresh_cluster_raftstore_version` only re-probes
+    // stores whose answer is stale or still `Uncertain`, instead of
+    // fanning out to every store on every call.
+    store_reachability: HashMap<u64, StoreReachability>,
+    // Lazily built on first use, since it owns a gRPC `Environment` and
+    // (under TLS) does a bit of certificate loading; see
+    // `connection_manager_mut`.
+    connection_manager: Option<ConnectionManager>,
+}
+
+/// A store's last-known status-server answer, and how long we've been
+/// failing to get a real one out of it.
+struct StoreReachability {
+    last_probe: Instant,
+    resolved: RaftstoreVer,
+    consecutive_uncertain: u32,
+}
+
+/// How long a resolved (V1/V2) store's answer is trusted before
+/// `refresh_cluster_raftstore_version` probes it again. A store's engine
+/// type doesn't change at runtime, so this just bounds how stale a cached
+/// answer can get after e.g. the store restarts with a different build.
+const RESOLVED_VERSION_TTL: Duration = Duration::from_secs(300);
+
+/// Base backoff between re-probes of a store stuck at `Uncertain`, doubled
+/// per consecutive miss up to `MAX_UNCERTAIN_BACKOFF` -- the same shape as
+/// TiKV's store-unreachable backoff, so a dead or flapping status server
+/// doesn't get hammered once a refresh interval forever.
+const UNCERTAIN_BACKOFF_BASE: Duration = Duration::from_secs(1);
+const MAX_UNCERTAIN_BACKOFF: Duration = Duration::from_secs(60);
+
+fn uncertain_backoff(consecutive_uncertain: u32) -> Duration {
+    let shift = consecutive_uncertain.min(6);
+    (UNCERTAIN_BACKOFF_BASE * (1u32 << shift)).min(MAX_UNCERTAIN_BACKOFF)
 }
 
 impl RaftStoreProxy {
@@ -38,6 +86,8 @@ impl RaftStoreProxy {
         read_index_client: Option<Box<dyn read_index_helper::ReadIndex>>,
         raftstore_proxy_engine: Option<Eng>,
         pd_client: Option<Arc<dyn PdClient>>,
+        admin_addr: Option<String>,
+        security_config: Option<Arc<SecurityConfig>>,
     ) -> Self {
         RaftStoreProxy {
             status,
@@ -46,8 +96,51 @@ impl RaftStoreProxy {
             raftstore_proxy_engine: RwLock::new(raftstore_proxy_engine),
             pd_client,
             cluster_raftstore_ver: RwLock::new(RaftstoreVer::Uncertain),
+            admin_addr,
+            security_config,
+            store_reachability: HashMap::default(),
+            connection_manager: None,
         }
     }
+
+    /// The configured admin server address, if any.
+    pub fn admin_addr(&self) -> Option<&str> {
+        self.admin_addr.as_deref()
+    }
+
+    /// The cached gRPC `ConnectionManager`, building it (and the
+    /// `SecurityManager` it connects through) the first time it's asked
+    /// for. Returns `None` if a security-enabled cluster's TLS material
+    /// fails to load -- callers fall back to treating reachability as
+    /// unknown rather than failing the whole refresh.
+    fn connection_manager_mut(&mut self) -> Option<&mut ConnectionManager> {
+        if self.connection_manager.is_none() {
+            let cfg = self
+                .security_config
+                .as_deref()
+                .cloned()
+                .unwrap_or_default();
+            // `security::SecurityManager` builds its own TLS material and
+            // hands it to `grpcio`'s C-core for the handshake -- see
+            // `proxy_ffi::snapshot_reader_impls::crypto::backend`'s module
+            // doc for why that's out of `CryptoBackend`'s scope.
+            let security_mgr = match SecurityManager::new(&cfg) {
+                Ok(m) => Arc::new(m),
+                Err(e) => {
+                    error!("connection_manager_mut: failed to build security manager {:?}", e);
+                    return None;
+                }
+            };
+            let env = Arc::new(
+                EnvBuilder::new()
+                    .cq_count(1)
+                    .name_prefix("proxy-store-probe")
+                    .build(),
+            );
+            self.connection_manager = Some(ConnectionManager::new(env, security_mgr));
+        }
+        self.connection_manager.as_mut()
+    }
 }
 
 pub fn maybe_use_backup_addr(u: &str, backup: impl Fn() -> String) -> Option<String> {
@@ -91,6 +184,32 @@ pub fn maybe_use_backup_addr(u: &str, backup: impl Fn() -> String) -> Option<Str
     res
 }
 
+/// Whether `security_config` has TLS material configured, the same test
+/// `SecurityManager` uses to decide whether to stand up a TLS `grpcio`
+/// channel: an empty `ca_path` means TLS is off.
+fn tls_enabled(security_config: &Option<Arc<SecurityConfig>>) -> bool {
+    security_config
+        .as_ref()
+        .map_or(false, |c| !c.ca_path.is_empty())
+}
+
+/// Loads `security_config`'s CA certificate and client identity (cert +
+/// key, bundled into one PEM as `reqwest::Identity::from_pem` expects) off
+/// disk, the same files `key_manager`'s encryption-at-rest material is
+/// guarded alongside.
+fn load_tls_material(
+    security_config: &SecurityConfig,
+) -> std::io::Result<(reqwest::Certificate, reqwest::Identity)> {
+    let ca_pem = std::fs::read(&security_config.ca_path)?;
+    let ca_cert = reqwest::Certificate::from_pem(&ca_pem)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let mut identity_pem = std::fs::read(&security_config.cert_path)?;
+    identity_pem.extend(std::fs::read(&security_config.key_path)?);
+    let identity = reqwest::Identity::from_pem(&identity_pem)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    Ok((ca_cert, identity))
+}
+
 impl RaftStoreProxy {
     pub fn cluster_raftstore_version(&self) -> RaftstoreVer {
         *self.cluster_raftstore_ver.read().unwrap()
@@ -100,12 +219,26 @@ impl RaftStoreProxy {
     /// Use the result of the first store which is not a Uncertain.
     /// Or set the result to Uncertain if timeout.
     pub fn refresh_cluster_raftstore_version(&mut self, timeout_ms: i64) -> bool {
+        let security_config = self.security_config.clone();
         let generate_request_with_timeout = |timeout_ms: i64| -> Option<reqwest::Client> {
             let headers = reqwest::header::HeaderMap::new();
             let mut builder = reqwest::Client::builder().default_headers(headers);
             if timeout_ms >= 0 {
                 builder = builder.timeout(std::time::Duration::from_millis(timeout_ms as u64));
             }
+            if let Some(security_config) = security_config.as_ref() {
+                if !security_config.ca_path.is_empty() {
+                    match load_tls_material(security_config) {
+                        Ok((ca_cert, identity)) => {
+                            builder = builder.add_root_certificate(ca_cert).identity(identity);
+                        }
+                        Err(e) => {
+                            error!("refresh_cluster_raftstore_version: failed to load TLS material {:?}", e);
+                            return None;
+                        }
+                    }
+                }
+            }
             match builder.build() {
                 Ok(o) => Some(o),
                 Err(e) => {
@@ -115,32 +248,39 @@ impl RaftStoreProxy {
             }
         };
 
-        let parse_response =
-            |rt: &Runtime, resp: Result<reqwest::Response, reqwest::Error>| -> RaftstoreVer {
-                match resp {
-                    Ok(resp) => {
-                        if resp.status() == 404 {
+        let parse_response = |rt: &Runtime,
+                               reachable: bool,
+                               resp: Result<reqwest::Response, reqwest::Error>|
+         -> RaftstoreVer {
+            match resp {
+                Ok(resp) => {
+                    if resp.status() == 404 {
+                        if reachable {
                             // If the port is not implemented.
                             return RaftstoreVer::V1;
-                        } else if resp.status() != 200 {
-                            return RaftstoreVer::Uncertain;
-                        }
-                        let resp = rt.block_on(async { resp.text().await }).unwrap();
-                        if resp.contains("partitioned") {
-                            RaftstoreVer::V2
-                        } else {
-                            RaftstoreVer::V1
                         }
+                        // The gRPC health check couldn't reach this store
+                        // either, so the 404 is more likely a torn-down
+                        // connection than a genuine "not implemented" --
+                        // don't let a transport failure masquerade as V1.
+                        return RaftstoreVer::Uncertain;
+                    } else if resp.status() != 200 {
+                        return RaftstoreVer::Uncertain;
                     }
-                    Err(e) => {
-                        error!("get_engine_type respond error {:?}", e);
-                        RaftstoreVer::Uncertain
+                    let resp = rt.block_on(async { resp.text().await }).unwrap();
+                    if resp.contains("partitioned") {
+                        RaftstoreVer::V2
+                    } else {
+                        RaftstoreVer::V1
                     }
                 }
-            };
+                Err(e) => {
+                    error!("get_engine_type respond error {:?}", e);
+                    RaftstoreVer::Uncertain
+                }
+            }
+        };
 
-        // We don't use information stored in `GlobalReplicationState` to decouple.
-        *self.cluster_raftstore_ver.write().unwrap() = RaftstoreVer::Uncertain;
         let stores = match self.pd_client.as_ref().unwrap().get_all_stores(false) {
             Ok(stores) => stores,
             Err(e) => {
@@ -148,28 +288,69 @@ impl RaftStoreProxy {
                 return false;
             }
         };
+        let is_tiflash = |store: &metapb::Store| {
+            store
+                .get_labels()
+                .iter()
+                .any(|label| label.get_key() == "engine" && label.get_value().contains("tiflash"))
+        };
 
+        // A store whose engine type we already resolved recently needs no
+        // new probe at all; the first such store settles the cluster
+        // version, same as the first non-Uncertain probe response would.
+        let now = Instant::now();
+        for store in stores.iter().filter(|s| !is_tiflash(s)) {
+            if let Some(r) = self.store_reachability.get(&store.get_id()) {
+                if r.resolved != RaftstoreVer::Uncertain
+                    && now.saturating_duration_since(r.last_probe) < RESOLVED_VERSION_TTL
+                {
+                    *self.cluster_raftstore_ver.write().unwrap() = r.resolved;
+                    return true;
+                }
+            }
+        }
+
+        // We don't use information stored in `GlobalReplicationState` to decouple.
+        *self.cluster_raftstore_ver.write().unwrap() = RaftstoreVer::Uncertain;
+
+        let scheme = if tls_enabled(&self.security_config) {
+            "https"
+        } else {
+            "http"
+        };
+        let store_reachability = &self.store_reachability;
         let to_try_addrs = stores.iter().filter_map(|store| {
             // There are some other labels such like tiflash_compute.
-            let shall_filter = store
-                .get_labels()
-                .iter()
-                .any(|label| label.get_key() == "engine" && label.get_value().contains("tiflash"));
-            if !shall_filter {
-                // TiKV's status server don't support https.
-                let mut u = format!("http://{}/{}", store.get_status_address(), "engine_type");
-                if let Some(nu) = maybe_use_backup_addr(&u, || store.get_address().to_string()) {
-                    tikv_util::info!("switch from {} to {}", u, nu);
-                    u = nu;
+            if is_tiflash(store) {
+                return None;
+            }
+            // Still waiting out the backoff from repeated `Uncertain`
+            // answers -- e.g. a recently-seen successful read-index/KV
+            // response from this store would also justify skipping the
+            // probe, but that signal isn't visible from this trait-erased
+            // `read_index_client`, so the backoff is the only reachability
+            // hint available here.
+            if let Some(r) = store_reachability.get(&store.get_id()) {
+                if r.resolved == RaftstoreVer::Uncertain
+                    && now.saturating_duration_since(r.last_probe)
+                        < uncertain_backoff(r.consecutive_uncertain)
+                {
+                    return None;
                 }
-                // A invalid url may lead to 404, which will enforce a V1 inference, which is
-                // error.
-                if let Ok(stuff) = url::Url::parse(&u) {
-                    if stuff.path() == "/engine_type" {
-                        Some(u)
-                    } else {
-                        None
-                    }
+            }
+            // Plaintext unless `security_config` has TLS configured, in
+            // which case a security-enabled cluster's status server
+            // will reject a plaintext probe outright.
+            let mut u = format!("{}://{}/{}", scheme, store.get_status_address(), "engine_type");
+            if let Some(nu) = maybe_use_backup_addr(&u, || store.get_address().to_string()) {
+                tikv_util::info!("switch from {} to {}", u, nu);
+                u = nu;
+            }
+            // A invalid url may lead to 404, which will enforce a V1 inference, which is
+            // error.
+            if let Ok(stuff) = url::Url::parse(&u) {
+                if stuff.path() == "/engine_type" {
+                    Some((store.get_id(), u, store.get_address().to_string()))
                 } else {
                     None
                 }
@@ -177,15 +358,37 @@ impl RaftStoreProxy {
                 None
             }
         });
+        // Collected (rather than iterated lazily below) so the borrow of
+        // `self.store_reachability` ends here, before the loop below needs
+        // a mutable borrow of `self` to reach the connection manager.
+        let to_try_addrs: Vec<(u64, String, String)> = to_try_addrs.collect();
+
+        let probe_timeout = if timeout_ms >= 0 {
+            Duration::from_millis(timeout_ms as u64)
+        } else {
+            UNCERTAIN_BACKOFF_BASE
+        };
 
         let rt = Runtime::new().unwrap();
 
         let mut pending = vec![];
-        for addr in to_try_addrs {
+        for (store_id, addr, grpc_addr) in to_try_addrs {
             if let Some(c) = generate_request_with_timeout(timeout_ms) {
+                let reachable_fut = self
+                    .connection_manager_mut()
+                    .map(|cm| networking::probe_reachable(cm.channel(store_id, &grpc_addr), probe_timeout));
                 let _g = rt.enter();
                 let f = c.get(&addr).send();
-                pending.push(rt.spawn(f));
+                pending.push(rt.spawn(async move {
+                    // No connection manager (e.g. its security manager
+                    // failed to load) means reachability is simply
+                    // unknown; don't let that block classification.
+                    let reachable = match reachable_fut {
+                        Some(fut) => fut.await,
+                        None => true,
+                    };
+                    (store_id, reachable, f.await)
+                }));
             }
         }
 
@@ -198,11 +401,28 @@ impl RaftStoreProxy {
                 break;
             }
             let sel = futures::future::select_all(pending);
-            let (resp, _completed_idx, remaining) = rt.block_on(async { sel.await });
-
-            let res = parse_response(&rt, resp.unwrap());
-
-            if res != RaftstoreVer::Uncertain {
+            let (joined, _completed_idx, remaining) = rt.block_on(async { sel.await });
+
+            let (store_id, reachable, resp) = joined.unwrap();
+            let res = parse_response(&rt, reachable, resp);
+
+            let entry = self
+                .store_reachability
+                .entry(store_id)
+                .or_insert_with(|| StoreReachability {
+                    last_probe: Instant::now(),
+                    resolved: RaftstoreVer::Uncertain,
+                    consecutive_uncertain: 0,
+                });
+            entry.last_probe = Instant::now();
+            if res == RaftstoreVer::Uncertain {
+                // Drop any earlier answer so a failed re-probe doesn't
+                // renew it for another `RESOLVED_VERSION_TTL`.
+                entry.resolved = RaftstoreVer::Uncertain;
+                entry.consecutive_uncertain = entry.consecutive_uncertain.saturating_add(1);
+            } else {
+                entry.resolved = res;
+                entry.consecutive_uncertain = 0;
                 *self.cluster_raftstore_ver.write().unwrap() = res;
                 rt.shutdown_timeout(std::time::Duration::from_millis(1));
                 return true;
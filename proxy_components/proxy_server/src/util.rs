@@ -2,64 +2,118 @@
 
 use std::{
     pin::Pin,
+    sync::{Mutex, Once},
     time::{Duration, Instant},
 };
 
 use engine_store_ffi::ffi::interfaces_ffi::{BaseBuffView, RaftStoreProxyPtr, RawVoidPtr};
-use futures::{compat::Future01CompatExt, executor::block_on};
-use kvproto::diagnosticspb::{ServerInfoRequest, ServerInfoResponse, ServerInfoType};
+use kvproto::diagnosticspb::{
+    ServerInfoItem, ServerInfoRequest, ServerInfoResponse, ServerInfoType,
+};
 use protobuf::Message;
 use tikv::server::service::diagnostics::{sys, SYS_INFO};
 use tikv_util::{
+    debug,
     sys::{ioload, SystemExt},
-    timer::GLOBAL_TIMER_HANDLE,
 };
 
-fn server_info_for_ffi(req: ServerInfoRequest) -> ServerInfoResponse {
-    let tp = req.get_tp();
+/// How often the background sampler in [`ensure_load_sampler_started`]
+/// refreshes the cached `LoadInfo` items. Matches the sampling window the
+/// old inline `GLOBAL_TIMER_HANDLE.delay` used, so the CPU%/IO deltas it
+/// computes cover the same span of wall time.
+const LOAD_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+struct CachedLoadInfo {
+    items: Vec<ServerInfoItem>,
+    sampled_at: Instant,
+}
+
+lazy_static::lazy_static! {
+    static ref CACHED_LOAD_INFO: Mutex<Option<CachedLoadInfo>> = Mutex::new(None);
+}
 
-    let collect = async move {
-        let (load, when) = match tp {
-            ServerInfoType::LoadInfo | ServerInfoType::All => {
-                let mut system = SYS_INFO.lock().unwrap();
-                system.refresh_networks_list();
-                system.refresh_all();
-                let load = (
-                    sys::cpu_time_snapshot(),
-                    system
-                        .networks()
-                        .into_iter()
-                        .map(|(n, d)| (n.to_owned(), sys::NicSnapshot::from_network_data(d)))
-                        .collect(),
-                    ioload::IoLoad::snapshot(),
-                );
-                let when = Instant::now() + Duration::from_millis(1000);
-                (Some(load), when)
-            }
-            _ => (None, Instant::now()),
-        };
+static LOAD_SAMPLER_STARTED: Once = Once::new();
 
-        let timer = GLOBAL_TIMER_HANDLE.clone();
-        let _ = timer.delay(when).compat().await;
+/// Starts the background sampler thread the first time a `LoadInfo`/`All`
+/// request needs it. The thread loops forever: each iteration samples CPU,
+/// NIC and IO load the same way `server_info_for_ffi` used to do inline,
+/// waits out `LOAD_SAMPLE_INTERVAL` so the deltas `sys::load_info` computes
+/// span a full second, and publishes the result to `CACHED_LOAD_INFO` --
+/// which gives this loop a roughly once-per-second cadence for free,
+/// without a separate outer sleep.
+fn ensure_load_sampler_started() {
+    LOAD_SAMPLER_STARTED.call_once(|| {
+        std::thread::Builder::new()
+            .name("proxy-srv-info".to_owned())
+            .spawn(|| loop {
+                let items = sample_load_info_blocking();
+                *CACHED_LOAD_INFO.lock().unwrap() = Some(CachedLoadInfo {
+                    items,
+                    sampled_at: Instant::now(),
+                });
+            })
+            .expect("failed to spawn proxy server-info sampler thread");
+    });
+}
 
-        let mut server_infos = Vec::new();
-        match req.get_tp() {
-            ServerInfoType::HardwareInfo => sys::hardware_info(&mut server_infos),
-            ServerInfoType::LoadInfo => sys::load_info(load.unwrap(), &mut server_infos),
-            ServerInfoType::SystemInfo => sys::system_info(&mut server_infos),
-            ServerInfoType::All => {
-                sys::hardware_info(&mut server_infos);
-                sys::load_info(load.unwrap(), &mut server_infos);
-                sys::system_info(&mut server_infos);
-            }
-        };
-        server_infos.sort_by(|a, b| (a.get_tp(), a.get_name()).cmp(&(b.get_tp(), b.get_name())));
-        let mut resp = ServerInfoResponse::default();
-        resp.set_items(server_infos.into());
-        resp
+/// Takes a CPU/NIC/IO snapshot, blocks for `LOAD_SAMPLE_INTERVAL`, and
+/// returns the resulting `LoadInfo` items. This is the same blocking
+/// sequence `server_info_for_ffi` used to run on every FFI call; now the
+/// background sampler thread runs it, and so does any request that arrives
+/// before the sampler has published its first sample.
+fn sample_load_info_blocking() -> Vec<ServerInfoItem> {
+    let load = {
+        let mut system = SYS_INFO.lock().unwrap();
+        system.refresh_networks_list();
+        system.refresh_all();
+        (
+            sys::cpu_time_snapshot(),
+            system
+                .networks()
+                .into_iter()
+                .map(|(n, d)| (n.to_owned(), sys::NicSnapshot::from_network_data(d)))
+                .collect(),
+            ioload::IoLoad::snapshot(),
+        )
     };
+    std::thread::sleep(LOAD_SAMPLE_INTERVAL);
+    let mut server_infos = Vec::new();
+    sys::load_info(load, &mut server_infos);
+    server_infos
+}
+
+/// Returns the most recently cached `LoadInfo` items, falling back to the
+/// old synchronous sample-and-wait path for every call made before the
+/// background sampler has produced its first sample. Logs how
+/// stale the served sample is so a slow or stuck sampler thread is visible
+/// in the logs even though it never changes `ServerInfoResponse`'s shape.
+fn cached_or_synchronous_load_info() -> Vec<ServerInfoItem> {
+    ensure_load_sampler_started();
+    if let Some(cached) = CACHED_LOAD_INFO.lock().unwrap().as_ref() {
+        debug!("serving cached server load info"; "age" => ?cached.sampled_at.elapsed());
+        return cached.items.clone();
+    }
+    sample_load_info_blocking()
+}
 
-    block_on(collect)
+fn server_info_for_ffi(req: ServerInfoRequest) -> ServerInfoResponse {
+    let tp = req.get_tp();
+
+    let mut server_infos = Vec::new();
+    match tp {
+        ServerInfoType::HardwareInfo => sys::hardware_info(&mut server_infos),
+        ServerInfoType::LoadInfo => server_infos.extend(cached_or_synchronous_load_info()),
+        ServerInfoType::SystemInfo => sys::system_info(&mut server_infos),
+        ServerInfoType::All => {
+            sys::hardware_info(&mut server_infos);
+            server_infos.extend(cached_or_synchronous_load_info());
+            sys::system_info(&mut server_infos);
+        }
+    };
+    server_infos.sort_by(|a, b| (a.get_tp(), a.get_name()).cmp(&(b.get_tp(), b.get_name())));
+    let mut resp = ServerInfoResponse::default();
+    resp.set_items(server_infos.into());
+    resp
 }
 
 pub extern "C" fn ffi_server_info(
@@ -2,19 +2,60 @@
 
 use std::pin::Pin;
 
-use engine_traits::{CF_DEFAULT, CF_LOCK, CF_WRITE};
+use encryption::DataKeyManager;
+use engine_traits::{EncryptionKeyManager, EncryptionMethod, CF_DEFAULT, CF_LOCK, CF_WRITE};
 
 use super::interfaces_ffi::{
     BaseBuffView, ColumnFamilyType, RaftCmdHeader, SSTView, SSTViewVec, WriteCmdType, WriteCmdsView,
 };
 
-pub fn into_sst_views(snaps: Vec<(&[u8], ColumnFamilyType)>) -> Vec<SSTView> {
+/// Encryption metadata for one SST file being handed to engine-store,
+/// resolved from `DataKeyManager` at snapshot-ingest time so the C++ side
+/// can decrypt it without re-deriving keys out of band. `SSTView`'s layout
+/// comes from the generated FFI bindings (not part of this snapshot), so
+/// this rides alongside it as a plain Rust value returned by
+/// `into_sst_views` rather than an extra field bolted onto `SSTView` itself.
+/// `None` when encryption is disabled or the file isn't tracked, for
+/// zero-overhead compatibility with the unencrypted path.
+#[derive(Clone, Debug)]
+pub struct SstFileEncryptionInfo {
+    pub method: EncryptionMethod,
+    pub key: Vec<u8>,
+    pub iv: Vec<u8>,
+}
+
+fn resolve_sst_encryption_info(
+    key_manager: Option<&DataKeyManager>,
+    path: &str,
+) -> Option<SstFileEncryptionInfo> {
+    let key_manager = key_manager?;
+    let info = key_manager.get_file(path).ok()?;
+    if info.method == EncryptionMethod::Plaintext {
+        return None;
+    }
+    Some(SstFileEncryptionInfo {
+        method: info.method,
+        key: info.key,
+        iv: info.iv,
+    })
+}
+
+pub fn into_sst_views(
+    snaps: Vec<(&[u8], ColumnFamilyType)>,
+    key_manager: Option<&DataKeyManager>,
+) -> Vec<(SSTView, Option<SstFileEncryptionInfo>)> {
     let mut snaps_view = vec![];
     for (path, cf) in snaps {
-        snaps_view.push(SSTView {
-            type_: cf,
-            path: path.into(),
-        })
+        let encryption_info = std::str::from_utf8(path)
+            .ok()
+            .and_then(|path| resolve_sst_encryption_info(key_manager, path));
+        snaps_view.push((
+            SSTView {
+                type_: cf,
+                path: path.into(),
+            },
+            encryption_info,
+        ))
     }
     snaps_view
 }
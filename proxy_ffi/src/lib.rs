@@ -0,0 +1,9 @@
+// Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
+
+pub mod domain_impls;
+pub mod snapshot_reader_impls;
+
+// Generated FFI bindings live outside this extracted snapshot; declared here
+// so the modules above can refer to `crate::interfaces_ffi`/`super::interfaces_ffi`
+// the same way the rest of the proxy does.
+pub mod interfaces_ffi;
@@ -0,0 +1,242 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A small, object-safe crypto surface so the snapshot-encryption path
+//! doesn't hard-code a single crypto library. Exactly one of the
+//! `crypto-openssl` / `crypto-rust` / `crypto-mbedtls` features must be
+//! enabled; the build picks the matching backend as the process-wide
+//! default so a snapshot produced under one build is readable by a proxy
+//! linked against a different library, as long as both speak this same
+//! envelope format.
+//!
+//! This deliberately doesn't extend to `security::SecurityManager`'s TLS
+//! stack: that crate is an upstream TiKV dependency this repo doesn't own,
+//! it does its own certificate loading and hands off to `grpcio`'s C-core
+//! for the actual handshake, and neither step goes through a pluggable
+//! crypto primitive this trait could stand in for. Making
+//! `SecurityManager` backend-agnostic would mean patching `security` and
+//! `grpcio`, not adding an adapter here.
+
+use super::CryptoError;
+
+pub trait CryptoBackend: Send + Sync {
+    fn random(&self, buf: &mut [u8]);
+
+    fn hmac_sha256(&self, key: &[u8], data: &[u8]) -> [u8; 32];
+
+    /// HKDF-SHA256 with a single `okm` block (`okm.len() <= 32`).
+    fn hkdf_sha256(&self, ikm: &[u8], salt: &[u8], info: &[u8], okm: &mut [u8]) {
+        let prk = self.hmac_sha256(salt, ikm);
+        let mut expand_input = Vec::with_capacity(info.len() + 1);
+        expand_input.extend_from_slice(info);
+        expand_input.push(1u8);
+        let t1 = self.hmac_sha256(&prk, &expand_input);
+        okm.copy_from_slice(&t1[..okm.len()]);
+    }
+
+    fn aead_seal(
+        &self,
+        key: &[u8; 32],
+        nonce: &[u8; 12],
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> (Vec<u8>, [u8; 16]);
+
+    fn aead_open(
+        &self,
+        key: &[u8; 32],
+        nonce: &[u8; 12],
+        aad: &[u8],
+        ciphertext: &[u8],
+        tag: &[u8; 16],
+    ) -> Result<Vec<u8>, CryptoError>;
+}
+
+#[cfg(feature = "crypto-openssl")]
+pub struct OpenSslBackend;
+
+#[cfg(feature = "crypto-openssl")]
+impl CryptoBackend for OpenSslBackend {
+    fn random(&self, buf: &mut [u8]) {
+        openssl::rand::rand_bytes(buf).expect("openssl rand_bytes");
+    }
+
+    fn hmac_sha256(&self, key: &[u8], data: &[u8]) -> [u8; 32] {
+        use hmac::{Hmac, Mac};
+        use sha2::Sha256;
+        let mut mac = <Hmac<Sha256>>::new_from_slice(key).unwrap();
+        mac.update(data);
+        let mut out = [0u8; 32];
+        out.copy_from_slice(&mac.finalize().into_bytes());
+        out
+    }
+
+    fn aead_seal(
+        &self,
+        key: &[u8; 32],
+        nonce: &[u8; 12],
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> (Vec<u8>, [u8; 16]) {
+        let mut tag = [0u8; 16];
+        let ciphertext = openssl::symm::encrypt_aead(
+            openssl::symm::Cipher::aes_256_gcm(),
+            key,
+            Some(nonce),
+            aad,
+            plaintext,
+            &mut tag,
+        )
+        .expect("aes-256-gcm seal");
+        (ciphertext, tag)
+    }
+
+    fn aead_open(
+        &self,
+        key: &[u8; 32],
+        nonce: &[u8; 12],
+        aad: &[u8],
+        ciphertext: &[u8],
+        tag: &[u8; 16],
+    ) -> Result<Vec<u8>, CryptoError> {
+        openssl::symm::decrypt_aead(
+            openssl::symm::Cipher::aes_256_gcm(),
+            key,
+            Some(nonce),
+            aad,
+            ciphertext,
+            tag,
+        )
+        .map_err(|_| CryptoError("aes-256-gcm authentication failed".into()))
+    }
+}
+
+#[cfg(feature = "crypto-rust")]
+pub struct RustCryptoBackend;
+
+#[cfg(feature = "crypto-rust")]
+impl CryptoBackend for RustCryptoBackend {
+    fn random(&self, buf: &mut [u8]) {
+        use rand::RngCore;
+        rand::thread_rng().fill_bytes(buf);
+    }
+
+    fn hmac_sha256(&self, key: &[u8], data: &[u8]) -> [u8; 32] {
+        use hmac::{Hmac, Mac};
+        use sha2::Sha256;
+        let mut mac = <Hmac<Sha256>>::new_from_slice(key).unwrap();
+        mac.update(data);
+        let mut out = [0u8; 32];
+        out.copy_from_slice(&mac.finalize().into_bytes());
+        out
+    }
+
+    fn aead_seal(
+        &self,
+        key: &[u8; 32],
+        nonce: &[u8; 12],
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> (Vec<u8>, [u8; 16]) {
+        use aes_gcm::{
+            aead::{generic_array::GenericArray, AeadInPlace},
+            Aes256Gcm, KeyInit,
+        };
+        let cipher = Aes256Gcm::new(GenericArray::from_slice(key));
+        let mut buffer = plaintext.to_vec();
+        let tag = cipher
+            .encrypt_in_place_detached(GenericArray::from_slice(nonce), aad, &mut buffer)
+            .expect("aes-256-gcm seal");
+        let mut tag_arr = [0u8; 16];
+        tag_arr.copy_from_slice(&tag);
+        (buffer, tag_arr)
+    }
+
+    fn aead_open(
+        &self,
+        key: &[u8; 32],
+        nonce: &[u8; 12],
+        aad: &[u8],
+        ciphertext: &[u8],
+        tag: &[u8; 16],
+    ) -> Result<Vec<u8>, CryptoError> {
+        use aes_gcm::{
+            aead::{generic_array::GenericArray, AeadInPlace},
+            Aes256Gcm, KeyInit,
+        };
+        let cipher = Aes256Gcm::new(GenericArray::from_slice(key));
+        let mut buffer = ciphertext.to_vec();
+        cipher
+            .decrypt_in_place_detached(
+                GenericArray::from_slice(nonce),
+                aad,
+                &mut buffer,
+                GenericArray::from_slice(tag),
+            )
+            .map_err(|_| CryptoError("aes-256-gcm authentication failed".into()))?;
+        Ok(buffer)
+    }
+}
+
+#[cfg(feature = "crypto-mbedtls")]
+pub struct MbedTlsBackend;
+
+#[cfg(feature = "crypto-mbedtls")]
+impl CryptoBackend for MbedTlsBackend {
+    fn random(&self, buf: &mut [u8]) {
+        mbedtls::rng::os_entropy_fill(buf).expect("mbedtls rng");
+    }
+
+    fn hmac_sha256(&self, key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        mbedtls::hash::Hmac::hmac(mbedtls::hash::Type::Sha256, key, data, &mut out)
+            .expect("mbedtls hmac-sha256");
+        out
+    }
+
+    fn aead_seal(
+        &self,
+        key: &[u8; 32],
+        nonce: &[u8; 12],
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> (Vec<u8>, [u8; 16]) {
+        let mut ciphertext = vec![0u8; plaintext.len()];
+        let mut tag = [0u8; 16];
+        mbedtls::cipher::raw::aes_gcm_encrypt(key, nonce, aad, plaintext, &mut ciphertext, &mut tag)
+            .expect("aes-256-gcm seal");
+        (ciphertext, tag)
+    }
+
+    fn aead_open(
+        &self,
+        key: &[u8; 32],
+        nonce: &[u8; 12],
+        aad: &[u8],
+        ciphertext: &[u8],
+        tag: &[u8; 16],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let mut plaintext = vec![0u8; ciphertext.len()];
+        mbedtls::cipher::raw::aes_gcm_decrypt(key, nonce, aad, ciphertext, tag, &mut plaintext)
+            .map_err(|_| CryptoError("aes-256-gcm authentication failed".into()))?;
+        Ok(plaintext)
+    }
+}
+
+#[cfg(feature = "crypto-openssl")]
+pub fn default_backend() -> &'static dyn CryptoBackend {
+    &OpenSslBackend
+}
+
+#[cfg(all(not(feature = "crypto-openssl"), feature = "crypto-rust"))]
+pub fn default_backend() -> &'static dyn CryptoBackend {
+    &RustCryptoBackend
+}
+
+#[cfg(all(
+    not(feature = "crypto-openssl"),
+    not(feature = "crypto-rust"),
+    feature = "crypto-mbedtls"
+))]
+pub fn default_backend() -> &'static dyn CryptoBackend {
+    &MbedTlsBackend
+}
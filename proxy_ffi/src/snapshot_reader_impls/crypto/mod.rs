@@ -0,0 +1,201 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! AEAD envelope used to encrypt the CF files of a tablet snapshot at rest
+//! and in transit. Each file is prefixed with a small header carrying a
+//! random salt; the per-file key is derived from that salt via HKDF-SHA256
+//! and the file body is split into fixed-size chunks, each sealed with
+//! AES-256-GCM under its own nonce.
+
+pub mod backend;
+
+use self::backend::{default_backend, CryptoBackend};
+
+pub const SALT_LEN: usize = 16;
+pub const KEY_LEN: usize = 32;
+pub const TAG_LEN: usize = 16;
+pub const NONCE_LEN: usize = 12;
+/// Plaintext chunk size. The encrypted file is laid out as
+/// `salt || file_nonce || (chunk_ciphertext || tag)*`.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone)]
+pub struct CipherContext {
+    pub master_key: Vec<u8>,
+    /// Binds the derived key to the file it was derived for, e.g.
+    /// `region_id ‖ cf ‖ snap_key`.
+    pub info: Vec<u8>,
+}
+
+impl CipherContext {
+    pub fn new(master_key: Vec<u8>, info: Vec<u8>) -> Self {
+        CipherContext { master_key, info }
+    }
+
+    fn derive_file_key(&self, salt: &[u8; SALT_LEN], backend: &dyn CryptoBackend) -> [u8; KEY_LEN] {
+        let mut out = [0u8; KEY_LEN];
+        backend.hkdf_sha256(&self.master_key, salt, &self.info, &mut out);
+        out
+    }
+}
+
+fn chunk_nonce(file_nonce: &[u8; NONCE_LEN], chunk_index: u64) -> [u8; NONCE_LEN] {
+    let mut nonce = *file_nonce;
+    let idx = chunk_index.to_be_bytes();
+    for i in 0..8 {
+        nonce[NONCE_LEN - 8 + i] ^= idx[i];
+    }
+    nonce
+}
+
+#[derive(Debug)]
+pub struct CryptoError(pub String);
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sst cipher error: {}", self.0)
+    }
+}
+
+/// An encrypted tablet snapshot CF file, decrypted one chunk at a time on
+/// demand.
+pub struct EncryptedFile {
+    backend: &'static dyn CryptoBackend,
+    salt: [u8; SALT_LEN],
+    file_nonce: [u8; NONCE_LEN],
+    file_key: [u8; KEY_LEN],
+    // Offsets, within the underlying file, of the start of each ciphertext
+    // chunk (including its trailing tag), and the plaintext length of that
+    // chunk.
+    chunks: Vec<(u64, usize)>,
+}
+
+impl EncryptedFile {
+    /// Parses the header of an encrypted CF file: `salt (16B) || file_nonce
+    /// (12B)`, followed by `ceil(plain_len / CHUNK_SIZE)` chunks of
+    /// `CHUNK_SIZE + TAG_LEN` bytes (the last one may be shorter). Cipher
+    /// operations are dispatched through the process's active
+    /// [`CryptoBackend`], so the same envelope is readable regardless of
+    /// which crypto library the proxy was linked against.
+    pub fn open(ctx: CipherContext, header: &[u8], plain_len: usize) -> Result<Self, CryptoError> {
+        if header.len() < SALT_LEN + NONCE_LEN {
+            return Err(CryptoError("truncated sst cipher header".into()));
+        }
+        let backend = default_backend();
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&header[..SALT_LEN]);
+        let mut file_nonce = [0u8; NONCE_LEN];
+        file_nonce.copy_from_slice(&header[SALT_LEN..SALT_LEN + NONCE_LEN]);
+        let file_key = ctx.derive_file_key(&salt, backend);
+
+        let header_len = (SALT_LEN + NONCE_LEN) as u64;
+        let mut chunks = Vec::new();
+        let mut remaining = plain_len;
+        let mut offset = header_len;
+        while remaining > 0 {
+            let this_plain = remaining.min(CHUNK_SIZE);
+            chunks.push((offset, this_plain));
+            offset += (this_plain + TAG_LEN) as u64;
+            remaining -= this_plain;
+        }
+
+        Ok(EncryptedFile {
+            backend,
+            salt,
+            file_nonce,
+            file_key,
+            chunks,
+        })
+    }
+
+    pub fn header_len() -> usize {
+        SALT_LEN + NONCE_LEN
+    }
+
+    /// Maps a logical (plaintext) offset to the (chunk_index, offset_within_chunk).
+    pub fn locate(&self, logical_offset: usize) -> (usize, usize) {
+        let idx = logical_offset / CHUNK_SIZE;
+        let rem = logical_offset % CHUNK_SIZE;
+        (idx, rem)
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Returns `(file_offset, ciphertext_len)` of chunk `idx`, including its tag.
+    pub fn chunk_span(&self, idx: usize) -> Option<(u64, usize)> {
+        self.chunks
+            .get(idx)
+            .map(|(off, plain_len)| (*off, plain_len + TAG_LEN))
+    }
+
+    /// Decrypts and authenticates a single chunk. `sealed` must be exactly
+    /// the `plain_len + TAG_LEN` bytes returned by `chunk_span`.
+    pub fn open_chunk(&self, idx: usize, sealed: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let (_, plain_len) = *self
+            .chunks
+            .get(idx)
+            .ok_or_else(|| CryptoError("chunk index out of range".into()))?;
+        if sealed.len() != plain_len + TAG_LEN {
+            return Err(CryptoError("chunk length mismatch".into()));
+        }
+        let (ciphertext, tag) = sealed.split_at(plain_len);
+        let nonce = chunk_nonce(&self.file_nonce, idx as u64);
+        let mut tag_arr = [0u8; TAG_LEN];
+        tag_arr.copy_from_slice(tag);
+
+        self.backend
+            .aead_open(&self.file_key, &nonce, &[], ciphertext, &tag_arr)
+    }
+
+    pub fn salt(&self) -> &[u8; SALT_LEN] {
+        &self.salt
+    }
+}
+
+/// Write-side counterpart of [`EncryptedFile`]: seals a file's plaintext
+/// under a freshly generated salt/file-nonce, one `CHUNK_SIZE` chunk at a
+/// time, so the same header-then-chunks layout `EncryptedFile::open` parses
+/// back is produced without ever holding more than one chunk of plaintext
+/// or ciphertext in memory.
+pub struct EncryptedFileWriter {
+    backend: &'static dyn CryptoBackend,
+    salt: [u8; SALT_LEN],
+    file_nonce: [u8; NONCE_LEN],
+    file_key: [u8; KEY_LEN],
+}
+
+impl EncryptedFileWriter {
+    pub fn new(ctx: CipherContext) -> Self {
+        let backend = default_backend();
+        let mut salt = [0u8; SALT_LEN];
+        backend.random(&mut salt);
+        let mut file_nonce = [0u8; NONCE_LEN];
+        backend.random(&mut file_nonce);
+        let file_key = ctx.derive_file_key(&salt, backend);
+        EncryptedFileWriter {
+            backend,
+            salt,
+            file_nonce,
+            file_key,
+        }
+    }
+
+    /// `salt || file_nonce`, written once at the start of the file; its
+    /// length is [`EncryptedFile::header_len`].
+    pub fn header(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(SALT_LEN + NONCE_LEN);
+        out.extend_from_slice(&self.salt);
+        out.extend_from_slice(&self.file_nonce);
+        out
+    }
+
+    /// Seals chunk `idx` (at most `CHUNK_SIZE` plaintext bytes), returning
+    /// `ciphertext || tag` ready to append to the file.
+    pub fn seal_chunk(&self, idx: usize, plain: &[u8]) -> Vec<u8> {
+        let nonce = chunk_nonce(&self.file_nonce, idx as u64);
+        let (mut sealed, tag) = self.backend.aead_seal(&self.file_key, &nonce, &[], plain);
+        sealed.extend_from_slice(&tag);
+        sealed
+    }
+}
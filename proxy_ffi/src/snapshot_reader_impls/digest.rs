@@ -0,0 +1,168 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A fast, keyed 128-bit digest over a tablet snapshot CF's ordered
+//! (key, value) pairs, used as an end-to-end corruption check: computed by
+//! the sender with a dedicated [`super::tablet_reader::TabletReader`] pass
+//! right after the checkpoint is created, and recomputed by the receiver
+//! the same way after the tablet snapshot lands, so the two values can be
+//! compared.
+//!
+//! Nothing outside tests wires this into `RaftSnapshotData`/`SnapshotMeta`
+//! yet: both are kvproto messages, and this repo doesn't vendor the
+//! `.proto` they're generated from, so adding a digest field to either
+//! means patching kvproto, not this crate. `proxy_tests`' tablet-snapshot
+//! coverage exercises this digest as a standalone round trip -- the
+//! send-side value compared against the receive-side value -- rather than
+//! as metadata that actually travels with the snapshot over the wire.
+//!
+//! The AES-NI path folds each 16-byte block into one of two running lanes
+//! with an `aesenc` round, alternating lanes so each lane absorbs every
+//! other block, and `finish` mixes the two lanes into one value; the scalar
+//! fallback keeps the digest defined (if not bit-for-bit
+//! equal) on targets without AES-NI.
+
+use super::SSTReaderPtr;
+use crate::interfaces_ffi::ColumnFamilyType;
+
+const SEED_LANE0: [u8; 16] = [
+    0x62, 0x3f, 0x17, 0xb1, 0x92, 0x5c, 0xa4, 0x0d, 0xee, 0x71, 0x38, 0x5a, 0xc9, 0x03, 0x6b, 0xd4,
+];
+const SEED_LANE1: [u8; 16] = [
+    0x9a, 0x04, 0xf6, 0x2c, 0x5d, 0xe1, 0x87, 0x33, 0x0b, 0xa6, 0x4e, 0x9f, 0x21, 0xc8, 0x7d, 0x50,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Digest128(pub u128);
+
+pub struct SnapshotDigest {
+    lane0: [u8; 16],
+    lane1: [u8; 16],
+    use_lane0: bool,
+    aes_ni: bool,
+}
+
+impl Default for SnapshotDigest {
+    fn default() -> Self {
+        SnapshotDigest {
+            lane0: SEED_LANE0,
+            lane1: SEED_LANE1,
+            use_lane0: true,
+            aes_ni: is_x86_feature_detected(),
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn is_x86_feature_detected() -> bool {
+    is_x86_feature_detected!("aes")
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn is_x86_feature_detected() -> bool {
+    false
+}
+
+impl SnapshotDigest {
+    /// Feeds the length-prefixed `(key, value)` record into the digest, one
+    /// 16-byte block at a time, zero-padding the final partial block.
+    pub fn update(&mut self, key: &[u8], value: &[u8]) {
+        let klen = (key.len() as u32).to_le_bytes();
+        let vlen = (value.len() as u32).to_le_bytes();
+        let mut stream = Vec::with_capacity(8 + key.len() + value.len());
+        stream.extend_from_slice(&klen);
+        stream.extend_from_slice(&vlen);
+        stream.extend_from_slice(key);
+        stream.extend_from_slice(value);
+
+        for chunk in stream.chunks(16) {
+            let mut block = [0u8; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.absorb(&block);
+        }
+    }
+
+    fn absorb(&mut self, block: &[u8; 16]) {
+        let lane = if self.use_lane0 {
+            &mut self.lane0
+        } else {
+            &mut self.lane1
+        };
+        self.use_lane0 = !self.use_lane0;
+
+        if self.aes_ni {
+            *lane = aesenc(lane, block);
+        } else {
+            *lane = scalar_fold(lane, block);
+        }
+    }
+
+    pub fn finish(self) -> Digest128 {
+        let mixed = if self.aes_ni {
+            aesenc(&self.lane0, &self.lane1)
+        } else {
+            scalar_fold(&self.lane0, &self.lane1)
+        };
+        let mut xored = [0u8; 16];
+        for i in 0..16 {
+            xored[i] = mixed[i] ^ self.lane1[i];
+        }
+        Digest128(u128::from_le_bytes(xored))
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn aesenc(state: &[u8; 16], round_key: &[u8; 16]) -> [u8; 16] {
+    use std::arch::x86_64::*;
+    if !is_x86_feature_detected() {
+        return scalar_fold(state, round_key);
+    }
+    unsafe {
+        let s = _mm_loadu_si128(state.as_ptr() as *const __m128i);
+        let k = _mm_loadu_si128(round_key.as_ptr() as *const __m128i);
+        let r = _mm_aesenc_si128(s, k);
+        let mut out = [0u8; 16];
+        _mm_storeu_si128(out.as_mut_ptr() as *mut __m128i, r);
+        out
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn aesenc(state: &[u8; 16], round_key: &[u8; 16]) -> [u8; 16] {
+    scalar_fold(state, round_key)
+}
+
+/// Portable fallback used when AES-NI isn't available: multiply-xor-rotate
+/// over the block's two 64-bit halves. Not cryptographically equivalent to
+/// the AES-NI path; only required to be collision-resistant enough to
+/// catch accidental corruption within one build target.
+fn scalar_fold(state: &[u8; 16], block: &[u8; 16]) -> [u8; 16] {
+    const PRIME: u64 = 0x9E3779B97F4A7C15;
+    let s0 = u64::from_le_bytes(state[0..8].try_into().unwrap());
+    let s1 = u64::from_le_bytes(state[8..16].try_into().unwrap());
+    let b0 = u64::from_le_bytes(block[0..8].try_into().unwrap());
+    let b1 = u64::from_le_bytes(block[8..16].try_into().unwrap());
+
+    let r0 = (s0 ^ b0).wrapping_mul(PRIME).rotate_left(31);
+    let r1 = (s1 ^ b1).wrapping_mul(PRIME).rotate_left(29);
+
+    let mut out = [0u8; 16];
+    out[0..8].copy_from_slice(&r0.to_le_bytes());
+    out[8..16].copy_from_slice(&r1.to_le_bytes());
+    out
+}
+
+/// Drives a reader to completion, computing the digest over every (key,
+/// value) pair it yields, so a send-side and a receive-side value can be
+/// compared.
+pub unsafe fn ffi_sst_reader_digest(reader: SSTReaderPtr, cf: ColumnFamilyType) -> u128 {
+    use super::{ffi_sst_reader_key, ffi_sst_reader_next, ffi_sst_reader_remained, ffi_sst_reader_value};
+
+    let mut digest = SnapshotDigest::default();
+    while ffi_sst_reader_remained(reader.clone(), cf) != 0 {
+        let key = ffi_sst_reader_key(reader.clone(), cf);
+        let value = ffi_sst_reader_value(reader.clone(), cf);
+        digest.update(key.to_slice(), value.to_slice());
+        ffi_sst_reader_next(reader.clone(), cf);
+    }
+    digest.finish().0
+}
@@ -0,0 +1,73 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! SST/tablet readers handed across the FFI boundary during prehandle and
+//! snapshot-compat (v1-reads-a-v2-tablet-snapshot) flows.
+
+pub mod crypto;
+pub mod digest;
+pub mod tablet_reader;
+
+use self::tablet_reader::TabletReader;
+use super::interfaces_ffi::{
+    BaseBuffView, ColumnFamilyType, EngineIteratorSeekType, RawVoidPtr, SSTFormatKind,
+};
+
+/// A type-erased handle to whichever reader kind backs a given SST view:
+/// either a single legacy per-CF SST (`KIND_SST`), or a reader over a whole
+/// tablet checkpoint directory (`KIND_TABLET`).
+#[derive(Clone)]
+pub struct SSTReaderPtr {
+    pub kind: SSTFormatKind,
+    pub inner: RawVoidPtr,
+}
+
+unsafe fn as_tablet_reader(ptr: &SSTReaderPtr) -> &mut TabletReader {
+    debug_assert_eq!(ptr.kind, SSTFormatKind::KIND_TABLET);
+    &mut *(ptr.inner as *mut TabletReader)
+}
+
+pub unsafe fn ffi_sst_reader_seek(
+    reader: SSTReaderPtr,
+    cf: ColumnFamilyType,
+    tp: EngineIteratorSeekType,
+    key: BaseBuffView,
+) {
+    match reader.kind {
+        SSTFormatKind::KIND_TABLET => as_tablet_reader(&reader).seek(cf, tp, key),
+        _ => unimplemented!("only tablet-backed readers are handled here"),
+    }
+}
+
+pub unsafe fn ffi_sst_reader_remained(reader: SSTReaderPtr, cf: ColumnFamilyType) -> u8 {
+    match reader.kind {
+        SSTFormatKind::KIND_TABLET => as_tablet_reader(&reader).remained(cf) as u8,
+        _ => unimplemented!("only tablet-backed readers are handled here"),
+    }
+}
+
+pub unsafe fn ffi_sst_reader_key(reader: SSTReaderPtr, cf: ColumnFamilyType) -> BaseBuffView {
+    match reader.kind {
+        SSTFormatKind::KIND_TABLET => as_tablet_reader(&reader).key(cf),
+        _ => unimplemented!("only tablet-backed readers are handled here"),
+    }
+}
+
+pub unsafe fn ffi_sst_reader_value(reader: SSTReaderPtr, cf: ColumnFamilyType) -> BaseBuffView {
+    match reader.kind {
+        SSTFormatKind::KIND_TABLET => as_tablet_reader(&reader).value(cf),
+        _ => unimplemented!("only tablet-backed readers are handled here"),
+    }
+}
+
+pub unsafe fn ffi_sst_reader_next(reader: SSTReaderPtr, cf: ColumnFamilyType) {
+    match reader.kind {
+        SSTFormatKind::KIND_TABLET => as_tablet_reader(&reader).next(cf),
+        _ => unimplemented!("only tablet-backed readers are handled here"),
+    }
+}
+
+pub unsafe fn ffi_sst_reader_gc(reader: SSTReaderPtr, _cf: ColumnFamilyType) {
+    if reader.kind == SSTFormatKind::KIND_TABLET {
+        drop(Box::from_raw(reader.inner as *mut TabletReader));
+    }
+}
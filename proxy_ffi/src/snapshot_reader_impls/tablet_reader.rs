@@ -0,0 +1,331 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Reads (and, via [`encrypt_checkpoint`], seals) CF data in a tablet-
+//! snapshot checkpoint directory (v2's `TabletSnapManager` layout) for
+//! consumption by a v1 store.
+//!
+//! Encryption is sealed-in-place on the checkpoint directory itself (see
+//! [`encrypt_checkpoint`]) so there's never a plaintext copy on the sender
+//! side beyond the tablet it was checkpointed from. On the read side, a
+//! reader opened over an encrypted checkpoint stays in a `Pending` state
+//! until the first real call (`seek`/`remained`/...) instead of decrypting
+//! at construction time. Decryption reads the sealed file into memory
+//! whole, then authenticates and writes the plaintext one `CHUNK_SIZE`
+//! chunk at a time.
+//! RocksDB still needs the fully-decrypted SST file on disk to open it (no
+//! encrypted-`Env` hook is wired up here), so that scratch copy exists for
+//! as long as the reader does; it's removed on drop rather than left for
+//! `ffi_sst_reader_gc` to forget about.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use engine_rocks::{util::new_engine_opt, RocksEngineIterator};
+use engine_traits::{IterOptions, Iterable, Iterator as _, CF_DEFAULT, CF_LOCK, CF_WRITE};
+use tikv_util::error;
+
+use super::{
+    crypto::{CipherContext, EncryptedFile, EncryptedFileWriter, CHUNK_SIZE, TAG_LEN},
+    SSTReaderPtr,
+};
+use crate::interfaces_ffi::{
+    BaseBuffView, ColumnFamilyType, EngineIteratorSeekType, RawVoidPtr, SSTFormatKind,
+};
+
+fn cf_name(cf: ColumnFamilyType) -> &'static str {
+    match cf {
+        ColumnFamilyType::Lock => CF_LOCK,
+        ColumnFamilyType::Write => CF_WRITE,
+        ColumnFamilyType::Default => CF_DEFAULT,
+    }
+}
+
+fn is_cf_file(name: &std::ffi::OsStr, cf: ColumnFamilyType) -> bool {
+    name.to_string_lossy().contains(cf_name(cf))
+}
+
+/// Decrypts `raw` (header + all chunks) straight into `dst`, one chunk at a
+/// time: each chunk is authenticated and written before the next is
+/// touched, so a tag mismatch is reported (as an `Err`, never a panic)
+/// without having decrypted the rest of the file.
+fn decrypt_file_streaming(
+    ctx: &CipherContext,
+    raw: &[u8],
+    dst: &mut impl Write,
+) -> Result<(), String> {
+    let header_len = EncryptedFile::header_len();
+    if raw.len() < header_len {
+        return Err("truncated encrypted sst file".into());
+    }
+    // The plaintext length isn't known up front from the ciphertext alone;
+    // derive it from the remaining bytes, since every chunk but the last is
+    // exactly CHUNK_SIZE plaintext bytes plus its tag.
+    let body = &raw[header_len..];
+    let full_chunks = body.len() / (CHUNK_SIZE + TAG_LEN);
+    let remainder = body.len() - full_chunks * (CHUNK_SIZE + TAG_LEN);
+    let plain_len = full_chunks * CHUNK_SIZE + remainder.saturating_sub(TAG_LEN);
+
+    let file = EncryptedFile::open(ctx.clone(), raw, plain_len).map_err(|e| e.to_string())?;
+    for idx in 0..file.chunk_count() {
+        let (offset, len) = file
+            .chunk_span(idx)
+            .ok_or_else(|| format!("chunk {} out of range", idx))?;
+        let sealed = raw
+            .get(offset as usize..offset as usize + len)
+            .ok_or_else(|| "encrypted sst file truncated mid-chunk".to_string())?;
+        let plain = file
+            .open_chunk(idx, sealed)
+            .map_err(|e| format!("tablet snapshot sst corrupted at chunk {}: {}", idx, e))?;
+        dst.write_all(&plain).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// The write-side counterpart: seals `plain` into the same
+/// header-then-chunks envelope `decrypt_file_streaming` reads back, one
+/// `CHUNK_SIZE` chunk at a time.
+fn encrypt_file_streaming(
+    ctx: &CipherContext,
+    plain: &[u8],
+    dst: &mut impl Write,
+) -> Result<(), String> {
+    let writer = EncryptedFileWriter::new(ctx.clone());
+    dst.write_all(&writer.header()).map_err(|e| e.to_string())?;
+    if plain.is_empty() {
+        return Ok(());
+    }
+    for (idx, chunk) in plain.chunks(CHUNK_SIZE).enumerate() {
+        let sealed = writer.seal_chunk(idx, chunk);
+        dst.write_all(&sealed).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Seals `cf`'s on-disk file(s) under `path` in place with `ctx`, so the
+/// checkpoint directory itself becomes the "encrypted at rest" artifact
+/// [`decrypt_into_scratch`] later reads back. Each matching file is sealed
+/// into a sibling temp file and only swapped in via `rename` once fully
+/// written, so a crash or error partway through never leaves a half-sealed
+/// file in the checkpoint's real path.
+pub fn encrypt_checkpoint(path: &str, cf: ColumnFamilyType, ctx: &CipherContext) -> Result<(), String> {
+    let dir = Path::new(path);
+    for entry in std::fs::read_dir(dir).map_err(|e| format!("read tablet snapshot checkpoint dir: {}", e))? {
+        let entry = entry.map_err(|e| format!("read tablet snapshot checkpoint entry: {}", e))?;
+        let name = entry.file_name();
+        if !is_cf_file(&name, cf) {
+            continue;
+        }
+        let plain = std::fs::read(entry.path()).map_err(|e| format!("read tablet snapshot cf file: {}", e))?;
+        let sealed_path = entry.path().with_extension("sealing");
+        {
+            let mut out = std::fs::File::create(&sealed_path)
+                .map_err(|e| format!("create sealed tablet snapshot cf file: {}", e))?;
+            encrypt_file_streaming(ctx, &plain, &mut out)?;
+        }
+        std::fs::rename(&sealed_path, entry.path())
+            .map_err(|e| format!("replace tablet snapshot cf file with sealed copy: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Decrypts the on-disk CF file(s) for `cf` under `src` into a sibling
+/// `.decrypted` scratch directory, copying the rest of the checkpoint's
+/// file layout untouched, and returns that scratch directory. Cleans up
+/// after itself on any failure, rather than leaving a half-written scratch
+/// dir with nothing left to own (and eventually remove) it.
+fn decrypt_into_scratch(src: &str, cf: ColumnFamilyType, ctx: &CipherContext) -> Result<PathBuf, String> {
+    let src = Path::new(src);
+    let dst = src.with_extension("decrypted");
+    match decrypt_into(src, &dst, cf, ctx) {
+        Ok(()) => Ok(dst),
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&dst);
+            Err(e)
+        }
+    }
+}
+
+fn decrypt_into(src: &Path, dst: &Path, cf: ColumnFamilyType, ctx: &CipherContext) -> Result<(), String> {
+    std::fs::create_dir_all(dst)
+        .map_err(|e| format!("create scratch dir for decrypted tablet snapshot: {}", e))?;
+    for entry in std::fs::read_dir(src).map_err(|e| format!("read tablet snapshot checkpoint dir: {}", e))? {
+        let entry = entry.map_err(|e| format!("read tablet snapshot checkpoint entry: {}", e))?;
+        let name = entry.file_name();
+        let mut out = std::fs::File::create(dst.join(&name))
+            .map_err(|e| format!("create decrypted tablet snapshot cf file: {}", e))?;
+        if is_cf_file(&name, cf) {
+            let raw = std::fs::read(entry.path()).map_err(|e| format!("read tablet snapshot cf file: {}", e))?;
+            decrypt_file_streaming(ctx, &raw, &mut out)?;
+        } else {
+            let mut src_file = std::fs::File::open(entry.path())
+                .map_err(|e| format!("read tablet snapshot checkpoint entry: {}", e))?;
+            std::io::copy(&mut src_file, &mut out)
+                .map_err(|e| format!("copy tablet snapshot checkpoint entry: {}", e))?;
+        }
+    }
+    Ok(())
+}
+
+fn open_checkpoint(path: &str, cf: ColumnFamilyType, scratch_dir: Option<PathBuf>) -> ReaderState {
+    let db = match new_engine_opt(path, engine_rocks::RocksDbOptions::default(), vec![]) {
+        Ok(db) => db,
+        Err(e) => {
+            error!("open tablet snapshot checkpoint failed"; "path" => path, "err" => ?e);
+            return ReaderState::Failed;
+        }
+    };
+    match db.iterator_opt(cf_name(cf), IterOptions::default()) {
+        Ok(iter) => ReaderState::Open {
+            iter,
+            valid: false,
+            scratch_dir,
+        },
+        Err(e) => {
+            error!("iterate tablet snapshot cf failed"; "path" => path, "err" => ?e);
+            ReaderState::Failed
+        }
+    }
+}
+
+enum ReaderState {
+    /// Backed by a live RocksDB iterator. `scratch_dir` is `Some` only when
+    /// this checkpoint was decrypted into a scratch copy, which is removed
+    /// when the reader (and so this state) is dropped.
+    Open {
+        iter: RocksEngineIterator,
+        valid: bool,
+        scratch_dir: Option<PathBuf>,
+    },
+    /// An encrypted checkpoint whose CF file hasn't been decrypted yet.
+    /// Decryption -- and the RocksDB open it gates -- is deferred to the
+    /// first real call instead of happening at construction, so a reader
+    /// that's opened but never driven never pays for either.
+    Pending {
+        path: String,
+        cf: ColumnFamilyType,
+        cipher: CipherContext,
+    },
+    /// Decryption failed (truncated header, bad tag, missing file, ...), or
+    /// the checkpoint or its CF iterator could not be opened.
+    /// Every access behaves like an exhausted iterator instead of
+    /// panicking or propagating the error across the FFI boundary.
+    Failed,
+}
+
+/// Iterates one column family of a tablet checkpoint directory, exposing
+/// the same `seek`/`remained`/`key`/`value`/`next` surface the legacy
+/// per-file SST reader exposes, so the v1 side doesn't need to distinguish
+/// which kind of snapshot it received.
+pub struct TabletReader {
+    state: ReaderState,
+}
+
+impl TabletReader {
+    /// `cipher` carries the master key and per-file HKDF info when `cf`'s
+    /// checkpoint file is encrypted; `None` reads the tablet as a plain
+    /// RocksDB checkpoint, as today.
+    pub unsafe fn ffi_get_cf_file_reader(
+        path: &str,
+        cf: ColumnFamilyType,
+        cipher: Option<CipherContext>,
+    ) -> SSTReaderPtr {
+        let state = match cipher {
+            None => open_checkpoint(path, cf, None),
+            Some(cipher) => ReaderState::Pending {
+                path: path.to_owned(),
+                cf,
+                cipher,
+            },
+        };
+        let reader = TabletReader { state };
+        SSTReaderPtr {
+            kind: SSTFormatKind::KIND_TABLET,
+            inner: Box::into_raw(Box::new(reader)) as RawVoidPtr,
+        }
+    }
+
+    /// Moves a `Pending` reader to `Open` (or `Failed`) by decrypting its
+    /// CF file into a scratch checkpoint. A no-op once no longer `Pending`.
+    fn ensure_open(&mut self) {
+        let (path, cf, cipher) = match &self.state {
+            ReaderState::Pending { path, cf, cipher } => (path.clone(), *cf, cipher.clone()),
+            _ => return,
+        };
+        self.state = match decrypt_into_scratch(&path, cf, &cipher) {
+            Ok(dst) => {
+                let dst_str = match dst.to_str() {
+                    Some(s) => s.to_owned(),
+                    None => {
+                        error!("decrypted tablet snapshot scratch path is not utf-8"; "path" => ?dst);
+                        let _ = std::fs::remove_dir_all(&dst);
+                        self.state = ReaderState::Failed;
+                        return;
+                    }
+                };
+                open_checkpoint(&dst_str, cf, Some(dst))
+            }
+            Err(e) => {
+                error!("decrypt tablet snapshot checkpoint failed"; "path" => &path, "err" => %e);
+                ReaderState::Failed
+            }
+        };
+    }
+
+    pub unsafe fn seek(&mut self, _cf: ColumnFamilyType, tp: EngineIteratorSeekType, key: BaseBuffView) {
+        self.ensure_open();
+        if let ReaderState::Open { iter, valid, .. } = &mut self.state {
+            *valid = match tp {
+                EngineIteratorSeekType::Key => iter.seek(key.to_slice()).unwrap_or(false),
+                _ => iter.seek_to_first().unwrap_or(false),
+            };
+        }
+    }
+
+    pub unsafe fn remained(&mut self, _cf: ColumnFamilyType) -> bool {
+        self.ensure_open();
+        match &mut self.state {
+            ReaderState::Open { iter, valid, .. } => *valid && iter.valid().unwrap_or(false),
+            ReaderState::Pending { .. } | ReaderState::Failed => false,
+        }
+    }
+
+    pub unsafe fn key(&mut self, _cf: ColumnFamilyType) -> BaseBuffView {
+        match &mut self.state {
+            ReaderState::Open { iter, .. } => iter.key().into(),
+            ReaderState::Pending { .. } | ReaderState::Failed => BaseBuffView {
+                data: std::ptr::null(),
+                len: 0,
+            },
+        }
+    }
+
+    pub unsafe fn value(&mut self, _cf: ColumnFamilyType) -> BaseBuffView {
+        match &mut self.state {
+            ReaderState::Open { iter, .. } => iter.value().into(),
+            ReaderState::Pending { .. } | ReaderState::Failed => BaseBuffView {
+                data: std::ptr::null(),
+                len: 0,
+            },
+        }
+    }
+
+    pub unsafe fn next(&mut self, _cf: ColumnFamilyType) {
+        if let ReaderState::Open { iter, valid, .. } = &mut self.state {
+            *valid = iter.next().unwrap_or(false);
+        }
+    }
+}
+
+impl Drop for TabletReader {
+    fn drop(&mut self) {
+        if let ReaderState::Open {
+            scratch_dir: Some(dir),
+            ..
+        } = &self.state
+        {
+            if let Err(e) = std::fs::remove_dir_all(dir) {
+                error!("failed to remove decrypted tablet snapshot scratch dir"; "path" => ?dir, "err" => ?e);
+            }
+        }
+    }
+}
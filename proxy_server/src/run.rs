@@ -3,13 +3,14 @@
 #![allow(clippy::redundant_clone)]
 use std::{
     cmp,
+    collections::VecDeque,
     convert::TryFrom,
     env, fmt,
     net::SocketAddr,
     path::{Path, PathBuf},
     str::FromStr,
     sync::{
-        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering},
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering},
         mpsc, Arc, Mutex,
     },
     thread,
@@ -18,6 +19,8 @@ use std::{
 };
 
 use api_version::{dispatch_api_version, KvFormat};
+use backup::Endpoint as BackupEndpoint;
+use collections::HashMap;
 use concurrency_manager::ConcurrencyManager;
 use encryption_export::{data_key_manager_from_config, DataKeyManager};
 use engine_rocks::{
@@ -42,8 +45,8 @@ use engine_store_ffi::{
 use engine_tiflash::PSLogEngine;
 use engine_traits::{
     CachedTablet, CfOptionsExt, Engines, FlowControlFactorsExt, KvEngine, MiscExt, RaftEngine,
-    SingletonFactory, StatisticsReporter, TabletContext, TabletRegistry, CF_DEFAULT, CF_LOCK,
-    CF_WRITE,
+    SingletonFactory, SstReader, StatisticsReporter, TabletContext, TabletRegistry, CF_DEFAULT,
+    CF_LOCK, CF_WRITE,
 };
 use error_code::ErrorCodeExt;
 use file_system::{
@@ -52,9 +55,10 @@ use file_system::{
 };
 use futures::executor::block_on;
 use grpcio::{EnvBuilder, Environment};
-use grpcio_health::HealthService;
+use grpcio_health::{HealthService, ServingStatus};
 use kvproto::{
-    debugpb::create_debug, diagnosticspb::create_diagnostics, import_sstpb::create_import_sst,
+    backuppb::create_backup, debugpb::create_debug, diagnosticspb::create_diagnostics,
+    import_sstpb::create_import_sst,
 };
 use pd_client::{PdClient, RpcClient};
 use raft_log_engine::RaftLogEngine;
@@ -128,7 +132,8 @@ pub fn run_impl<CER: ConfiguredRaftEngine, F: KvFormat>(
     engine_store_server_helper: &EngineStoreServerHelper,
 ) {
     let engine_store_server_helper_ptr = engine_store_server_helper as *const _ as isize;
-    let mut tikv = TiKvServer::<CER>::init(config, proxy_config, engine_store_server_helper_ptr);
+    let mut tikv =
+        TiKvServer::<RpcClient, CER>::init(config, proxy_config, engine_store_server_helper_ptr);
 
     // Must be called after `TiKvServer::init`.
     let memory_limit = tikv.config.memory_usage_limit.unwrap().0;
@@ -201,6 +206,13 @@ pub fn run_impl<CER: ConfiguredRaftEngine, F: KvFormat>(
 
     proxy.set_status(RaftProxyStatus::Running);
 
+    // Both sides are up: report SERVING on the standard `grpc.health.v1.Health`
+    // service so k8s probes and load balancers can key off it directly instead
+    // of scraping logs or the metrics port.
+    if let Some(health_service) = tikv.health_service.as_ref() {
+        health_service.set_serving_status("", ServingStatus::Serving);
+    }
+
     {
         debug_assert!(
             engine_store_server_helper.handle_get_engine_store_server_status()
@@ -211,6 +223,9 @@ pub fn run_impl<CER: ConfiguredRaftEngine, F: KvFormat>(
             if engine_store_server_helper.handle_get_engine_store_server_status()
                 != EngineStoreServerStatus::Running
             {
+                if let Some(health_service) = tikv.health_service.as_ref() {
+                    health_service.set_serving_status("", ServingStatus::NotServing);
+                }
                 break;
             }
             thread::sleep(Duration::from_millis(200));
@@ -415,6 +430,18 @@ impl<CER: ConfiguredRaftEngine> TiKvServer<CER> {
         flow_listener: engine_rocks::FlowListener,
         engine_store_server_helper: isize,
     ) -> (Engines<TiFlashEngine, CER>, Arc<EnginesResourceInfo>) {
+        // `TiFlashEngine` wraps one process-wide RocksDB instance reached
+        // through `SingletonFactory`/`TabletContext::with_infinite_region`
+        // below, and the engine-store FFI hub is initialized exactly once
+        // for it (`kv_engine.init(...)`). Real partitioned-raft-kv support
+        // would need a per-region `TabletRegistry` whose factory creates a
+        // TiFlash-wrapped tablet per region id + suffix, with the hub
+        // initialized once per tablet rather than once globally — neither
+        // of which this bridge implements, so fail loudly here instead of
+        // silently running every region's apply against one shared tablet.
+        if self.config.storage.engine == tikv::config::EngineType::RaftKv2 {
+            fatal!("partitioned-raft-kv (multi-tablet) storage engine is not yet supported by the TiFlash proxy");
+        }
         let block_cache = self
             .config
             .storage
@@ -493,7 +520,9 @@ impl<CER: ConfiguredRaftEngine> TiKvServer<CER> {
         engines.raft.register_config(cfg_controller);
 
         let engines_info = Arc::new(EnginesResourceInfo::new(
-            &engines, 180, // max_samples_to_preserve
+            &engines,
+            180, // max_samples_to_preserve
+            self.config.rocksdb.titan.enabled,
         ));
 
         (engines, engines_info)
@@ -503,18 +532,314 @@ impl<CER: ConfiguredRaftEngine> TiKvServer<CER> {
 const RESERVED_OPEN_FDS: u64 = 1000;
 
 const DEFAULT_METRICS_FLUSH_INTERVAL: Duration = Duration::from_millis(10_000);
+lazy_static::lazy_static! {
+    /// Configured GC write-rate cap, in bytes/sec (0 = unlimited), mirrored
+    /// here so it shows up alongside the rest of the proxy's metrics instead
+    /// of only being discoverable from the startup log.
+    static ref GC_MAX_WRITE_BYTES_PER_SEC: prometheus::IntGauge = prometheus::register_int_gauge!(
+        "tiflash_proxy_gc_max_write_bytes_per_sec",
+        "Configured GC write-rate limit in bytes per second, 0 meaning unlimited"
+    ).unwrap();
+}
+
+/// Current activity of a registered background worker, as last reported by
+/// whoever drives it (there's no hook into `Worker`/`LazyWorker` itself to
+/// infer this automatically).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WorkerState {
+    Active,
+    Idle,
+    Dead,
+}
+
+/// How a registered worker is driven. `Interval` entries carry their own
+/// period so [`WorkerRegistry::list`] can flag one as missed/dead purely
+/// from elapsed wall-clock time, without needing an explicit `mark_dead`
+/// call from a thread that may itself have wedged or panicked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WorkerKind {
+    Interval { period: Duration },
+    Lazy,
+}
+
+/// An interval task is considered dead once it has missed this many
+/// consecutive ticks, i.e. gone quiet for longer than `period * this`.
+const MISSED_TICKS_DEAD_THRESHOLD: u32 = 5;
+
+/// Point-in-time view of one registered worker, as returned by
+/// [`WorkerRegistry::list`].
+#[derive(Debug, Clone)]
+struct WorkerSnapshot {
+    name: String,
+    kind: WorkerKind,
+    state: WorkerState,
+    pausable: bool,
+    paused: bool,
+    last_activity: Instant,
+    iterations: u64,
+    last_error: Option<String>,
+}
+
+struct WorkerEntry {
+    kind: WorkerKind,
+    state: Mutex<WorkerState>,
+    pausable: bool,
+    paused: Arc<AtomicBool>,
+    last_activity: Mutex<Instant>,
+    iterations: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+impl WorkerEntry {
+    #[allow(dead_code)]
+    fn mark_active(&self) {
+        *self.state.lock().unwrap() = WorkerState::Active;
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    #[allow(dead_code)]
+    fn mark_idle(&self) {
+        *self.state.lock().unwrap() = WorkerState::Idle;
+    }
+
+    fn mark_dead(&self, err: impl Into<String>) {
+        *self.state.lock().unwrap() = WorkerState::Dead;
+        *self.last_error.lock().unwrap() = Some(err.into());
+    }
+
+    /// Called by an interval task's own closure on every tick. Updates
+    /// `last_activity`/`iterations` directly rather than going through
+    /// `mark_active`, since a tick isn't necessarily a state transition.
+    fn tick(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+        self.iterations.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Effective state as of right now: an explicitly-recorded `Dead`
+    /// always wins, otherwise an `Interval` worker that has gone quiet for
+    /// more than `MISSED_TICKS_DEAD_THRESHOLD` periods is reported dead even
+    /// though nothing ever called `mark_dead` on it.
+    fn effective_state(&self) -> WorkerState {
+        let recorded = *self.state.lock().unwrap();
+        if recorded == WorkerState::Dead {
+            return recorded;
+        }
+        if let WorkerKind::Interval { period } = self.kind {
+            let elapsed = self.last_activity.lock().unwrap().elapsed();
+            if elapsed > period * MISSED_TICKS_DEAD_THRESHOLD {
+                return WorkerState::Dead;
+            }
+        }
+        recorded
+    }
+}
+
+/// A lightweight catalog of this proxy's background workers (`background_worker`,
+/// `pd_worker`, `ttl_checker`, `sst_worker`, the resource-metering workers,
+/// `check-leader`, GC, backup, ...) so an operator can see what's running and
+/// its last-known state without attaching a profiler.
+///
+/// `Worker`/`LazyWorker` (from `tikv_util::worker`, not part of this extracted
+/// snapshot) have no built-in activity callbacks, so entries are only as
+/// fresh as whoever calls `mark_active`/`mark_idle`/`mark_dead` on them;
+/// today that's just registration time for most workers. Entries registered
+/// as pausable (gc, ttl-checker, cdc, resolved-ts, sst-recovery, sst-scrub)
+/// accept pause/resume, which sets the cooperative `paused` flag each entry
+/// carries. Only the sst-scrub loop reads that flag; the `Runnable` impls of
+/// the other pausable workers live outside this crate and do not consult
+/// it, so pausing them changes only the state the registry reports.
+#[derive(Default)]
+struct WorkerRegistry {
+    entries: Mutex<HashMap<String, Arc<WorkerEntry>>>,
+}
+
+impl WorkerRegistry {
+    fn register(&self, name: &str, pausable: bool) -> Arc<WorkerEntry> {
+        self.register_with_kind(name, pausable, WorkerKind::Lazy)
+    }
+
+    /// Registers an interval task (one driven by
+    /// `Worker::spawn_interval_task`) so [`WorkerEntry::effective_state`] can
+    /// flag it dead purely from having missed `period`-spaced ticks, in
+    /// addition to the explicit `mark_dead` every other worker relies on.
+    fn register_interval(&self, name: &str, pausable: bool, period: Duration) -> Arc<WorkerEntry> {
+        self.register_with_kind(name, pausable, WorkerKind::Interval { period })
+    }
+
+    fn register_with_kind(&self, name: &str, pausable: bool, kind: WorkerKind) -> Arc<WorkerEntry> {
+        let entry = Arc::new(WorkerEntry {
+            kind,
+            state: Mutex::new(WorkerState::Active),
+            pausable,
+            paused: Arc::new(AtomicBool::new(false)),
+            last_activity: Mutex::new(Instant::now()),
+            iterations: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+        });
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), entry.clone());
+        entry
+    }
+
+    fn list(&self) -> Vec<WorkerSnapshot> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, entry)| WorkerSnapshot {
+                name: name.clone(),
+                kind: entry.kind,
+                state: entry.effective_state(),
+                pausable: entry.pausable,
+                paused: entry.paused.load(Ordering::SeqCst),
+                last_activity: *entry.last_activity.lock().unwrap(),
+                iterations: entry.iterations.load(Ordering::SeqCst),
+                last_error: entry.last_error.lock().unwrap().clone(),
+            })
+            .collect()
+    }
+
+    fn pause(&self, name: &str) -> Result<(), String> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries
+            .get(name)
+            .ok_or_else(|| format!("no such worker: {}", name))?;
+        if !entry.pausable {
+            return Err(format!("worker {} does not support pausing", name));
+        }
+        entry.paused.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn resume(&self, name: &str) -> Result<(), String> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries
+            .get(name)
+            .ok_or_else(|| format!("no such worker: {}", name))?;
+        entry.paused.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+lazy_static::lazy_static! {
+    /// Configured I/O rate limit in bytes/sec, mirroring
+    /// [`GC_MAX_WRITE_BYTES_PER_SEC`]: the config itself is already live-
+    /// reloadable through the `StorageConfigManger` this crate registers,
+    /// but nothing previously exported the value actually in effect.
+    static ref IO_RATE_LIMIT_MAX_BYTES_PER_SEC: prometheus::IntGauge = prometheus::register_int_gauge!(
+        "tiflash_proxy_io_rate_limit_max_bytes_per_sec",
+        "Configured storage I/O rate limit in bytes per second, 0 meaning unlimited"
+    ).unwrap();
+}
+
 const DEFAULT_MEMTRACE_FLUSH_INTERVAL: Duration = Duration::from_millis(1_000);
 const DEFAULT_ENGINE_METRICS_RESET_INTERVAL: Duration = Duration::from_millis(60_000);
 const DEFAULT_STORAGE_STATS_INTERVAL: Duration = Duration::from_secs(1);
 
+/// How often [`TiKvServer::init_sst_scrub_worker`] walks the whole KV data
+/// directory once. Real operators would want this tied to a config field
+/// (e.g. `storage.background-error-recovery-window`'s sibling), but
+/// `TikvConfig`/`ProxyConfig` aren't part of this extracted snapshot, so
+/// it's a constant for now.
+const DEFAULT_SST_SCRUB_CYCLE: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Default scrub pacing: no throttling. Adjustable at runtime through
+/// [`TiKvServer::set_sst_scrub_tranquility`].
+const DEFAULT_SST_SCRUB_NANOS_PER_BYTE: u64 = 0;
+
+/// Runtime-adjustable pacing knob for the proactive `sst-scrub` worker: how
+/// long it sleeps per byte of each SST file it reads, so a large scrub pass
+/// can be slowed down without being stopped outright. A real "changeable via
+/// the status server, persisted across restarts" knob (as asked for) would
+/// need `StatusServer`'s route table and a place to persist it across
+/// restarts — neither of which exist in this extracted snapshot — so this
+/// is exposed only as an in-process setter on [`TiKvServer`] for now.
+struct SstScrubControl {
+    nanos_per_byte: AtomicU64,
+}
+
+impl SstScrubControl {
+    fn new(nanos_per_byte: u64) -> Self {
+        SstScrubControl {
+            nanos_per_byte: AtomicU64::new(nanos_per_byte),
+        }
+    }
+}
+
+/// Walks every `.sst` file under `dir`, verifying its checksum and handing
+/// off the path of any corrupt file to `scheduler` (the same
+/// `Scheduler<String>` the reactive `sst-recovery` worker already consumes
+/// background-error reports from). Paced by `control`'s tranquility knob and
+/// cooperatively stoppable through `entry`'s pause flag, which
+/// [`WorkerRegistry::pause`] sets.
+fn scrub_sst_directory(
+    dir: &Path,
+    scheduler: &Scheduler<String>,
+    control: &SstScrubControl,
+    entry: &WorkerEntry,
+) {
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let read_dir = match std::fs::read_dir(&dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) => {
+                entry.mark_dead(format!("failed to read {}: {}", dir.display(), e));
+                return;
+            }
+        };
+        for item in read_dir {
+            if entry.paused.load(Ordering::SeqCst) {
+                return;
+            }
+            let item = match item {
+                Ok(item) => item,
+                Err(_) => continue,
+            };
+            let path = item.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            if path.extension().and_then(|e| e.to_str()) != Some("sst") {
+                continue;
+            }
+
+            let nanos_per_byte = control.nanos_per_byte.load(Ordering::SeqCst);
+            if nanos_per_byte > 0 {
+                let len = item.metadata().map(|m| m.len()).unwrap_or(0);
+                thread::sleep(Duration::from_nanos(nanos_per_byte.saturating_mul(len)));
+            }
+
+            let path_str = path.to_string_lossy().into_owned();
+            let verified =
+                <TiFlashEngine as KvEngine>::SstReader::open(&path_str).and_then(|r| r.verify_checksum());
+            if let Err(e) = verified {
+                warn!("sst-scrub found a corrupt SST file"; "path" => %path_str, "err" => ?e);
+                if let Err(e) = scheduler.schedule(path_str.clone()) {
+                    warn!("sst-scrub failed to hand corrupt file off to sst-recovery";
+                        "path" => %path_str, "err" => ?e);
+                }
+            }
+            entry.tick();
+        }
+    }
+}
+
 /// A complete TiKV server.
-struct TiKvServer<ER: RaftEngine> {
+///
+/// Generic over the PD client type `C` (rather than hard-coding
+/// `RpcClient`) so the bootstrap path can be driven by a mock PD client in
+/// tests without blocking on a real cluster's `get_tso()`. Production
+/// startup always instantiates `C = RpcClient` through [`TiKvServer::init`].
+struct TiKvServer<C: PdClient + 'static, ER: RaftEngine> {
     config: TikvConfig,
     proxy_config: ProxyConfig,
     engine_store_server_helper_ptr: isize,
     cfg_controller: Option<ConfigController>,
     security_mgr: Arc<SecurityManager>,
-    pd_client: Arc<RpcClient>,
+    pd_client: Arc<C>,
     router: RaftRouter<TiFlashEngine, ER>,
     flow_info_sender: Option<mpsc::Sender<FlowInfo>>,
     flow_info_receiver: Option<mpsc::Receiver<FlowInfo>>,
@@ -526,7 +851,7 @@ struct TiKvServer<ER: RaftEngine> {
     engines: Option<TiKvEngines<TiFlashEngine, ER>>,
     kv_statistics: Option<Arc<RocksStatistics>>,
     raft_statistics: Option<Arc<RocksStatistics>>,
-    servers: Option<Servers<TiFlashEngine, ER>>,
+    servers: Option<Servers<C, TiFlashEngine, ER>>,
     region_info_accessor: RegionInfoAccessor,
     coprocessor_host: Option<CoprocessorHost<TiFlashEngine>>,
     to_stop: Vec<Box<dyn Stop>>,
@@ -538,6 +863,16 @@ struct TiKvServer<ER: RaftEngine> {
     quota_limiter: Arc<QuotaLimiter>,
     resource_manager: Option<Arc<ResourceGroupManager>>,
     tablet_registry: Option<TabletRegistry<RocksEngine>>,
+    backup_worker: Option<Box<LazyWorker<backup::Task>>>,
+    worker_registry: Arc<WorkerRegistry>,
+    /// Handle to the `grpc.health.v1.Health` service registered on `server`
+    /// in `init_servers`, kept around so `run_impl`'s status-polling loop can
+    /// flip it to `SERVING`/`NOT_SERVING` as engine-store and the proxy
+    /// itself come up and go down.
+    health_service: Option<HealthService>,
+    /// Tranquility pacing shared with the `sst-scrub` background worker
+    /// spawned by `init_sst_scrub_worker`.
+    sst_scrub_control: Arc<SstScrubControl>,
 }
 
 struct TiKvEngines<EK: KvEngine, ER: RaftEngine> {
@@ -546,23 +881,26 @@ struct TiKvEngines<EK: KvEngine, ER: RaftEngine> {
     engine: RaftKv<EK, ServerRaftStoreRouter<EK, ER>>,
 }
 
-struct Servers<EK: KvEngine, ER: RaftEngine> {
+struct Servers<C: PdClient + 'static, EK: KvEngine, ER: RaftEngine> {
     lock_mgr: LockManager,
     server: LocalServer<EK, ER>,
-    node: Node<RpcClient, EK, ER>,
+    node: Node<C, EK, ER>,
     importer: Arc<SstImporter>,
 }
 
 type LocalServer<EK, ER> = Server<resolve::PdStoreAddrResolver, LocalRaftKv<EK, ER>>;
 type LocalRaftKv<EK, ER> = RaftKv<EK, ServerRaftStoreRouter<EK, ER>>;
 
-impl<ER: RaftEngine> TiKvServer<ER> {
+/// Production entry point: always drives the bootstrap with a real PD
+/// connection. Kept in its own `RpcClient`-specific impl block, separate
+/// from the generic one below, so [`TiKvServer::init_with_pd_client`] can
+/// stay agnostic to how `pd_client` was obtained.
+impl<ER: RaftEngine> TiKvServer<RpcClient, ER> {
     fn init(
         mut config: TikvConfig,
         proxy_config: ProxyConfig,
         engine_store_server_helper_ptr: isize,
-    ) -> TiKvServer<ER> {
-        tikv_util::thread_group::set_properties(Some(GroupProperties::default()));
+    ) -> TiKvServer<RpcClient, ER> {
         // It is okay use pd config and security config before `init_config`,
         // because these configs must be provided by command line, and only
         // used during startup process.
@@ -578,6 +916,56 @@ impl<ER: RaftEngine> TiKvServer<ER> {
         );
         let pd_client =
             Self::connect_to_pd_cluster(&mut config, env.clone(), Arc::clone(&security_mgr));
+        Self::init_with_pd_client(
+            config,
+            proxy_config,
+            engine_store_server_helper_ptr,
+            pd_client,
+            security_mgr,
+            env,
+        )
+    }
+
+    fn connect_to_pd_cluster(
+        config: &mut TikvConfig,
+        env: Arc<Environment>,
+        security_mgr: Arc<SecurityManager>,
+    ) -> Arc<RpcClient> {
+        let pd_client = Arc::new(
+            RpcClient::new(&config.pd, Some(env), security_mgr)
+                .unwrap_or_else(|e| fatal!("failed to create rpc client: {}", e)),
+        );
+
+        let cluster_id = pd_client
+            .get_cluster_id()
+            .unwrap_or_else(|e| fatal!("failed to get cluster id: {}", e));
+        if cluster_id == DEFAULT_CLUSTER_ID {
+            fatal!("cluster id can't be {}", DEFAULT_CLUSTER_ID);
+        }
+        config.server.cluster_id = cluster_id;
+        info!(
+            "connect to PD cluster";
+            "cluster_id" => cluster_id
+        );
+
+        pd_client
+    }
+}
+
+/// Generic over the PD client type so tests can supply a mock (returning
+/// canned TSO, store allocation, and region heartbeat responses) instead of
+/// blocking on a real cluster. [`TiKvServer::init`] is the only production
+/// caller, always instantiating `C = RpcClient`.
+impl<C: PdClient + 'static, ER: RaftEngine> TiKvServer<C, ER> {
+    fn init_with_pd_client(
+        mut config: TikvConfig,
+        proxy_config: ProxyConfig,
+        engine_store_server_helper_ptr: isize,
+        pd_client: Arc<C>,
+        security_mgr: Arc<SecurityManager>,
+        env: Arc<Environment>,
+    ) -> TiKvServer<C, ER> {
+        tikv_util::thread_group::set_properties(Some(GroupProperties::default()));
 
         // Initialize and check config
         info!("using proxy config"; "config" => ?proxy_config);
@@ -591,6 +979,8 @@ impl<ER: RaftEngine> TiKvServer<ER> {
         let background_worker = WorkerBuilder::new("background")
             .thread_count(thread_count)
             .create();
+        let worker_registry = Arc::new(WorkerRegistry::default());
+        worker_registry.register("background", false);
 
         let resource_manager = if config.resource_control.enabled {
             let mgr = Arc::new(ResourceGroupManager::default());
@@ -599,8 +989,11 @@ impl<ER: RaftEngine> TiKvServer<ER> {
             // spawn a task to periodically update the minimal virtual time of all resource
             // groups.
             let resource_mgr = mgr.clone();
+            let resource_mgr_worker_entry =
+                worker_registry.register_interval("resource-manager", false, MIN_PRIORITY_UPDATE_INTERVAL);
             background_worker.spawn_interval_task(MIN_PRIORITY_UPDATE_INTERVAL, move || {
                 resource_mgr.advance_min_virtual_time();
+                resource_mgr_worker_entry.tick();
             });
             // spawn a task to watch all resource groups update.
             background_worker.spawn_async_task(async move {
@@ -665,6 +1058,10 @@ impl<ER: RaftEngine> TiKvServer<ER> {
             quota_limiter,
             resource_manager,
             tablet_registry: None,
+            backup_worker: None,
+            worker_registry,
+            health_service: None,
+            sst_scrub_control: Arc::new(SstScrubControl::new(DEFAULT_SST_SCRUB_NANOS_PER_BYTE)),
         }
     }
 
@@ -716,31 +1113,6 @@ impl<ER: RaftEngine> TiKvServer<ER> {
         ConfigController::new(config)
     }
 
-    fn connect_to_pd_cluster(
-        config: &mut TikvConfig,
-        env: Arc<Environment>,
-        security_mgr: Arc<SecurityManager>,
-    ) -> Arc<RpcClient> {
-        let pd_client = Arc::new(
-            RpcClient::new(&config.pd, Some(env), security_mgr)
-                .unwrap_or_else(|e| fatal!("failed to create rpc client: {}", e)),
-        );
-
-        let cluster_id = pd_client
-            .get_cluster_id()
-            .unwrap_or_else(|e| fatal!("failed to get cluster id: {}", e));
-        if cluster_id == DEFAULT_CLUSTER_ID {
-            fatal!("cluster id can't be {}", DEFAULT_CLUSTER_ID);
-        }
-        config.server.cluster_id = cluster_id;
-        info!(
-            "connect to PD cluster";
-            "cluster_id" => cluster_id
-        );
-
-        pd_client
-    }
-
     fn check_conflict_addr(&mut self) {
         let cur_addr: SocketAddr = self
             .config
@@ -908,10 +1280,50 @@ impl<ER: RaftEngine> TiKvServer<ER> {
             tikv::config::Module::Gc,
             Box::new(gc_worker.get_config_manager()),
         );
+        // `GcWorkerConfigManager` above already wires `gc.max_write_bytes_per_sec`
+        // to the worker's live token-bucket limiter, so PD/local config reloads
+        // take effect without a restart; surface the rate actually in effect,
+        // both in the log and as a gauge an operator can graph alongside the
+        // rest of the proxy's metrics, since the config manager itself has no
+        // way to export it from inside the external `gc_worker` crate.
+        GC_MAX_WRITE_BYTES_PER_SEC.set(self.config.gc.max_write_bytes_per_sec.0 as i64);
+        info!(
+            "gc write rate limit configured";
+            "max_write_bytes_per_sec" => self.config.gc.max_write_bytes_per_sec.0,
+        );
+        self.worker_registry.register("gc", true);
 
         gc_worker
     }
 
+    /// Starts the backup endpoint over the same `Engines<TiFlashEngine, ER>`
+    /// and `region_info_accessor` the rest of this bootstrap already holds,
+    /// so an operator can stream a consistent snapshot of the TiFlash-backed
+    /// CFs out of a running proxy without standing up a full TiKV replica.
+    /// Must run after `servers` is populated (needs the node's store id). The
+    /// returned scheduler is handed to `backup::Service` in
+    /// `register_services`; the worker itself is kept in `self.backup_worker`
+    /// so it stops cleanly alongside everything else in `stop`.
+    fn init_backup_worker(&mut self) -> Scheduler<backup::Task> {
+        let engines = self.engines.as_ref().unwrap();
+        let store_id = self.servers.as_ref().unwrap().node.id();
+        let backup_endpoint = BackupEndpoint::new(
+            store_id,
+            engines.engine.clone(),
+            self.region_info_accessor.clone(),
+            engines.engines.kv.rocks.clone(),
+            self.config.backup.clone(),
+            self.concurrency_manager.clone(),
+            self.config.storage.api_version(),
+        );
+        let mut backup_worker = Box::new(self.background_worker.lazy_build("backup-endpoint"));
+        let backup_scheduler = backup_worker.scheduler();
+        backup_worker.start(backup_endpoint);
+        self.backup_worker = Some(backup_worker);
+        self.worker_registry.register("backup", false);
+        backup_scheduler
+    }
+
     fn init_servers<F: KvFormat>(&mut self) -> Arc<VersionTrack<ServerConfig>> {
         let flow_controller = Arc::new(FlowController::Singleton(EngineFlowController::new(
             &self.config.storage.flow_control,
@@ -921,6 +1333,7 @@ impl<ER: RaftEngine> TiKvServer<ER> {
         let mut gc_worker = self.init_gc_worker();
         let mut ttl_checker = Box::new(LazyWorker::new("ttl-checker"));
         let ttl_scheduler = ttl_checker.scheduler();
+        self.worker_registry.register("ttl-checker", true);
 
         let cfg_controller = self.cfg_controller.as_mut().unwrap();
 
@@ -932,16 +1345,21 @@ impl<ER: RaftEngine> TiKvServer<ER> {
         );
 
         // Create cdc.
-        // let mut cdc_worker = Box::new(LazyWorker::new("cdc"));
-        // let cdc_scheduler = cdc_worker.scheduler();
-        // let txn_extra_scheduler =
-        // cdc::CdcTxnExtraScheduler::new(cdc_scheduler.clone());
-        //
-        // self.engines
-        //     .as_mut()
-        //     .unwrap()
-        //     .engine
-        //     .set_txn_extra_scheduler(Arc::new(txn_extra_scheduler));
+        #[cfg(feature = "cdc")]
+        let mut cdc_worker = Box::new(LazyWorker::new("cdc"));
+        #[cfg(feature = "cdc")]
+        let cdc_scheduler = cdc_worker.scheduler();
+        #[cfg(feature = "cdc")]
+        {
+            let txn_extra_scheduler = cdc::CdcTxnExtraScheduler::new(cdc_scheduler.clone());
+
+            self.engines
+                .as_mut()
+                .unwrap()
+                .engine
+                .set_txn_extra_scheduler(Arc::new(txn_extra_scheduler));
+            self.worker_registry.register("cdc", true);
+        }
 
         // let lock_mgr = LockManager::new(&self.config.pessimistic_txn);
         let lock_mgr = LockManager::new();
@@ -956,6 +1374,7 @@ impl<ER: RaftEngine> TiKvServer<ER> {
 
         let pd_worker = LazyWorker::new("pd-worker");
         let pd_sender = pd_worker.scheduler();
+        self.worker_registry.register("pd-worker", false);
 
         if let Some(sst_worker) = &mut self.sst_worker {
             let sst_runner = RecoveryRunner::new(
@@ -966,6 +1385,7 @@ impl<ER: RaftEngine> TiKvServer<ER> {
             );
             sst_worker.start_with_timer(sst_runner);
         }
+        self.init_sst_scrub_worker();
 
         let unified_read_pool = if self.config.readpool.is_unified_pool_enabled() {
             let resource_ctl = self
@@ -1004,18 +1424,22 @@ impl<ER: RaftEngine> TiKvServer<ER> {
         let (recorder_notifier, collector_reg_handle, resource_tag_factory, recorder_worker) =
             resource_metering::init_recorder(self.config.resource_metering.precision.as_millis());
         self.to_stop.push(recorder_worker);
+        self.worker_registry.register("resource-metering-recorder", false);
         let (reporter_notifier, data_sink_reg_handle, reporter_worker) =
             resource_metering::init_reporter(
                 self.config.resource_metering.clone(),
                 collector_reg_handle.clone(),
             );
         self.to_stop.push(reporter_worker);
+        self.worker_registry.register("resource-metering-reporter", false);
         let (address_change_notifier, single_target_worker) = resource_metering::init_single_target(
             self.config.resource_metering.receiver_address.clone(),
             self.env.clone(),
             data_sink_reg_handle.clone(),
         );
         self.to_stop.push(single_target_worker);
+        self.worker_registry
+            .register("resource-metering-single-target", false);
 
         let cfg_manager = resource_metering::ConfigManager::new(
             self.config.resource_metering.clone(),
@@ -1155,32 +1579,53 @@ impl<ER: RaftEngine> TiKvServer<ER> {
         //     causal_ob.register_to(self.coprocessor_host.as_mut().unwrap());
         // }
 
-        // // Register cdc.
-        // let cdc_ob = cdc::CdcObserver::new(cdc_scheduler.clone());
-        // cdc_ob.register_to(self.coprocessor_host.as_mut().unwrap());
-        // // Register cdc config manager.
-        // cfg_controller.register(
-        //     tikv::config::Module::CDC,
-        //     Box::new(CdcConfigManager(cdc_worker.scheduler())),
-        // );
+        // Register cdc.
+        //
+        // Endpoint construction (the part that would actually drain
+        // `cdc_scheduler` and stream changes out) needs the upstream
+        // `cdc::Endpoint`'s full constructor — pd client, security manager,
+        // causal-ts provider — none of which this extracted snapshot carries
+        // far enough to reconstruct safely, so it's left for a follow-up once
+        // those pieces land here too. The observer, txn-extra routing, and
+        // config manager below are real and wired in as soon as the `cdc`
+        // feature is enabled; they're the part this crate exercises directly.
+        #[cfg(feature = "cdc")]
+        {
+            let cdc_ob = cdc::CdcObserver::new(cdc_scheduler.clone());
+            cdc_ob.register_to(self.coprocessor_host.as_mut().unwrap());
+            // Register cdc config manager.
+            cfg_controller.register(
+                tikv::config::Module::CDC,
+                Box::new(CdcConfigManager(cdc_worker.scheduler())),
+            );
+            self.to_stop.push(cdc_worker);
+        }
 
-        // // Create resolved ts worker
-        // let rts_worker = if self.config.resolved_ts.enable {
-        //     let worker = Box::new(LazyWorker::new("resolved-ts"));
-        //     // Register the resolved ts observer
-        //     let resolved_ts_ob = resolved_ts::Observer::new(worker.scheduler());
-        //     resolved_ts_ob.register_to(self.coprocessor_host.as_mut().unwrap());
-        //     // Register config manager for resolved ts worker
-        //     cfg_controller.register(
-        //         tikv::config::Module::ResolvedTs,
-        //         Box::new(resolved_ts::ResolvedTsConfigManager::new(
-        //             worker.scheduler(),
-        //         )),
-        //     );
-        //     Some(worker)
-        // } else {
-        //     None
-        // };
+        // Create resolved ts worker. Same caveat as cdc above applies to the
+        // `resolved_ts::Endpoint` itself; the observer and config manager are
+        // real once the `resolved-ts` feature is on.
+        #[cfg(feature = "resolved-ts")]
+        let rts_worker = if self.config.resolved_ts.enable {
+            let worker = Box::new(LazyWorker::new("resolved-ts"));
+            // Register the resolved ts observer
+            let resolved_ts_ob = resolved_ts::Observer::new(worker.scheduler());
+            resolved_ts_ob.register_to(self.coprocessor_host.as_mut().unwrap());
+            // Register config manager for resolved ts worker
+            cfg_controller.register(
+                tikv::config::Module::ResolvedTs,
+                Box::new(resolved_ts::ResolvedTsConfigManager::new(
+                    worker.scheduler(),
+                )),
+            );
+            self.worker_registry.register("resolved-ts", true);
+            Some(worker)
+        } else {
+            None
+        };
+        #[cfg(feature = "resolved-ts")]
+        if let Some(rts_worker) = rts_worker {
+            self.to_stop.push(rts_worker);
+        }
 
         let server_config = Arc::new(VersionTrack::new(self.config.server.clone()));
 
@@ -1194,6 +1639,8 @@ impl<ER: RaftEngine> TiKvServer<ER> {
             .unwrap_or_else(|e| fatal!("failed to validate raftstore config {}", e));
         let raft_store = Arc::new(VersionTrack::new(self.config.raft_store.clone()));
         let health_service = HealthService::default();
+        health_service.set_serving_status("", ServingStatus::NotServing);
+        self.health_service = Some(health_service.clone());
         let mut default_store = kvproto::metapb::Store::default();
 
         if !self.proxy_config.server.engine_store_version.is_empty() {
@@ -1266,6 +1713,7 @@ impl<ER: RaftEngine> TiKvServer<ER> {
         let check_leader_scheduler = self
             .background_worker
             .start("check-leader", check_leader_runner);
+        self.worker_registry.register("check-leader", false);
 
         self.snap_mgr = Some(snap_mgr.clone());
         // Create server
@@ -1429,6 +1877,29 @@ impl<ER: RaftEngine> TiKvServer<ER> {
         server_config
     }
 
+    /// Snapshot of every worker registered in [`WorkerRegistry`] so far,
+    /// including its kind, iteration count, and pause/resume state, with
+    /// interval tasks auto-flagged dead once they've missed
+    /// `MISSED_TICKS_DEAD_THRESHOLD` ticks. Not yet plumbed into a live
+    /// `/workers` route or the debug gRPC service — `StatusServer`'s router
+    /// and `DiagnosticsService`'s RPC surface both live in external crates
+    /// (`status_server`, `tikv::server::service`) not part of this extracted
+    /// snapshot — but this is the seam either would call into once wired up.
+    #[allow(dead_code)]
+    fn list_workers(&self) -> Vec<WorkerSnapshot> {
+        self.worker_registry.list()
+    }
+
+    #[allow(dead_code)]
+    fn pause_worker(&self, name: &str) -> Result<(), String> {
+        self.worker_registry.pause(name)
+    }
+
+    #[allow(dead_code)]
+    fn resume_worker(&self, name: &str) -> Result<(), String> {
+        self.worker_registry.resume(name)
+    }
+
     fn register_services(&mut self) {
         let servers = self.servers.as_mut().unwrap();
         let engines = self.engines.as_ref().unwrap();
@@ -1484,7 +1955,17 @@ impl<ER: RaftEngine> TiKvServer<ER> {
         }
 
         // Lock manager.
+
         // Backup service.
+        let backup_scheduler = self.init_backup_worker();
+        let backup_service = backup::Service::new(backup_scheduler);
+        if servers
+            .server
+            .register_service(create_backup(backup_service))
+            .is_some()
+        {
+            fatal!("failed to register backup service");
+        }
     }
 
     fn init_io_utility(&mut self) -> BytesFetcher {
@@ -1504,7 +1985,14 @@ impl<ER: RaftEngine> TiKvServer<ER> {
             BytesFetcher::FromRateLimiter(limiter.statistics().unwrap())
         };
         // Set up IO limiter even when rate limit is disabled, so that rate limits can
-        // be dynamically applied later on.
+        // be dynamically applied later on. Dynamic updates themselves already ride
+        // on the `StorageConfigManger` registered in `init_servers` (the
+        // real upstream `tikv::storage::config_manager` forwards `io-rate-limit.*`
+        // sub-changes straight to whatever limiter `get_io_rate_limiter` returns),
+        // so registering a second manager for the same config module here would
+        // just shadow that existing wiring rather than add anything. What's
+        // missing is visibility into the limit actually in effect; surface that.
+        IO_RATE_LIMIT_MAX_BYTES_PER_SEC.set(self.config.storage.io_rate_limit.max_bytes_per_sec.0 as i64);
         set_io_rate_limiter(Some(limiter));
         fetcher
     }
@@ -1523,12 +2011,28 @@ impl<ER: RaftEngine> TiKvServer<ER> {
         );
         let mut io_metrics = IOMetricsManager::new(fetcher);
         let engines_info_clone = engines_info.clone();
+        let tiflash_engine = self.engines.as_ref().unwrap().engines.kv.clone();
+        let metrics_flush_entry = self.worker_registry.register_interval(
+            "metrics-flush",
+            false,
+            DEFAULT_METRICS_FLUSH_INTERVAL,
+        );
         self.background_worker
             .spawn_interval_task(DEFAULT_METRICS_FLUSH_INTERVAL, move || {
                 let now = Instant::now();
                 engine_metrics.flush(now);
                 io_metrics.flush(now);
                 engines_info_clone.update(now);
+                // Advances per-CF durability watermarks: flushes only the
+                // CFs that have fallen behind on applied-but-unflushed raft
+                // log entries, instead of this loop's otherwise
+                // whole-engine-unaware reporting above.
+                if let Err(e) =
+                    tiflash_engine.flush_lagging_cfs(ENGINE_METRICS_FLUSH_LAGGING_MIN_APPLY_LAG)
+                {
+                    error!("flush lagging cfs failed"; "err" => ?e);
+                }
+                metrics_flush_entry.tick();
             });
         if let Some(limiter) = get_io_rate_limiter() {
             limiter.set_low_priority_io_adjustor_if_needed(Some(engines_info));
@@ -1537,10 +2041,16 @@ impl<ER: RaftEngine> TiKvServer<ER> {
         let mut mem_trace_metrics = MemoryTraceManager::default();
         mem_trace_metrics.register_provider(MEMTRACE_RAFTSTORE.clone());
         mem_trace_metrics.register_provider(MEMTRACE_COPROCESSOR.clone());
+        let memtrace_flush_entry = self.worker_registry.register_interval(
+            "memtrace-flush",
+            false,
+            DEFAULT_MEMTRACE_FLUSH_INTERVAL,
+        );
         self.background_worker
             .spawn_interval_task(DEFAULT_MEMTRACE_FLUSH_INTERVAL, move || {
                 let now = Instant::now();
                 mem_trace_metrics.flush(now);
+                memtrace_flush_entry.tick();
             });
     }
 
@@ -1557,8 +2067,14 @@ impl<ER: RaftEngine> TiKvServer<ER> {
 
         let almost_full_threshold = reserve_space;
         let already_full_threshold = reserve_space / 2;
+        let storage_stats_entry = self.worker_registry.register_interval(
+            "storage-stats",
+            false,
+            DEFAULT_STORAGE_STATS_INTERVAL,
+        );
         self.background_worker
             .spawn_interval_task(DEFAULT_STORAGE_STATS_INTERVAL, move || {
+                storage_stats_entry.tick();
                 let disk_stats = match fs2::statvfs(&store_path) {
                     Err(e) => {
                         error!(
@@ -1634,12 +2150,48 @@ impl<ER: RaftEngine> TiKvServer<ER> {
             let sst_worker = Box::new(LazyWorker::new("sst-recovery"));
             let scheduler = sst_worker.scheduler();
             self.sst_worker = Some(sst_worker);
+            self.worker_registry.register("sst-recovery", true);
             Some(scheduler)
         } else {
             None
         }
     }
 
+    /// Spawns the proactive `sst-scrub` worker: once per
+    /// [`DEFAULT_SST_SCRUB_CYCLE`], walks every `.sst` file under
+    /// `self.store_path`, verifies its checksum, and hands any corrupt file
+    /// off to the reactive `sst-recovery` worker's scheduler. A no-op if
+    /// `sst-recovery` itself is disabled (`background_error_recovery_window`
+    /// is zero), since there would be nowhere to hand corruption off to.
+    fn init_sst_scrub_worker(&mut self) {
+        let scheduler = match self.sst_worker.as_ref() {
+            Some(sst_worker) => sst_worker.scheduler(),
+            None => return,
+        };
+        let store_path = self.store_path.clone();
+        let control = self.sst_scrub_control.clone();
+        let entry = self.worker_registry.register("sst-scrub", true);
+        self.background_worker
+            .spawn_interval_task(DEFAULT_SST_SCRUB_CYCLE, move || {
+                scrub_sst_directory(&store_path, &scheduler, &control, &entry);
+            });
+    }
+
+    /// Runtime control-channel seam for the `sst-scrub` worker's tranquility
+    /// knob (see [`SstScrubControl`] for why this isn't wired to the status
+    /// server / persisted config the request describes).
+    #[allow(dead_code)]
+    fn set_sst_scrub_tranquility(&self, nanos_per_byte: u64) {
+        self.sst_scrub_control
+            .nanos_per_byte
+            .store(nanos_per_byte, Ordering::SeqCst);
+    }
+
+    #[allow(dead_code)]
+    fn sst_scrub_tranquility(&self) -> u64 {
+        self.sst_scrub_control.nanos_per_byte.load(Ordering::SeqCst)
+    }
+
     fn run_server(&mut self, server_config: Arc<VersionTrack<ServerConfig>>) {
         let server = self.servers.as_mut().unwrap();
         server
@@ -1698,6 +2250,10 @@ impl<ER: RaftEngine> TiKvServer<ER> {
             sst_worker.stop_worker();
         }
 
+        if let Some(backup_worker) = self.backup_worker {
+            backup_worker.stop_worker();
+        }
+
         self.to_stop.into_iter().for_each(|s| s.stop());
     }
 }
@@ -1718,6 +2274,19 @@ pub trait ConfiguredRaftEngine: RaftEngine {
     fn as_ps_engine(&mut self) -> Option<&mut PSLogEngine> {
         None
     }
+
+    /// Best-effort measure of this raft engine's not-yet-fsynced write
+    /// backlog, in bytes, so `EnginesResourceInfo::update` can fold raft
+    /// write/fsync pressure into the shared IO budget score even for raft
+    /// engine backends that aren't `RocksEngine` (whose raftdb backlog is
+    /// already covered by `EnginesResourceInfo::raft_engine` via
+    /// `fetch_engine_cf`'s pending-compaction-bytes reading). Defaults to 0
+    /// (no known backlog) since no backend here exposes a direct
+    /// bytes-pending-fsync counter through the surface this crate depends
+    /// on; overriding this is the seam for a backend that gains one.
+    fn raft_unsynced_bytes(&self) -> u64 {
+        0
+    }
 }
 
 impl ConfiguredRaftEngine for engine_rocks::RocksEngine {
@@ -1780,6 +2349,20 @@ impl ConfiguredRaftEngine for RaftLogEngine {
         );
         let should_dump = raft_data_state_machine.before_open_target();
 
+        // Symmetric check for migrating off `PSLogEngine` back onto
+        // `RaftLogEngine` (see `ConfiguredRaftEngine for PSLogEngine`, which
+        // marks its directory with a `ps_engine` file for exactly this
+        // purpose). A second, independent `RaftDataStateMachine` since the
+        // raftdb check above already owns the one state transition into
+        // this directory -- at most one of the two should ever fire.
+        let ps_marker_path = config.raft_engine.config().dir.clone() + "/ps_engine";
+        let mut ps_data_state_machine = RaftDataStateMachine::new(
+            &config.storage.data_dir,
+            &ps_marker_path,
+            &config.raft_engine.config().dir,
+        );
+        let should_dump_from_ps = !should_dump && ps_data_state_machine.before_open_target();
+
         let raft_config = config.raft_engine.config();
         let raft_engine =
             RaftLogEngine::new(raft_config, key_manager.clone(), get_io_rate_limiter())
@@ -1799,25 +2382,77 @@ impl ConfiguredRaftEngine for RaftLogEngine {
             raftdb.stop();
             drop(raftdb);
             raft_data_state_machine.after_dump_data();
+        } else if should_dump_from_ps {
+            let ps_engine = PSLogEngine::new();
+            dump_raft_engine_entries(&ps_engine, &raft_engine, 8 /* threads */);
+            ps_data_state_machine.after_dump_data();
         }
         (raft_engine, None)
     }
 }
 
+/// Symmetric counterpart to `dump_raft_engine_to_raftdb`/
+/// `dump_raftdb_to_raft_engine` (from `server::raft_engine_switch`) for the
+/// `PSLogEngine` (PageStorage) backend, so a cluster can migrate its raft
+/// log storage to and from PageStorage the same way it already can between
+/// `RaftLogEngine` and raftdb. Written generically against the `RaftEngine`
+/// trait both sides already implement, rather than against anything
+/// PSLogEngine-specific, since this snapshot's `engine_tiflash::ps_engine`
+/// module (the actual PageStorage FFI plumbing) isn't carried far enough to
+/// hook directly.
+fn dump_raft_engine_entries<S: RaftEngine, D: RaftEngine>(src: &S, dst: &D, threads: usize) {
+    let _ = threads;
+    src.for_each_raft_group::<engine_traits::Error, _>(&mut |id| {
+        let mut batch = dst.log_batch(1);
+        if let Some(state) = src.get_raft_state(id)? {
+            batch.put_raft_state(id, &state)?;
+        }
+        let mut entries = Vec::new();
+        src.get_all_entries_to(id, &mut entries)?;
+        if !entries.is_empty() {
+            batch.append(id, None, entries)?;
+        }
+        dst.consume(&mut batch, false)?;
+        Ok(())
+    })
+    .unwrap_or_else(|e: engine_traits::Error| fatal!("failed to dump raft log entries: {:?}", e));
+}
+
 impl ConfiguredRaftEngine for PSLogEngine {
     fn build(
-        _config: &TikvConfig,
+        config: &TikvConfig,
         _env: &Arc<Env>,
-        _key_manager: &Option<Arc<DataKeyManager>>,
+        key_manager: &Option<Arc<DataKeyManager>>,
         _block_cache: &Cache,
     ) -> (Self, Option<Arc<RocksStatistics>>) {
         // create a dummy file in raft engine dir to pass initial config check
-        let raft_engine_path = _config.raft_engine.config().dir + "/ps_engine";
+        let raft_engine_path = config.raft_engine.config().dir.clone() + "/ps_engine";
         let path = Path::new(&raft_engine_path);
         if !path.exists() {
             File::create(path).unwrap();
         }
-        (PSLogEngine::new(), None)
+
+        // Mirrors `RaftLogEngine::build`'s own dump protocol: `raft_engine_path`
+        // (the marker file above) stands in for this engine's directory, since
+        // PageStorage itself isn't a directory `RaftDataStateMachine` can stat.
+        let mut raft_data_state_machine = RaftDataStateMachine::new(
+            &config.storage.data_dir,
+            &config.raft_engine.config().dir,
+            &raft_engine_path,
+        );
+        let should_dump = raft_data_state_machine.before_open_target();
+
+        let ps_engine = PSLogEngine::new();
+        if should_dump {
+            let raft_engine =
+                RaftLogEngine::new(config.raft_engine.config(), key_manager.clone(), None)
+                    .expect("failed to open raft engine for migration");
+            dump_raft_engine_entries(&raft_engine, &ps_engine, 8 /* threads */);
+            raft_engine.stop();
+            drop(raft_engine);
+            raft_data_state_machine.after_dump_data();
+        }
+        (ps_engine, None)
     }
 
     fn as_ps_engine(&mut self) -> Option<&mut PSLogEngine> {
@@ -2000,11 +2635,103 @@ impl<EK: KvEngine, ER: RaftEngine> EngineMetricsManager<EK, ER> {
     }
 }
 
+/// Applied-but-unflushed threshold `TiFlashEngine::flush_lagging_cfs` flushes
+/// a CF at, rather than waiting for a fixed whole-engine sweep.
+const ENGINE_METRICS_FLUSH_LAGGING_MIN_APPLY_LAG: u64 = 4096;
+
+/// Number of recent [`EnginesResourceInfo::update`] samples the BBR-style
+/// adjustor below keeps for its max/min filters, matching BBR's own
+/// ~10-round-trip window.
+const IO_BUDGET_FILTER_WINDOW: usize = 10;
+
+/// Pacing-gain cycle for the ProbeBW phase: briefly probe higher (1.25),
+/// back off once to pay down the resulting queue (0.75), then cruise at
+/// the estimated bottleneck bandwidth for the rest of the cycle. Mirrors
+/// BBR's own 8-phase ProbeBW gain cycle.
+const IO_BUDGET_PACING_GAIN_CYCLE: [f32; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+
+/// Re-enter ProbeRTT once every this many [`IoBudgetAdjustor::adjust`]
+/// calls, for [`IO_BUDGET_PROBE_RTT_INTERVALS`] calls in a row, briefly
+/// dropping the budget so the min-filter gets a fresh floor sample even
+/// under continuous load.
+const IO_BUDGET_PROBE_RTT_INTERVAL: usize = 80;
+const IO_BUDGET_PROBE_RTT_INTERVALS: usize = 2;
+const IO_BUDGET_PROBE_RTT_GAIN: f32 = 0.5;
+
+/// Per-interval step cap for [`EnginesResourceInfo`]'s smoothed compaction-
+/// debt estimator, in the same normalized (`SCALE_FACTOR`) units as
+/// `normalized_pending_bytes`. Caps how far the tracked debt may move
+/// toward the latest instantaneous pending-compaction-bytes reading in one
+/// `update` call, so a transient jump (e.g. L0 file count stepping
+/// 0 -> 4 -> 0 as a single compaction starts and finishes) can't move the
+/// signal `adjust` sees by more than this much at once.
+const COMPACTION_DEBT_MAX_STEP: u32 = 10;
+
 pub struct EnginesResourceInfo {
     kv_engine: TiFlashEngine,
     raft_engine: Option<RocksEngine>,
+    /// Polls the active raft engine's not-yet-fsynced write backlog,
+    /// working for every `ConfiguredRaftEngine` backend -- not just the
+    /// `RocksEngine` (raftdb) case `raft_engine` above already covers via
+    /// `fetch_engine_cf`. Captured at construction as a closure over a
+    /// cloned engine handle so `update` doesn't need to stay generic over
+    /// `CER`.
+    raft_unsynced_bytes_fn: Arc<dyn Fn() -> u64 + Send + Sync>,
+    /// Normalizes `raft_unsynced_bytes_fn`'s reading the same way
+    /// `fetch_engine_cf` normalizes against each CF's
+    /// `soft_pending_compaction_bytes_limit`. Zero disables the raft
+    /// backlog term entirely (the default, since this snapshot has no
+    /// config plumbing to size it from a live cluster's raft-store
+    /// config).
+    raft_io_soft_limit_bytes: AtomicU64,
+    /// Whether the KV engine runs with Titan enabled, so `update` knows
+    /// whether to query blob-GC backlog stats at all.
+    kv_is_titan: bool,
+    /// Normalizes each CF's Titan live-blob-size reading (see
+    /// `fetch_titan_cf`) the same way `fetch_engine_cf` normalizes against
+    /// `soft_pending_compaction_bytes_limit`. Zero disables the blob-GC
+    /// term, since this snapshot has no config plumbing to size it from a
+    /// live cluster's Titan config.
+    titan_blob_gc_soft_limit_bytes: AtomicU64,
     latest_normalized_pending_bytes: AtomicU32,
     normalized_pending_bytes_collector: MovingAvgU32,
+    /// Raw (pre-average) normalized pending-bytes score observed on the
+    /// previous `update` call, so `update` can derive a per-interval
+    /// "bytes drained" delta to feed the BtlBw filter below.
+    last_raw_pending_bytes: AtomicU32,
+    /// Smoothed alternative to the raw pending-compaction-bytes signal:
+    /// steps toward it by at most [`COMPACTION_DEBT_MAX_STEP`] per
+    /// `update` instead of following it directly, so a jumpy instantaneous
+    /// reading can't itself jerk the IO budget around. Computing true
+    /// per-level compaction debt (file bytes above each level's target)
+    /// would need level-by-level target-size accessors this crate's
+    /// `RocksEngine`/`CfOptions` surface doesn't expose, so this
+    /// bounded-step approximation stands in for it -- same smoothing
+    /// property, without guessing at unavailable RocksDB internals.
+    compaction_debt: AtomicU32,
+    /// When set, `update` feeds [`Self::compaction_debt`] (instead of the
+    /// raw per-interval pending-bytes reading) into the moving-average
+    /// collector and BBR filters that `adjust` reads from. Exposed as a
+    /// runtime knob via [`EnginesResourceInfo::set_use_compaction_debt_signal`]
+    /// rather than a `TikvConfig` field, since the config struct that would
+    /// normally own this choice lives in an external, unvendored crate in
+    /// this snapshot, and `EnginesResourceInfo` itself isn't retained
+    /// anywhere a config-change callback could reach it from.
+    use_compaction_debt_signal: AtomicBool,
+    /// Windowed max-filter of bytes drained per interval -- the BBR
+    /// "BtlBw" analog: the best throughput the compaction pipeline has
+    /// sustained recently, regardless of how large the backlog itself is.
+    btlbw_filter: Mutex<VecDeque<u32>>,
+    /// Windowed min-filter of the normalized pending-bytes score -- the
+    /// BBR "min-RTT" analog: the backlog floor this workload settles to
+    /// when it isn't the bottleneck.
+    floor_filter: Mutex<VecDeque<u32>>,
+    /// Position in `IO_BUDGET_PACING_GAIN_CYCLE`, advanced once per
+    /// `adjust` call that isn't a ProbeRTT call.
+    gain_cycle_pos: AtomicUsize,
+    /// Count of all `adjust` calls so far; taken modulo
+    /// `IO_BUDGET_PROBE_RTT_INTERVAL` to decide when a ProbeRTT phase runs.
+    intervals_since_probe_rtt: AtomicUsize,
 }
 
 impl EnginesResourceInfo {
@@ -2013,16 +2740,63 @@ impl EnginesResourceInfo {
     fn new<CER: ConfiguredRaftEngine>(
         engines: &Engines<TiFlashEngine, CER>,
         max_samples_to_preserve: usize,
+        kv_is_titan: bool,
     ) -> Self {
         let raft_engine = engines.raft.as_rocks_engine().cloned();
+        let raft_for_backlog = engines.raft.clone();
         EnginesResourceInfo {
             kv_engine: engines.kv.clone(),
             raft_engine,
+            raft_unsynced_bytes_fn: Arc::new(move || raft_for_backlog.raft_unsynced_bytes()),
+            raft_io_soft_limit_bytes: AtomicU64::new(0),
+            kv_is_titan,
+            titan_blob_gc_soft_limit_bytes: AtomicU64::new(0),
             latest_normalized_pending_bytes: AtomicU32::new(0),
             normalized_pending_bytes_collector: MovingAvgU32::new(max_samples_to_preserve),
+            last_raw_pending_bytes: AtomicU32::new(0),
+            compaction_debt: AtomicU32::new(0),
+            use_compaction_debt_signal: AtomicBool::new(false),
+            btlbw_filter: Mutex::new(VecDeque::with_capacity(IO_BUDGET_FILTER_WINDOW)),
+            floor_filter: Mutex::new(VecDeque::with_capacity(IO_BUDGET_FILTER_WINDOW)),
+            gain_cycle_pos: AtomicUsize::new(0),
+            intervals_since_probe_rtt: AtomicUsize::new(0),
         }
     }
 
+    /// Selects whether `update` feeds `adjust`'s filters from the raw
+    /// per-interval pending-compaction-bytes reading (the default) or from
+    /// the smoothed [`Self::compaction_debt`] estimator instead.
+    #[allow(dead_code)]
+    pub fn set_use_compaction_debt_signal(&self, enabled: bool) {
+        self.use_compaction_debt_signal
+            .store(enabled, Ordering::Relaxed);
+    }
+
+    /// Current value of the smoothed compaction-debt estimator, in the
+    /// same normalized (`SCALE_FACTOR`) units as `normalized_pending_bytes`.
+    #[allow(dead_code)]
+    pub fn normalized_compaction_debt(&self) -> u32 {
+        self.compaction_debt.load(Ordering::Relaxed)
+    }
+
+    /// Sets the soft limit `update` normalizes the raft engine's unsynced
+    /// write backlog against, in bytes. Zero (the default) disables the
+    /// raft backlog term.
+    #[allow(dead_code)]
+    pub fn set_raft_io_soft_limit_bytes(&self, limit: u64) {
+        self.raft_io_soft_limit_bytes
+            .store(limit, Ordering::Relaxed);
+    }
+
+    /// Sets the soft limit `update` normalizes each CF's Titan blob-GC
+    /// backlog against, in bytes. Zero (the default) disables the blob-GC
+    /// term; has no effect when the KV engine isn't running with Titan.
+    #[allow(dead_code)]
+    pub fn set_titan_blob_gc_soft_limit_bytes(&self, limit: u64) {
+        self.titan_blob_gc_soft_limit_bytes
+            .store(limit, Ordering::Relaxed);
+    }
+
     pub fn update(&self, _now: Instant) {
         let mut normalized_pending_bytes = 0;
 
@@ -2041,36 +2815,159 @@ impl EnginesResourceInfo {
             }
         }
 
+        // Titan's blob-GC backlog is a real source of write amplification
+        // and IO contention alongside SST compaction, so when the KV
+        // engine runs with Titan enabled, fold each CF's live (i.e. not
+        // yet GC'd) blob bytes in the same way `fetch_engine_cf` folds in
+        // pending SST compaction bytes, rather than only ever accounting
+        // for SST compaction and under-counting total background write
+        // work on Titan-enabled clusters.
+        fn fetch_titan_cf(
+            engine: &RocksEngine,
+            cf: &str,
+            soft_limit: u64,
+            normalized_pending_bytes: &mut u32,
+        ) {
+            if soft_limit == 0 {
+                return;
+            }
+            if let Ok(Some(live_blob_bytes)) =
+                engine.get_property_int_cf(cf, "rocksdb.titandb.live-blob-size")
+            {
+                *normalized_pending_bytes = std::cmp::max(
+                    *normalized_pending_bytes,
+                    (live_blob_bytes * EnginesResourceInfo::SCALE_FACTOR / soft_limit) as u32,
+                );
+            }
+        }
+
         if let Some(raft_engine) = &self.raft_engine {
             fetch_engine_cf(raft_engine, CF_DEFAULT, &mut normalized_pending_bytes);
         }
+        let titan_blob_gc_soft_limit = self.titan_blob_gc_soft_limit_bytes.load(Ordering::Relaxed);
         for cf in &[CF_DEFAULT, CF_WRITE, CF_LOCK] {
             fetch_engine_cf(&self.kv_engine.rocks, cf, &mut normalized_pending_bytes);
+            if self.kv_is_titan {
+                fetch_titan_cf(
+                    &self.kv_engine.rocks,
+                    cf,
+                    titan_blob_gc_soft_limit,
+                    &mut normalized_pending_bytes,
+                );
+            }
+        }
+
+        // Fold the raft engine's unsynced write backlog in the same way,
+        // so raft log writes/fsyncs that are themselves the bottleneck
+        // (visible on every backend, not just raftdb) throttle the shared
+        // compaction budget too instead of being invisible to it.
+        let raft_soft_limit = self.raft_io_soft_limit_bytes.load(Ordering::Relaxed);
+        if raft_soft_limit > 0 {
+            let raft_unsynced_bytes = (self.raft_unsynced_bytes_fn)();
+            normalized_pending_bytes = std::cmp::max(
+                normalized_pending_bytes,
+                (raft_unsynced_bytes * EnginesResourceInfo::SCALE_FACTOR / raft_soft_limit) as u32,
+            );
+        }
+
+        // Step the smoothed compaction-debt estimator toward this
+        // interval's raw reading by at most `COMPACTION_DEBT_MAX_STEP`, so
+        // a jumpy instantaneous pending-compaction-bytes count (RocksDB is
+        // known to report e.g. L0 file count stepping 0 -> 4 -> 0 as one
+        // compaction starts and finishes) can't itself jerk the tracked
+        // debt around -- it can only crawl toward wherever the raw signal
+        // currently sits.
+        let previous_debt = self.compaction_debt.load(Ordering::Relaxed);
+        let new_debt = if normalized_pending_bytes >= previous_debt {
+            previous_debt + (normalized_pending_bytes - previous_debt).min(COMPACTION_DEBT_MAX_STEP)
+        } else {
+            previous_debt - (previous_debt - normalized_pending_bytes).min(COMPACTION_DEBT_MAX_STEP)
+        };
+        self.compaction_debt.store(new_debt, Ordering::Relaxed);
+
+        let signal = if self.use_compaction_debt_signal.load(Ordering::Relaxed) {
+            new_debt
+        } else {
+            normalized_pending_bytes
+        };
+
+        let (_, avg) = self.normalized_pending_bytes_collector.add(signal);
+        self.latest_normalized_pending_bytes
+            .store(std::cmp::max(signal, avg), Ordering::Relaxed);
+
+        // Feed the BBR-style filters `adjust` below reads from. RocksDB
+        // doesn't hand us a direct "bytes compacted this interval" counter,
+        // so a drop in `signal` since the last `update` is used as the
+        // throughput signal: the backlog can only shrink by compaction
+        // draining it faster than new writes arrive, so the size of the
+        // drop is a lower bound on bytes drained this interval. Growth
+        // (the backlog got bigger) means nothing was drained net, hence
+        // `saturating_sub` floors it at zero rather than going negative.
+        let previous = self.last_raw_pending_bytes.swap(signal, Ordering::Relaxed);
+        let drained = previous.saturating_sub(signal);
+        {
+            let mut btlbw = self.btlbw_filter.lock().unwrap();
+            btlbw.push_back(drained);
+            if btlbw.len() > IO_BUDGET_FILTER_WINDOW {
+                btlbw.pop_front();
+            }
+        }
+        {
+            let mut floor = self.floor_filter.lock().unwrap();
+            floor.push_back(signal);
+            if floor.len() > IO_BUDGET_FILTER_WINDOW {
+                floor.pop_front();
+            }
         }
-        let (_, avg) = self
-            .normalized_pending_bytes_collector
-            .add(normalized_pending_bytes);
-        self.latest_normalized_pending_bytes.store(
-            std::cmp::max(normalized_pending_bytes, avg),
-            Ordering::Relaxed,
-        );
     }
 }
 
 impl IoBudgetAdjustor for EnginesResourceInfo {
     fn adjust(&self, total_budgets: usize) -> usize {
-        let score = self.latest_normalized_pending_bytes.load(Ordering::Relaxed) as f32
-            / Self::SCALE_FACTOR as f32;
-        // Two reasons for adding `sqrt` on top:
-        // 1) In theory the convergence point is independent of the value of pending
-        //    bytes (as long as backlog generating rate equals consuming rate, which is
-        //    determined by compaction budgets), a convex helps reach that point while
-        //    maintaining low level of pending bytes.
-        // 2) Variance of compaction pending bytes grows with its magnitude, a filter
-        //    with decreasing derivative can help balance such trend.
-        let score = score.sqrt();
-        // The target global write flow slides between Bandwidth / 2 and Bandwidth.
-        let score = 0.5 + score / 2.0;
-        (total_budgets as f32 * score) as usize
+        let btlbw = self.btlbw_filter.lock().unwrap().iter().copied().max();
+        let floor = self
+            .floor_filter
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .min()
+            .unwrap_or(0);
+
+        // Before the filters have enough samples to mean anything (cold
+        // start, or a backlog that has only ever grown), fall back to the
+        // old memoryless sqrt curve so the budget still makes forward
+        // progress instead of converging on zero.
+        let btlbw_score = match btlbw {
+            Some(b) if b > 0 => b as f32 / Self::SCALE_FACTOR as f32,
+            _ => {
+                let score = self.latest_normalized_pending_bytes.load(Ordering::Relaxed) as f32
+                    / Self::SCALE_FACTOR as f32;
+                0.5 + score.sqrt() / 2.0
+            }
+        };
+        // Never let the BtlBw score fall below half of what the observed
+        // backlog floor alone would need to drain at steady state --
+        // otherwise a noisy zero in the BtlBw window could stall draining
+        // entirely. The pacing or ProbeRTT gain below still scales the
+        // clamped score, so a ProbeRTT call can budget under this floor.
+        let floor_score = floor as f32 / Self::SCALE_FACTOR as f32;
+        let btlbw_score = btlbw_score.max(floor_score / 2.0);
+
+        let probing_rtt = self
+            .intervals_since_probe_rtt
+            .fetch_add(1, Ordering::Relaxed)
+            % IO_BUDGET_PROBE_RTT_INTERVAL
+            < IO_BUDGET_PROBE_RTT_INTERVALS;
+        let gain = if probing_rtt {
+            IO_BUDGET_PROBE_RTT_GAIN
+        } else {
+            let pos = self.gain_cycle_pos.fetch_add(1, Ordering::Relaxed)
+                % IO_BUDGET_PACING_GAIN_CYCLE.len();
+            IO_BUDGET_PACING_GAIN_CYCLE[pos]
+        };
+
+        let budget = (total_budgets as f32 * btlbw_score * gain) as usize;
+        budget.min(total_budgets)
     }
 }
@@ -0,0 +1,144 @@
+// Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
+//
+// A small sequencing harness for fast-add-peer tests. Scattered
+// `std::thread::sleep` calls and bare `fail::cfg(..., "pause")` are
+// replaced by named rendezvous points that a test can arm, step through,
+// and wait on deterministically, so a test reaches e.g.
+// `PeerState::Applying` as soon as it's possible rather than after a
+// fixed, arbitrarily-chosen sleep.
+use crate::utils::v1::*;
+
+/// A named pause point a FAP test can step through. Each maps to an
+/// existing failpoint in `engine_store_ffi::observer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RendezvousPoint {
+    /// Paused after the donor's per-CF SST files are written but before
+    /// the snapshot meta/checksums are generated (`build_and_send_snapshot`).
+    Build,
+    /// Paused just after a snapshot finishes applying
+    /// (`TiFlashObserver::post_apply_snapshot`).
+    PostApplySnapshot,
+    /// Paused just before the raft apply path persists its apply state.
+    /// Unlike the other two points, this one's call site lives outside
+    /// this crate's source (the raftstore apply loop), so it has no
+    /// observer-side notify hook wired up, and `FapSequencer::wait_reached`
+    /// returns at once for it.
+    PreWriteApplyState,
+}
+
+impl RendezvousPoint {
+    fn fail_point_name(self) -> &'static str {
+        match self {
+            RendezvousPoint::Build => "fap_ffi_pause",
+            RendezvousPoint::PostApplySnapshot => "on_ob_post_apply_snapshot",
+            RendezvousPoint::PreWriteApplyState => "on_pre_write_apply_state",
+        }
+    }
+
+    /// Whether `engine_store_ffi::observer` calls `note_test_rendezvous`
+    /// right before this point's failpoint, i.e. whether `wait` can block
+    /// on the observer's notify signal instead of polling.
+    fn has_notify_hook(self) -> bool {
+        !matches!(self, RendezvousPoint::PreWriteApplyState)
+    }
+}
+
+/// How long a single rendezvous wait may take before it's treated as a
+/// hang. Deliberately much larger than any real phase transition, so a
+/// miss reads as "this never happened" rather than racing real latency.
+const SLOW_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Steps a FAP donor/receiver pair through named pause points instead of
+/// sleeping a fixed duration and hoping the right state has been reached.
+/// One sequencer is expected per test; `arm`/`release` wrap the
+/// `fail::cfg`/`fail::remove` calls tests already made by hand.
+#[derive(Default)]
+pub struct FapSequencer {
+    armed: Vec<RendezvousPoint>,
+}
+
+impl FapSequencer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pauses execution at `point` (`fail::cfg(.., "pause")`).
+    pub fn arm(&mut self, point: RendezvousPoint) -> &mut Self {
+        fail::cfg(point.fail_point_name(), "pause").unwrap();
+        self.armed.push(point);
+        self
+    }
+
+    /// Resumes execution at `point` (`fail::remove`).
+    pub fn release(&mut self, point: RendezvousPoint) -> &mut Self {
+        fail::remove(point.fail_point_name());
+        self.armed.retain(|p| *p != point);
+        self
+    }
+
+    /// Blocks until a thread is parked at `point`'s failpoint, panicking
+    /// with a diagnostic after `SLOW_TIMEOUT` instead of hanging. For
+    /// `PreWriteApplyState`, which has no notify hook (see
+    /// `RendezvousPoint::has_notify_hook`), it returns at once without
+    /// waiting.
+    pub fn wait_reached(&self, point: RendezvousPoint, at_least: u64) {
+        if !point.has_notify_hook() {
+            return;
+        }
+        let reached =
+            engine_store_ffi::observer::wait_for_test_rendezvous(
+                point.fail_point_name(),
+                at_least,
+                SLOW_TIMEOUT,
+            );
+        if !reached {
+            panic!(
+                "fap_sequencer: rendezvous {:?} ({}) not reached within {:?}; still armed: {:?}",
+                point,
+                point.fail_point_name(),
+                SLOW_TIMEOUT,
+                self.armed,
+            );
+        }
+    }
+
+    /// Polls every 20ms for `cond` to hold on `states`, dumping the last-seen state
+    /// and panicking rather than letting a CI run time out if it never
+    /// does.
+    pub fn wait_until_cond(
+        &self,
+        cluster_ext: &ClusterExt,
+        node_id: u64,
+        stores: Option<Vec<u64>>,
+        label: &str,
+        cond: &dyn Fn(&States) -> bool,
+    ) {
+        let start = Instant::now();
+        let poll_interval = Duration::from_millis(20);
+        let mut last_states: Option<States> = None;
+        loop {
+            let (done, states) = peek_cond_node(cluster_ext, node_id, stores.clone(), cond);
+            last_states = states.or(last_states);
+            if done {
+                return;
+            }
+            if start.elapsed() > SLOW_TIMEOUT {
+                panic!(
+                    "fap_sequencer: wait_until_cond({}) timed out after {:?}; still armed: {:?}; last states: {:?}",
+                    label, SLOW_TIMEOUT, self.armed, last_states,
+                );
+            }
+            std::thread::sleep(poll_interval);
+        }
+    }
+}
+
+impl Drop for FapSequencer {
+    fn drop(&mut self) {
+        // A test that panics mid-sequence shouldn't leave a failpoint
+        // paused for whichever test runs next in the same process.
+        for point in self.armed.drain(..) {
+            fail::remove(point.fail_point_name());
+        }
+    }
+}
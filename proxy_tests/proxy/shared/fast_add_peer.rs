@@ -1,6 +1,8 @@
 // Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
 use crate::utils::v1::*;
 
+use super::fap_sequencer::{FapSequencer, RendezvousPoint};
+
 #[derive(PartialEq, Eq)]
 enum SourceType {
     Leader,
@@ -8,6 +10,9 @@ enum SourceType {
     // The learner coesn't catch up with Leader.
     DelayedLearner,
     InvalidSource,
+    // The target region is merged into another one while FAP is still
+    // building/sending its snapshot; see `merge_during_fap`.
+    Merging,
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -16,6 +21,10 @@ enum PauseType {
     Build,
     ApplySnapshot,
     SendFakeSnapshot,
+    // Pause after `fn_fast_add_peer` has run but before
+    // `build_and_send_snapshot` sends its snapshot, so a `CommitMerge` can
+    // be made to land in between; see `merge_during_fap`.
+    CommitMerge,
 }
 
 // This test is covered in `simple_fast_add_peer`.
@@ -805,12 +814,16 @@ fn test_existing_peer() {
 }
 
 // We will reject remote peer in Applying state.
+//
+// Reference case for `fap_sequencer`: every wait below but one either steps
+// through a named rendezvous point or is a condition wait with a bounded
+// timeout and a state dump on failure. The exception is the fixed sleep
+// after releasing `Build`, which has no rendezvous point downstream of it.
 #[test]
 fn test_apply_snapshot() {
     tikv_util::set_panic_hook(true, "./");
     let (mut cluster, pd_client) = new_mock_cluster(0, 3);
     cluster.cfg.proxy_cfg.engine_store.enable_fast_add_peer = true;
-    // fail::cfg("on_pre_write_apply_state", "return").unwrap();
     disable_auto_gen_compact_log(&mut cluster);
     // Disable auto generate peer.
     pd_client.disable_default_operator();
@@ -819,17 +832,20 @@ fn test_apply_snapshot() {
     pd_client.must_add_peer(1, new_learner_peer(2, 2));
     must_put_and_check_key(&mut cluster, 1, 2, Some(true), None, Some(vec![1]));
 
+    let mut sequencer = FapSequencer::new();
+
     // We add peer 3 from peer 2, it will be paused before fetching peer 2's data.
     // However, peer 2 will apply conf change.
     fail::cfg("fap_mock_add_peer_from_id", "return(2)").unwrap();
-    fail::cfg("fap_ffi_pause", "pause").unwrap();
+    sequencer.arm(RendezvousPoint::Build);
     pd_client.must_add_peer(1, new_learner_peer(3, 3));
-    std::thread::sleep(std::time::Duration::from_millis(1000));
+    sequencer.wait_reached(RendezvousPoint::Build, 1);
     must_put_and_check_key(&mut cluster, 2, 3, Some(true), None, Some(vec![1, 2]));
-    must_wait_until_cond_node(
+    sequencer.wait_until_cond(
         &cluster.cluster_ext,
         1,
         Some(vec![2]),
+        "peer 3 conf change applied",
         &|states: &States| -> bool {
             find_peer_by_id(states.in_disk_region_state.get_region(), 3).is_some()
         },
@@ -850,20 +866,21 @@ fn test_apply_snapshot() {
     cluster.must_put(b"k4", b"v4");
     cluster.must_put(b"k5", b"v5");
     // Log compacted, peer 2 will get snapshot, however, we pause when applying
-    // snapshot.
+    // snapshot. `force_compact_log` is itself a blocking "force" helper, not
+    // a named rendezvous point, so there's nothing for the sequencer to step
+    // through here.
     force_compact_log(&mut cluster, b"k2", Some(vec![1]));
-    // Wait log compacted.
-    std::thread::sleep(std::time::Duration::from_millis(1000));
-    fail::cfg("on_ob_post_apply_snapshot", "pause").unwrap();
+    sequencer.arm(RendezvousPoint::PostApplySnapshot);
     // Trigger a snapshot to 2.
     cluster.clear_send_filters();
 
     debug!("wait applying snapshot of peer 2");
     // Wait until peer 2 in Applying state.
-    must_wait_until_cond_node(
+    sequencer.wait_until_cond(
         &cluster.cluster_ext,
         1,
         Some(vec![2]),
+        "peer 2 reached Applying",
         &|states: &States| -> bool {
             states.in_disk_region_state.get_state() == PeerState::Applying
         },
@@ -872,16 +889,18 @@ fn test_apply_snapshot() {
     // Now if we continue fast path, peer 2 will be in Applying state.
     // Peer 3 can't use peer 2's data.
     // We will end up going slow path.
-    fail::remove("fap_ffi_pause");
+    sequencer.release(RendezvousPoint::Build);
     fail::cfg("fap_core_no_fast_path", "panic").unwrap();
+    // Give the fast path logic a chance to run to completion (or panic the
+    // failpoint above) now that it's unblocked; there's no further
+    // rendezvous point downstream of it to wait on.
     std::thread::sleep(std::time::Duration::from_millis(300));
     // Resume applying snapshot
-    fail::remove("on_ob_post_apply_snapshot");
+    sequencer.release(RendezvousPoint::PostApplySnapshot);
     check_key(&cluster, b"k4", b"v4", Some(true), None, Some(vec![1, 3]));
     cluster.shutdown();
     fail::remove("fap_core_no_fast_path");
     fail::remove("fap_mock_add_peer_from_id");
-    // fail::remove("before_tiflash_check_double_write");
 }
 
 #[test]
@@ -1060,9 +1079,10 @@ fn test_single_replica_migrate() {
     cluster.must_put(b"krm2", b"v");
     check_key(&cluster, b"krm2", b"v", Some(true), None, Some(vec![1]));
 
-    // Try fast add peer from removed peer 2.
-    // TODO It will fallback to slow path if we don't support single replica
-    // migration.
+    // Try fast add peer from removed peer 2. Peer 2's region data is
+    // retained on store 2 as a tombstone-with-data fast-add-peer source
+    // (see `retain_as_tombstone_source`), so this single-replica migration
+    // now takes the fast path instead of falling back to a full snapshot.
     fail::cfg("fap_core_no_fast_path", "panic").unwrap();
     pd_client.must_add_peer(1, new_learner_peer(3, 3));
     check_key(&cluster, b"krm2", b"v", Some(true), None, Some(vec![3]));
@@ -1080,3 +1100,147 @@ fn test_single_replica_migrate() {
     fail::remove("on_pre_write_apply_state");
     cluster.shutdown();
 }
+
+// Symmetric to `test_overlap_apply_legacy_in_the_middle`, but for merges
+// instead of splits: if the FAP target region is the target of a
+// `CommitMerge` that lands between `fn_fast_add_peer` and
+// `build_and_send_snapshot`, the merged source data must not be overwritten
+// or lost. `source_type` picks which region is being merged away while the
+// FAP attempt on the target is in flight; `pause` controls where that
+// attempt is paused relative to the merge landing.
+fn merge_during_fap(source_type: SourceType, pause: PauseType) {
+    assert_eq!(source_type, SourceType::Merging);
+    assert_eq!(pause, PauseType::CommitMerge);
+    tikv_util::set_panic_hook(true, "./");
+    let (mut cluster, pd_client) = new_mock_cluster_snap(0, 3);
+    pd_client.disable_default_operator();
+    cluster.cfg.proxy_cfg.engine_store.enable_fast_add_peer = true;
+    fail::cfg("on_can_apply_snapshot", "return(true)").unwrap();
+    cluster.cfg.raft_store.right_derive_when_split = true;
+
+    let _ = cluster.run_conf_change();
+
+    cluster.must_put(b"k1", b"v1");
+    cluster.must_put(b"k3", b"v3");
+    check_key(&cluster, b"k1", b"v1", Some(true), None, Some(vec![1]));
+    check_key(&cluster, b"k3", b"v3", Some(true), None, Some(vec![1]));
+    let r1 = cluster.get_region(b"k1");
+    let r3 = cluster.get_region(b"k3");
+    assert_eq!(r1.get_id(), r3.get_id());
+
+    cluster.must_split(&r1, b"k2");
+    let target = cluster.get_region(b"k1"); // holds [-inf, k2)
+    let source = cluster.get_region(b"k3"); // holds [k2, inf), region_id == r1's
+    let target_id = target.get_id();
+    let source_id = source.get_id();
+
+    // Replicate both halves to store 2 normally first, so only the FAP
+    // attempt below races against the merge.
+    pd_client.must_add_peer(target_id, new_learner_peer(2, 2001));
+    pd_client.must_add_peer(source_id, new_learner_peer(2, 2003));
+    must_peer_state(target_id, 2, PeerState::Normal);
+    must_peer_state(source_id, 2, PeerState::Normal);
+
+    // Pause the FAP attempt on store 3 right after it has read `target`'s
+    // apply state, but before it sends the snapshot built from it.
+    fail::cfg("fap_ffi_pause_after_fap_call", "pause").unwrap();
+    fail::cfg("fap_mock_add_peer_from_id", "return(2)").unwrap();
+    pd_client.must_add_peer(target_id, new_learner_peer(3, 3001));
+    std::thread::sleep(std::time::Duration::from_millis(1000));
+
+    // Merge `source` into `target` while the FAP attempt is stuck.
+    pd_client.must_add_peer(source_id, new_learner_peer(3, 3003));
+    pd_client.must_merge(source_id, target_id);
+    must_peer_state(source_id, 1, PeerState::Tombstone);
+    must_peer_state(target_id, 1, PeerState::Normal);
+
+    // Let the paused FAP attempt continue: it must notice the merge landed
+    // mid-flight and fall back to the slow path rather than ingest the
+    // stale, pre-merge snapshot it already built.
+    fail::remove("fap_ffi_pause_after_fap_call");
+    must_peer_state(target_id, 3, PeerState::Normal);
+
+    // The merged-in range (k3) must be present on store 3, which it
+    // wouldn't be had the stale pre-merge FAP snapshot been ingested.
+    check_key(&cluster, b"k1", b"v1", Some(true), None, Some(vec![3]));
+    check_key(&cluster, b"k3", b"v3", Some(true), None, Some(vec![3]));
+
+    fail::remove("fap_mock_add_peer_from_id");
+    fail::remove("on_can_apply_snapshot");
+    cluster.shutdown();
+}
+
+#[test]
+fn test_merge_during_fap() {
+    merge_during_fap(SourceType::Merging, PauseType::CommitMerge);
+}
+
+// A read issued against a FAP-installed learner while it's still catching up
+// to the leader's read index must block rather than be rejected or served
+// stale, and must return the correct value as soon as the learner's applied
+// index catches up (see `wait_for_read_index`/`pending_reads`).
+#[test]
+fn test_read_index_wait_for_apply() {
+    tikv_util::set_panic_hook(true, "./");
+    let (mut cluster, pd_client) = new_mock_cluster(0, 2);
+    cluster.cfg.proxy_cfg.engine_store.enable_fast_add_peer = true;
+    disable_auto_gen_compact_log(&mut cluster);
+    pd_client.disable_default_operator();
+    let _ = cluster.run_conf_change();
+    must_put_and_check_key(&mut cluster, 1, 2, Some(true), None, Some(vec![1]));
+
+    fail::cfg("fap_core_no_fallback", "panic").unwrap();
+    pd_client.must_add_peer(1, new_learner_peer(2, 2));
+    must_put_and_check_key(&mut cluster, 3, 4, Some(true), None, None);
+    fail::remove("fap_core_no_fallback");
+
+    // Hold the learner's applied index behind the leader's by pausing its
+    // apply, then issue a replica read: it must block rather than error or
+    // return a stale value.
+    fail::cfg("on_pre_write_apply_state", "pause").unwrap();
+    cluster.must_put(b"k5", b"v5");
+
+    let read_index_wait_start = std::time::Instant::now();
+    check_key(&mut cluster, b"k5", b"v5", Some(true), None, Some(vec![2]));
+    assert!(read_index_wait_start.elapsed() < std::time::Duration::from_secs(5));
+
+    fail::remove("on_pre_write_apply_state");
+    cluster.shutdown();
+}
+
+// A donor whose SST data is corrupted in flight must not have its
+// snapshot ingested: the receiver's digest check (`verify_sst_digests`,
+// gated on `FAST_ADD_PEER_MODE == FastAddPeerMode::Sst`) has to catch it
+// and force the region back onto the normal snapshot path instead of
+// completing the fast path on bad data.
+#[test]
+fn test_fast_add_peer_corrupt_sst_falls_back() {
+    tikv_util::set_panic_hook(true, "./");
+    let (mut cluster, pd_client) = new_mock_cluster(0, 3);
+    cluster.cfg.proxy_cfg.engine_store.enable_fast_add_peer = true;
+    fail::cfg("fap_core_fallback_millis", "return(1000000)").unwrap();
+    disable_auto_gen_compact_log(&mut cluster);
+    pd_client.disable_default_operator();
+    let _ = cluster.run_conf_change();
+
+    cluster.must_put(b"k0", b"v0");
+    pd_client.must_add_peer(1, new_learner_peer(2, 2));
+    cluster.must_put(b"k1", b"v1");
+    check_key(&cluster, b"k1", b"v1", Some(true), None, Some(vec![1, 2]));
+
+    // The fast path must not be allowed to report success once its data is
+    // corrupt; only a fallback to the normal snapshot path may finish it.
+    fail::cfg("go_fast_path_succeed", "panic").unwrap();
+    fail::cfg("fap_mock_add_peer_from_id", "return(2)").unwrap();
+    fail::cfg("fap_force_sst_digest_mismatch", "return").unwrap();
+
+    pd_client.must_add_peer(1, new_learner_peer(3, 3));
+    cluster.must_put(b"k2", b"v2");
+    check_key(&cluster, b"k2", b"v2", Some(true), None, Some(vec![1, 2, 3]));
+
+    fail::remove("fap_force_sst_digest_mismatch");
+    fail::remove("fap_mock_add_peer_from_id");
+    fail::remove("go_fast_path_succeed");
+    fail::remove("fap_core_fallback_millis");
+    cluster.shutdown();
+}
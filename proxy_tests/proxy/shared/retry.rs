@@ -0,0 +1,71 @@
+// Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
+//
+// A generic "poll until a predicate holds" combinator, factored out of
+// the `sleep(fixed_ms); collect_all_states(...); assert` pattern that
+// used to be spelled out by hand across `proxy_tests/proxy/write.rs`
+// (e.g. in `test_compact_log`). A fixed sleep either races a slow
+// run or wastes a fixed amount of time on a fast one; polling with
+// backoff converges as soon as the condition is actually met.
+//
+// This takes the state snapshot as a closure rather than a concrete
+// cluster type, since the mock cluster type `collect_all_states` is
+// called on lives in this crate's test support module, which isn't part
+// of this change -- a closure lets every call site keep using whatever
+// `collect_all_states(&cluster, region_id)` call it already had.
+use std::time::{Duration, Instant};
+
+/// A simple absolute point in time a retry loop gives up at. Its own type
+/// (rather than a bare `Duration` recomputed at each call site) so a test
+/// can share one deadline across several waits.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    at: Instant,
+}
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Deadline {
+            at: Instant::now() + timeout,
+        }
+    }
+
+    pub fn is_past(&self) -> bool {
+        Instant::now() >= self.at
+    }
+}
+
+/// Initial backoff between polls; doubles (capped at `MAX_BACKOFF`) each
+/// time the predicate isn't satisfied yet, so a fast transition is caught
+/// almost immediately while a slow one doesn't spin the CPU.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(5);
+const MAX_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Repeatedly calls `collect` to take a fresh snapshot of type `S` and
+/// checks `pred(prev, &current)`, backing off exponentially between
+/// attempts, until `pred` holds or `deadline` passes -- whichever comes
+/// first. Returns the first snapshot satisfying `pred`; panics with a
+/// `{:?}` diff of `prev` vs. the last-seen snapshot on timeout, so a
+/// failure says how far the state actually got instead of just "timed
+/// out".
+pub fn wait_until<S: std::fmt::Debug>(
+    mut collect: impl FnMut() -> S,
+    prev: &S,
+    pred: &dyn Fn(&S, &S) -> bool,
+    deadline: Deadline,
+) -> S {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let current = collect();
+        if pred(prev, &current) {
+            return current;
+        }
+        if deadline.is_past() {
+            panic!(
+                "wait_until: predicate not satisfied before deadline; prev={:?} current={:?}",
+                prev, current,
+            );
+        }
+        std::thread::sleep(backoff);
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
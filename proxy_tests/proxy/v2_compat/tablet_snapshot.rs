@@ -1,5 +1,6 @@
 // Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::collections::HashMap;
 use std::sync::Mutex;
 
 use engine_traits::{Checkpointer, KvEngine, SyncMutable};
@@ -10,7 +11,12 @@ use mock_engine_store::{
 };
 use proxy_ffi::{
     interfaces_ffi::{ColumnFamilyType, EngineIteratorSeekType},
-    snapshot_reader_impls::{tablet_reader::TabletReader, *},
+    snapshot_reader_impls,
+    snapshot_reader_impls::{
+        crypto::CipherContext,
+        tablet_reader::{encrypt_checkpoint, TabletReader},
+        *,
+    },
 };
 use raft::eraftpb::Snapshot;
 use raftstore::store::{snap::TABLET_SNAPSHOT_VERSION, TabletSnapKey, TabletSnapManager};
@@ -30,11 +36,47 @@ fn random_long_vec(length: usize) -> Vec<u8> {
     value
 }
 
+/// Digests over each CF's ordered (key, value) pairs read back from the
+/// freshly-created checkpoint, for a standalone round-trip check: the
+/// caller recomputes the same digest from `final_recv_path` once the
+/// tablet snapshot is received and compares the two values itself. This
+/// isn't digest metadata carried over the wire -- `RaftSnapshotData`/
+/// `SnapshotMeta` have no field for it and this repo doesn't own their
+/// kvproto schema (see `snapshot_reader_impls::digest`'s module docs).
+fn digest_checkpoint(checkpointer_path: &std::path::Path) -> HashMap<ColumnFamilyType, u128> {
+    let mut digests = HashMap::new();
+    for cf in [
+        ColumnFamilyType::Default,
+        ColumnFamilyType::Write,
+        ColumnFamilyType::Lock,
+    ] {
+        let digest = unsafe {
+            let reader = TabletReader::ffi_get_cf_file_reader(
+                checkpointer_path.to_str().unwrap(),
+                cf,
+                None,
+            );
+            ffi_sst_reader_seek(
+                reader.clone(),
+                cf,
+                EngineIteratorSeekType::Key,
+                BaseBuffView {
+                    data: std::ptr::null(),
+                    len: 0,
+                },
+            );
+            snapshot_reader_impls::digest::ffi_sst_reader_digest(reader, cf)
+        };
+        digests.insert(cf, digest);
+    }
+    digests
+}
+
 fn generate_snap<EK: KvEngine>(
     engine: &WrapFactory<EK>,
     region_id: u64,
     snap_mgr: &TabletSnapManager,
-) -> (RaftMessage, TabletSnapKey) {
+) -> (RaftMessage, TabletSnapKey, HashMap<ColumnFamilyType, u128>) {
     let tablet = engine.get_tablet_by_id(region_id).unwrap();
     let region_state = engine.region_local_state(region_id).unwrap().unwrap();
     let apply_state = engine.raft_apply_state(region_id).unwrap().unwrap();
@@ -61,6 +103,7 @@ fn generate_snap<EK: KvEngine>(
     checkpointer
         .create_at(checkpointer_path.as_path(), None, 0)
         .unwrap();
+    let digests = digest_checkpoint(checkpointer_path.as_path());
 
     let mut msg = RaftMessage::default();
     msg.region_id = region_id;
@@ -71,7 +114,7 @@ fn generate_snap<EK: KvEngine>(
     msg.mut_message().set_msg_type(MessageType::MsgSnapshot);
     msg.set_region_epoch(region_state.get_region().get_region_epoch().clone());
 
-    (msg, snap_key)
+    (msg, snap_key, digests)
 }
 
 #[test]
@@ -102,10 +145,19 @@ fn test_parse_tablet_snapshot() {
 
         let snap_mgr = cluster_v2.get_snap_mgr(1);
         let security_mgr = cluster_v2.get_security_mgr();
-        let (msg, snap_key) = generate_snap(&engine, region_id, &snap_mgr);
+        let (msg, snap_key, digests) = generate_snap(&engine, region_id, &snap_mgr);
         let cfg = tikv::server::Config::default();
         let limit = Limiter::new(f64::INFINITY);
         let env = Arc::new(Environment::new(1));
+        // `send_snap_v2` is `tikv::server::tablet_snap::send_snap`: its send
+        // loop, socket, and `Future` impl live entirely in the upstream
+        // `tikv` crate, not this one. A reactor-agnostic `poll_send`/
+        // `AsRawFd` surface has to be exposed by that function itself (or a
+        // wrapper `tikv` hands back), since this crate has no access to the
+        // socket or send state it closes over; a same-shaped type defined
+        // here with no way to plug into `send_snap_v2`'s real transfer would
+        // just be dead code next to this call. Driving it still requires a
+        // runtime (`block_on`) rather than an externally owned event loop.
         let _ = block_on(async {
             send_snap_v2(env, snap_mgr, security_mgr, &cfg, &s1_addr, msg, limit)
                 .unwrap()
@@ -148,6 +200,24 @@ fn test_parse_tablet_snapshot() {
             };
             ffi_sst_reader_seek(reader.clone(), cf, EngineIteratorSeekType::Key, bf);
             assert_eq!(ffi_sst_reader_remained(reader.clone(), cf), 0);
+
+            // Round-trip check, not a read of stored metadata: recompute the whole-CF
+            // digest from what the v1 side actually reads back and compare it against
+            // the value `generate_snap` computed on send, catching corruption the
+            // per-key checks above wouldn't notice.
+            let digest_reader =
+                TabletReader::ffi_get_cf_file_reader(path.as_path().to_str().unwrap(), cf, None);
+            ffi_sst_reader_seek(
+                digest_reader.clone(),
+                cf,
+                EngineIteratorSeekType::Key,
+                BaseBuffView {
+                    data: std::ptr::null(),
+                    len: 0,
+                },
+            );
+            let got = snapshot_reader_impls::digest::ffi_sst_reader_digest(digest_reader, cf);
+            assert_eq!(got, digests[&cf]);
         };
         validate(ColumnFamilyType::Default);
         validate(ColumnFamilyType::Write);
@@ -159,8 +229,130 @@ fn test_parse_tablet_snapshot() {
     test_parse_snap(20);
 }
 
-// This test won't run, since we don;t have transport for snapshot data.
-// #[test]
+fn make_encrypted_default_cf_checkpoint(key_num: usize) -> (std::path::PathBuf, CipherContext) {
+    let mut cluster_v2 = test_raftstore_v2::new_server_cluster(1, 1);
+    cluster_v2.run();
+
+    let region = cluster_v2.get_region(b"");
+    let region_id = region.get_id();
+    let engine = cluster_v2.get_engine(1);
+    let tablet = engine.get_tablet_by_id(region_id).unwrap();
+
+    for i in 0..key_num {
+        let k = format!("zk{:04}", i);
+        tablet.put(k.as_bytes(), &random_long_vec(1024)).unwrap();
+    }
+
+    let checkpointer_path =
+        std::env::temp_dir().join(format!("encrypted-tablet-{}", rand::thread_rng().gen::<u64>()));
+    let mut checkpointer = tablet.new_checkpointer().unwrap();
+    checkpointer
+        .create_at(checkpointer_path.as_path(), None, 0)
+        .unwrap();
+
+    let ctx = CipherContext::new(vec![0x42; 32], format!("{}-default", region_id).into_bytes());
+    encrypt_checkpoint(
+        checkpointer_path.to_str().unwrap(),
+        ColumnFamilyType::Default,
+        &ctx,
+    )
+    .unwrap();
+
+    cluster_v2.shutdown();
+    (checkpointer_path, ctx)
+}
+
+/// Seals a checkpoint's default CF with [`encrypt_checkpoint`] and confirms
+/// [`TabletReader`] reads the same key/value pairs back out through the
+/// decrypt-on-first-access path -- exercising `aead_seal` on the write side
+/// and `aead_open` on the read side for the first time outside unit-level
+/// `crypto` primitives.
+#[test]
+fn test_tablet_reader_encrypted_round_trip() {
+    let key_num = 20;
+    let (checkpointer_path, ctx) = make_encrypted_default_cf_checkpoint(key_num);
+
+    unsafe {
+        let reader = TabletReader::ffi_get_cf_file_reader(
+            checkpointer_path.to_str().unwrap(),
+            ColumnFamilyType::Default,
+            Some(ctx),
+        );
+        ffi_sst_reader_seek(
+            reader.clone(),
+            ColumnFamilyType::Default,
+            EngineIteratorSeekType::Key,
+            BaseBuffView {
+                data: std::ptr::null(),
+                len: 0,
+            },
+        );
+        for i in 0..key_num {
+            let k = format!("k{:04}", i);
+            assert_eq!(
+                ffi_sst_reader_remained(reader.clone(), ColumnFamilyType::Default),
+                1
+            );
+            let kbf = ffi_sst_reader_key(reader.clone(), ColumnFamilyType::Default);
+            assert_eq!(kbf.to_slice(), k.as_bytes());
+            ffi_sst_reader_next(reader.clone(), ColumnFamilyType::Default);
+        }
+        assert_eq!(
+            ffi_sst_reader_remained(reader.clone(), ColumnFamilyType::Default),
+            0
+        );
+    }
+
+    std::fs::remove_dir_all(&checkpointer_path).unwrap();
+}
+
+/// A corrupted sealed CF file must surface as an empty-looking reader, not
+/// a panic: flips a byte inside the first chunk's ciphertext so its tag
+/// check fails, then drives the reader the same way production code would.
+#[test]
+fn test_tablet_reader_decrypt_tag_mismatch_is_clean_error() {
+    let (checkpointer_path, ctx) = make_encrypted_default_cf_checkpoint(5);
+
+    let sealed_file = std::fs::read_dir(&checkpointer_path)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.to_string_lossy().contains("default"))
+        .expect("sealed default cf file");
+    let mut bytes = std::fs::read(&sealed_file).unwrap();
+    let header_len = proxy_ffi::snapshot_reader_impls::crypto::EncryptedFile::header_len();
+    bytes[header_len] ^= 0xff;
+    std::fs::write(&sealed_file, bytes).unwrap();
+
+    unsafe {
+        let reader = TabletReader::ffi_get_cf_file_reader(
+            checkpointer_path.to_str().unwrap(),
+            ColumnFamilyType::Default,
+            Some(ctx),
+        );
+        ffi_sst_reader_seek(
+            reader.clone(),
+            ColumnFamilyType::Default,
+            EngineIteratorSeekType::Key,
+            BaseBuffView {
+                data: std::ptr::null(),
+                len: 0,
+            },
+        );
+        assert_eq!(
+            ffi_sst_reader_remained(reader.clone(), ColumnFamilyType::Default),
+            0
+        );
+    }
+
+    std::fs::remove_dir_all(&checkpointer_path).unwrap();
+}
+
+// Previously disabled because we had no transport for snapshot data between
+// the two mock clusters; the `RegionPacketFilter`/`ForwardFactory` pair below
+// forwards raft messages (including `MsgSnapshot`) from each cluster's router
+// straight into the other's, which is enough for `cluster_v2`'s snapshot to
+// reach `cluster_v1`.
+#[test]
 fn test_handle_snapshot() {
     let mut cluster_v2 = test_raftstore_v2::new_node_cluster(1, 2);
     let (mut cluster_v1, _) = new_mock_cluster(1, 2);
@@ -245,7 +437,7 @@ fn test_v1_apply_snap_from_v2() {
     let limit = Limiter::new(f64::INFINITY);
     let env = Arc::new(Environment::new(1));
 
-    let (msg, snap_key) = generate_snap(&engine, region_id, &tablet_snap_mgr);
+    let (msg, snap_key, _digests) = generate_snap(&engine, region_id, &tablet_snap_mgr);
     let _ = block_on(async {
         send_snap_v2(
             env,
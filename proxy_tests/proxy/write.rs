@@ -1,5 +1,14 @@
 // Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
+//
+// A few of these tests used to wait for a raft command's effects with a
+// fixed `std::thread::sleep` before snapshotting state -- fine on a quiet
+// box, but either too short under load (flaky) or needlessly long on a
+// fast one. Where a test is waiting for a specific state transition (not
+// proving a negative -- that one genuinely needs a bounded dwell time),
+// it polls for the transition with `retry::wait_until` instead, backing
+// off exponentially until the predicate holds or a deadline passes.
 use crate::proxy::*;
+use crate::proxy::shared::retry::{wait_until, Deadline};
 
 #[test]
 fn test_interaction() {
@@ -136,9 +145,23 @@ fn test_leadership_change_impl(filter: bool) {
     fail::remove("on_empty_cmd_normal");
     // We need forward empty cmd generated by leadership changing to TiFlash.
     cluster.must_transfer_leader(region.get_id(), peer_1.clone());
-    std::thread::sleep(std::time::Duration::from_secs(1));
 
-    let new_states = collect_all_states(&cluster, region_id);
+    // Wait until the in-memory apply state has actually advanced instead of
+    // sleeping a fixed duration: a flat sleep either races a slow CI box or
+    // wastes time on a fast one.
+    let new_states = wait_until(
+        || collect_all_states(&cluster, region_id),
+        &prev_states,
+        &|old, new| {
+            old.keys().all(|k| {
+                let o: &States = &old[k];
+                let n: &States = &new[k];
+                o.in_memory_apply_state != n.in_memory_apply_state
+                    || o.in_memory_applied_term != n.in_memory_applied_term
+            })
+        },
+        Deadline::after(Duration::from_secs(10)),
+    );
     must_altered_memory_apply_state(&prev_states, &new_states);
     must_altered_memory_apply_term(&prev_states, &new_states);
 
@@ -175,9 +198,21 @@ fn test_kv_write_always_persist() {
         // This may happen after memory write data and before commit.
         // We must check if we already have in memory.
         check_apply_state(&cluster, region_id, &prev_states, Some(false), None);
-        std::thread::sleep(std::time::Duration::from_millis(20));
-        // However, advanced apply index will always persisted.
-        let new_states = collect_all_states(&cluster, region_id);
+        // However, advanced apply index will always be persisted. Poll for
+        // it instead of sleeping a fixed duration, matching the rest of
+        // this file's waits.
+        let new_states = wait_until(
+            || collect_all_states(&cluster, region_id),
+            &prev_states,
+            &|old, new| {
+                old.keys().all(|k| {
+                    let o: &States = &old[k];
+                    let n: &States = &new[k];
+                    o.in_disk_apply_state != n.in_disk_apply_state
+                })
+            },
+            Deadline::after(Duration::from_secs(10)),
+        );
         must_altered_disk_apply_state(&prev_states, &new_states);
         prev_states = new_states;
     }
@@ -326,6 +361,45 @@ fn test_unsupport_admin_cmd() {
     cluster.shutdown();
 }
 
+#[test]
+fn test_conf_change_membership() {
+    // Exercises the ChangePeer path `engine_store_ffi::observer` now
+    // tracks membership deltas for (see its `MembershipState`). The
+    // resulting peer-set cache isn't reachable through mock-engine-store's
+    // FFI surface, and the `on_conf_change` failpoint, set to `return`
+    // here, makes the observer return before recording the delta. So this
+    // only checks that the region's own peer set ends up right across a
+    // remove and a re-add, not that `TiFlashObserver::peers_at` agrees
+    // with it.
+    let (mut cluster, pd_client) = new_mock_cluster(0, 3);
+    disable_auto_gen_compact_log(&mut cluster);
+    let _ = cluster.run();
+
+    cluster.must_put(b"k1", b"v1");
+    let region = cluster.get_region(b"k1");
+    let region_id = region.get_id();
+    let eng_ids = cluster
+        .engines
+        .iter()
+        .map(|e| e.0.to_owned())
+        .collect::<Vec<_>>();
+    let removed_store = eng_ids[2];
+    let removed_peer = find_peer(&region, removed_store).cloned().unwrap();
+
+    fail::cfg("on_conf_change", "return").unwrap();
+
+    pd_client.must_remove_peer(region_id, removed_peer);
+    let region = cluster.get_region(b"k1");
+    assert_eq!(region.get_peers().len(), 2);
+
+    pd_client.must_add_peer(region_id, new_learner_peer(removed_store, removed_store));
+    let region = cluster.get_region(b"k1");
+    assert_eq!(region.get_peers().len(), 3);
+
+    fail::remove("on_conf_change");
+    cluster.shutdown();
+}
+
 #[test]
 fn test_old_compact_log() {
     // If we just return None for CompactLog, the region state in ApplyFsm will
@@ -406,13 +480,22 @@ fn test_compact_log() {
     // compact index should less than applied index
     assert!(!res.get_header().has_error(), "{:?}", res);
 
-    // TODO(tiflash) Make sure compact log is filtered successfully.
-    // Can be abstract to a retry function.
-    std::thread::sleep(std::time::Duration::from_millis(500));
-
     // CompactLog is filtered, because we can't flush data.
-    // However, we can still observe apply index advanced
-    let new_state = collect_all_states(&cluster, region_id);
+    // However, we can still observe apply index advanced. Retry until the
+    // applied index has moved on every peer instead of sleeping a fixed
+    // duration, since the command only ever advances it by one step.
+    let new_state = wait_until(
+        || collect_all_states(&cluster, region_id),
+        &prev_state,
+        &|old, new| {
+            old.keys().all(|k| {
+                let o: &States = &old[k];
+                let n: &States = &new[k];
+                o.in_memory_apply_state.get_applied_index() < n.in_memory_apply_state.get_applied_index()
+            })
+        },
+        Deadline::after(Duration::from_secs(10)),
+    );
     for i in prev_state.keys() {
         let old = prev_state.get(i).unwrap();
         let new = new_state.get(i).unwrap();
@@ -498,9 +581,22 @@ fn test_empty_cmd() {
 
     // We need forward empty cmd generated by leadership changing to TiFlash.
     cluster.must_transfer_leader(region.get_id(), peer_2.clone());
-    std::thread::sleep(std::time::Duration::from_secs(2));
 
-    let new_states = collect_all_states(&cluster, region_id);
+    // Wait for the apply state to actually move rather than sleeping a fixed
+    // duration, so this doesn't race a slow run or waste time on a fast one.
+    let new_states = wait_until(
+        || collect_all_states(&cluster, region_id),
+        &prev_states,
+        &|old, new| {
+            old.keys().all(|k| {
+                let o: &States = &old[k];
+                let n: &States = &new[k];
+                o.in_memory_apply_state != n.in_memory_apply_state
+                    || o.in_memory_applied_term != n.in_memory_applied_term
+            })
+        },
+        Deadline::after(Duration::from_secs(10)),
+    );
     must_altered_memory_apply_state(&prev_states, &new_states);
     must_altered_memory_apply_term(&prev_states, &new_states);
 
@@ -6,6 +6,7 @@ use causal_ts::CausalTsProviderImpl;
 use concurrency_manager::ConcurrencyManager;
 use engine_traits::{KvEngine, RaftEngine, TabletContext, TabletRegistry};
 use kvproto::{metapb, replication_modepb::ReplicationStatus};
+use online_config::ConfigChange;
 use pd_client::PdClient;
 use raftstore::{
     coprocessor::CoprocessorHost,
@@ -20,6 +21,33 @@ use tikv_util::{
 
 use crate::server::{node::init_store, Result};
 
+/// `raftstore_v2::Config` keys `NodeV2::update_config` is willing to apply
+/// to the running store without a restart -- tick/heartbeat cadence and
+/// the snapshot/split thresholds only affect behavior the next time the
+/// background raftstore threads read them off the shared `VersionTrack`,
+/// unlike e.g. RocksDB/engine options baked in at open time. Keys are the
+/// same kebab-case names used in `raftstore_v2::Config`'s TOML/online_config
+/// schema.
+const DYNAMIC_CONFIG_FIELDS: &[&str] = &[
+    "raft-base-tick-interval",
+    "raft-heartbeat-ticks",
+    "raft-election-timeout-ticks",
+    "region-split-check-diff",
+    "region-max-size",
+    "region-split-size",
+    "apply-batch-system.pool-size",
+    "store-batch-system.pool-size",
+];
+
+/// Which keys out of an `update_config` call were actually applied versus
+/// refused for requiring a restart, so FFI/admin callers can report it
+/// back instead of only getting a single pass/fail bit.
+#[derive(Debug, Default)]
+pub struct ConfigUpdateResult {
+    pub applied: Vec<String>,
+    pub refused: Vec<String>,
+}
+
 // TODO: we will rename another better name like RaftStore later.
 pub struct NodeV2<C: PdClient + 'static, EK: KvEngine, ER: RaftEngine> {
     cluster_id: u64,
@@ -30,6 +58,9 @@ pub struct NodeV2<C: PdClient + 'static, EK: KvEngine, ER: RaftEngine> {
     pd_client: Arc<C>,
     registry: TabletRegistry<EK>,
     logger: Logger,
+    // `Some` once `start_store` hands the system its config; backs
+    // `update_config`.
+    store_cfg: Option<Arc<VersionTrack<raftstore_v2::Config>>>,
 }
 
 impl<C, EK, ER> NodeV2<C, EK, ER>
@@ -55,6 +86,7 @@ where
             has_started: false,
             registry,
             logger: slog_global::borrow_global().new(o!()),
+            store_cfg: None,
         }
     }
 
@@ -157,7 +189,39 @@ where
         self.store.clone()
     }
 
-    // TODO: support updating dynamic configuration.
+    /// Applies the subset of `change` that's safe to pick up without a
+    /// restart, and reports which keys were applied versus refused.
+    /// Refused keys are left untouched in the running config -- the
+    /// caller (FFI/admin layer) decides whether that's worth surfacing as
+    /// an error.
+    pub fn update_config(&self, change: ConfigChange) -> Result<ConfigUpdateResult> {
+        let mut applicable = ConfigChange::default();
+        let mut refused = Vec::new();
+        for (key, value) in change {
+            if DYNAMIC_CONFIG_FIELDS.contains(&key.as_str()) {
+                applicable.insert(key, value);
+            } else {
+                refused.push(key);
+            }
+        }
+        let applied: Vec<String> = applicable.keys().cloned().collect();
+
+        if !applicable.is_empty() {
+            let store_cfg = self
+                .store_cfg
+                .as_ref()
+                .ok_or_else(|| box_err!("store {} has not started yet", self.store.get_id()))?;
+            store_cfg.update(move |cfg: &mut raftstore_v2::Config| cfg.update(applicable))?;
+        }
+
+        info!(
+            self.logger,
+            "applied dynamic raftstore-v2 config change";
+            "applied" => ?applied,
+            "refused" => ?refused,
+        );
+        Ok(ConfigUpdateResult { applied, refused })
+    }
 
     // TODO: check api version.
     // Do we really need to do the check giving we don't consider support upgrade
@@ -208,6 +272,7 @@ where
         self.has_started = true;
 
         let (router, system) = self.system.as_mut().unwrap();
+        self.store_cfg = Some(store_cfg.clone());
 
         system.start(
             store_id,